@@ -0,0 +1,201 @@
+use std::fmt::Display;
+
+use crate::{
+    Basileus,
+    err::{AclCheckError, AclGrantError, GetPermError},
+    perm::Perm,
+};
+use sqlx::{query, query_as};
+
+use tracing::info;
+
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS acl (
+    resource TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    action TEXT NOT NULL,
+    PRIMARY KEY (resource, kind, subject, action)
+);
+CREATE INDEX IF NOT EXISTS idx_acl_resource ON acl (resource);
+CREATE TRIGGER IF NOT EXISTS after_user_delete_acl
+AFTER DELETE ON user
+FOR EACH ROW
+BEGIN
+    DELETE FROM acl WHERE kind = 'user' AND subject = OLD.user;
+END;
+"#;
+
+/// The subject an access control entry is granted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AclSubject {
+    /// A single user.
+    #[cfg_attr(feature = "serde", serde(rename = "user"))]
+    User(String),
+    /// Every user holding the permissions, either directly or through roles.
+    #[cfg_attr(feature = "serde", serde(rename = "perm"))]
+    Perm(Perm),
+}
+
+impl AclSubject {
+    fn kind(&self) -> &'static str {
+        match self {
+            AclSubject::User(_) => "user",
+            AclSubject::Perm(_) => "perm",
+        }
+    }
+
+    fn subject(&self) -> String {
+        match self {
+            AclSubject::User(user) => user.clone(),
+            AclSubject::Perm(perm) => perm.to_string(),
+        }
+    }
+
+    /// An invalid permission of the subject, which is empty if the subject holds no permission at all,
+    /// since every user would satisfy it.
+    pub(crate) fn find_invalid(&self) -> Option<&str> {
+        match self {
+            AclSubject::User(_) => None,
+            AclSubject::Perm(perm) if perm.is_empty() => Some(""),
+            AclSubject::Perm(perm) => perm.find_invalid(),
+        }
+    }
+
+    pub(crate) fn from_row(kind: &str, subject: String) -> Self {
+        match kind {
+            "user" => AclSubject::User(subject),
+            _ => AclSubject::Perm(subject.into()),
+        }
+    }
+}
+
+impl Display for AclSubject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.kind(), self.subject().trim_end())
+    }
+}
+
+/// An access control entry of a resource.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AclGrant {
+    /// The subject the action is granted to.
+    pub subject: AclSubject,
+    /// The action granted, where `*` grants every action.
+    pub action: String,
+}
+
+impl Basileus {
+    /// Grant `subject` the right to perform `action` on `resource`.
+    /// This does not result in an error if the grant already exists.
+    ///
+    /// Resources are identified by arbitrary application-defined strings.
+    /// Granting to an empty [`AclSubject::Perm`] fails with [`AclGrantError::InvalidPerm`],
+    /// since it would grant the action to every user.
+    pub async fn acl_grant(
+        &self,
+        resource: &str,
+        subject: &AclSubject,
+        action: &str,
+    ) -> Result<(), AclGrantError> {
+        if let AclSubject::User(user) = subject
+            && !self.exist_user(user).await?
+        {
+            return Err(AclGrantError::UserNotExist(user.into()));
+        }
+        if let Some(invalid) = subject.find_invalid() {
+            return Err(AclGrantError::InvalidPerm(invalid.into()));
+        }
+        let query = query(self.sql(
+            "INSERT OR IGNORE INTO acl (resource, kind, subject, action) VALUES (?, ?, ?, ?);",
+        ))
+        .bind(resource)
+        .bind(subject.kind())
+        .bind(subject.subject())
+        .bind(action);
+        query.execute(&self.writer).await?;
+        info!("granted {action} on {resource} to {subject}");
+        Ok(())
+    }
+
+    /// Revoke a previous grant of `action` on `resource` to `subject`.
+    /// This does not result in an error if the grant does not currently exist.
+    pub async fn acl_revoke(
+        &self,
+        resource: &str,
+        subject: &AclSubject,
+        action: &str,
+    ) -> Result<(), sqlx::error::Error> {
+        let query =
+            query(self.sql(
+                "DELETE FROM acl WHERE resource = ? AND kind = ? AND subject = ? AND action = ?",
+            ))
+            .bind(resource)
+            .bind(subject.kind())
+            .bind(subject.subject())
+            .bind(action);
+        query.execute(&self.writer).await?;
+        info!("revoked {action} on {resource} from {subject}");
+        Ok(())
+    }
+
+    /// Revoke every grant on `resource`, e.g. when the resource is deleted.
+    pub async fn acl_clear(&self, resource: &str) -> Result<(), sqlx::error::Error> {
+        let query = query(self.sql("DELETE FROM acl WHERE resource = ?")).bind(resource);
+        query.execute(&self.writer).await?;
+        info!("cleared grants on {resource}");
+        Ok(())
+    }
+
+    /// List every grant on `resource`.
+    pub async fn acl_list_grants(
+        &self,
+        resource: &str,
+    ) -> Result<Vec<AclGrant>, sqlx::error::Error> {
+        let query = query_as(
+            self.sql("SELECT kind, subject, action FROM acl WHERE resource = ? ORDER BY kind, subject, action"),
+        )
+        .bind(resource);
+        let res: Vec<(String, String, String)> = query.fetch_all(&self.db).await?;
+        let res = res
+            .into_iter()
+            .map(|(kind, subject, action)| AclGrant {
+                subject: AclSubject::from_row(&kind, subject),
+                action,
+            })
+            .collect();
+        Ok(res)
+    }
+
+    /// Check if the user may perform `action` on `resource`,
+    /// either through a grant to the user, or a grant to permissions the user holds.
+    pub async fn acl_check(
+        &self,
+        resource: &str,
+        user: &str,
+        action: &str,
+    ) -> Result<bool, AclCheckError> {
+        let perm = match self.get_effective_perm(user).await {
+            Err(GetPermError::UserNotExist(user)) => {
+                return Err(AclCheckError::UserNotExist(user));
+            }
+            res => res?,
+        };
+        let query = query_as(self.sql(
+            "SELECT kind, subject FROM acl WHERE resource = ? AND (action = ? OR action = '*')",
+        ))
+        .bind(resource)
+        .bind(action);
+        let res: Vec<(String, String)> = query.fetch_all(&self.db).await?;
+        let granted = res
+            .into_iter()
+            .map(|(kind, subject)| AclSubject::from_row(&kind, subject))
+            .any(|subject| match subject {
+                AclSubject::User(x) => x == user,
+                AclSubject::Perm(req) => !req.is_empty() && perm.satisfies(&req),
+            });
+        Ok(granted)
+    }
+}
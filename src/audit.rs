@@ -0,0 +1,508 @@
+use std::{fmt::Display, mem, net::IpAddr, sync::Mutex, time::SystemTime};
+
+use sqlx::{Connection, query, query_as};
+use tracing::{debug, warn};
+
+use crate::{
+    Basileus,
+    err::ErrorCode,
+    event::{AuthEvent, Subscribers, SubscriptionId},
+    from_unix,
+    perm::PermEvent,
+    to_unix,
+};
+
+/// Initialize the append-only audit log, see [`Basileus::query_audit`].
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time INTEGER NOT NULL,
+    op TEXT NOT NULL,
+    actor TEXT,
+    target TEXT,
+    ip TEXT,
+    code TEXT,
+    detail TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_time ON audit_log (time);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log (actor);
+CREATE INDEX IF NOT EXISTS idx_audit_log_target ON audit_log (target);
+CREATE TRIGGER IF NOT EXISTS before_audit_log_update
+BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+"#;
+
+/// Audit configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct AuditConfig {
+    /// Whether to write audit events to the database, so that they can be [queried](Basileus::query_audit).
+    ///
+    /// Subscribers receive events regardless.
+    #[cfg_attr(feature = "serde", serde(rename = "persist"))]
+    pub persist: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { persist: true }
+    }
+}
+
+/// A security-relevant event recorded for auditing.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct AuditEvent {
+    /// Time the event occurred.
+    pub time: SystemTime,
+    /// The user who performed the operation, if known.
+    pub actor: Option<String>,
+    /// Name of the operation, e.g. `verify_pass`, or the [kind](AuthEvent::kind) of an [`AuthEvent`].
+    pub op: String,
+    /// The user affected by the operation, if any.
+    pub target: Option<String>,
+    /// Source address of the request, if known.
+    pub ip: Option<IpAddr>,
+    /// [Code](ErrorCode::code) of the error if the operation failed.
+    pub code: Option<String>,
+    /// Description of the error if the operation failed.
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    /// Create an event of a successful operation.
+    pub fn new(actor: Option<&str>, op: &str) -> Self {
+        Self {
+            time: SystemTime::now(),
+            actor: actor.map(Into::into),
+            op: op.into(),
+            target: None,
+            ip: None,
+            code: None,
+            detail: None,
+        }
+    }
+
+    /// Create an event of a failed operation.
+    pub fn failure<E: ErrorCode + Display + ?Sized>(actor: Option<&str>, op: &str, e: &E) -> Self {
+        Self {
+            code: Some(e.code().into()),
+            detail: Some(e.to_string()),
+            ..Self::new(actor, op)
+        }
+    }
+
+    /// Set the user affected by the operation.
+    pub fn target(mut self, user: &str) -> Self {
+        self.target = Some(user.into());
+        self
+    }
+
+    /// Set the source address of the request.
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+
+    /// Whether the operation succeeded.
+    pub fn success(&self) -> bool {
+        self.code.is_none()
+    }
+}
+
+impl From<&AuthEvent> for AuditEvent {
+    fn from(event: &AuthEvent) -> Self {
+        let res = Self::new(None, event.kind());
+        match event {
+            AuthEvent::UserCreated { user }
+            | AuthEvent::UserDeleted { user }
+            | AuthEvent::PassChanged { user }
+            | AuthEvent::LoginSucceeded { user }
+            | AuthEvent::TokenIssued { user }
+            | AuthEvent::TokenRevoked { user }
+            | AuthEvent::SessionsRevoked { user }
+            | AuthEvent::AccountUnlocked { user } => res.target(user),
+            AuthEvent::AccountLocked {
+                user,
+                until,
+                failures,
+            } => Self {
+                detail: Some(match until {
+                    Some(until) => format!(
+                        "locked after {failures} failed attempts until {}",
+                        to_unix(*until)
+                    ),
+                    None => format!("locked after {failures} failed attempts until unlocked"),
+                }),
+                ..res.target(user)
+            },
+            AuthEvent::LoginFailed { user } => Self {
+                code: Some("unauthorized".into()),
+                ..res.target(user)
+            },
+            AuthEvent::ImpersonationStarted {
+                id,
+                admin,
+                user,
+                reason,
+            } => Self {
+                actor: Some(admin.clone()),
+                detail: Some(format!("impersonation {id}: {reason}")),
+                ..res.target(user)
+            },
+            AuthEvent::CanaryTripped {
+                label,
+                cert,
+                ip,
+                op,
+            } => {
+                let mut detail = format!("canary token '{label}'");
+                if let Some(op) = op {
+                    detail += &format!(" for {op}");
+                }
+                if let Some(cert) = cert {
+                    detail += &format!(" with client certificate {cert}");
+                }
+                Self {
+                    code: Some("unauthorized".into()),
+                    detail: Some(detail),
+                    ip: *ip,
+                    ..res
+                }
+            }
+            AuthEvent::ImpersonationEnded { id, admin, user } => Self {
+                actor: Some(admin.clone()),
+                detail: Some(format!("impersonation {id}")),
+                ..res.target(user)
+            },
+            AuthEvent::PermChanged { change } => match change {
+                PermEvent::User {
+                    user,
+                    actor,
+                    added,
+                    removed,
+                } => Self {
+                    actor: actor.clone(),
+                    detail: Some(format!(
+                        "added [{}], removed [{}]",
+                        added.to_string().trim_end(),
+                        removed.to_string().trim_end()
+                    )),
+                    ..res.target(user)
+                },
+                PermEvent::UserRole {
+                    user,
+                    role,
+                    assigned,
+                } => Self {
+                    detail: Some(match assigned {
+                        true => format!("assigned role '{role}'"),
+                        false => format!("unassigned role '{role}'"),
+                    }),
+                    ..res.target(user)
+                },
+                PermEvent::Role { role } => Self {
+                    detail: Some(format!("changed role '{role}'")),
+                    ..res
+                },
+                PermEvent::RoleDeleted { role } => Self {
+                    detail: Some(format!("deleted role '{role}'")),
+                    ..res
+                },
+                PermEvent::GroupDeleted { group } => Self {
+                    detail: Some(format!("deleted group '{group}'")),
+                    ..res
+                },
+            },
+        }
+    }
+}
+
+/// An [audit event](AuditEvent) written to the database, see [`Basileus::query_audit`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct AuditRecord {
+    /// Identifier of the record, increasing in the order of writing.
+    pub id: i64,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub event: AuditEvent,
+}
+
+/// Filters of [`Basileus::query_audit`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct AuditQuery {
+    /// Only return events of this operation, e.g. `login_failed`.
+    pub op: Option<String>,
+    /// Only return events performed by this user.
+    pub actor: Option<String>,
+    /// Only return events affecting this user.
+    pub target: Option<String>,
+    /// Only return events which occurred at or after this time.
+    pub since: Option<SystemTime>,
+    /// Only return events which occurred before this time.
+    pub until: Option<SystemTime>,
+    /// Only return records older than the one with this [identifier](AuditRecord::id),
+    /// i.e. [`AuditPage::next`] of the previous page.
+    pub before: Option<i64>,
+    /// Maximum number of records per page.
+    pub limit: u32,
+}
+
+impl Default for AuditQuery {
+    fn default() -> Self {
+        Self {
+            op: None,
+            actor: None,
+            target: None,
+            since: None,
+            until: None,
+            before: None,
+            limit: 100,
+        }
+    }
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only return events of operation `op`.
+    pub fn op(mut self, op: impl Into<String>) -> Self {
+        self.op = Some(op.into());
+        self
+    }
+
+    /// Only return events performed by `user`.
+    pub fn actor(mut self, user: impl Into<String>) -> Self {
+        self.actor = Some(user.into());
+        self
+    }
+
+    /// Only return events affecting `user`.
+    pub fn target(mut self, user: impl Into<String>) -> Self {
+        self.target = Some(user.into());
+        self
+    }
+
+    /// Only return events which occurred from `since` until before `until`.
+    pub fn between(mut self, since: SystemTime, until: SystemTime) -> Self {
+        self.since = Some(since);
+        self.until = Some(until);
+        self
+    }
+
+    /// Continue after the page whose [`AuditPage::next`] is `id`.
+    pub fn before(mut self, id: i64) -> Self {
+        self.before = Some(id);
+        self
+    }
+
+    /// Return at most `limit` records.
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+/// A page of [audit records](AuditRecord), newest first.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct AuditPage {
+    pub records: Vec<AuditRecord>,
+    /// Cursor to pass as [`AuditQuery::before`] for the next page, unless this is the last one.
+    pub next: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct AuditModule {
+    /// Subscribers to audit events.
+    events: Subscribers<AuditEvent>,
+    /// Events not yet written to the database.
+    pending: Mutex<Vec<AuditEvent>>,
+}
+
+impl AuditModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Record an audit event, passing it to every [subscriber](Self::subscribe_audit).
+    ///
+    /// Unless [`AuditConfig::persist`] is unset, the event is also written to the database
+    /// once the operation in progress completes.
+    pub fn audit(&self, event: &AuditEvent) {
+        self.audit.events.emit(event);
+        if self.config.read().unwrap().audit.persist {
+            self.audit.pending.lock().unwrap().push(event.clone());
+        }
+    }
+
+    /// Write every recorded audit event to the database.
+    ///
+    /// Events are written after each operation anyway, so this is only needed
+    /// before reading the database from elsewhere, e.g. when shutting down.
+    pub async fn flush_audit(&self) -> Result<(), sqlx::error::Error> {
+        let mut conn = self.writer.acquire().await?;
+        self.write_audit(&mut conn).await
+    }
+
+    /// Write recorded audit events to the database, unless this would have to wait for the writer,
+    /// since the caller might be holding it in a transaction.
+    pub(crate) async fn try_flush_audit(&self) {
+        if self.audit.pending.lock().unwrap().is_empty() {
+            return;
+        }
+        // a connection in use might belong to a transaction of the caller, e.g. in non-WAL mode
+        if self.writer.num_idle() as u32 != self.writer.size() {
+            return;
+        }
+        let Some(mut conn) = self.writer.try_acquire() else {
+            return;
+        };
+        if let Err(e) = self.write_audit(&mut conn).await {
+            warn!("failed to write audit events: {e}");
+        }
+    }
+
+    async fn write_audit(
+        &self,
+        conn: &mut sqlx::SqliteConnection,
+    ) -> Result<(), sqlx::error::Error> {
+        let events = mem::take(&mut *self.audit.pending.lock().unwrap());
+        if events.is_empty() {
+            return Ok(());
+        }
+        let res = async {
+            let mut tx = conn.begin_with("BEGIN IMMEDIATE").await?;
+            for event in &events {
+                let q = query(self.sql(
+                    "INSERT INTO audit_log (time, op, actor, target, ip, code, detail)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)",
+                ))
+                .bind(to_unix(event.time))
+                .bind(&event.op)
+                .bind(&event.actor)
+                .bind(&event.target)
+                .bind(event.ip.map(|x| x.to_string()))
+                .bind(&event.code)
+                .bind(&event.detail);
+                q.execute(&mut *tx).await?;
+            }
+            tx.commit().await
+        }
+        .await;
+        if let Err(e) = res {
+            // keep the events for the next attempt, preserving their order
+            let mut pending = self.audit.pending.lock().unwrap();
+            let newer = mem::replace(&mut *pending, events);
+            pending.extend(newer);
+            return Err(e);
+        }
+        debug!("wrote {} audit events", events.len());
+        Ok(())
+    }
+
+    /// Read [audit records](AuditRecord) matching `filter`, newest first.
+    pub async fn query_audit(&self, filter: &AuditQuery) -> Result<AuditPage, sqlx::error::Error> {
+        self.flush_audit().await?;
+        let q = query_as(self.sql(
+            "SELECT id, time, op, actor, target, ip, code, detail FROM audit_log
+            WHERE (?1 IS NULL OR op = ?1) AND (?2 IS NULL OR actor = ?2) AND (?3 IS NULL OR target = ?3)
+            AND (?4 IS NULL OR time >= ?4) AND (?5 IS NULL OR time < ?5) AND (?6 IS NULL OR id < ?6)
+            ORDER BY id DESC LIMIT ?7",
+        ))
+        .bind(&filter.op)
+        .bind(&filter.actor)
+        .bind(&filter.target)
+        .bind(filter.since.map(to_unix))
+        .bind(filter.until.map(to_unix))
+        .bind(filter.before)
+        .bind(filter.limit);
+        type Row = (
+            i64,
+            i64,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        );
+        let res: Vec<Row> = q.fetch_all(&self.db).await?;
+        let records: Vec<AuditRecord> = res
+            .into_iter()
+            .map(
+                |(id, time, op, actor, target, ip, code, detail)| AuditRecord {
+                    id,
+                    event: AuditEvent {
+                        time: from_unix(time),
+                        actor,
+                        op,
+                        target,
+                        ip: ip.and_then(|x| x.parse().ok()),
+                        code,
+                        detail,
+                    },
+                },
+            )
+            .collect();
+        let next = match records.len() == filter.limit as usize {
+            true => records.last().map(|x| x.id),
+            false => None,
+        };
+        Ok(AuditPage { records, next })
+    }
+
+    /// Register a callback invoked on every recorded audit event.
+    ///
+    /// Callbacks are invoked synchronously, so they should return quickly, e.g. by forwarding the event into a channel.
+    pub fn subscribe_audit(
+        &self,
+        f: impl Fn(&AuditEvent) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        self.audit.events.subscribe(f)
+    }
+
+    /// Remove a callback registered by [`Self::subscribe_audit`].
+    pub fn unsubscribe_audit(&self, id: SubscriptionId) -> bool {
+        self.audit.events.unsubscribe(id)
+    }
+}
+
+/// Extension of results for recording failures.
+pub trait ResultExt {
+    /// Log the error, if any, with structured fields and record it as an [audit event](Basileus::audit)
+    /// of operation `op` performed by `actor`.
+    ///
+    /// The result is returned unchanged, e.g.
+    /// `basileus.verify_pass(user, pass).await.audited(&basileus, Some(user), "verify_pass")?`.
+    fn audited(self, basileus: &Basileus, actor: Option<&str>, op: &str) -> Self;
+}
+
+impl<T, E: ErrorCode + Display> ResultExt for Result<T, E> {
+    fn audited(self, basileus: &Basileus, actor: Option<&str>, op: &str) -> Self {
+        if let Err(e) = &self {
+            warn!(
+                op,
+                actor = actor.map(|x| basileus.log_user(x)).as_deref(),
+                code = e.code(),
+                transient = e.is_transient(),
+                "{e}"
+            );
+            basileus.audit(&AuditEvent::failure(actor, op, e));
+        }
+        self
+    }
+}
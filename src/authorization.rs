@@ -0,0 +1,125 @@
+use std::{fmt::Debug, str::FromStr};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use crate::{
+    Basileus,
+    err::{AuthError, CredentialsError},
+};
+
+/// Credentials carried by an `Authorization` header, see [`parse_authorization`].
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Credentials {
+    /// A token of the `Bearer` scheme, as defined in [RFC 6750](https://datatracker.ietf.org/doc/html/rfc6750#section-2.1).
+    Bearer(String),
+    /// A username and password of the `Basic` scheme, as defined in [RFC 7617](https://datatracker.ietf.org/doc/html/rfc7617#section-2).
+    Basic { user: String, pass: String },
+    /// A key of the `ApiKey` scheme, or of an `X-API-Key` header, see [`parse_api_key`].
+    ApiKey(String),
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never print secrets
+        match self {
+            Self::Bearer(_) => write!(f, "Bearer(**)"),
+            Self::Basic { user, .. } => write!(f, "Basic {{ user: {user:?}, pass: ** }}"),
+            Self::ApiKey(_) => write!(f, "ApiKey(**)"),
+        }
+    }
+}
+
+impl Credentials {
+    /// Get the token of the `Bearer` scheme.
+    pub fn into_bearer(self) -> Result<String, CredentialsError> {
+        match self {
+            Self::Bearer(token) => Ok(token),
+            Self::Basic { .. } => Err(CredentialsError::UnsupportedScheme("Basic".into())),
+            Self::ApiKey(_) => Err(CredentialsError::UnsupportedScheme("ApiKey".into())),
+        }
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = CredentialsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_authorization(s)
+    }
+}
+
+/// Whether `s` is a `token68` as defined in [RFC 9110](https://datatracker.ietf.org/doc/html/rfc9110#section-11.2).
+fn is_token68(s: &str) -> bool {
+    let body = s.trim_end_matches('=');
+    !body.is_empty()
+        && body
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~+/".contains(c))
+}
+
+/// Parse the value of an `Authorization` header.
+///
+/// Surrounding whitespace is ignored and the scheme is matched case-insensitively.
+pub fn parse_authorization(value: &str) -> Result<Credentials, CredentialsError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(CredentialsError::Empty);
+    }
+    let (scheme, param) = value.split_once(' ').unwrap_or((value, ""));
+    let param = param.trim();
+    if param.is_empty() {
+        return Err(CredentialsError::MissingValue);
+    }
+    if scheme.eq_ignore_ascii_case("bearer") {
+        if !is_token68(param) {
+            return Err(CredentialsError::InvalidToken);
+        }
+        return Ok(Credentials::Bearer(param.into()));
+    }
+    if scheme.eq_ignore_ascii_case("basic") {
+        let buf = BASE64_STANDARD
+            .decode(param)
+            .map_err(|_| CredentialsError::InvalidBase64)?;
+        let buf = String::from_utf8(buf).map_err(|_| CredentialsError::InvalidUtf8)?;
+        let (user, pass) = buf.split_once(':').ok_or(CredentialsError::MissingColon)?;
+        return Ok(Credentials::Basic {
+            user: user.into(),
+            pass: pass.into(),
+        });
+    }
+    if scheme.eq_ignore_ascii_case("apikey") {
+        return parse_api_key(param);
+    }
+    Err(CredentialsError::UnsupportedScheme(scheme.into()))
+}
+
+/// Parse the value of an API key header, e.g. `X-API-Key`.
+pub fn parse_api_key(value: &str) -> Result<Credentials, CredentialsError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(CredentialsError::Empty);
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(CredentialsError::InvalidToken);
+    }
+    Ok(Credentials::ApiKey(value.into()))
+}
+
+/// Get the bearer token from the value of an `Authorization` header, if present, as web adapters do.
+pub fn bearer(authorization: Option<&str>) -> Result<String, AuthError> {
+    let value = authorization.ok_or(AuthError::MissingToken)?;
+    Ok(parse_authorization(value)?.into_bearer()?)
+}
+
+impl Basileus {
+    /// Parse the value of an `Authorization` header as [`parse_authorization`] does,
+    /// rejecting API keys with [`CredentialsError::FeatureDisabled`] while they are [disabled](crate::config::ModuleConfig::api_key).
+    pub fn parse_credentials(&self, value: &str) -> Result<Credentials, CredentialsError> {
+        let res = parse_authorization(value)?;
+        if matches!(res, Credentials::ApiKey(_)) && !self.config.read().unwrap().modules.api_key {
+            return Err(CredentialsError::FeatureDisabled);
+        }
+        Ok(res)
+    }
+}
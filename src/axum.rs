@@ -0,0 +1,705 @@
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    Form, Json, Router,
+    extract::{Extension, FromRef, FromRequestParts, Path, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    Basileus,
+    audit::AuditEvent,
+    blocklist::IpBlock,
+    challenge::Challenge,
+    err::{AuthError, ErrorBody, ErrorCode, HttpStatus, LoginError, OAuthErrorResponse},
+    impersonate::Impersonation,
+    lockout::LockoutStatus,
+    logging::Secret,
+    login::LoginContext,
+    mfa::MfaChallenge,
+    perm::Perm,
+    token::{AuthUser, ClientCert, Presentation},
+    tower::{bearer_token, presentation},
+};
+
+/// Respond with status `status` and [`ErrorBody`] of `e`.
+fn error_response<E: HttpStatus + std::fmt::Display>(e: &E) -> Response {
+    let status = StatusCode::from_u16(e.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut res = (status, Json(ErrorBody::new(e))).into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        res.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    res
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        error_response(&self)
+    }
+}
+
+/// Source address of a request, e.g. for [`login`], see [`crate::token::ClientIp`].
+pub use crate::token::ClientIp;
+
+/// Body of an error response to a [`login`] request failing a challenge.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ChallengeBody {
+    #[serde(flatten)]
+    pub error: ErrorBody,
+    /// Challenge to solve before retrying, see [`LoginRequest::challenge`].
+    pub challenge: Challenge,
+}
+
+/// Body of an error response to a [`login`] request lacking a second factor.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MfaRequiredBody {
+    #[serde(flatten)]
+    pub error: ErrorBody,
+    /// Continuation to retry with, see [`LoginRequest::mfa_challenge`].
+    pub mfa: MfaChallenge,
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> Response {
+        if let Self::RateLimited(wait) = &self {
+            let mut res = error_response(&self);
+            res.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(wait.as_secs().max(1)),
+            );
+            return res;
+        }
+        if let Self::MfaRequired(mfa) = &self {
+            let body = MfaRequiredBody {
+                error: ErrorBody::new(&self),
+                mfa: mfa.clone(),
+            };
+            return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+        }
+        let Self::Challenge(e) = &self else {
+            return error_response(&self);
+        };
+        let body = ChallengeBody {
+            error: ErrorBody::new(&self),
+            challenge: e.challenge().clone(),
+        };
+        (StatusCode::UNAUTHORIZED, Json(body)).into_response()
+    }
+}
+
+impl IntoResponse for OAuthErrorResponse {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status()).unwrap_or(StatusCode::BAD_REQUEST);
+        (status, Json(self)).into_response()
+    }
+}
+
+/// Extracts the user authenticated by the bearer token of the request.
+///
+/// The state must provide an `Arc<Basileus>`, see [`FromRef`].
+/// If the request already passed [`RequirePerm`] or [`AuthLayer`](crate::tower::AuthLayer), the user authenticated there is reused.
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser
+where
+    Arc<Basileus>: FromRef<S>,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(auth) = parts.extensions.get::<AuthUser>() {
+            return Ok(auth.clone());
+        }
+        let token = bearer_token(&parts.headers)?;
+        let from = presentation(&parts.extensions, parts.uri.path());
+        (Arc::<Basileus>::from_ref(state))
+            .authenticate_from(&token, &from)
+            .await
+    }
+}
+
+/// Layer rejecting requests whose bearer token does not authorize the required permissions.
+///
+/// The [`AuthUser`] is inserted into the request extensions for handlers to extract, e.g.
+/// `Router::new().route("/admin", get(admin)).route_layer(RequirePerm::new(basileus, "admin"))`.
+#[derive(Clone)]
+pub struct RequirePerm {
+    basileus: Arc<Basileus>,
+    perm: Perm,
+    /// Whether [impersonated](Basileus::impersonate) sessions are rejected.
+    reject_impersonation: bool,
+}
+
+impl RequirePerm {
+    pub fn new(basileus: Arc<Basileus>, perm: impl Into<Perm>) -> Self {
+        Self {
+            basileus,
+            perm: perm.into(),
+            reject_impersonation: false,
+        }
+    }
+
+    /// Reject [impersonated](Basileus::impersonate) sessions with [`AuthError::Forbidden`],
+    /// e.g. for routes whose actions must be attributed to the admin acting rather than the impersonated user.
+    pub fn reject_impersonation(mut self) -> Self {
+        self.reject_impersonation = true;
+        self
+    }
+
+    /// Authorize a request by its headers, presented as in `from`.
+    async fn check(
+        &self,
+        headers: &HeaderMap,
+        from: &Presentation<'_>,
+    ) -> Result<AuthUser, AuthError> {
+        let token = bearer_token(headers)?;
+        let auth = (self.basileus)
+            .authorize_from(&token, from, &self.perm)
+            .await?;
+        if self.reject_impersonation && auth.impersonator.is_some() {
+            return Err(AuthError::Forbidden(auth.user));
+        }
+        Ok(auth)
+    }
+}
+
+impl<S> Layer<S> for RequirePerm {
+    type Service = RequirePermService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequirePermService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service produced by [`RequirePerm`].
+#[derive(Clone)]
+pub struct RequirePermService<S> {
+    inner: S,
+    layer: RequirePerm,
+}
+
+impl<S> Service<Request> for RequirePermService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        // the ready service must be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let from = presentation(req.extensions(), req.uri().path());
+            match layer.check(req.headers(), &from).await {
+                Ok(auth) => {
+                    req.extensions_mut().insert(auth);
+                    inner.call(req).await
+                }
+                Err(e) => Ok(e.into_response()),
+            }
+        })
+    }
+}
+
+/// Body of a [`login`] request.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct LoginRequest {
+    pub user: String,
+    /// Password of the user, which may be left empty when continuing with [`Self::mfa_challenge`].
+    #[serde(default)]
+    pub pass: Secret<String>,
+    /// Response to the challenge of a previous [`ChallengeBody`], if any.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub challenge: Option<Secret<String>>,
+    /// One-time password of a second factor, required after a response with code `mfa_required`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub otp: Option<Secret<String>>,
+    /// Response to a WebAuthn challenge, as alternative to [`Self::otp`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub webauthn: Option<Secret<String>>,
+    /// Token of a trusted device, skipping the second factor, see [`LoginResponse::device`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub device: Option<Secret<String>>,
+    /// Whether to trust the device once a second factor was presented.
+    #[serde(default)]
+    pub remember_device: bool,
+    /// Identifier of the challenge of a previous [`MfaRequiredBody`], if any.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mfa_challenge: Option<String>,
+    /// Whether to wait for the user to approve the login on their device instead of presenting a second factor.
+    #[serde(default)]
+    pub push: bool,
+}
+
+/// Body of a successful [`login`] response.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct LoginResponse {
+    pub token: Secret<String>,
+    /// Token of the device, issued if [`LoginRequest::remember_device`] was set and a second factor was presented,
+    /// see [`Basileus::trust_device`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub device: Option<Secret<String>>,
+}
+
+/// Handler verifying a username and password and issuing a token, see [`Basileus::login`].
+///
+/// The source address is known if the request carries a [`ClientIp`] extension.
+/// If it carries a [`ClientCert`] extension, the token is [bound](Basileus::issue_bound_token) to the certificate.
+/// Trusted devices are named by their `User-Agent` header.
+pub async fn login(
+    State(basileus): State<Arc<Basileus>>,
+    ip: Option<Extension<ClientIp>>,
+    cert: Option<Extension<ClientCert>>,
+    headers: HeaderMap,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, Response> {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default();
+    let cert = cert.map(|Extension(ClientCert(x))| x);
+    let mut ctx = LoginContext::new();
+    if let Some(Extension(ClientIp(ip))) = ip {
+        ctx = ctx.ip(ip);
+    }
+    if let Some(cert) = &cert {
+        ctx = ctx.cert(cert);
+    }
+    if !user_agent.is_empty() {
+        ctx = ctx.user_agent(user_agent);
+    }
+    if let Some(response) = req.challenge {
+        ctx = ctx.challenge(response);
+    }
+    if let Some(otp) = req.otp {
+        ctx = ctx.otp(otp);
+    }
+    if let Some(response) = req.webauthn {
+        ctx = ctx.webauthn(response);
+    }
+    if let Some(device) = req.device {
+        ctx = ctx.device(device);
+    }
+    if let Some(id) = req.mfa_challenge {
+        ctx = ctx.mfa_challenge(id);
+    }
+    if req.push {
+        ctx = ctx.push();
+    }
+    let token = (basileus.login(&req.user, req.pass.expose(), &ctx).await)
+        .map_err(IntoResponse::into_response)?;
+    let mut device = None;
+    if req.remember_device {
+        device = match (basileus)
+            .trust_device_bound(&token, cert.as_deref(), user_agent)
+            .await
+        {
+            Ok(device) => device,
+            // no second factor was presented
+            Err(AuthError::ReauthRequired) => None,
+            Err(e) => return Err(e.into_response()),
+        };
+    }
+    Ok(Json(LoginResponse {
+        token: token.into(),
+        device: device.map(Into::into),
+    }))
+}
+
+/// Body of a PKCE [access token request](https://datatracker.ietf.org/doc/html/rfc7636#section-4.5).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Secret<String>,
+    pub code_verifier: Secret<String>,
+}
+
+/// Body of a successful [access token response](https://datatracker.ietf.org/doc/html/rfc6749#section-5.1).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct TokenResponse {
+    pub access_token: Secret<String>,
+    pub token_type: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_in: Option<u64>,
+}
+
+/// Handler exchanging an authorization code for a token, see [`Basileus::pkce_token_req`].
+///
+/// If the request carries a [`ClientIp`] extension, requests are limited per address,
+/// and if it carries a [`ClientCert`] extension, the token is bound to the certificate, see [`Basileus::pkce_token_req_from`].
+pub async fn token(
+    State(basileus): State<Arc<Basileus>>,
+    ip: Option<Extension<ClientIp>>,
+    cert: Option<Extension<ClientCert>>,
+    Form(req): Form<TokenRequest>,
+) -> Result<Json<TokenResponse>, OAuthErrorResponse> {
+    if req.grant_type != "authorization_code" {
+        return Err(OAuthErrorResponse::new(
+            "unsupported_grant_type",
+            "only authorization_code is supported",
+        ));
+    }
+    let ip = ip.map(|Extension(ClientIp(ip))| ip);
+    let cert = cert.map(|Extension(ClientCert(cert))| cert);
+    let access_token = basileus.pkce_token_req_from(
+        req.code.expose(),
+        req.code_verifier.expose(),
+        ip,
+        cert.as_deref(),
+    )?;
+    Ok(Json(TokenResponse {
+        access_token: access_token.into(),
+        token_type: "Bearer".into(),
+        expires_in: basileus.config.read().unwrap().token.ttl,
+    }))
+}
+
+/// Router serving [`login`] at `POST /login` and [`token`] at `POST /token`,
+/// e.g. to be nested under `/auth`.
+pub fn router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Arc<Basileus>: FromRef<S>,
+{
+    Router::new()
+        .route("/login", post(login))
+        .route("/token", post(token))
+}
+
+/// Error response of [`admin_router`] handlers.
+pub struct AdminError(Response);
+
+impl<E: ErrorCode + std::fmt::Display> From<E> for AdminError {
+    fn from(e: E) -> Self {
+        Self(error_response(&e))
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        self.0
+    }
+}
+
+/// Body of a user creation request of [`admin_router`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CreateUserRequest {
+    pub user: String,
+    /// Initial password, if any.
+    #[serde(default)]
+    pub pass: Option<Secret<String>>,
+}
+
+/// Body of a password change request of [`admin_router`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct SetPassRequest {
+    pub pass: Secret<String>,
+}
+
+/// Body of an IP block request of [`admin_router`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct BlockIpRequest {
+    /// Duration of the block in seconds, or [`None`] to block permanently.
+    #[serde(default)]
+    pub duration: Option<u64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Body of an impersonation request of [`admin_router`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ImpersonateRequest {
+    pub reason: String,
+}
+
+/// Response to an impersonation request of [`admin_router`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ImpersonateResponse {
+    /// Token acting as the impersonated user.
+    pub token: Secret<String>,
+}
+
+async fn list_users(
+    State(basileus): State<Arc<Basileus>>,
+) -> Result<Json<Vec<String>>, AdminError> {
+    Ok(Json(basileus.list_users().await?))
+}
+
+/// Record `action` of `admin` on `user` as an [audit event](Basileus::audit),
+/// attributed to the true actor if `admin` is [impersonated](Basileus::impersonate).
+fn audit_admin(basileus: &Basileus, admin: &AuthUser, action: &str, user: &str) {
+    let op = format!("admin.{action}");
+    let event = match &admin.impersonator {
+        Some(actor) => AuditEvent {
+            detail: Some(format!("impersonating {}", admin.user)),
+            ..AuditEvent::new(Some(actor), &op)
+        },
+        None => AuditEvent::new(Some(&admin.user), &op),
+    };
+    basileus.audit(&event.target(user));
+}
+
+/// Refuse `admin` acting on `user` with [`AuthError::Forbidden`] unless `admin` [outranks](Basileus::outranks) `user`.
+async fn check_outranks(
+    basileus: &Basileus,
+    admin: &AuthUser,
+    user: &str,
+) -> Result<(), AdminError> {
+    if !basileus.outranks(&admin.user, user).await? {
+        return Err(AuthError::Forbidden(admin.user.clone()).into());
+    }
+    Ok(())
+}
+
+async fn create_user(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Json(req): Json<CreateUserRequest>,
+) -> Result<StatusCode, AdminError> {
+    basileus.create_user(&req.user).await?;
+    audit_admin(&basileus, &admin, "create_user", &req.user);
+    if let Some(pass) = &req.pass {
+        basileus.update_pass(&req.user, pass.expose()).await?;
+        audit_admin(&basileus, &admin, "set_pass", &req.user);
+    }
+    Ok(StatusCode::CREATED)
+}
+
+async fn delete_user(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    check_outranks(&basileus, &admin, &user).await?;
+    basileus.delete_user(&user).await?;
+    audit_admin(&basileus, &admin, "delete_user", &user);
+    basileus.invalidate_user_token(&user);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_pass(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user): Path<String>,
+    Json(req): Json<SetPassRequest>,
+) -> Result<StatusCode, AdminError> {
+    check_outranks(&basileus, &admin, &user).await?;
+    basileus.update_pass(&user, req.pass.expose()).await?;
+    audit_admin(&basileus, &admin, "set_pass", &user);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_perm(
+    State(basileus): State<Arc<Basileus>>,
+    Path(user): Path<String>,
+) -> Result<Json<Perm>, AdminError> {
+    Ok(Json(basileus.get_perm(&user).await?))
+}
+
+async fn set_perm(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user): Path<String>,
+    Json(perm): Json<Perm>,
+) -> Result<StatusCode, AdminError> {
+    check_outranks(&basileus, &admin, &user).await?;
+    basileus.set_perm_by(&admin.user, &user, &perm).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_effective_perm(
+    State(basileus): State<Arc<Basileus>>,
+    Path(user): Path<String>,
+) -> Result<Json<Perm>, AdminError> {
+    Ok(Json(basileus.get_effective_perm(&user).await?))
+}
+
+async fn revoke_sessions(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    check_outranks(&basileus, &admin, &user).await?;
+    basileus.invalidate_user_token(&user);
+    audit_admin(&basileus, &admin, "revoke_sessions", &user);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_blocked_ips(
+    State(basileus): State<Arc<Basileus>>,
+) -> Result<Json<Vec<IpBlock>>, AdminError> {
+    Ok(Json(basileus.blocked_ips().await?))
+}
+
+async fn block_ip(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(ip): Path<IpAddr>,
+    Json(req): Json<BlockIpRequest>,
+) -> Result<StatusCode, AdminError> {
+    let duration = req.duration.map(Duration::from_secs);
+    basileus.block_ip(ip, duration, &req.reason).await?;
+    let event = AuditEvent {
+        detail: Some(format!("blocked {ip}: {}", req.reason)),
+        ..AuditEvent::new(Some(&admin.user), "admin.block_ip")
+    };
+    basileus.audit(&event);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unblock_ip(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(ip): Path<IpAddr>,
+) -> Result<StatusCode, AdminError> {
+    if !basileus.unblock_ip(ip).await? {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    let event = AuditEvent {
+        detail: Some(format!("unblocked {ip}")),
+        ..AuditEvent::new(Some(&admin.user), "admin.unblock_ip")
+    };
+    basileus.audit(&event);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_lockout(
+    State(basileus): State<Arc<Basileus>>,
+    Path(user): Path<String>,
+) -> Result<Json<LockoutStatus>, AdminError> {
+    Ok(Json(basileus.lockout_status(&user).await?))
+}
+
+async fn unlock_user(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    check_outranks(&basileus, &admin, &user).await?;
+    if !basileus.unlock_user(&user).await? {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    audit_admin(&basileus, &admin, "unlock_user", &user);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn impersonate(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user): Path<String>,
+    Json(req): Json<ImpersonateRequest>,
+) -> Result<Json<ImpersonateResponse>, AdminError> {
+    let token = basileus
+        .impersonate(&admin.user, &user, &req.reason)
+        .await?;
+    Ok(Json(ImpersonateResponse {
+        token: token.into(),
+    }))
+}
+
+async fn list_impersonations(State(basileus): State<Arc<Basileus>>) -> Json<Vec<Impersonation>> {
+    Json(basileus.list_impersonations())
+}
+
+async fn end_impersonation(
+    State(basileus): State<Arc<Basileus>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let Some(imp) = (basileus.list_impersonations().into_iter()).find(|x| x.id == id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    if !basileus.end_impersonation(&id) {
+        return StatusCode::NOT_FOUND;
+    }
+    audit_admin(&basileus, &admin, "end_impersonation", &imp.user);
+    StatusCode::NO_CONTENT
+}
+
+async fn list_roles(
+    State(basileus): State<Arc<Basileus>>,
+) -> Result<Json<Vec<String>>, AdminError> {
+    Ok(Json(basileus.list_roles().await?))
+}
+
+/// Router exposing user, permission and session management, accessible only to users holding `perm`,
+/// e.g. to be nested under `/admin`, and never to [impersonated](Basileus::impersonate) sessions.
+/// Changes are recorded as [audit events](Basileus::audit) of operation `admin.<action>` performed by the admin.
+/// Changes to an existing user are refused with [`AuthError::Forbidden`] unless the admin [outranks](Basileus::outranks) the user,
+/// so that e.g. resetting the password of a superuser can not escalate privileges of the admin:
+///
+/// | Route | Action |
+/// | --- | --- |
+/// | `GET /users` | [list users](Basileus::list_users) |
+/// | `POST /users` | [create a user](Basileus::create_user), see [`CreateUserRequest`] |
+/// | `DELETE /users/{user}` | [delete a user](Basileus::delete_user) and invalidate their tokens |
+/// | `PUT /users/{user}/pass` | [set the password](Basileus::update_pass), see [`SetPassRequest`] |
+/// | `GET /users/{user}/perm` | [get direct permissions](Basileus::get_perm) |
+/// | `PUT /users/{user}/perm` | [set direct permissions](Basileus::set_perm_by) on behalf of the admin |
+/// | `GET /users/{user}/effective-perm` | [get effective permissions](Basileus::get_effective_perm) |
+/// | `DELETE /users/{user}/sessions` | [invalidate every token](Basileus::invalidate_user_token) of a user |
+/// | `GET /users/{user}/lockout` | [get the lockout state](Basileus::lockout_status) of a user |
+/// | `DELETE /users/{user}/lockout` | [unlock](Basileus::unlock_user) a locked account |
+/// | `POST /users/{user}/impersonate` | [impersonate](Basileus::impersonate) a user, see [`ImpersonateRequest`] |
+/// | `GET /impersonations` | [list active impersonations](Basileus::list_impersonations) |
+/// | `DELETE /impersonations/{id}` | [end an impersonation](Basileus::end_impersonation) |
+/// | `GET /roles` | [list roles](Basileus::list_roles) |
+/// | `GET /blocked-ips` | [list blocked addresses](Basileus::blocked_ips) |
+/// | `PUT /blocked-ips/{ip}` | [block an address](Basileus::block_ip), see [`BlockIpRequest`] |
+/// | `DELETE /blocked-ips/{ip}` | [unblock an address](Basileus::unblock_ip) |
+pub fn admin_router<S>(basileus: Arc<Basileus>, perm: impl Into<Perm>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Arc<Basileus>: FromRef<S>,
+{
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/{user}", delete(delete_user))
+        .route("/users/{user}/pass", put(set_pass))
+        .route("/users/{user}/perm", get(get_perm).put(set_perm))
+        .route("/users/{user}/effective-perm", get(get_effective_perm))
+        .route("/users/{user}/sessions", delete(revoke_sessions))
+        .route(
+            "/users/{user}/lockout",
+            get(get_lockout).delete(unlock_user),
+        )
+        .route("/users/{user}/impersonate", post(impersonate))
+        .route("/impersonations", get(list_impersonations))
+        .route("/impersonations/{id}", delete(end_impersonation))
+        .route("/roles", get(list_roles))
+        .route("/blocked-ips", get(list_blocked_ips))
+        .route("/blocked-ips/{ip}", put(block_ip).delete(unblock_ip))
+        .route_layer(RequirePerm::new(basileus, perm).reject_impersonation())
+}
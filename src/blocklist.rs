@@ -0,0 +1,107 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use sqlx::{query, query_as};
+use tracing::{debug, info};
+
+use crate::{Basileus, from_unix, unix_now};
+
+/// Initialize the blocklist of source addresses, see [`Basileus::block_ip`].
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS ip_block (
+    ip TEXT NOT NULL PRIMARY KEY,
+    reason TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER
+);
+"#;
+
+/// A blocked source address, see [`Basileus::block_ip`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct IpBlock {
+    pub ip: IpAddr,
+    /// Reason given when blocking the address, e.g. for other admins.
+    pub reason: String,
+    pub created_at: SystemTime,
+    /// Time the block is lifted, or [`None`] if it is permanent.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Basileus {
+    /// Reject logins from `ip` for `duration`, or permanently if [`None`],
+    /// failing them with [`LoginError::IpBlocked`](crate::err::LoginError::IpBlocked).
+    ///
+    /// Blocking an address again replaces its previous block.
+    /// Blocks are stored in the database, so they persist across restarts and apply to every instance sharing it.
+    pub async fn block_ip(
+        &self,
+        ip: IpAddr,
+        duration: Option<Duration>,
+        reason: &str,
+    ) -> Result<(), sqlx::error::Error> {
+        let now = unix_now();
+        let expires_at = duration.map(|x| now.saturating_add(x.as_secs() as i64));
+        let q = query(self.sql(
+            "INSERT OR REPLACE INTO ip_block (ip, reason, created_at, expires_at) VALUES (?, ?, ?, ?)",
+        ))
+        .bind(ip.to_string())
+        .bind(reason)
+        .bind(now)
+        .bind(expires_at);
+        q.execute(&self.writer).await?;
+        match duration {
+            Some(x) => info!("blocked {ip} for {}s: {reason}", x.as_secs()),
+            None => info!("blocked {ip} permanently: {reason}"),
+        }
+        Ok(())
+    }
+
+    /// Lift the block of `ip`, returning whether it was blocked.
+    pub async fn unblock_ip(&self, ip: IpAddr) -> Result<bool, sqlx::error::Error> {
+        let q = query(self.sql("DELETE FROM ip_block WHERE ip = ?")).bind(ip.to_string());
+        let res = q.execute(&self.writer).await?.rows_affected() > 0;
+        if res {
+            info!("unblocked {ip}");
+        }
+        Ok(res)
+    }
+
+    /// Whether logins from `ip` are [blocked](Self::block_ip).
+    pub async fn is_ip_blocked(&self, ip: IpAddr) -> Result<bool, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT EXISTS(SELECT 1 FROM ip_block WHERE ip = ? AND (expires_at IS NULL OR expires_at > ?))",
+        ))
+        .bind(ip.to_string())
+        .bind(unix_now());
+        let (res,): (bool,) = q.fetch_one(&self.db).await?;
+        if res {
+            debug!("rejected blocked {ip}");
+        }
+        Ok(res)
+    }
+
+    /// Addresses currently [blocked](Self::block_ip), most recently blocked first.
+    pub async fn blocked_ips(&self) -> Result<Vec<IpBlock>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT ip, reason, created_at, expires_at FROM ip_block
+            WHERE expires_at IS NULL OR expires_at > ? ORDER BY created_at DESC",
+        ))
+        .bind(unix_now());
+        let res: Vec<(String, String, i64, Option<i64>)> = q.fetch_all(&self.db).await?;
+        Ok(res
+            .into_iter()
+            .filter_map(|(ip, reason, created_at, expires_at)| {
+                Some(IpBlock {
+                    ip: ip.parse().ok()?,
+                    reason,
+                    created_at: from_unix(created_at),
+                    expires_at: expires_at.map(from_unix),
+                })
+            })
+            .collect())
+    }
+}
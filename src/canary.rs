@@ -0,0 +1,148 @@
+use std::{collections::HashMap, sync::RwLock, time::SystemTime};
+
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as};
+use tracing::{debug, info, warn};
+
+use crate::{Basileus, event::AuthEvent, from_unix, rand_buf, token::Presentation, unix_now};
+
+/// Initialize the table of canary tokens, see [`Basileus::issue_canary_token`].
+///
+/// Only hashes of canary tokens are stored, so that a leaked database does not reveal which decoys to avoid.
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS canary_token (
+    id INTEGER NOT NULL PRIMARY KEY,
+    token_hash BLOB NOT NULL UNIQUE,
+    label TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+"#;
+
+/// A decoy token, see [`Basileus::issue_canary_token`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CanaryToken {
+    /// Identifier to [revoke](Basileus::revoke_canary_token) the token with.
+    pub id: i64,
+    /// Label given when issuing the token, e.g. where it was planted.
+    pub label: String,
+    pub created_at: SystemTime,
+}
+
+#[derive(Default)]
+pub struct CanaryModule {
+    /// Map from hashes of canary tokens to their labels.
+    labels: RwLock<HashMap<Vec<u8>, String>>,
+}
+
+impl CanaryModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn hash_canary_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+impl Basileus {
+    /// Issue a decoy token labelled `label`, which looks like a session token but never verifies.
+    ///
+    /// Presenting it emits [`AuthEvent::CanaryTripped`], so that decoys planted in e.g. configuration files,
+    /// backups or test fixtures detect when those leak.
+    pub async fn issue_canary_token(&self, label: &str) -> Result<String, sqlx::error::Error> {
+        let config = self.config.read().unwrap().token.clone();
+        let token = config.encoding.encode(&rand_buf(config.length));
+        let hash = hash_canary_token(&token);
+        let q = query(
+            self.sql("INSERT INTO canary_token (token_hash, label, created_at) VALUES (?, ?, ?)"),
+        )
+        .bind(&hash)
+        .bind(label)
+        .bind(unix_now());
+        q.execute(&self.writer).await?;
+        self.canary
+            .labels
+            .write()
+            .unwrap()
+            .insert(hash, label.into());
+        info!("issued canary token '{label}'");
+        Ok(token)
+    }
+
+    /// Canary tokens issued and not revoked, oldest first.
+    pub async fn list_canary_tokens(&self) -> Result<Vec<CanaryToken>, sqlx::error::Error> {
+        let q = query_as(
+            self.sql("SELECT id, label, created_at FROM canary_token ORDER BY created_at, id"),
+        );
+        let res: Vec<(i64, String, i64)> = q.fetch_all(&self.db).await?;
+        Ok(res
+            .into_iter()
+            .map(|(id, label, created_at)| CanaryToken {
+                id,
+                label,
+                created_at: from_unix(created_at),
+            })
+            .collect())
+    }
+
+    /// Revoke the canary token with `id`, returning whether it existed.
+    ///
+    /// Presenting a revoked canary token is an ordinary invalid token.
+    pub async fn revoke_canary_token(&self, id: i64) -> Result<bool, sqlx::error::Error> {
+        let q =
+            query_as(self.sql("SELECT token_hash, label FROM canary_token WHERE id = ?")).bind(id);
+        let res: Option<(Vec<u8>, String)> = q.fetch_optional(&self.writer).await?;
+        let Some((hash, label)) = res else {
+            return Ok(false);
+        };
+        let q = query(self.sql("DELETE FROM canary_token WHERE id = ?")).bind(id);
+        q.execute(&self.writer).await?;
+        self.canary.labels.write().unwrap().remove(&hash);
+        info!("revoked canary token '{label}'");
+        Ok(true)
+    }
+
+    /// Raise the alarm if `token`, which is not a valid session token, is a canary token presented as in `from`.
+    pub(crate) fn check_canary_token(&self, token: &str, from: &Presentation) {
+        let label = {
+            let labels = self.canary.labels.read().unwrap();
+            if labels.is_empty() {
+                return;
+            }
+            let Some(label) = labels.get(&hash_canary_token(token)) else {
+                return;
+            };
+            label.clone()
+        };
+        let ip = from.ip.map_or("unknown address".into(), |x| x.to_string());
+        let op = from.op.unwrap_or("unknown operation");
+        match from.cert {
+            Some(cert) => warn!(
+                "canary token '{label}' presented from {ip} for {op} with client certificate {cert}"
+            ),
+            None => warn!("canary token '{label}' presented from {ip} for {op}"),
+        }
+        self.count("canary.tripped");
+        let event = AuthEvent::CanaryTripped {
+            label,
+            cert: from.cert.map(Into::into),
+            ip: from.ip,
+            op: from.op.map(Into::into),
+        };
+        self.emit_from(event, from.ip);
+    }
+
+    /// Load the canary tokens from the database.
+    pub(crate) async fn load_canary_tokens(&self) -> Result<(), sqlx::error::Error> {
+        let q = query_as(self.sql("SELECT token_hash, label FROM canary_token"));
+        let res: Vec<(Vec<u8>, String)> = q.fetch_all(&self.db).await?;
+        let cnt = res.len();
+        *self.canary.labels.write().unwrap() = res.into_iter().collect();
+        if cnt > 0 {
+            debug!("loaded {cnt} canary tokens");
+        }
+        Ok(())
+    }
+}
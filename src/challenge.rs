@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{Basileus, err::ChallengeError, rand_buf};
+
+/// Configuration of when clients have to solve a challenge, see [`Basileus::check_challenge`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ChallengeConfig {
+    /// Failed logins from an address within [`Self::window`] after which further attempts from it are challenged,
+    /// or `0` to challenge every attempt.
+    #[cfg_attr(feature = "serde", serde(rename = "after-failures"))]
+    pub after_failures: u32,
+    /// Time in seconds over which failed logins are counted.
+    #[cfg_attr(feature = "serde", serde(rename = "window"))]
+    pub window: u64,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            after_failures: 3,
+            window: 600,
+        }
+    }
+}
+
+/// A challenge presented to a client, e.g. a CAPTCHA widget or a proof-of-work puzzle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Challenge {
+    /// Kind of challenge the client has to render or solve, e.g. `turnstile` or `pow`.
+    pub kind: String,
+    /// Parameters of the challenge, e.g. the site key of a CAPTCHA.
+    pub data: String,
+}
+
+/// Future returned by [`ChallengeProvider::verify`].
+pub type VerifyFuture<'a> = Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+
+/// Provider of challenges telling humans from bots, see [`Basileus::set_challenge_provider`].
+///
+/// CAPTCHA services such as hCaptcha or Turnstile are implemented by the application on top of its HTTP client,
+/// whereas [`ProofOfWork`] is built in.
+pub trait ChallengeProvider: Send + Sync {
+    /// Create a challenge to present to a client.
+    fn issue(&self) -> Challenge;
+
+    /// Check the `response` of a client from `ip`, if known, to a challenge issued earlier.
+    fn verify<'a>(&'a self, response: &'a str, ip: Option<IpAddr>) -> VerifyFuture<'a>;
+}
+
+/// [`ChallengeProvider`] requiring clients to spend computation, which needs no third party.
+///
+/// The [data](Challenge::data) of challenges of kind `pow` is `<difficulty>:<nonce>`,
+/// and a response is `<nonce>:<counter>` for any `<counter>` such that the SHA-256 of the response
+/// starts with `<difficulty>` zero bits.
+/// Every nonce is accepted once within the time-to-live given.
+pub struct ProofOfWork {
+    difficulty: u32,
+    ttl: Duration,
+    /// Map from nonces issued and not yet solved to the time they were issued.
+    issued: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl ProofOfWork {
+    /// Create a provider of puzzles of `difficulty` bits, which take about `2^difficulty` hashes to solve,
+    /// and must be solved within `ttl`.
+    pub fn new(difficulty: u32, ttl: Duration) -> Self {
+        Self {
+            difficulty,
+            ttl,
+            issued: Default::default(),
+        }
+    }
+}
+
+/// Number of leading zero bits of `buf`.
+fn leading_zeros(buf: &[u8]) -> u32 {
+    let mut res = 0;
+    for x in buf {
+        res += x.leading_zeros();
+        if *x != 0 {
+            break;
+        }
+    }
+    res
+}
+
+impl ChallengeProvider for ProofOfWork {
+    fn issue(&self) -> Challenge {
+        let nonce = BASE64_URL_SAFE_NO_PAD.encode(rand_buf(16));
+        let now = SystemTime::now();
+        let mut issued = self.issued.lock().unwrap();
+        issued.retain(|_, x| now.duration_since(*x).is_ok_and(|d| d < self.ttl));
+        issued.insert(nonce.clone(), now);
+        Challenge {
+            kind: "pow".into(),
+            data: format!("{}:{nonce}", self.difficulty),
+        }
+    }
+
+    fn verify<'a>(&'a self, response: &'a str, _: Option<IpAddr>) -> VerifyFuture<'a> {
+        Box::pin(async move {
+            let Some((nonce, _)) = response.split_once(':') else {
+                return false;
+            };
+            let issued = self.issued.lock().unwrap().remove(nonce);
+            if !issued.is_some_and(|x| x.elapsed().is_ok_and(|d| d < self.ttl)) {
+                return false;
+            }
+            leading_zeros(&Sha256::digest(response)) >= self.difficulty
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct ChallengeModule {
+    provider: RwLock<Option<Arc<dyn ChallengeProvider>>>,
+    /// Times of recent failed logins by source address.
+    failures: Mutex<HashMap<IpAddr, Vec<SystemTime>>>,
+}
+
+impl ChallengeModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Challenge risky login and registration attempts with `provider`, replacing any previously set.
+    ///
+    /// No attempt is challenged until a provider is set.
+    pub fn set_challenge_provider(&self, provider: impl ChallengeProvider + 'static) {
+        *self.challenge.provider.write().unwrap() = Some(Arc::new(provider));
+    }
+
+    /// Stop challenging attempts.
+    pub fn clear_challenge_provider(&self) {
+        *self.challenge.provider.write().unwrap() = None;
+    }
+
+    /// Number of failed logins from `ip` within [`ChallengeConfig::window`].
+    fn recent_failures(&self, ip: IpAddr) -> usize {
+        let window = Duration::from_secs(self.config.read().unwrap().challenge.window);
+        let now = SystemTime::now();
+        let mut failures = self.challenge.failures.lock().unwrap();
+        let Some(times) = failures.get_mut(&ip) else {
+            return 0;
+        };
+        times.retain(|x| now.duration_since(*x).is_ok_and(|d| d < window));
+        let res = times.len();
+        if res == 0 {
+            failures.remove(&ip);
+        }
+        res
+    }
+
+    /// Count a failed login from `ip` towards [`ChallengeConfig::after_failures`].
+    pub fn record_login_failure(&self, ip: IpAddr) {
+        let window = Duration::from_secs(self.config.read().unwrap().challenge.window);
+        let now = SystemTime::now();
+        let mut failures = self.challenge.failures.lock().unwrap();
+        // drop addresses which stopped failing, so that the map does not grow without bound
+        failures.retain(|_, x| {
+            x.last()
+                .is_some_and(|x| now.duration_since(*x).is_ok_and(|d| d < window))
+        });
+        failures.entry(ip).or_default().push(now);
+    }
+
+    /// Whether an attempt from `ip`, or from an unknown address, has to solve a challenge,
+    /// i.e. a [provider](Self::set_challenge_provider) is set and the risk heuristics trigger.
+    pub fn challenge_required(&self, ip: Option<IpAddr>) -> bool {
+        if self.challenge.provider.read().unwrap().is_none() {
+            return false;
+        }
+        let threshold = self.config.read().unwrap().challenge.after_failures;
+        threshold == 0 || ip.is_some_and(|ip| self.recent_failures(ip) >= threshold as usize)
+    }
+
+    /// Check a login or registration attempt from `ip`, if known, carrying `response` to a challenge, if any.
+    ///
+    /// Unless a challenge is [required](Self::challenge_required), this always succeeds.
+    /// Otherwise, attempts without a response fail with [`ChallengeError::Required`] carrying a new challenge,
+    /// to be presented to the client before retrying.
+    /// [`Basileus::login`] calls this, whereas registration paths of the application should call it themselves.
+    pub async fn check_challenge(
+        &self,
+        ip: Option<IpAddr>,
+        response: Option<&str>,
+    ) -> Result<(), ChallengeError> {
+        if !self.challenge_required(ip) {
+            return Ok(());
+        }
+        let Some(provider) = self.challenge.provider.read().unwrap().clone() else {
+            return Ok(());
+        };
+        let Some(response) = response else {
+            debug!("challenging attempt from {ip:?}");
+            self.count("challenge.issued");
+            return Err(ChallengeError::Required(provider.issue()));
+        };
+        if !provider.verify(response, ip).await {
+            warn!("failed challenge from {ip:?}");
+            self.count("challenge.failed");
+            return Err(ChallengeError::Failed(provider.issue()));
+        }
+        self.count("challenge.passed");
+        Ok(())
+    }
+}
@@ -0,0 +1,835 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use tracing::info;
+
+use crate::{
+    Basileus, Config, MEMORY,
+    audit::AuditConfig,
+    challenge::ChallengeConfig,
+    contact::ContactConfig,
+    db::{SqliteConfig, Synchronous},
+    err::{ConfigError, ConfigProblem},
+    lockout::LockoutPolicy,
+    logging::LogConfig,
+    magic_link::MagicLinkConfig,
+    maintenance::MaintenanceConfig,
+    mfa::MfaConfig,
+    oob::OobConfig,
+    pass::{MIN_MEM_COST, PassConfig},
+    perm::{PermConfig, check_perm_name},
+    pkce::PkceConfig,
+    policy::{PolicyConfig, parse_network},
+    qr_login::QrLoginConfig,
+    ratelimit::RateLimitConfig,
+    token::TokenConfig,
+    user::UserConfig,
+    webauthn::WebauthnConfig,
+};
+
+/// Switches of optional subsystems, all enabled by default.
+///
+/// APIs of a disabled subsystem fail with a `FeatureDisabled` error.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ModuleConfig {
+    /// Whether to enable [PKCE](crate::pkce) authorization.
+    #[cfg_attr(feature = "serde", serde(rename = "pkce"))]
+    pub pkce: bool,
+    /// Whether to enable [second factors](crate::mfa) of any kind.
+    ///
+    /// While disabled, second factors are neither enrolled nor required,
+    /// so that users who enrolled some log in by password alone.
+    /// Their tables are not created for new databases, and are created once the module is enabled.
+    #[cfg_attr(feature = "serde", serde(rename = "mfa"))]
+    pub mfa: bool,
+    /// Whether to accept [security keys](crate::webauthn) as second factor.
+    ///
+    /// While disabled, users relying on security keys alone have to use recovery codes.
+    #[cfg_attr(feature = "serde", serde(rename = "webauthn"))]
+    pub webauthn: bool,
+    /// Whether to accept [push approval](crate::push) as second factor.
+    #[cfg_attr(feature = "serde", serde(rename = "push"))]
+    pub push: bool,
+    /// Whether to send [one-time codes](crate::oob) out of band, both as second factor and for passwordless logins.
+    #[cfg_attr(feature = "serde", serde(rename = "oob"))]
+    pub oob: bool,
+    /// Whether to enable [magic link](crate::magic_link) logins.
+    #[cfg_attr(feature = "serde", serde(rename = "magic-link"))]
+    pub magic_link: bool,
+    /// Whether to enable [cross-device](crate::qr_login) logins.
+    #[cfg_attr(feature = "serde", serde(rename = "qr-login"))]
+    pub qr_login: bool,
+    /// Whether admins may [impersonate](crate::impersonate) users.
+    #[cfg_attr(feature = "serde", serde(rename = "impersonation"))]
+    pub impersonation: bool,
+    /// Whether to keep public keys of users for authentication by them.
+    ///
+    /// While disabled, their table is not created for new databases.
+    #[cfg_attr(feature = "serde", serde(rename = "pubkey"))]
+    pub pubkey: bool,
+    /// Whether to accept [API keys](crate::authorization::Credentials::ApiKey),
+    /// see [`Basileus::parse_credentials`](crate::Basileus::parse_credentials).
+    #[cfg_attr(feature = "serde", serde(rename = "api-key"))]
+    pub api_key: bool,
+}
+
+impl Default for ModuleConfig {
+    fn default() -> Self {
+        Self {
+            pkce: true,
+            mfa: true,
+            webauthn: true,
+            push: true,
+            oob: true,
+            magic_link: true,
+            qr_login: true,
+            impersonation: true,
+            pubkey: true,
+            api_key: true,
+        }
+    }
+}
+
+/// Builder of [`Config`], see [`Config::builder`].
+///
+/// Every setting not specified keeps its default value.
+#[derive(Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl Config {
+    /// Start building a configuration from the defaults.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Preset for production, trading performance for security.
+    ///
+    /// Tokens expire after 8 hours or 30 minutes of inactivity, PKCE codes after a minute,
+    /// `plain` PKCE is disallowed, passwords are hashed with 64 MiB of memory,
+    /// missing users are [concealed](PassConfig::conceal_users), usernames are hashed in logs,
+    /// and every write is synced to disk.
+    pub fn hardened() -> Self {
+        let mut config = Self::default();
+        config.sqlite.synchronous = Synchronous::Full;
+        config.token.ttl = Some(8 * 3600);
+        config.token.idle_timeout = Some(1800);
+        config.token.max_sessions = Some(10);
+        config.pkce.allow_plain = false;
+        config.pkce.code_ttl = 60;
+        config.pass.mem_cost = 65536;
+        config.pass.time_cost = 3;
+        config.pass.legacy = vec![];
+        config.pass.conceal_users = true;
+        config.rate_limit.user_attempts = 5;
+        config.rate_limit.lockout = 1800;
+        config.maintenance.perm_history_retention = Some(365 * 24 * 3600);
+        config.log.hash_users = true;
+        config.log.token_prefix = false;
+        config
+    }
+
+    /// Preset for development and tests, trading security for speed.
+    ///
+    /// The database is [in memory](MEMORY), passwords are hashed with the least memory accepted,
+    /// rate limits are disabled and nothing is cached, so that changes are visible immediately.
+    pub fn development() -> Self {
+        let mut config = Self {
+            db: MEMORY.into(),
+            ..Default::default()
+        };
+        config.user.exist_cache_ttl = 0;
+        config.perm.cache_ttl = 0;
+        config.pass.mem_cost = MIN_MEM_COST;
+        config.pass.time_cost = 1;
+        config.rate_limit.user_attempts = 0;
+        config.rate_limit.ip_attempts = 0;
+        config
+    }
+
+    /// Load a configuration from environment variables, using the default for every variable not set.
+    ///
+    /// The following variables are recognized:
+    ///
+    /// | Variable | Setting |
+    /// | --- | --- |
+    /// | `BASILEUS_DB` | [`Config::db`] |
+    /// | `BASILEUS_TABLE_PREFIX` | [`SqliteConfig::table_prefix`] |
+    /// | `BASILEUS_WAL` | [`SqliteConfig::wal`] |
+    /// | `BASILEUS_BUSY_TIMEOUT` | [`SqliteConfig::busy_timeout`] |
+    /// | `BASILEUS_MIN_CONNECTIONS` | [`SqliteConfig::min_connections`] |
+    /// | `BASILEUS_MAX_CONNECTIONS` | [`SqliteConfig::max_connections`] |
+    /// | `BASILEUS_EXIST_CACHE_TTL` | [`UserConfig::exist_cache_ttl`] |
+    /// | `BASILEUS_ARGON2_MEM_COST` | [`PassConfig::mem_cost`] |
+    /// | `BASILEUS_ARGON2_TIME_COST` | [`PassConfig::time_cost`] |
+    /// | `BASILEUS_PEPPER` | [`PassConfig::pepper`] |
+    /// | `BASILEUS_REVOKE_SESSIONS_ON_PASS_CHANGE` | [`PassConfig::revoke_sessions`] |
+    /// | `BASILEUS_PKCE` | [`ModuleConfig::pkce`] |
+    /// | `BASILEUS_MFA` | [`ModuleConfig::mfa`] |
+    /// | `BASILEUS_WEBAUTHN` | [`ModuleConfig::webauthn`] |
+    /// | `BASILEUS_PUSH` | [`ModuleConfig::push`] |
+    /// | `BASILEUS_OOB` | [`ModuleConfig::oob`] |
+    /// | `BASILEUS_MAGIC_LINK` | [`ModuleConfig::magic_link`] |
+    /// | `BASILEUS_QR_LOGIN` | [`ModuleConfig::qr_login`] |
+    /// | `BASILEUS_IMPERSONATION` | [`ModuleConfig::impersonation`] |
+    /// | `BASILEUS_PUBKEY` | [`ModuleConfig::pubkey`] |
+    /// | `BASILEUS_API_KEY` | [`ModuleConfig::api_key`] |
+    /// | `BASILEUS_PKCE_ALLOW_PLAIN` | [`PkceConfig::allow_plain`] |
+    /// | `BASILEUS_DEFAULT_PERM` | [`PermConfig::default`], separated by whitespace |
+    /// | `BASILEUS_PERM_CACHE_TTL` | [`PermConfig::cache_ttl`] |
+    /// | `BASILEUS_RATE_LIMIT_USER_ATTEMPTS` | [`RateLimitConfig::user_attempts`] |
+    /// | `BASILEUS_RATE_LIMIT_IP_ATTEMPTS` | [`RateLimitConfig::ip_attempts`] |
+    /// | `BASILEUS_RATE_LIMIT_WINDOW` | [`RateLimitConfig::window`] |
+    /// | `BASILEUS_RATE_LIMIT_LOCKOUT` | [`RateLimitConfig::lockout`] |
+    /// | `BASILEUS_RATE_LIMIT_IP_MAX_LOCKOUT` | [`RateLimitConfig::ip_max_lockout`] |
+    /// | `BASILEUS_LOCKOUT_THRESHOLD` | [`LockoutPolicy::threshold`] |
+    /// | `BASILEUS_LOCKOUT_WINDOW` | [`LockoutPolicy::window`] |
+    /// | `BASILEUS_LOCKOUT_DURATION` | [`LockoutPolicy::duration`] |
+    /// | `BASILEUS_LOCKOUT_AUTO_UNLOCK` | [`LockoutPolicy::auto_unlock`] |
+    /// | `BASILEUS_LOCKOUT_NOTIFY` | [`LockoutPolicy::notify`] |
+    /// | `BASILEUS_LOG_HASH_USERS` | [`LogConfig::hash_users`] |
+    /// | `BASILEUS_LOG_TOKEN_PREFIX` | [`LogConfig::token_prefix`] |
+    /// | `BASILEUS_MAINTENANCE_INTERVAL` | [`MaintenanceConfig::interval`] |
+    /// | `BASILEUS_TOKEN_TTL` | [`TokenConfig::ttl`] |
+    /// | `BASILEUS_TOKEN_IDLE_TIMEOUT` | [`TokenConfig::idle_timeout`] |
+    /// | `BASILEUS_MAX_SESSIONS` | [`TokenConfig::max_sessions`] |
+    /// | `BASILEUS_TOKEN_PERSIST` | [`TokenConfig::persist`] |
+    /// | `BASILEUS_PERM_HISTORY_RETENTION` | [`MaintenanceConfig::perm_history_retention`] |
+    /// | `BASILEUS_AUDIT_RETENTION` | [`MaintenanceConfig::audit_retention`] |
+    /// | `BASILEUS_LOGIN_HISTORY_RETENTION` | [`MaintenanceConfig::login_history_retention`] |
+    /// | `BASILEUS_AUDIT_PERSIST` | [`AuditConfig::persist`] |
+    ///
+    /// Booleans are `true`, `false`, `1` or `0`, and durations are given in seconds.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut builder = Self::builder();
+        let config = &mut builder.config;
+        if let Some(x) = var("BASILEUS_DB")? {
+            config.db = x.into();
+        }
+        if let Some(x) = var("BASILEUS_TABLE_PREFIX")? {
+            config.sqlite.table_prefix = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_WAL")? {
+            config.sqlite.wal = x;
+        }
+        if let Some(x) = parse("BASILEUS_BUSY_TIMEOUT")? {
+            config.sqlite.busy_timeout = x;
+        }
+        if let Some(x) = parse("BASILEUS_MIN_CONNECTIONS")? {
+            config.sqlite.min_connections = x;
+        }
+        if let Some(x) = parse("BASILEUS_MAX_CONNECTIONS")? {
+            config.sqlite.max_connections = x;
+        }
+        if let Some(x) = parse("BASILEUS_EXIST_CACHE_TTL")? {
+            config.user.exist_cache_ttl = x;
+        }
+        if let Some(x) = parse("BASILEUS_ARGON2_MEM_COST")? {
+            config.pass.mem_cost = x;
+        }
+        if let Some(x) = parse("BASILEUS_ARGON2_TIME_COST")? {
+            config.pass.time_cost = x;
+        }
+        if let Some(x) = var("BASILEUS_PEPPER")? {
+            config.pass.pepper = Some(x.into());
+        }
+        if let Some(x) = parse_bool("BASILEUS_REVOKE_SESSIONS_ON_PASS_CHANGE")? {
+            config.pass.revoke_sessions = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_PKCE")? {
+            config.modules.pkce = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_MFA")? {
+            config.modules.mfa = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_WEBAUTHN")? {
+            config.modules.webauthn = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_PUSH")? {
+            config.modules.push = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_OOB")? {
+            config.modules.oob = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_MAGIC_LINK")? {
+            config.modules.magic_link = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_QR_LOGIN")? {
+            config.modules.qr_login = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_IMPERSONATION")? {
+            config.modules.impersonation = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_PUBKEY")? {
+            config.modules.pubkey = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_API_KEY")? {
+            config.modules.api_key = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_PKCE_ALLOW_PLAIN")? {
+            config.pkce.allow_plain = x;
+        }
+        if let Some(x) = var("BASILEUS_DEFAULT_PERM")? {
+            config.perm.default = x.as_str().into();
+        }
+        if let Some(x) = parse("BASILEUS_PERM_CACHE_TTL")? {
+            config.perm.cache_ttl = x;
+        }
+        if let Some(x) = parse("BASILEUS_RATE_LIMIT_USER_ATTEMPTS")? {
+            config.rate_limit.user_attempts = x;
+        }
+        if let Some(x) = parse("BASILEUS_RATE_LIMIT_IP_ATTEMPTS")? {
+            config.rate_limit.ip_attempts = x;
+        }
+        if let Some(x) = parse("BASILEUS_RATE_LIMIT_WINDOW")? {
+            config.rate_limit.window = x;
+        }
+        if let Some(x) = parse("BASILEUS_RATE_LIMIT_LOCKOUT")? {
+            config.rate_limit.lockout = x;
+        }
+        if let Some(x) = parse("BASILEUS_RATE_LIMIT_IP_MAX_LOCKOUT")? {
+            config.rate_limit.ip_max_lockout = x;
+        }
+        if let Some(x) = parse("BASILEUS_LOCKOUT_THRESHOLD")? {
+            config.lockout.threshold = x;
+        }
+        if let Some(x) = parse("BASILEUS_LOCKOUT_WINDOW")? {
+            config.lockout.window = x;
+        }
+        if let Some(x) = parse("BASILEUS_LOCKOUT_DURATION")? {
+            config.lockout.duration = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_LOCKOUT_AUTO_UNLOCK")? {
+            config.lockout.auto_unlock = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_LOCKOUT_NOTIFY")? {
+            config.lockout.notify = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_LOG_HASH_USERS")? {
+            config.log.hash_users = x;
+        }
+        if let Some(x) = parse_bool("BASILEUS_LOG_TOKEN_PREFIX")? {
+            config.log.token_prefix = x;
+        }
+        if let Some(x) = parse("BASILEUS_MAINTENANCE_INTERVAL")? {
+            config.maintenance.interval = x;
+        }
+        if let Some(x) = parse("BASILEUS_TOKEN_TTL")? {
+            config.token.ttl = Some(x);
+        }
+        if let Some(x) = parse("BASILEUS_TOKEN_IDLE_TIMEOUT")? {
+            config.token.idle_timeout = Some(x);
+        }
+        if let Some(x) = parse("BASILEUS_MAX_SESSIONS")? {
+            config.token.max_sessions = Some(x);
+        }
+        if let Some(x) = parse_bool("BASILEUS_TOKEN_PERSIST")? {
+            config.token.persist = x;
+        }
+        if let Some(x) = parse("BASILEUS_PERM_HISTORY_RETENTION")? {
+            config.maintenance.perm_history_retention = Some(x);
+        }
+        if let Some(x) = parse("BASILEUS_AUDIT_RETENTION")? {
+            config.maintenance.audit_retention = Some(x);
+        }
+        if let Some(x) = parse("BASILEUS_LOGIN_HISTORY_RETENTION")? {
+            config.maintenance.login_history_retention = Some(x);
+        }
+        if let Some(x) = parse_bool("BASILEUS_AUDIT_PERSIST")? {
+            config.audit.persist = x;
+        }
+        builder.build()
+    }
+
+    /// Load a configuration from a TOML or YAML file, depending on its extension.
+    ///
+    /// Missing settings and sections keep their defaults.
+    /// Each format requires the feature of the same name, i.e. `toml` or `yaml`.
+    #[cfg(any(feature = "toml", feature = "yaml"))]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.into(), e))?;
+        let ext = path
+            .extension()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default();
+        let config: Self = match ext.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "toml")]
+            "toml" => toml::from_str(&content).map_err(|e| {
+                let offset = e.span().map_or(0, |x| x.start);
+                let line = content[..offset].matches('\n').count() + 1;
+                ConfigError::Parse(path.into(), line, e.message().into())
+            })?,
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => serde_yaml::from_str(&content).map_err(|e| {
+                let line = e.location().map_or(1, |x| x.line());
+                ConfigError::Parse(path.into(), line, e.to_string())
+            })?,
+            _ => return Err(ConfigError::UnknownFormat(path.into())),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Resolve references to secrets kept outside the configuration, e.g. [`PassConfig::pepper`].
+    ///
+    /// A secret given as `env:VAR` is read from the environment variable `VAR`,
+    /// and one given as `file:PATH` from the file at `PATH`, without a trailing newline.
+    /// Any other value is taken literally.
+    ///
+    /// This is called by [`Basileus::new`] and [`Basileus::reload_config`],
+    /// so that serialized configurations only contain the references.
+    /// Since [`validate`](Self::validate) can not see through references, resolved secrets are checked here.
+    pub fn resolve_secrets(mut self) -> Result<Self, ConfigError> {
+        if let Some(pepper) = &self.pass.pepper {
+            self.pass.pepper = Some(resolve_secret(pepper.expose())?.into());
+        }
+        #[cfg(feature = "cookie")]
+        if let Some(secret) = &self.cookie.secret {
+            let secret = resolve_secret(secret.expose())?;
+            if !secret.is_empty() && secret.len() < 32 {
+                return Err(ConfigError::Invalid(vec![ConfigProblem::ShortSecret(
+                    secret.len(),
+                )]));
+            }
+            self.cookie.secret = Some(secret.into());
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(secret) = &self.webhook.secret {
+            self.webhook.secret = Some(resolve_secret(secret.expose())?.into());
+        }
+        #[cfg(feature = "oidc")]
+        for provider in self.oidc.providers.values_mut() {
+            if let Some(secret) = &provider.client_secret {
+                provider.client_secret = Some(resolve_secret(secret.expose())?.into());
+            }
+        }
+        #[cfg(feature = "smtp")]
+        if let Some(password) = &self.smtp.password {
+            self.smtp.password = Some(resolve_secret(password.expose())?.into());
+        }
+        Ok(self)
+    }
+
+    /// Check the configuration for settings which can not work, reporting every problem found at once.
+    ///
+    /// This is called by [`Basileus::new`](crate::Basileus::new) and [`ConfigBuilder::build`].
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+        let scheme = (self.db.to_str()).and_then(|x| x.split_once("://").map(|x| x.0));
+        if self.db.as_os_str().is_empty() {
+            problems.push(ConfigProblem::EmptyPath);
+        } else if let Some(scheme) = scheme {
+            problems.push(ConfigProblem::UnsupportedBackend(scheme.into()));
+        } else if self.db.as_os_str() != MEMORY && !writable(&self.db) {
+            problems.push(ConfigProblem::Unwritable(self.db.clone()));
+        }
+        let sqlite = &self.sqlite;
+        if sqlite.max_connections == 0 {
+            problems.push(ConfigProblem::ZeroConnections);
+        }
+        if sqlite.min_connections > sqlite.max_connections {
+            problems.push(ConfigProblem::MinExceedMax(
+                sqlite.min_connections,
+                sqlite.max_connections,
+            ));
+        }
+        // the prefix is spliced into statements unquoted
+        if !sqlite
+            .table_prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            || sqlite
+                .table_prefix
+                .starts_with(|c: char| c.is_ascii_digit())
+        {
+            problems.push(ConfigProblem::InvalidTablePrefix(
+                sqlite.table_prefix.clone(),
+            ));
+        }
+        if self.maintenance.interval == 0 {
+            problems.push(ConfigProblem::ZeroInterval);
+        }
+        let token = &self.token;
+        if token.ttl == Some(0) || token.idle_timeout == Some(0) {
+            problems.push(ConfigProblem::ZeroTokenTtl);
+        }
+        if token.max_sessions == Some(0) {
+            problems.push(ConfigProblem::ZeroMaxSessions);
+        }
+        // fewer bytes would make tokens guessable
+        if token.length < 16 {
+            problems.push(ConfigProblem::ShortToken(token.length));
+        }
+        let pass = &self.pass;
+        if pass.mem_cost < MIN_MEM_COST {
+            problems.push(ConfigProblem::WeakHash(pass.mem_cost));
+        }
+        if pass.time_cost == 0 || pass.lanes == 0 {
+            problems.push(ConfigProblem::InvalidHash);
+        }
+        let limit = &self.rate_limit;
+        if (limit.user_attempts > 0 || limit.ip_attempts > 0) && limit.window == 0 {
+            problems.push(ConfigProblem::ZeroWindow);
+        }
+        let lockout = &self.lockout;
+        if lockout.threshold > 0 && lockout.window == 0 {
+            problems.push(ConfigProblem::ZeroWindow);
+        }
+        if lockout.threshold > 0 && lockout.auto_unlock && lockout.duration == 0 {
+            problems.push(ConfigProblem::ZeroLockout);
+        }
+        if self.user.max_name_length == 0 {
+            problems.push(ConfigProblem::ZeroNameLength);
+        }
+        let mfa = &self.mfa;
+        if !(6..=8).contains(&mfa.digits)
+            || mfa.period == 0
+            || mfa.look_ahead == 0
+            || mfa.secret_length < 16
+            || mfa.challenge_ttl == 0
+            || mfa.push_timeout == 0
+        {
+            problems.push(ConfigProblem::InvalidOtp);
+        }
+        // PKCE clients are public, so a plain challenge protects nothing once intercepted
+        if self.pkce.allow_plain {
+            problems.push(ConfigProblem::InsecurePlainPkce);
+        }
+        #[cfg(feature = "cookie")]
+        {
+            let cookie = &self.cookie;
+            if cookie.same_site == crate::cookie::SameSite::None && !cookie.secure {
+                problems.push(ConfigProblem::InsecureSameSite);
+            }
+            let secret = cookie.secret.as_ref().map_or("", |x| x.expose());
+            if !secret.is_empty()
+                && !secret.starts_with("env:")
+                && !secret.starts_with("file:")
+                && secret.len() < 32
+            {
+                problems.push(ConfigProblem::ShortSecret(secret.len()));
+            }
+        }
+        if let Some(invalid) = self.perm.default.find_invalid() {
+            problems.push(ConfigProblem::InvalidPerm(invalid.into()));
+        }
+        if let Some(invalid) = mfa.required_groups.iter().find(|x| !check_perm_name(x)) {
+            problems.push(ConfigProblem::InvalidMfaGroup(invalid.into()));
+        }
+        for rule in &self.policy.rules {
+            if let Some(invalid) = rule.networks.iter().find(|x| parse_network(x).is_none()) {
+                problems.push(ConfigProblem::InvalidNetwork(invalid.clone()));
+            }
+            if let Some((from, to)) = rule.hours.filter(|&(from, to)| from > 23 || to > 23) {
+                problems.push(ConfigProblem::InvalidHours(from, to));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+/// Whether the database at `path` can be written, or created if missing.
+fn writable(path: &Path) -> bool {
+    match path.metadata() {
+        Ok(meta) => meta.is_file() && !meta.permissions().readonly(),
+        Err(_) => {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            dir.metadata()
+                .is_ok_and(|meta| meta.is_dir() && !meta.permissions().readonly())
+        }
+    }
+}
+
+impl Basileus {
+    /// Get the current configuration.
+    pub fn config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace the configuration at runtime, without dropping sessions.
+    ///
+    /// Secrets are [resolved](Config::resolve_secrets) again.
+    /// Every setting takes effect immediately, e.g. token and PKCE code lifetimes,
+    /// cache lifetimes, permission rules and the policy,
+    /// except for [`Config::db`] and [`Config::sqlite`], which must be left unchanged,
+    /// and [modules](ModuleConfig) whose tables are created on start, which must not be enabled if disabled on start.
+    /// [Default permissions](Basileus::default_perm) are replaced as well if changed in `config`.
+    pub fn reload_config(&self, config: Config) -> Result<(), ConfigError> {
+        config.validate()?;
+        let config = config.resolve_secrets()?;
+        let mut current = self.config.write().unwrap();
+        if config.db != current.db {
+            return Err(ConfigError::RequiresRestart("database-path".into()));
+        }
+        if config.sqlite != current.sqlite {
+            return Err(ConfigError::RequiresRestart("sqlite".into()));
+        }
+        if config.modules.mfa && !self.schema.mfa {
+            return Err(ConfigError::RequiresRestart("modules.mfa".into()));
+        }
+        if config.modules.pubkey && !self.schema.pubkey {
+            return Err(ConfigError::RequiresRestart("modules.pubkey".into()));
+        }
+        #[cfg(feature = "smtp")]
+        if config.smtp != current.smtp {
+            return Err(ConfigError::RequiresRestart("smtp".into()));
+        }
+        if config.perm.default != current.perm.default {
+            self.set_default_perm(config.perm.default.clone());
+        }
+        self.throttles.configure(&config.rate_limit);
+        *current = config;
+        drop(current);
+        // cached permissions may be expanded by outdated rules
+        self.perm.invalidate_all();
+        info!("reloaded configuration");
+        Ok(())
+    }
+}
+
+/// Resolve a secret reference, see [`Config::resolve_secrets`].
+fn resolve_secret(value: &str) -> Result<String, ConfigError> {
+    let fail =
+        |e: &dyn std::fmt::Display| ConfigError::UnresolvedSecret(value.into(), e.to_string());
+    if let Some(name) = value.strip_prefix("env:") {
+        return env::var(name).map_err(|e| fail(&e));
+    }
+    if let Some(path) = value.strip_prefix("file:") {
+        let content = std::fs::read_to_string(path).map_err(|e| fail(&e))?;
+        let content = content.strip_suffix('\n').unwrap_or(&content);
+        return Ok(content.strip_suffix('\r').unwrap_or(content).into());
+    }
+    Ok(value.into())
+}
+
+/// Read an environment variable, treating an empty value as unset.
+fn var(name: &str) -> Result<Option<String>, ConfigError> {
+    match env::var(name) {
+        Ok(x) if x.is_empty() => Ok(None),
+        Ok(x) => Ok(Some(x)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(x)) => Err(ConfigError::InvalidEnv(
+            name.into(),
+            x.to_string_lossy().into(),
+        )),
+    }
+}
+
+fn parse<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    let Some(x) = var(name)? else {
+        return Ok(None);
+    };
+    match x.trim().parse() {
+        Ok(res) => Ok(Some(res)),
+        Err(_) => Err(ConfigError::InvalidEnv(name.into(), x)),
+    }
+}
+
+fn parse_bool(name: &str) -> Result<Option<bool>, ConfigError> {
+    let Some(x) = var(name)? else {
+        return Ok(None);
+    };
+    match x.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Ok(Some(true)),
+        "false" | "0" => Ok(Some(false)),
+        _ => Err(ConfigError::InvalidEnv(name.into(), x)),
+    }
+}
+
+impl ConfigBuilder {
+    /// Path to the SQLite storage, see [`Config::db`].
+    pub fn db(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.db = path.into();
+        self
+    }
+
+    /// SQLite tuning, see [`Config::sqlite`].
+    pub fn sqlite(mut self, sqlite: SqliteConfig) -> Self {
+        self.config.sqlite = sqlite;
+        self
+    }
+
+    /// Prefix of every table name, see [`SqliteConfig::table_prefix`].
+    pub fn table_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.sqlite.table_prefix = prefix.into();
+        self
+    }
+
+    /// User management configuration, see [`Config::user`].
+    pub fn user(mut self, user: UserConfig) -> Self {
+        self.config.user = user;
+        self
+    }
+
+    /// Password hashing configuration, see [`Config::pass`].
+    pub fn pass(mut self, pass: PassConfig) -> Self {
+        self.config.pass = pass;
+        self
+    }
+
+    /// Session token configuration, see [`Config::token`].
+    ///
+    /// This overrides any previous [`Self::token_ttl`].
+    pub fn token(mut self, token: TokenConfig) -> Self {
+        self.config.token = token;
+        self
+    }
+
+    /// Age in seconds after which tokens expire, see [`TokenConfig::ttl`].
+    pub fn token_ttl(mut self, secs: u64) -> Self {
+        self.config.token.ttl = Some(secs);
+        self
+    }
+
+    /// PKCE configuration, see [`Config::pkce`].
+    pub fn pkce(mut self, pkce: PkceConfig) -> Self {
+        self.config.pkce = pkce;
+        self
+    }
+
+    /// Permission management configuration, see [`Config::perm`].
+    pub fn perm(mut self, perm: PermConfig) -> Self {
+        self.config.perm = perm;
+        self
+    }
+
+    /// Attribute-based policy configuration, see [`Config::policy`].
+    pub fn policy(mut self, policy: PolicyConfig) -> Self {
+        self.config.policy = policy;
+        self
+    }
+
+    /// Switches of optional subsystems, see [`Config::modules`].
+    pub fn modules(mut self, modules: ModuleConfig) -> Self {
+        self.config.modules = modules;
+        self
+    }
+
+    /// Rate limiting configuration, see [`Config::rate_limit`].
+    pub fn rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.config.rate_limit = rate_limit;
+        self
+    }
+
+    /// Account lockout policy, see [`Config::lockout`].
+    pub fn lockout(mut self, lockout: LockoutPolicy) -> Self {
+        self.config.lockout = lockout;
+        self
+    }
+
+    /// Log redaction configuration, see [`Config::log`].
+    pub fn log(mut self, log: LogConfig) -> Self {
+        self.config.log = log;
+        self
+    }
+
+    /// Periodic maintenance configuration, see [`Config::maintenance`].
+    pub fn maintenance(mut self, maintenance: MaintenanceConfig) -> Self {
+        self.config.maintenance = maintenance;
+        self
+    }
+
+    /// Audit configuration, see [`Config::audit`].
+    pub fn audit(mut self, audit: AuditConfig) -> Self {
+        self.config.audit = audit;
+        self
+    }
+
+    /// Contact address configuration, see [`Config::contact`].
+    pub fn contact(mut self, contact: ContactConfig) -> Self {
+        self.config.contact = contact;
+        self
+    }
+
+    /// Magic link configuration, see [`Config::magic_link`].
+    pub fn magic_link(mut self, magic_link: MagicLinkConfig) -> Self {
+        self.config.magic_link = magic_link;
+        self
+    }
+
+    /// Cross-device login configuration, see [`Config::qr_login`].
+    pub fn qr_login(mut self, qr_login: QrLoginConfig) -> Self {
+        self.config.qr_login = qr_login;
+        self
+    }
+
+    /// Challenge configuration, see [`Config::challenge`].
+    pub fn challenge(mut self, challenge: ChallengeConfig) -> Self {
+        self.config.challenge = challenge;
+        self
+    }
+
+    /// Multi-factor authentication configuration, see [`Config::mfa`].
+    pub fn mfa(mut self, mfa: MfaConfig) -> Self {
+        self.config.mfa = mfa;
+        self
+    }
+
+    /// Out-of-band one-time code configuration, see [`Config::oob`].
+    pub fn oob(mut self, oob: OobConfig) -> Self {
+        self.config.oob = oob;
+        self
+    }
+
+    /// WebAuthn relying party configuration, see [`Config::webauthn`].
+    pub fn webauthn(mut self, webauthn: WebauthnConfig) -> Self {
+        self.config.webauthn = webauthn;
+        self
+    }
+
+    /// Session cookie configuration, see [`Config::cookie`].
+    #[cfg(feature = "cookie")]
+    pub fn cookie(mut self, cookie: crate::cookie::CookieConfig) -> Self {
+        self.config.cookie = cookie;
+        self
+    }
+
+    /// Webhook configuration, see [`Config::webhook`].
+    #[cfg(feature = "webhook")]
+    pub fn webhook(mut self, webhook: crate::webhook::WebhookConfig) -> Self {
+        self.config.webhook = webhook;
+        self
+    }
+
+    /// SAML service provider configuration, see [`Config::saml`].
+    #[cfg(feature = "saml")]
+    pub fn saml(mut self, saml: crate::saml::SamlConfig) -> Self {
+        self.config.saml = saml;
+        self
+    }
+
+    /// OpenID Connect federation configuration, see [`Config::oidc`].
+    #[cfg(feature = "oidc")]
+    pub fn oidc(mut self, oidc: crate::oidc::OidcConfig) -> Self {
+        self.config.oidc = oidc;
+        self
+    }
+
+    /// SMTP configuration, see [`Config::smtp`].
+    #[cfg(feature = "smtp")]
+    pub fn smtp(mut self, smtp: crate::messenger::SmtpConfig) -> Self {
+        self.config.smtp = smtp;
+        self
+    }
+
+    /// Finish building, failing if the configuration can not work.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use sqlx::{query, query_as};
+use tracing::{debug, info, warn};
+
+use crate::{
+    Basileus, constant_time_eq,
+    err::{ContactError, SendMessageError},
+    messenger::{Channel, Message},
+    rand_digits,
+};
+
+/// Initialize the table of contact addresses.
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS contact (
+    user TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    address TEXT NOT NULL,
+    verified INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (user, channel),
+    UNIQUE (channel, address),
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+"#;
+
+/// Contact address configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ContactConfig {
+    /// Number of digits of verification codes.
+    #[cfg_attr(feature = "serde", serde(rename = "code-length"))]
+    pub code_length: usize,
+    /// Time in seconds a verification code stays valid.
+    #[cfg_attr(feature = "serde", serde(rename = "code-ttl"))]
+    pub code_ttl: u64,
+    /// Wrong codes accepted before the verification has to be started over.
+    #[cfg_attr(feature = "serde", serde(rename = "max-attempts"))]
+    pub max_attempts: u32,
+    /// Subject of messages carrying verification codes.
+    #[cfg_attr(feature = "serde", serde(rename = "verify-subject"))]
+    pub verify_subject: String,
+    /// Text of messages carrying verification codes, where `{code}` is replaced by the code.
+    #[cfg_attr(feature = "serde", serde(rename = "verify-body"))]
+    pub verify_body: String,
+}
+
+impl Default for ContactConfig {
+    fn default() -> Self {
+        Self {
+            code_length: 6,
+            code_ttl: 600,
+            max_attempts: 5,
+            verify_subject: "Verification code".into(),
+            verify_body: "Your verification code is {code}.".into(),
+        }
+    }
+}
+
+/// An address a user can be reached at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Contact {
+    pub channel: Channel,
+    pub address: String,
+    /// Whether the user proved to receive messages at the address, see [`Basileus::verify_contact`].
+    pub verified: bool,
+}
+
+/// A verification code sent and not yet entered.
+struct PendingCode {
+    address: String,
+    code: String,
+    issued: SystemTime,
+    attempts: u32,
+}
+
+#[derive(Default)]
+pub struct ContactModule {
+    pending: Mutex<HashMap<(String, Channel), PendingCode>>,
+}
+
+impl ContactModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Set the address `user` is reached at through `channel`, replacing any previous one.
+    ///
+    /// A changed address is unverified until [verified](Self::verify_contact) again.
+    /// No two users may share an address, so that users can be found by it, see [`Self::contact_user`].
+    pub async fn set_contact(
+        &self,
+        user: &str,
+        channel: Channel,
+        address: &str,
+    ) -> Result<(), ContactError> {
+        if !self.exist_user(user).await? {
+            return Err(ContactError::UserNotExist(user.into()));
+        }
+        let q = query(self.sql(
+            "INSERT INTO contact (user, channel, address) VALUES (?, ?, ?)
+            ON CONFLICT (user, channel) DO UPDATE SET address = excluded.address,
+            verified = verified AND address = excluded.address",
+        ))
+        .bind(user)
+        .bind(channel.name())
+        .bind(address);
+        match q.execute(&self.writer).await {
+            Ok(_) => {}
+            Err(e)
+                if e.as_database_error()
+                    .is_some_and(|x| x.is_unique_violation()) =>
+            {
+                return Err(ContactError::AddressTaken(address.into()));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        info!("set {} address of {}", channel.name(), self.log_user(user));
+        Ok(())
+    }
+
+    /// Get the address `user` is reached at through `channel`, if any.
+    pub async fn get_contact(
+        &self,
+        user: &str,
+        channel: Channel,
+    ) -> Result<Option<Contact>, sqlx::error::Error> {
+        let q = query_as(
+            self.sql("SELECT address, verified FROM contact WHERE user = ? AND channel = ?"),
+        )
+        .bind(user)
+        .bind(channel.name());
+        let res: Option<(String, bool)> = q.fetch_optional(&self.db).await?;
+        Ok(res.map(|(address, verified)| Contact {
+            channel,
+            address,
+            verified,
+        }))
+    }
+
+    /// Remove the address `user` is reached at through `channel`.
+    ///
+    /// Returns whether the address was set.
+    pub async fn delete_contact(
+        &self,
+        user: &str,
+        channel: Channel,
+    ) -> Result<bool, sqlx::error::Error> {
+        self.contact
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&(user.into(), channel));
+        let q = query(self.sql("DELETE FROM contact WHERE user = ? AND channel = ?"))
+            .bind(user)
+            .bind(channel.name());
+        Ok(q.execute(&self.writer).await?.rows_affected() > 0)
+    }
+
+    /// Get the user reached at `address` through `channel`, if any.
+    pub async fn contact_user(
+        &self,
+        channel: Channel,
+        address: &str,
+    ) -> Result<Option<String>, sqlx::error::Error> {
+        let q = query_as(self.sql("SELECT user FROM contact WHERE channel = ? AND address = ?"))
+            .bind(channel.name())
+            .bind(address);
+        let res: Option<(String,)> = q.fetch_optional(&self.db).await?;
+        Ok(res.map(|(user,)| user))
+    }
+
+    /// Send `subject` and `body` to the address `user` is reached at through `channel`.
+    pub async fn message_user(
+        &self,
+        user: &str,
+        channel: Channel,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), SendMessageError> {
+        let contact = self
+            .get_contact(user, channel)
+            .await?
+            .ok_or_else(|| SendMessageError::NoContact(user.into(), channel))?;
+        self.send_message(&Message {
+            channel,
+            to: contact.address,
+            subject: subject.into(),
+            body: body.into(),
+        })
+        .await
+    }
+
+    /// Send a verification code to the address `user` is reached at through `channel`,
+    /// to be entered with [`Self::verify_contact`].
+    ///
+    /// Any code sent earlier to the same user and channel is invalidated.
+    pub async fn begin_contact_verification(
+        &self,
+        user: &str,
+        channel: Channel,
+    ) -> Result<(), SendMessageError> {
+        self.spanned("begin_contact_verification", Some(user), async {
+            let contact = self
+                .get_contact(user, channel)
+                .await?
+                .ok_or_else(|| SendMessageError::NoContact(user.into(), channel))?;
+            let config = self.config.read().unwrap().contact.clone();
+            let code = rand_digits(config.code_length);
+            let message = Message {
+                channel,
+                to: contact.address.clone(),
+                subject: config.verify_subject.clone(),
+                body: config.verify_body.replace("{code}", &code).into(),
+            };
+            self.send_message(&message).await?;
+            let pending = PendingCode {
+                address: contact.address,
+                code,
+                issued: SystemTime::now(),
+                attempts: 0,
+            };
+            self.contact
+                .pending
+                .lock()
+                .unwrap()
+                .insert((user.into(), channel), pending);
+            debug!(
+                "sent {} verification code to {}",
+                channel.name(),
+                self.log_user(user)
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    /// Check the verification code entered by `user` for `channel`, marking the address verified if it is correct.
+    ///
+    /// Returns whether the code was correct.
+    /// Codes are rejected once expired, after [`ContactConfig::max_attempts`] wrong guesses,
+    /// and when the address has been changed since the code was sent.
+    pub async fn verify_contact(
+        &self,
+        user: &str,
+        channel: Channel,
+        code: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        self.spanned("verify_contact", Some(user), async {
+            let config = self.config.read().unwrap().contact.clone();
+            let address = {
+                let mut pending = self.contact.pending.lock().unwrap();
+                let key = (user.to_owned(), channel);
+                let Some(entry) = pending.get_mut(&key) else {
+                    return Ok(false);
+                };
+                let expired = entry
+                    .issued
+                    .elapsed()
+                    .is_ok_and(|x| x > Duration::from_secs(config.code_ttl));
+                if expired {
+                    pending.remove(&key);
+                    return Ok(false);
+                }
+                if !constant_time_eq(&entry.code, code) {
+                    entry.attempts += 1;
+                    if entry.attempts >= config.max_attempts {
+                        warn!(
+                            "too many wrong verification codes for {}",
+                            self.log_user(user)
+                        );
+                        pending.remove(&key);
+                    }
+                    return Ok(false);
+                }
+                pending.remove(&key).unwrap().address
+            };
+            let q = query(self.sql(
+                "UPDATE contact SET verified = 1 WHERE user = ? AND channel = ? AND address = ?",
+            ))
+            .bind(user)
+            .bind(channel.name())
+            .bind(address);
+            if q.execute(&self.writer).await?.rows_affected() == 0 {
+                return Ok(false);
+            }
+            info!(
+                "verified {} address of {}",
+                channel.name(),
+                self.log_user(user)
+            );
+            Ok(true)
+        })
+        .await
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt::Display;
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, aead::Aead};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{Basileus, err::AuthError, logging::Secret, rand_buf, token::AuthUser};
+
+/// The `SameSite` attribute of cookies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SameSite {
+    #[cfg_attr(feature = "serde", serde(rename = "strict"))]
+    Strict,
+    #[cfg_attr(feature = "serde", serde(rename = "lax"))]
+    Lax,
+    /// Send the cookie with cross-site requests, which requires [`CookieConfig::secure`].
+    #[cfg_attr(feature = "serde", serde(rename = "none"))]
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Strict => write!(f, "Strict"),
+            Self::Lax => write!(f, "Lax"),
+            Self::None => write!(f, "None"),
+        }
+    }
+}
+
+/// Session cookie configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CookieConfig {
+    /// Name of the cookie.
+    #[cfg_attr(feature = "serde", serde(rename = "name"))]
+    pub name: String,
+    /// Secret the signing and encryption keys are derived from,
+    /// which may reference the environment or a file, see [`Config::resolve_secrets`](crate::Config::resolve_secrets).
+    ///
+    /// If unset, a random secret is used, so that cookies no longer verify after a restart.
+    #[cfg_attr(feature = "serde", serde(rename = "secret"))]
+    pub secret: Option<Secret<String>>,
+    /// Whether to encrypt the token, instead of only signing it.
+    #[cfg_attr(feature = "serde", serde(rename = "encrypt"))]
+    pub encrypt: bool,
+    #[cfg_attr(feature = "serde", serde(rename = "same-site"))]
+    pub same_site: SameSite,
+    /// Whether browsers only send the cookie over HTTPS.
+    #[cfg_attr(feature = "serde", serde(rename = "secure"))]
+    pub secure: bool,
+    /// Whether the cookie is hidden from scripts.
+    #[cfg_attr(feature = "serde", serde(rename = "http-only"))]
+    pub http_only: bool,
+    /// Domain the cookie is sent to, or only the host which set it if [`None`].
+    #[cfg_attr(feature = "serde", serde(rename = "domain"))]
+    pub domain: Option<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "path"))]
+    pub path: String,
+    /// Age in seconds after which browsers discard the cookie,
+    /// or [`None`] to discard it when the browser closes.
+    #[cfg_attr(feature = "serde", serde(rename = "max-age"))]
+    pub max_age: Option<u64>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            name: "session".into(),
+            secret: None,
+            encrypt: false,
+            same_site: SameSite::Lax,
+            secure: true,
+            http_only: true,
+            domain: None,
+            path: "/".into(),
+            max_age: None,
+        }
+    }
+}
+
+/// A cookie to be sent in a `Set-Cookie` header, which it is displayed as.
+#[derive(Clone, Debug)]
+pub struct Cookie {
+    pub name: String,
+    pub value: Secret<String>,
+    pub config: CookieConfig,
+}
+
+impl Display for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let config = &self.config;
+        write!(
+            f,
+            "{}={}; Path={}",
+            self.name,
+            self.value.expose(),
+            config.path
+        )?;
+        if let Some(domain) = &config.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = config.max_age {
+            write!(f, "; Max-Age={max_age}")?;
+        }
+        write!(f, "; SameSite={}", config.same_site)?;
+        if config.secure {
+            write!(f, "; Secure")?;
+        }
+        if config.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct CookieModule {
+    /// Secret used if [`CookieConfig::secret`] is unset.
+    fallback: Vec<u8>,
+}
+
+impl CookieModule {
+    pub fn new() -> Self {
+        Self {
+            fallback: rand_buf(32),
+        }
+    }
+}
+
+impl Default for CookieModule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Derive the key for `purpose` from `secret`, so that signing and encryption never share a key.
+fn derive_key(secret: &[u8], purpose: &str) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.update(purpose);
+    hash.update(secret);
+    hash.finalize().into()
+}
+
+impl Basileus {
+    fn cookie_key(&self, config: &CookieConfig, purpose: &str) -> [u8; 32] {
+        match &config.secret {
+            Some(secret) => derive_key(secret.expose().as_bytes(), purpose),
+            None => derive_key(&self.cookie.fallback, purpose),
+        }
+    }
+
+    fn cookie_mac(&self, config: &CookieConfig) -> Hmac<Sha256> {
+        let key = self.cookie_key(config, "sign");
+        <Hmac<Sha256> as Mac>::new_from_slice(&key).unwrap()
+    }
+
+    /// Wrap a token into a signed, and if [configured](CookieConfig::encrypt) encrypted, session cookie.
+    pub fn session_cookie(&self, token: &str) -> Cookie {
+        let config = self.config.read().unwrap().cookie.clone();
+        let value = if config.encrypt {
+            let cipher = ChaCha20Poly1305::new(&self.cookie_key(&config, "encrypt").into());
+            let nonce = rand_buf(12);
+            let nonce = Nonce::from_slice(&nonce);
+            // encryption only fails for absurdly long inputs
+            let mut buf = cipher.encrypt(nonce, token.as_bytes()).unwrap();
+            buf.splice(0..0, nonce.iter().copied());
+            BASE64_URL_SAFE_NO_PAD.encode(buf)
+        } else {
+            let mut mac = self.cookie_mac(&config);
+            mac.update(token.as_bytes());
+            let sig = mac.finalize().into_bytes();
+            format!(
+                "{}.{}",
+                BASE64_URL_SAFE_NO_PAD.encode(token),
+                BASE64_URL_SAFE_NO_PAD.encode(sig)
+            )
+        };
+        Cookie {
+            name: config.name.clone(),
+            value: value.into(),
+            config,
+        }
+    }
+
+    /// A cookie replacing the session cookie so that browsers discard it, e.g. on logout.
+    pub fn clear_session_cookie(&self) -> Cookie {
+        let mut config = self.config.read().unwrap().cookie.clone();
+        config.max_age = Some(0);
+        Cookie {
+            name: config.name.clone(),
+            value: Secret::default(),
+            config,
+        }
+    }
+
+    /// Get the token from the session cookie in the value of a `Cookie` header,
+    /// or [`None`] if the cookie is missing or fails to verify.
+    pub fn read_session_cookie(&self, header: &str) -> Option<String> {
+        let config = self.config.read().unwrap().cookie.clone();
+        let value = header.split(';').find_map(|x| {
+            let (name, value) = x.trim().split_once('=')?;
+            (name == config.name).then_some(value)
+        })?;
+        let buf = if config.encrypt {
+            let buf = BASE64_URL_SAFE_NO_PAD.decode(value).ok()?;
+            if buf.len() < 12 {
+                return None;
+            }
+            let (nonce, buf) = buf.split_at(12);
+            let cipher = ChaCha20Poly1305::new(&self.cookie_key(&config, "encrypt").into());
+            cipher.decrypt(Nonce::from_slice(nonce), buf).ok()?
+        } else {
+            let (token, sig) = value.split_once('.')?;
+            let token = BASE64_URL_SAFE_NO_PAD.decode(token).ok()?;
+            let sig = BASE64_URL_SAFE_NO_PAD.decode(sig).ok()?;
+            let mut mac = self.cookie_mac(&config);
+            mac.update(&token);
+            mac.verify_slice(&sig).ok()?;
+            token
+        };
+        String::from_utf8(buf).ok()
+    }
+
+    /// Authenticate a request by the session cookie in its `Cookie` header, see [`Self::authenticate`].
+    pub async fn authenticate_cookie(&self, header: &str) -> Result<AuthUser, AuthError> {
+        let token = self
+            .read_session_cookie(header)
+            .ok_or(AuthError::InvalidToken)?;
+        self.authenticate(&token).await
+    }
+}
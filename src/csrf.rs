@@ -0,0 +1,54 @@
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use tracing::{debug, warn};
+
+use crate::{Basileus, constant_time_eq, rand_buf, token::token_key};
+
+impl Basileus {
+    /// Get the CSRF token of the session identified by `token`, issuing one if none exists yet,
+    /// or [`None`] if the session is invalid.
+    ///
+    /// This implements the synchronizer token pattern: the CSRF token is embedded into forms or sent in a header by the client,
+    /// and checked against the session with [`Self::verify_csrf`].
+    /// As the CSRF token belongs to the session, a new one is used after every login and discarded on logout.
+    pub fn csrf_token(&self, token: &str) -> Option<String> {
+        let config = self.config.read().unwrap().token.clone();
+        let mut store = self.token.store.write().unwrap();
+        let session = store.get_mut(&token_key(token))?;
+        if session.expired(&config) {
+            return None;
+        }
+        let csrf = session
+            .csrf
+            .get_or_insert_with(|| BASE64_URL_SAFE_NO_PAD.encode(rand_buf(32)));
+        Some(csrf.clone())
+    }
+
+    /// Replace the CSRF token of the session identified by `token`, e.g. after a privilege change,
+    /// returning the new one, or [`None`] if the session is invalid.
+    pub fn rotate_csrf(&self, token: &str) -> Option<String> {
+        let user = {
+            let mut store = self.token.store.write().unwrap();
+            let session = store.get_mut(&token_key(token))?;
+            session.csrf = None;
+            session.user.clone()
+        };
+        debug!("rotated CSRF token of {}", self.log_user(&user));
+        self.csrf_token(token)
+    }
+
+    /// Verify the CSRF token submitted with a request of the session identified by `token`.
+    pub fn verify_csrf(&self, token: &str, csrf: &str) -> bool {
+        let store = self.token.store.read().unwrap();
+        let Some(session) = store.get(&token_key(token)) else {
+            return false;
+        };
+        let valid = session
+            .csrf
+            .as_deref()
+            .is_some_and(|x| constant_time_eq(x, csrf));
+        if !valid {
+            warn!("CSRF check failed for {}", self.log_user(&session.user));
+        }
+        valid
+    }
+}
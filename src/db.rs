@@ -0,0 +1,685 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Basileus, check_perm_name, check_rolename, check_username, err::RestoreError,
+    migrate::MIGRATIONS,
+};
+use sqlx::{
+    Connection, Sqlite, SqliteConnection, SqlitePool, Transaction, query, query_as,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
+
+use tracing::info;
+
+/// Special database path for an in-memory database, see [`Config::db`](crate::Config::db).
+pub const MEMORY: &str = ":memory:";
+
+/// Durability level of SQLite writes, see SQLite's `PRAGMA synchronous`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Synchronous {
+    /// Leave syncing to the operating system.
+    #[cfg_attr(feature = "serde", serde(rename = "off"))]
+    Off,
+    /// Sync at critical moments, safe from corruption in WAL mode.
+    #[cfg_attr(feature = "serde", serde(rename = "normal"))]
+    Normal,
+    /// Sync on every commit.
+    #[cfg_attr(feature = "serde", serde(rename = "full"))]
+    Full,
+    /// Like [`Synchronous::Full`], additionally syncing the directory in rollback journal mode.
+    #[cfg_attr(feature = "serde", serde(rename = "extra"))]
+    Extra,
+}
+
+impl From<Synchronous> for SqliteSynchronous {
+    fn from(value: Synchronous) -> Self {
+        match value {
+            Synchronous::Off => Self::Off,
+            Synchronous::Normal => Self::Normal,
+            Synchronous::Full => Self::Full,
+            Synchronous::Extra => Self::Extra,
+        }
+    }
+}
+
+/// Tuning of the SQLite storage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SqliteConfig {
+    /// Whether to use write-ahead logging, which allows reads concurrent to writes.
+    #[cfg_attr(feature = "serde", serde(rename = "wal"))]
+    pub wal: bool,
+    /// Time in seconds to wait for a locked database before failing with `database is locked`.
+    #[cfg_attr(feature = "serde", serde(rename = "busy-timeout"))]
+    pub busy_timeout: u64,
+    /// Durability level of writes.
+    #[cfg_attr(feature = "serde", serde(rename = "synchronous"))]
+    pub synchronous: Synchronous,
+    /// Minimum number of connections kept open.
+    #[cfg_attr(feature = "serde", serde(rename = "min-connections"))]
+    pub min_connections: u32,
+    /// Maximum number of connections open at the same time.
+    #[cfg_attr(feature = "serde", serde(rename = "max-connections"))]
+    pub max_connections: u32,
+    /// Number of prepared statements cached per connection.
+    ///
+    /// Every statement of the library is prepared once per connection and reused afterwards,
+    /// as long as the cache is large enough to hold all of them,
+    /// which the default of 256 is, unlike the default of `sqlx` of 100.
+    /// Statements are not checked at compile time, since table names depend on [`Self::table_prefix`].
+    #[cfg_attr(feature = "serde", serde(rename = "statement-cache-capacity"))]
+    pub statement_cache_capacity: usize,
+    /// Prefix of every table, index and trigger name, e.g. `basileus_` for tables `basileus_user`, `basileus_pass`, etc.
+    ///
+    /// This allows sharing a database with an application whose schema would otherwise clash.
+    /// Databases created without a prefix are not renamed, so changing the prefix starts with empty tables.
+    #[cfg_attr(feature = "serde", serde(rename = "table-prefix"))]
+    pub table_prefix: String,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            wal: true,
+            busy_timeout: 5,
+            synchronous: Synchronous::Normal,
+            min_connections: 0,
+            max_connections: 10,
+            statement_cache_capacity: 256,
+            table_prefix: "".into(),
+        }
+    }
+}
+
+/// Names of every table, which are subject to [`SqliteConfig::table_prefix`].
+const TABLE_NAMES: &[&str] = &[
+    "account_lock",
+    "acl",
+    "audit_log",
+    "canary_token",
+    "contact",
+    "grp",
+    "identity",
+    "ip_block",
+    "login_history",
+    "mfa_factor",
+    "pass",
+    "perm_log",
+    "pubkey",
+    "role",
+    "role_perm",
+    "schema_version",
+    "token",
+    "token_store",
+    "trusted_device",
+    "user",
+    "user_perm",
+    "user_role",
+];
+
+/// Names of schemas which may qualify a table name.
+const SCHEMA_NAMES: &[&str] = &["main", "temp", "backup"];
+
+/// Keywords followed by a table name.
+const TABLE_KEYWORDS: &[&str] = &["FROM", "JOIN", "INTO", "UPDATE", "TABLE", "REFERENCES"];
+
+/// Keywords followed by the name of an index or trigger.
+const NAME_KEYWORDS: &[&str] = &["INDEX", "TRIGGER"];
+
+/// Keywords skipped when looking for the keyword preceding a name, e.g. in `CREATE TABLE IF NOT EXISTS`.
+const SKIPPED_KEYWORDS: &[&str] = &["IF", "NOT", "EXISTS", "OR", "IGNORE", "REPLACE"];
+
+/// Apply `prefix` to every table, index and trigger name in `sql`.
+///
+/// This only understands the subset of SQL used by the library:
+/// - table names must either follow one of [`TABLE_KEYWORDS`], qualify a column as in `user_role.user`,
+///   or follow the `ON` of a `CREATE INDEX` or `CREATE TRIGGER`, whereas the `ON` of a join or conflict clause is left alone;
+/// - string literals and quoted identifiers are copied verbatim, since e.g. `kind = 'user'` is not a table,
+///   so statements referring to tables by name, e.g. in `sqlite_master`, must bind the prefixed name as parameter;
+/// - an unquoted column named like a table, e.g. `user`, must not directly follow one of [`TABLE_KEYWORDS`].
+fn rewrite(prefix: &str, sql: &str) -> String {
+    enum Token<'a> {
+        Word(&'a str),
+        Punct(char),
+    }
+    let mut res = String::with_capacity(sql.len() + prefix.len() * 8);
+    let mut prev: Option<Token> = None;
+    // the word before a `.` preceding the current word, if any
+    let mut qualifier: Option<&str> = None;
+    // whether an index or trigger was named, so that the next `ON` is followed by a table name
+    let mut header = false;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            res.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        if c == '\'' || c == '"' {
+            // copy string literals and quoted identifiers verbatim
+            let end = rest[1..].find(c).map_or(rest.len(), |i| i + 2);
+            res.push_str(&rest[..end]);
+            rest = &rest[end..];
+            prev = Some(Token::Punct(c));
+            qualifier = None;
+            continue;
+        }
+        if !(c.is_ascii_alphabetic() || c == '_') {
+            res.push(c);
+            rest = &rest[c.len_utf8()..];
+            qualifier = match (&prev, c) {
+                (Some(Token::Word(word)), '.') => Some(word),
+                _ => None,
+            };
+            prev = Some(Token::Punct(c));
+            continue;
+        }
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        rest = &rest[end..];
+        let upper = word.to_ascii_uppercase();
+        if SKIPPED_KEYWORDS.contains(&upper.as_str()) {
+            res.push_str(word);
+            continue;
+        }
+        let keyword = match &prev {
+            Some(Token::Word(x)) => Some(x.to_ascii_uppercase()),
+            _ => None,
+        };
+        let keyword = keyword.as_deref();
+        let is_table = TABLE_NAMES.contains(&word);
+        let prefixed = if matches!(prev, Some(Token::Punct('.'))) {
+            // a qualified name, i.e. either `schema.table` or `table.column`
+            is_table && qualifier.is_some_and(|x| SCHEMA_NAMES.contains(&x))
+        } else if keyword.is_some_and(|x| NAME_KEYWORDS.contains(&x)) {
+            header = true;
+            true
+        } else if header && keyword == Some("ON") {
+            header = false;
+            is_table
+        } else {
+            let qualifies = rest.trim_start().starts_with('.');
+            is_table && (qualifies || keyword.is_some_and(|x| TABLE_KEYWORDS.contains(&x)))
+        };
+        if prefixed {
+            res.push_str(prefix);
+        }
+        res.push_str(word);
+        prev = Some(Token::Word(word));
+        qualifier = None;
+    }
+    res
+}
+
+/// Apply `prefix` to every table, index and trigger name in `sql`, see [`SqliteConfig::table_prefix`].
+///
+/// Results are cached for the lifetime of the program,
+/// so this must only be used with the finitely many statements of the library.
+pub(crate) fn prefixed(prefix: &str, sql: &'static str) -> &'static str {
+    type Cache = Mutex<HashMap<(String, &'static str), &'static str>>;
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    if prefix.is_empty() {
+        return sql;
+    }
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+    cache
+        .entry((prefix.into(), sql))
+        .or_insert_with(|| rewrite(prefix, sql).leak())
+}
+
+/// Open connection pools to the database at `path` for reading and writing respectively, creating it if missing.
+///
+/// In WAL mode, writes are serialized through a single dedicated connection,
+/// while reads are served concurrently from a pool of read-only connections.
+/// Otherwise, as with in-memory databases, both pools are the same.
+pub(crate) async fn connect(
+    path: &Path,
+    config: &SqliteConfig,
+) -> Result<(SqlitePool, SqlitePool), sqlx::error::Error> {
+    if path.as_os_str() == MEMORY {
+        // keep a single connection open for the whole lifetime of the pool,
+        // since every connection to `:memory:` opens a distinct database, dropped with the connection
+        let opt = SqliteConnectOptions::default()
+            .filename(MEMORY)
+            .foreign_keys(true)
+            .statement_cache_capacity(config.statement_cache_capacity);
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(opt)
+            .await?;
+        return Ok((pool.clone(), pool));
+    }
+    let journal = if config.wal {
+        SqliteJournalMode::Wal
+    } else {
+        SqliteJournalMode::Delete
+    };
+    let opt = SqliteConnectOptions::default()
+        .filename(path)
+        .create_if_missing(true)
+        // cascading deletes of user data rely on foreign keys, which SQLite does not enforce by default
+        .foreign_keys(true)
+        .journal_mode(journal)
+        .busy_timeout(Duration::from_secs(config.busy_timeout))
+        .synchronous(config.synchronous.into())
+        .statement_cache_capacity(config.statement_cache_capacity);
+    if !config.wal {
+        let pool = SqlitePoolOptions::new()
+            .min_connections(config.min_connections)
+            .max_connections(config.max_connections)
+            .connect_with(opt)
+            .await?;
+        return Ok((pool.clone(), pool));
+    }
+    // the writer must be connected first to create the database and switch it to WAL
+    let writer = SqlitePoolOptions::new()
+        .min_connections(1)
+        .max_connections(1)
+        .connect_with(opt.clone())
+        .await?;
+    let reader = SqlitePoolOptions::new()
+        .min_connections(config.min_connections)
+        .max_connections(config.max_connections)
+        .connect_with(opt.read_only(true))
+        .await?;
+    Ok((reader, writer))
+}
+
+/// Begin a transaction which writes to the database.
+///
+/// The write lock is acquired upfront, since upgrading a deferred transaction from reading to writing
+/// fails immediately instead of waiting for the busy timeout if another connection has written meanwhile.
+pub(crate) async fn begin_write(
+    pool: &SqlitePool,
+) -> Result<Transaction<'static, Sqlite>, sqlx::error::Error> {
+    pool.begin_with("BEGIN IMMEDIATE").await
+}
+
+impl Basileus {
+    /// Apply the [table prefix](SqliteConfig::table_prefix) to `sql`.
+    pub(crate) fn sql(&self, sql: &'static str) -> &'static str {
+        prefixed(&self.config.read().unwrap().sqlite.table_prefix, sql)
+    }
+
+    /// Begin a transaction which writes to the database, see [`begin_write`].
+    pub(crate) async fn begin_write(
+        &self,
+    ) -> Result<Transaction<'static, Sqlite>, sqlx::error::Error> {
+        let start = Instant::now();
+        let tx = begin_write(&self.writer).await?;
+        self.record_acquire(start);
+        Ok(tx)
+    }
+
+    /// Write a consistent snapshot of the whole database to a new SQLite file at `path`.
+    ///
+    /// The database remains usable meanwhile, so this is safe to call on a live service.
+    /// Fails if `path` already exists.
+    pub async fn backup(&self, path: impl AsRef<Path>) -> Result<(), sqlx::error::Error> {
+        let path = path.as_ref();
+        query("VACUUM INTO ?")
+            .bind(path.to_string_lossy())
+            .execute(&self.db)
+            .await?;
+        info!("backed up database to {path:?}");
+        Ok(())
+    }
+}
+
+/// How [`Basileus::restore`] treats data already in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RestorePolicy {
+    /// Discard all current data, leaving exactly the content of the backup.
+    #[cfg_attr(feature = "serde", serde(rename = "replace"))]
+    Replace,
+    /// Merge the backup into current data, preferring the backup on conflicts.
+    ///
+    /// Data of users and roles present in both is merged row by row,
+    /// so that rows the backup lacks, e.g. second factors enrolled since, are kept.
+    #[cfg_attr(feature = "serde", serde(rename = "overwrite"))]
+    Overwrite,
+    /// Merge the backup into current data, preferring current data on conflicts.
+    #[cfg_attr(feature = "serde", serde(rename = "skip-existing"))]
+    SkipExisting,
+}
+
+/// Tables holding persistent data, parents before children.
+const TABLES: &[&str] = &[
+    "user",
+    "pass",
+    "pubkey",
+    "token",
+    "user_perm",
+    "role",
+    "role_perm",
+    "user_role",
+    "acl",
+    "grp",
+    "identity",
+    "contact",
+    "mfa_factor",
+];
+
+/// Tables referenced by foreign keys, holding nothing but their primary key.
+///
+/// These are never replaced, since SQLite replaces a row by deleting it,
+/// which cascades to every row referencing it.
+const PARENT_TABLES: &[&str] = &["user", "role"];
+
+/// Whether `schema` has the table `name`, which is missing if its [module](crate::config::ModuleConfig) was never enabled.
+async fn has_table(
+    conn: &mut SqliteConnection,
+    schema: &str,
+    name: &str,
+) -> Result<bool, sqlx::error::Error> {
+    let q = query_as("SELECT EXISTS(SELECT 1 FROM pragma_table_info(?, ?))")
+        .bind(name)
+        .bind(schema);
+    let (res,): (bool,) = q.fetch_one(conn).await?;
+    Ok(res)
+}
+
+impl Basileus {
+    async fn restore_attached(
+        &self,
+        conn: &mut SqliteConnection,
+        policy: RestorePolicy,
+    ) -> Result<(), RestoreError> {
+        let prefix = self.config.read().unwrap().sqlite.table_prefix.clone();
+        let q = query_as(self.sql("SELECT COALESCE(MAX(version), 0) FROM backup.schema_version"));
+        let (version,): (i64,) = q.fetch_one(&mut *conn).await?;
+        let expected = MIGRATIONS.last().map_or(0, |m| m.version);
+        if version != expected {
+            return Err(RestoreError::VersionMismatch(expected, version));
+        }
+        let q = query_as(self.sql("SELECT user FROM backup.user"));
+        let users: Vec<(String,)> = q.fetch_all(&mut *conn).await?;
+        if let Some((user,)) = users.into_iter().find(|(x,)| !check_username(x)) {
+            return Err(RestoreError::InvalidName(user));
+        }
+        let q = query_as(self.sql("SELECT role FROM backup.role"));
+        let roles: Vec<(String,)> = q.fetch_all(&mut *conn).await?;
+        if let Some((role,)) = roles.into_iter().find(|(x,)| !check_rolename(x)) {
+            return Err(RestoreError::InvalidName(role));
+        }
+        let q = query_as(
+            self.sql("SELECT grp FROM backup.user_perm UNION SELECT grp FROM backup.role_perm"),
+        );
+        let perms: Vec<(String,)> = q.fetch_all(&mut *conn).await?;
+        if let Some((perm,)) = perms.into_iter().find(|(x,)| !check_perm_name(x)) {
+            return Err(RestoreError::InvalidPerm(perm));
+        }
+
+        let mut tx = conn.begin_with("BEGIN IMMEDIATE").await?;
+        let insert = match policy {
+            RestorePolicy::Replace => {
+                for table in TABLES.iter().rev().chain(&["perm_log"]) {
+                    if !has_table(&mut tx, "main", &format!("{prefix}{table}")).await? {
+                        continue;
+                    }
+                    let sql = format!("DELETE FROM main.{table}");
+                    query(&rewrite(&prefix, &sql)).execute(&mut *tx).await?;
+                }
+                "INSERT"
+            }
+            RestorePolicy::Overwrite => "INSERT OR REPLACE",
+            RestorePolicy::SkipExisting => "INSERT OR IGNORE",
+        };
+        for table in TABLES {
+            let name = format!("{prefix}{table}");
+            if !has_table(&mut tx, "main", &name).await?
+                || !has_table(&mut tx, "backup", &name).await?
+            {
+                continue;
+            }
+            let insert = match PARENT_TABLES.contains(table) && insert == "INSERT OR REPLACE" {
+                true => "INSERT OR IGNORE",
+                false => insert,
+            };
+            // surrogate IDs are assigned anew, since they may collide with unrelated rows
+            let q = query_as("SELECT name FROM pragma_table_info(?, 'main') WHERE name <> 'id'")
+                .bind(name);
+            let columns: Vec<(String,)> = q.fetch_all(&mut *tx).await?;
+            let columns = columns
+                .into_iter()
+                .map(|(x,)| x)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let sql = format!(
+                "{insert} INTO main.{table} ({columns}) SELECT {columns} FROM backup.{table}"
+            );
+            query(&rewrite(&prefix, &sql)).execute(&mut *tx).await?;
+        }
+        // log entries are never overwritten, but appended unless already present
+        query(self.sql(
+            "INSERT INTO main.perm_log (time, actor, user, added, removed)
+            SELECT time, actor, user, added, removed FROM backup.perm_log b
+            WHERE NOT EXISTS (
+                SELECT 1 FROM main.perm_log m
+                WHERE m.time = b.time AND m.actor IS b.actor AND m.user = b.user
+                AND m.added = b.added AND m.removed = b.removed
+            )
+            ORDER BY b.id",
+        ))
+        .execute(&mut *tx)
+        .await?;
+        let q = query_as("SELECT COUNT(*) FROM pragma_foreign_key_check");
+        let (violations,): (i64,) = q.fetch_one(&mut *tx).await?;
+        if violations > 0 {
+            return Err(RestoreError::Inconsistent(violations));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Restore data from a [backup](Self::backup) file at `path`.
+    ///
+    /// The backup must have been taken with the same [schema version](Self::schema_version),
+    /// and is validated before anything is written.
+    /// The restore is atomic, i.e. either everything or nothing is restored.
+    pub async fn restore(
+        &self,
+        path: impl AsRef<Path>,
+        policy: RestorePolicy,
+    ) -> Result<(), RestoreError> {
+        let path = path.as_ref();
+        let mut conn = self.writer.acquire().await?;
+        query("ATTACH DATABASE ? AS backup")
+            .bind(path.to_string_lossy())
+            .execute(&mut *conn)
+            .await?;
+        let res = self.restore_attached(&mut conn, policy).await;
+        if let Err(e) = query("DETACH DATABASE backup").execute(&mut *conn).await {
+            // never return a connection with the backup attached to the pool
+            conn.close_on_drop();
+            return Err(e.into());
+        }
+        res?;
+        self.user.invalidate_all();
+        self.perm.invalidate_all();
+        info!("restored database from {path:?} with {policy:?}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use sqlx::{SqlitePool, query, query_as};
+
+    use super::RestorePolicy;
+    use crate::{Basileus, Config, messenger::Channel, perm::Perm};
+
+    /// A database file private to the test `name`, removed on drop.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let file = format!("basileus-{name}-{}.db", std::process::id());
+            let res = Self(std::env::temp_dir().join(file));
+            res.remove();
+            res
+        }
+
+        fn remove(&self) {
+            for ext in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(ext);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    async fn open(db: &TempDb) -> Basileus {
+        let config = Config {
+            db: db.0.clone(),
+            ..Default::default()
+        };
+        Basileus::new(config).await.unwrap()
+    }
+
+    /// Number of rows of `table` whose `column` is `value`.
+    async fn count(b: &Basileus, table: &str, column: &str, value: &str) -> i64 {
+        let sql = format!("SELECT COUNT(*) FROM {table} WHERE {column} = ?");
+        let (res,): (i64,) = query_as(&sql).bind(value).fetch_one(&b.db).await.unwrap();
+        res
+    }
+
+    #[tokio::test]
+    async fn foreign_keys_on_every_connection() {
+        let db = TempDb::new("foreign-keys");
+        let b = open(&db).await;
+        let pools: [&SqlitePool; 2] = [&b.db, &b.writer];
+        for pool in pools {
+            let mut conns = vec![];
+            for _ in 0..pool.options().get_max_connections() {
+                conns.push(pool.acquire().await.unwrap());
+            }
+            for conn in &mut conns {
+                let (on,): (bool,) = query_as("PRAGMA foreign_keys")
+                    .fetch_one(&mut **conn)
+                    .await
+                    .unwrap();
+                assert!(on);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn deleting_user_cascades() {
+        let db = TempDb::new("delete-user");
+        let b = open(&db).await;
+        b.create_user("alice").await.unwrap();
+        b.update_pass("alice", "correct-horse").await.unwrap();
+        b.give_perm("alice", &Perm::from("doc.read")).await.unwrap();
+        b.create_role("editor", &Perm::from("doc.write"))
+            .await
+            .unwrap();
+        b.assign_role("alice", "editor").await.unwrap();
+        (b.set_contact("alice", Channel::Email, "alice@example.com"))
+            .await
+            .unwrap();
+        b.begin_totp_enrollment("alice").await.unwrap();
+        let tables = ["pass", "user_perm", "user_role", "contact", "mfa_factor"];
+        for table in tables {
+            assert_eq!(count(&b, table, "user", "alice").await, 1, "{table}");
+        }
+
+        b.delete_user("alice").await.unwrap();
+        for table in tables {
+            assert_eq!(count(&b, table, "user", "alice").await, 0, "{table}");
+        }
+        assert_eq!(count(&b, "role", "role", "editor").await, 1);
+    }
+
+    /// [`Basileus::delete_role`] clears references itself, so delete the row directly.
+    #[tokio::test]
+    async fn deleting_role_cascades() {
+        let db = TempDb::new("delete-role");
+        let b = open(&db).await;
+        b.create_user("alice").await.unwrap();
+        b.create_role("editor", &Perm::from("doc.write"))
+            .await
+            .unwrap();
+        b.assign_role("alice", "editor").await.unwrap();
+
+        let q = query(b.sql("DELETE FROM role WHERE role = ?")).bind("editor");
+        q.execute(&b.writer).await.unwrap();
+        assert_eq!(count(&b, "role_perm", "role", "editor").await, 0);
+        assert_eq!(count(&b, "user_role", "role", "editor").await, 0);
+        assert_eq!(count(&b, "user", "user", "alice").await, 1);
+    }
+
+    #[tokio::test]
+    async fn overwrite_keeps_existing_user_data() {
+        let db = TempDb::new("overwrite");
+        let backup = TempDb::new("overwrite-backup");
+        let b = open(&db).await;
+        b.create_user("alice").await.unwrap();
+        b.update_pass("alice", "correct-horse").await.unwrap();
+        b.create_user("bob").await.unwrap();
+        b.begin_totp_enrollment("bob").await.unwrap();
+        b.backup(&backup.0).await.unwrap();
+
+        // alice's factor reuses the ID bob's factor has in the backup
+        b.delete_user("bob").await.unwrap();
+        b.begin_totp_enrollment("alice").await.unwrap();
+        b.update_pass("alice", "battery-staple").await.unwrap();
+
+        b.restore(&backup.0, RestorePolicy::Overwrite)
+            .await
+            .unwrap();
+        assert_eq!(count(&b, "pass", "user", "alice").await, 1);
+        assert_eq!(count(&b, "mfa_factor", "user", "alice").await, 1);
+        assert_eq!(count(&b, "mfa_factor", "user", "bob").await, 1);
+        assert!(b.verify_pass("alice", "correct-horse").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn disabled_modules_skip_their_tables() {
+        let db = TempDb::new("modules");
+        let backup = TempDb::new("modules-backup");
+        let mut config = Config {
+            db: db.0.clone(),
+            ..Default::default()
+        };
+        config.modules.mfa = false;
+        config.modules.pubkey = false;
+        let b = Basileus::new(config).await.unwrap();
+        let mut conn = b.writer.acquire().await.unwrap();
+        for table in ["mfa_factor", "trusted_device", "pubkey"] {
+            assert!(!super::has_table(&mut conn, "main", table).await.unwrap());
+        }
+        drop(conn);
+        b.create_user("alice").await.unwrap();
+        assert!(b.list_hotp("alice").await.unwrap().is_empty());
+        b.backup(&backup.0).await.unwrap();
+        b.restore(&backup.0, RestorePolicy::Replace).await.unwrap();
+        drop(b);
+
+        let b = open(&db).await;
+        b.begin_totp_enrollment("alice").await.unwrap();
+        assert_eq!(count(&b, "mfa_factor", "user", "alice").await, 1);
+        assert_eq!(count(&b, "pubkey", "user", "alice").await, 0);
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::SystemTime;
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as};
+use tracing::{debug, info};
+
+use crate::{Basileus, err::AuthError, from_unix, rand_buf, token::token_key, unix_now};
+
+/// Initialize the table of devices trusted to skip second factors.
+///
+/// Only hashes of device tokens are stored, so that reading the database does not allow skipping second factors.
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS trusted_device (
+    id INTEGER NOT NULL PRIMARY KEY,
+    user TEXT NOT NULL,
+    token_hash BLOB NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    last_used INTEGER,
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_trusted_device_user ON trusted_device (user);
+"#;
+
+/// A device trusted to skip second factors, see [`Basileus::trust_device`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct TrustedDevice {
+    /// Identifier to [revoke](Basileus::revoke_trusted_device) the device with.
+    pub id: i64,
+    /// Name given when trusting the device, e.g. its user agent.
+    pub name: String,
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+    /// Time the device last skipped a second factor, if ever.
+    pub last_used: Option<SystemTime>,
+}
+
+fn hash_device_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+impl Basileus {
+    /// Trust the device holding the session `token` to skip second factors of its user
+    /// for [`MfaConfig::trusted_device_ttl`](crate::mfa::MfaConfig::trusted_device_ttl), returning a device token
+    /// to be presented as [`LoginContext::device`](crate::login::LoginContext::device) on later logins.
+    ///
+    /// The session must have presented a second factor, or this fails with [`AuthError::ReauthRequired`].
+    /// Returns [`None`] if trusting devices or second factors are disabled.
+    pub async fn trust_device(&self, token: &str, name: &str) -> Result<Option<String>, AuthError> {
+        self.trust_device_bound(token, None, name).await
+    }
+
+    /// Like [`Self::trust_device`], for a request over a connection presenting the client certificate
+    /// with the [thumbprint](crate::token::cert_thumbprint) `cert`, if any, see [`Self::verify_bound_token`].
+    pub async fn trust_device_bound(
+        &self,
+        token: &str,
+        cert: Option<&str>,
+        name: &str,
+    ) -> Result<Option<String>, AuthError> {
+        self.spanned("trust_device", None, async {
+            let user = (self.verify_bound_token(token, cert)).ok_or(AuthError::InvalidToken)?;
+            self.record_user(&user);
+            let ttl = {
+                let config = self.config.read().unwrap();
+                config.mfa.trusted_device_ttl.filter(|_| config.modules.mfa)
+            };
+            let Some(ttl) = ttl else {
+                return Ok(None);
+            };
+            let mfa = self
+                .token
+                .store
+                .read()
+                .unwrap()
+                .get(&token_key(token))
+                .is_some_and(|x| x.mfa);
+            if !mfa {
+                return Err(AuthError::ReauthRequired);
+            }
+            let device = BASE64_URL_SAFE_NO_PAD.encode(rand_buf(32));
+            let now = unix_now();
+            let q = query(self.sql(
+                "INSERT INTO trusted_device (user, token_hash, name, created_at, expires_at)
+                VALUES (?, ?, ?, ?, ?)",
+            ))
+            .bind(&user)
+            .bind(hash_device_token(&device))
+            .bind(name)
+            .bind(now)
+            .bind(now.saturating_add(ttl as i64));
+            q.execute(&self.writer).await?;
+            info!("trusted device '{name}' of {}", self.log_user(&user));
+            Ok(Some(device))
+        })
+        .await
+    }
+
+    /// Check whether `device` is a token of a device `user` [trusts](Self::trust_device), recording its use.
+    pub(crate) async fn check_trusted_device(
+        &self,
+        user: &str,
+        device: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        let now = unix_now();
+        let q = query(self.sql(
+            "UPDATE trusted_device SET last_used = ? WHERE user = ? AND token_hash = ? AND expires_at > ?",
+        ))
+        .bind(now)
+        .bind(user)
+        .bind(hash_device_token(device))
+        .bind(now);
+        let res = q.execute(&self.writer).await?.rows_affected() > 0;
+        if res {
+            self.count("mfa.trusted");
+            debug!(
+                "trusted device of {} skipped second factor",
+                self.log_user(user)
+            );
+        }
+        Ok(res)
+    }
+
+    /// Devices `user` trusts and whose trust has not expired.
+    pub async fn list_trusted_devices(
+        &self,
+        user: &str,
+    ) -> Result<Vec<TrustedDevice>, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(vec![]);
+        }
+        let q = query_as(self.sql(
+            "SELECT id, name, created_at, expires_at, last_used FROM trusted_device
+            WHERE user = ? AND expires_at > ? ORDER BY created_at",
+        ))
+        .bind(user)
+        .bind(unix_now());
+        let res: Vec<(i64, String, i64, i64, Option<i64>)> = q.fetch_all(&self.db).await?;
+        Ok(res
+            .into_iter()
+            .map(
+                |(id, name, created_at, expires_at, last_used)| TrustedDevice {
+                    id,
+                    name,
+                    created_at: from_unix(created_at),
+                    expires_at: from_unix(expires_at),
+                    last_used: last_used.map(from_unix),
+                },
+            )
+            .collect())
+    }
+
+    /// Stop trusting the device of `user` with `id`, returning whether it was trusted.
+    pub async fn revoke_trusted_device(
+        &self,
+        user: &str,
+        id: i64,
+    ) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let q = query(self.sql("DELETE FROM trusted_device WHERE user = ? AND id = ?"))
+            .bind(user)
+            .bind(id);
+        let res = q.execute(&self.writer).await?.rows_affected() > 0;
+        if res {
+            info!("revoked trusted device {id} of {}", self.log_user(user));
+        }
+        Ok(res)
+    }
+
+    /// Stop trusting every device of `user`, returning the number of devices revoked.
+    pub async fn revoke_trusted_devices(&self, user: &str) -> Result<u64, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(0);
+        }
+        let q = query(self.sql("DELETE FROM trusted_device WHERE user = ?")).bind(user);
+        let res = q.execute(&self.writer).await?.rows_affected();
+        if res > 0 {
+            info!("revoked {res} trusted devices of {}", self.log_user(user));
+        }
+        Ok(res)
+    }
+}
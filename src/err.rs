@@ -1,16 +1,42 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
 use thiserror::Error;
 
+use crate::{
+    challenge::Challenge, logging::redact_url, messenger::Channel, mfa::MfaChallenge,
+    pass::MIN_MEM_COST,
+};
+
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum CreateUserError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
     #[error("user '{0}' already exists")]
     UserAlreadyExist(String),
-    #[error("invalid username '{0}'")]
-    InvalidName(String),
+    #[error("invalid username '{0}': {1}")]
+    InvalidName(String, #[source] UsernameError),
+}
+
+/// The rule a username violates, see [`validate_username`](crate::user::validate_username).
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UsernameError {
+    #[error("username is empty")]
+    Empty,
+    #[error("username is longer than {0} characters")]
+    TooLong(usize),
+    #[error("forbidden character {1:?} at index {0}")]
+    InvalidChar(usize, char),
+    #[error("username is reserved")]
+    Reserved,
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum UpdatePassError {
     #[error(transparent)]
     Argon2(#[from] argon2::Error),
@@ -21,6 +47,7 @@ pub enum UpdatePassError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum VerifyPassError {
     #[error(transparent)]
     Argon2(#[from] argon2::Error),
@@ -30,9 +57,12 @@ pub enum VerifyPassError {
     UserNotExist(String),
     #[error("user '{0}' has not yet defined password authorization")]
     PassUndefined(String),
+    #[error("password of user '{0}' is hashed with a disallowed algorithm")]
+    LegacyHash(String),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum DeleteUserError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
@@ -41,6 +71,7 @@ pub enum DeleteUserError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum DeletePassError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
@@ -51,6 +82,7 @@ pub enum DeletePassError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum GetPermError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
@@ -59,11 +91,14 @@ pub enum GetPermError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum GivePermError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
     #[error("user '{0}' does not exist")]
     UserNotExist(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
     #[error(transparent)]
     GetDirectPerm(#[from] GetPermError),
     #[error(transparent)]
@@ -71,14 +106,18 @@ pub enum GivePermError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum SetPermError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
     #[error("user '{0}' does not exist")]
     UserNotExist(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum RevokePermError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
@@ -91,6 +130,7 @@ pub enum RevokePermError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum CheckPermError {
     #[error(transparent)]
     SQL(#[from] sqlx::error::Error),
@@ -103,6 +143,48 @@ pub enum CheckPermError {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ApplyPermError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DelegatePermError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("user '{0}' may not delegate the permissions")]
+    Forbidden(String),
+    #[error(transparent)]
+    GetPerm(#[from] GetPermError),
+    #[error(transparent)]
+    GivePerm(#[from] GivePermError),
+    #[error(transparent)]
+    RevokePerm(#[from] RevokePermError),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ParsePermError {
+    #[error("empty permission")]
+    Empty,
+    #[error("empty segment in permission '{0}'")]
+    EmptySegment(String),
+    #[error("wildcard is only allowed as the last segment in permission '{0}'")]
+    MisplacedWildcard(String),
+    #[error("invalid character {1:?} in permission '{0}'")]
+    InvalidChar(String, char),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum PkceAuthError {
     #[error(transparent)]
     VerifyPass(#[from] VerifyPassError),
@@ -112,9 +194,16 @@ pub enum PkceAuthError {
     UnsupportedMethod,
     #[error("insecure `plain` transformation method is disallowed")]
     InsecurePlain,
+    #[error("PKCE is disabled")]
+    FeatureDisabled,
+    #[error("too many failed attempts, retry in {}s", .0.as_secs().max(1))]
+    RateLimited(Duration),
+    #[error(transparent)]
+    Login(LoginError),
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum PkceTokenError {
     #[error("invalid authorization code")]
     InvalidCode,
@@ -122,4 +211,1293 @@ pub enum PkceTokenError {
     ExpiredCode,
     #[error("invalid code verifier")]
     InvalidVerifier,
+    #[error("PKCE is disabled")]
+    FeatureDisabled,
+    #[error("too many failed attempts, retry in {}s", .0.as_secs().max(1))]
+    RateLimited(Duration),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CreateRoleError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("role '{0}' already exists")]
+    RoleAlreadyExist(String),
+    #[error("invalid role name '{0}'")]
+    InvalidName(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DeleteRoleError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("role '{0}' does not exist")]
+    RoleNotExist(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum GetRolePermError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("role '{0}' does not exist")]
+    RoleNotExist(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SetRolePermError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("role '{0}' does not exist")]
+    RoleNotExist(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AssignRoleError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("role '{0}' does not exist")]
+    RoleNotExist(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum GetUserRoleError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AclGrantError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AclCheckError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error(transparent)]
+    GetPerm(#[from] GetPermError),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum EvaluatePolicyError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error(transparent)]
+    GetPerm(#[from] GetPermError),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CreateGroupError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("group '{0}' already exists")]
+    GroupAlreadyExist(String),
+    #[error("invalid group name '{0}'")]
+    InvalidName(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DeleteGroupError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("group '{0}' does not exist")]
+    GroupNotExist(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum GetGroupError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("group '{0}' does not exist")]
+    GroupNotExist(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ImportPermError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("role '{0}' does not exist")]
+    RoleNotExist(String),
+    #[error("invalid role name '{0}'")]
+    InvalidRoleName(String),
+    #[error("invalid permission '{0}'")]
+    InvalidPerm(String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RestoreError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("backup has schema version {1} instead of {0}")]
+    VersionMismatch(i64, i64),
+    #[error("invalid name '{0}' in backup")]
+    InvalidName(String),
+    #[error("invalid permission '{0}' in backup")]
+    InvalidPerm(String),
+    #[error("restoring would violate {0} foreign key constraints")]
+    Inconsistent(i64),
+}
+
+/// A single problem found by [`Config::validate`](crate::Config::validate).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ConfigProblem {
+    #[error("database path is empty")]
+    EmptyPath,
+    #[error("database path {0:?} is not writable")]
+    Unwritable(PathBuf),
+    #[error("unsupported database '{0}', only SQLite is supported")]
+    UnsupportedBackend(String),
+    #[error("maximum number of connections must be positive")]
+    ZeroConnections,
+    #[error("minimum number of connections {0} exceeds maximum {1}")]
+    MinExceedMax(u32, u32),
+    #[error("invalid table prefix '{0}'")]
+    InvalidTablePrefix(String),
+    #[error("maintenance interval must be positive")]
+    ZeroInterval,
+    #[error("token time-to-live must be positive")]
+    ZeroTokenTtl,
+    #[error("maximum number of sessions must be positive")]
+    ZeroMaxSessions,
+    #[error("token length of {0} bytes is too short")]
+    ShortToken(usize),
+    #[error("argon2 memory cost of {0} KiB is below the minimum of {MIN_MEM_COST} KiB")]
+    WeakHash(u32),
+    #[error("argon2 time cost and lanes must be positive")]
+    InvalidHash,
+    #[error("rate limiting window must be positive")]
+    ZeroWindow,
+    #[error("maximum length of usernames must be positive")]
+    ZeroNameLength,
+    #[error("cookies with SameSite=None must be secure")]
+    InsecureSameSite,
+    #[error("cookie secret is only {0} bytes long, at least 32 are required")]
+    ShortSecret(usize),
+    #[error("invalid default permission '{0}'")]
+    InvalidPerm(String),
+    #[error("invalid SMTP configuration: {0}")]
+    InvalidSmtp(String),
+    #[error(
+        "one-time passwords need 6 to 8 digits, a positive period and look-ahead, and secrets of at least 16 bytes"
+    )]
+    InvalidOtp,
+    #[error("invalid group '{0}' requiring a second factor")]
+    InvalidMfaGroup(String),
+    #[error("account lockout duration must be positive")]
+    ZeroLockout,
+    #[error("invalid network '{0}' in policy rule")]
+    InvalidNetwork(String),
+    #[error("invalid hours {0} to {1} in policy rule, hours range from 0 to 23")]
+    InvalidHours(u8, u8),
+    #[error("plain PKCE challenges are insecure with public clients")]
+    InsecurePlainPkce,
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("invalid configuration: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Invalid(Vec<ConfigProblem>),
+    #[error("invalid value '{1}' of environment variable {0}")]
+    InvalidEnv(String, String),
+    #[error("setting '{0}' can not be changed without restarting")]
+    RequiresRestart(String),
+    #[error("failed to read {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("{0:?}, line {1}: {2}")]
+    Parse(PathBuf, usize, String),
+    #[error("unknown configuration format of {0:?}")]
+    UnknownFormat(PathBuf),
+    #[error("failed to resolve secret '{0}': {1}")]
+    UnresolvedSecret(String, String),
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum InitError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+/// Failure to authenticate a request, see [`Basileus::authenticate`](crate::Basileus::authenticate).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AuthError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("missing bearer token")]
+    MissingToken,
+    #[error("invalid or expired token")]
+    InvalidToken,
+    #[error("user '{0}' lacks required permissions")]
+    Forbidden(String),
+    #[error(transparent)]
+    Credentials(#[from] CredentialsError),
+    #[error("recent authentication required")]
+    ReauthRequired,
+}
+
+/// Malformed `Authorization` header, see [`parse_authorization`](crate::authorization::parse_authorization).
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CredentialsError {
+    #[error("empty credentials")]
+    Empty,
+    #[error("unsupported authorization scheme '{0}'")]
+    UnsupportedScheme(String),
+    #[error("missing credentials after authorization scheme")]
+    MissingValue,
+    #[error("malformed token")]
+    InvalidToken,
+    #[error("malformed base64 in credentials")]
+    InvalidBase64,
+    #[error("credentials are not valid UTF-8")]
+    InvalidUtf8,
+    #[error("missing ':' between username and password")]
+    MissingColon,
+    #[error("API keys are disabled")]
+    FeatureDisabled,
+}
+
+/// Failure to log in with a username and password.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LoginError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Argon2(#[from] argon2::Error),
+    #[error("invalid username or password")]
+    Unauthorized,
+    #[error(transparent)]
+    Challenge(#[from] ChallengeError),
+    #[error("too many failed attempts, retry in {}s", .0.as_secs().max(1))]
+    RateLimited(Duration),
+    #[error("a second factor is required")]
+    MfaRequired(MfaChallenge),
+    #[error("invalid one-time password")]
+    InvalidOtp,
+    #[error("a second factor has to be enrolled")]
+    MfaEnrollmentRequired,
+    #[error("push approval was denied")]
+    PushDenied,
+    #[error("push approval timed out")]
+    PushTimedOut,
+    #[error("failed to request push approval: {0}")]
+    PushFailed(String),
+    #[error("logins from this address are blocked")]
+    IpBlocked,
+    /// The [risk evaluator](crate::risk::RiskEvaluator) denied the attempt.
+    #[error("login denied as too risky")]
+    RiskDenied,
+    /// The account is locked until the given time, or until an admin unlocks it.
+    #[error("account is locked")]
+    AccountLocked(Option<SystemTime>),
+}
+
+/// Failure to pass a challenge, see [`Basileus::check_challenge`](crate::Basileus::check_challenge).
+///
+/// Both variants carry a new challenge to present to the client.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ChallengeError {
+    #[error("a challenge has to be solved")]
+    Required(Challenge),
+    #[error("wrong response to challenge")]
+    Failed(Challenge),
+}
+
+impl ChallengeError {
+    /// The challenge to present to the client.
+    pub fn challenge(&self) -> &Challenge {
+        match self {
+            Self::Required(x) | Self::Failed(x) => x,
+        }
+    }
+}
+
+/// Failure to start impersonating a user, see [`Basileus::impersonate`](crate::Basileus::impersonate).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ImpersonateError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("users cannot impersonate themselves")]
+    SelfImpersonation,
+    #[error("a reason is required to impersonate a user")]
+    MissingReason,
+    #[error("user '{0}' may not impersonate a user holding more permissions")]
+    Forbidden(String),
+    #[error(transparent)]
+    GetPerm(#[from] GetPermError),
+    #[error("impersonation is disabled")]
+    FeatureDisabled,
+}
+
+/// Failure to deliver a webhook, see [`Basileus::deliver_webhook`](crate::Basileus::deliver_webhook).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum WebhookError {
+    #[error("failed to deliver webhook to {}", .0.iter().map(|x| redact_url(x)).collect::<Vec<_>>().join(", "))]
+    Undelivered(Vec<String>),
+}
+
+/// Failure to log in through SAML, see [`Basileus::saml_response`](crate::Basileus::saml_response).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SamlError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    #[error(transparent)]
+    CreateUser(#[from] CreateUserError),
+    #[error(transparent)]
+    GivePerm(#[from] GivePermError),
+    #[error("no SAML signature verifier is set")]
+    NoVerifier,
+    #[error("malformed SAML response: {0}")]
+    Malformed(String),
+    #[error("unsupported SAML response: {0}")]
+    Unsupported(String),
+    #[error("identity provider reported status '{0}'")]
+    Status(String),
+    #[error("SAML response is not signed by the identity provider")]
+    InvalidSignature,
+    #[error("SAML response issued by unexpected '{0}'")]
+    InvalidIssuer(String),
+    #[error("SAML response addressed to unexpected '{0}'")]
+    InvalidDestination(String),
+    #[error("SAML assertion not intended for this service provider")]
+    InvalidAudience,
+    #[error("SAML assertion expired or not yet valid")]
+    Expired,
+    #[error("SAML response to unknown or expired request")]
+    UnknownRequest,
+    #[error("SAML assertion lacks user attribute '{0}'")]
+    MissingUser(String),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+}
+
+/// Failure to link an external identity, see [`Basileus::link_identity`](crate::Basileus::link_identity).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LinkIdentityError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("identity '{1}' at '{0}' is already linked")]
+    AlreadyLinked(String, String),
+}
+
+/// Failure to log in through an upstream OpenID Connect provider, see [`Basileus::oidc_callback`](crate::Basileus::oidc_callback).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OidcError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    #[error(transparent)]
+    CreateUser(#[from] CreateUserError),
+    #[error("unknown OIDC provider '{0}'")]
+    UnknownProvider(String),
+    #[error("unknown or expired OIDC state")]
+    UnknownState,
+    #[error("failed to exchange authorization code: {0}")]
+    Exchange(String),
+    #[error("malformed ID token: {0}")]
+    Malformed(String),
+    #[error("unsupported ID token algorithm '{0}'")]
+    UnsupportedAlgorithm(String),
+    #[error("no OIDC signature verifier is set")]
+    NoVerifier,
+    #[error("invalid ID token signature")]
+    InvalidSignature,
+    #[error("invalid ID token claim '{0}'")]
+    InvalidClaims(String),
+    #[error("ID token lacks claim '{0}'")]
+    MissingClaim(String),
+    #[error("ID token expired or issued in the future")]
+    Expired,
+    #[error("identity '{0}' is not linked to any user")]
+    Unlinked(String),
+}
+
+/// Failure to set a contact address, see [`Basileus::set_contact`](crate::Basileus::set_contact).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ContactError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("address '{0}' is taken by another user")]
+    AddressTaken(String),
+}
+
+/// Failure to send a message to a user, see [`Basileus::send_message`](crate::Basileus::send_message).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SendMessageError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("no messenger is set")]
+    NoMessenger,
+    #[error("user '{0}' has no {channel} address", channel = .1.name())]
+    NoContact(String, Channel),
+    #[error("failed to deliver message: {0}")]
+    Delivery(String),
+}
+
+/// Failure of a magic link login, see [`Basileus::begin_magic_login`](crate::Basileus::begin_magic_login).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MagicLinkError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Send(#[from] SendMessageError),
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    #[error("magic links are not configured")]
+    Unconfigured,
+    #[error("invalid or used magic link")]
+    InvalidToken,
+    #[error("expired magic link")]
+    Expired,
+    #[error("magic link login is disabled")]
+    FeatureDisabled,
+}
+
+/// Failure of a cross-device login, see [`Basileus::poll_qr_login`](crate::Basileus::poll_qr_login).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum QrLoginError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("unknown or completed login request")]
+    InvalidCode,
+    #[error("expired login request")]
+    Expired,
+    #[error("login request was denied")]
+    Denied,
+    #[error("polled too frequently")]
+    SlowDown,
+    #[error("cross-device login is disabled")]
+    FeatureDisabled,
+}
+
+/// Failure to manage second factors, see [`Basileus::begin_totp_enrollment`](crate::Basileus::begin_totp_enrollment).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MfaError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error("user '{0}' does not exist")]
+    UserNotExist(String),
+    #[error("user '{0}' has no such second factor")]
+    NotEnrolled(String),
+    #[error("user '{0}' already enabled this second factor")]
+    AlreadyEnrolled(String),
+    #[error("user '{0}' has no verified {channel} address", channel = .1.name())]
+    Unverified(String, Channel),
+    #[error("no WebAuthn verifier is set")]
+    NoVerifier,
+    #[error("no push approver is set")]
+    NoApprover,
+    #[error("this kind of second factor is disabled")]
+    FeatureDisabled,
+}
+
+/// Failure to deliver or accept a one-time code, see [`Basileus::send_login_code`](crate::Basileus::send_login_code).
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OobError {
+    #[error(transparent)]
+    SQL(#[from] sqlx::error::Error),
+    #[error(transparent)]
+    Send(#[from] SendMessageError),
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    #[error("user '{0}' has no verified {channel} address", channel = .1.name())]
+    Unverified(String, Channel),
+    #[error("a code was sent recently")]
+    SlowDown,
+    /// Either login codes are [disabled](crate::config::ModuleConfig::oob) altogether,
+    /// or [passwordless](crate::oob::OobConfig::passwordless) logins are not allowed.
+    #[error("login by code is disabled")]
+    Disabled,
+    #[error("invalid or expired code")]
+    InvalidCode,
+}
+
+impl From<LoginError> for PkceAuthError {
+    fn from(e: LoginError) -> Self {
+        match e {
+            LoginError::Unauthorized => Self::Unauthorized,
+            LoginError::RateLimited(x) => Self::RateLimited(x),
+            e => Self::Login(e),
+        }
+    }
+}
+
+impl From<VerifyPassError> for LoginError {
+    fn from(e: VerifyPassError) -> Self {
+        // do not tell clients whether the user exists
+        match e {
+            VerifyPassError::Argon2(e) => Self::Argon2(e),
+            VerifyPassError::SQL(e) => Self::SQL(e),
+            _ => Self::Unauthorized,
+        }
+    }
+}
+
+/// Stable machine-readable identification of errors, e.g. for API responses,
+/// which does not change with error messages.
+pub trait ErrorCode {
+    /// Code of the error in snake case, e.g. `user_not_found`.
+    ///
+    /// Errors wrapping another error share its code.
+    fn code(&self) -> &'static str;
+
+    /// Whether the error is temporary, e.g. a locked database or a timeout,
+    /// so that retrying the operation may succeed.
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
+
+/// An error together with the context it occurred in, see [`Basileus::traced`](crate::Basileus::traced).
+#[derive(Debug)]
+pub struct ErrorContext<E> {
+    /// Name of the operation which failed.
+    pub op: &'static str,
+    /// The user affected, [redacted](crate::logging::LogConfig::hash_users) as in logs.
+    pub user: Option<String>,
+    /// Time the operation took until failing.
+    pub elapsed: Duration,
+    /// The error itself.
+    pub error: E,
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ErrorContext<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.op)?;
+        if let Some(user) = &self.user {
+            write!(f, " for {user}")?;
+        }
+        write!(
+            f,
+            " failed after {} ms: {}",
+            self.elapsed.as_millis(),
+            self.error
+        )
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for ErrorContext<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<E: ErrorCode> ErrorCode for ErrorContext<E> {
+    fn code(&self) -> &'static str {
+        self.error.code()
+    }
+
+    fn is_transient(&self) -> bool {
+        self.error.is_transient()
+    }
+}
+
+/// Recommended HTTP status code of an error with the given [code](ErrorCode::code),
+/// so that web adapters respond consistently.
+///
+/// Unknown codes are treated as internal errors.
+pub fn http_status(code: &str) -> u16 {
+    match code {
+        "unauthorized"
+        | "missing_token"
+        | "invalid_token"
+        | "empty_credentials"
+        | "unsupported_scheme"
+        | "missing_credentials"
+        | "malformed_token"
+        | "malformed_credentials"
+        | "password_undefined"
+        | "legacy_hash"
+        | "invalid_code"
+        | "expired_code"
+        | "invalid_verifier"
+        | "idp_error"
+        | "invalid_signature"
+        | "invalid_issuer"
+        | "invalid_destination"
+        | "invalid_audience"
+        | "expired_assertion"
+        | "unknown_request"
+        | "unsupported_algorithm"
+        | "invalid_claims"
+        | "missing_claim"
+        | "expired_token"
+        | "challenge_required"
+        | "challenge_failed"
+        | "mfa_required"
+        | "invalid_otp"
+        | "push_denied"
+        | "push_timeout"
+        | "reauth_required" => 401,
+        "forbidden"
+        | "feature_disabled"
+        | "unlinked_identity"
+        | "access_denied"
+        | "mfa_enrollment_required"
+        | "ip_blocked"
+        | "account_locked"
+        | "risk_denied" => 403,
+        "user_not_found" | "role_not_found" | "group_not_found" | "unknown_provider"
+        | "mfa_not_enrolled" => 404,
+        "user_already_exists"
+        | "role_already_exists"
+        | "group_already_exists"
+        | "identity_already_linked"
+        | "address_taken"
+        | "mfa_already_enrolled" => 409,
+        "invalid_name"
+        | "empty_name"
+        | "name_too_long"
+        | "invalid_name_char"
+        | "reserved_name"
+        | "invalid_role_name"
+        | "invalid_perm"
+        | "empty_perm"
+        | "empty_segment"
+        | "misplaced_wildcard"
+        | "invalid_char"
+        | "unsupported_method"
+        | "insecure_plain"
+        | "malformed_saml"
+        | "unsupported_saml"
+        | "missing_user_attribute"
+        | "no_contact"
+        | "unverified_contact"
+        | "self_impersonation"
+        | "missing_reason" => 422,
+        "slow_down" | "rate_limited" => 429,
+        "delivery_failed" | "push_failed" => 502,
+        _ => 500,
+    }
+}
+
+/// Recommended HTTP status of errors, see [`http_status`].
+///
+/// [Transient](ErrorCode::is_transient) errors map to 503, inviting clients to retry.
+pub trait HttpStatus: ErrorCode {
+    fn http_status(&self) -> u16 {
+        if self.is_transient() {
+            return 503;
+        }
+        http_status(self.code())
+    }
+}
+
+impl<T: ErrorCode + ?Sized> HttpStatus for T {}
+
+/// Representation of an error safe to return to clients,
+/// serialized as `{"code": .., "message": .., "detail": ..}`.
+///
+/// Internal errors, e.g. of the database, are reduced to their code,
+/// so that no SQL or other implementation details leak.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ErrorBody {
+    /// Stable [code](ErrorCode::code) of the error.
+    pub code: String,
+    /// Generic description of the kind of error.
+    pub message: String,
+    /// Description of the specific error, omitted for internal errors.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub detail: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+impl ErrorBody {
+    pub fn new<E: ErrorCode + std::fmt::Display + ?Sized>(e: &E) -> Self {
+        let code = e.code();
+        let status = http_status(code);
+        let message = match status {
+            401 => "unauthorized",
+            403 => "forbidden",
+            404 => "not found",
+            409 => "conflict",
+            422 => "invalid request",
+            429 => "too many requests",
+            _ => "internal error",
+        };
+        Self {
+            code: code.into(),
+            message: message.into(),
+            detail: (status < 500).then(|| e.to_string()),
+        }
+    }
+}
+
+/// An OAuth 2.0 error response, as defined in [RFC 6749](https://datatracker.ietf.org/doc/html/rfc6749#section-5.2).
+///
+/// Converted from the PKCE errors with a fixed description for each kind of error,
+/// so that no internal error formatting leaks to clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct OAuthErrorResponse {
+    /// The `error` code, e.g. `invalid_grant`.
+    pub error: String,
+    /// The human-readable `error_description`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_serializing_if = "Option::is_none", default)
+    )]
+    pub error_description: Option<String>,
+}
+
+impl OAuthErrorResponse {
+    pub fn new(error: &str, error_description: &str) -> Self {
+        Self {
+            error: error.into(),
+            error_description: Some(error_description.into()),
+        }
+    }
+
+    /// Recommended HTTP status of the response.
+    pub fn status(&self) -> u16 {
+        match self.error.as_str() {
+            "invalid_client" => 401,
+            "slow_down" => 429,
+            "server_error" => 500,
+            "temporarily_unavailable" => 503,
+            _ => 400,
+        }
+    }
+
+    fn internal(e: &impl ErrorCode) -> Self {
+        match e.is_transient() {
+            true => Self::new(
+                "temporarily_unavailable",
+                "the server is temporarily unable to handle the request",
+            ),
+            false => Self::new("server_error", "internal error"),
+        }
+    }
+}
+
+impl From<PkceAuthError> for OAuthErrorResponse {
+    fn from(e: PkceAuthError) -> Self {
+        match e {
+            PkceAuthError::VerifyPass(
+                VerifyPassError::UserNotExist(_)
+                | VerifyPassError::PassUndefined(_)
+                | VerifyPassError::LegacyHash(_),
+            )
+            | PkceAuthError::Unauthorized => Self::new("access_denied", "invalid credentials"),
+            PkceAuthError::VerifyPass(e) => Self::internal(&e),
+            PkceAuthError::UnsupportedMethod => {
+                Self::new("invalid_request", "transform algorithm not supported")
+            }
+            PkceAuthError::InsecurePlain => Self::new(
+                "invalid_request",
+                "`plain` code challenge method is not allowed",
+            ),
+            PkceAuthError::FeatureDisabled => Self::new(
+                "unsupported_response_type",
+                "authorization code flow is disabled",
+            ),
+            PkceAuthError::RateLimited(_) => {
+                Self::new("temporarily_unavailable", "too many failed attempts")
+            }
+            PkceAuthError::Login(LoginError::MfaRequired(_)) => {
+                Self::new("access_denied", "second factor required")
+            }
+            PkceAuthError::Login(LoginError::InvalidOtp) => {
+                Self::new("access_denied", "invalid one-time password")
+            }
+            PkceAuthError::Login(LoginError::PushDenied) => {
+                Self::new("access_denied", "push approval denied")
+            }
+            PkceAuthError::Login(LoginError::PushTimedOut) => {
+                Self::new("access_denied", "push approval timed out")
+            }
+            PkceAuthError::Login(LoginError::MfaEnrollmentRequired) => {
+                Self::new("access_denied", "second factor enrollment required")
+            }
+            PkceAuthError::Login(LoginError::Challenge(_)) => {
+                Self::new("access_denied", "challenge required")
+            }
+            PkceAuthError::Login(LoginError::AccountLocked(_)) => {
+                Self::new("access_denied", "account locked")
+            }
+            PkceAuthError::Login(LoginError::RiskDenied) => {
+                Self::new("access_denied", "login denied as too risky")
+            }
+            PkceAuthError::Login(LoginError::IpBlocked) => {
+                Self::new("access_denied", "source address blocked")
+            }
+            PkceAuthError::Login(LoginError::Unauthorized) => {
+                Self::new("access_denied", "invalid credentials")
+            }
+            PkceAuthError::Login(e) => Self::internal(&e),
+        }
+    }
+}
+
+impl From<PkceTokenError> for OAuthErrorResponse {
+    fn from(e: PkceTokenError) -> Self {
+        match e {
+            PkceTokenError::InvalidCode => Self::new("invalid_grant", "invalid authorization code"),
+            PkceTokenError::ExpiredCode => Self::new("invalid_grant", "expired authorization code"),
+            PkceTokenError::InvalidVerifier => Self::new("invalid_grant", "invalid code verifier"),
+            PkceTokenError::FeatureDisabled => Self::new(
+                "unsupported_grant_type",
+                "authorization code flow is disabled",
+            ),
+            PkceTokenError::RateLimited(_) => Self::new("slow_down", "too many token requests"),
+        }
+    }
+}
+
+macro_rules! error_codes {
+    ($($ty:ident { $($variant:ident $(($e:ident))? $(=> $code:literal)?,)* })*) => {
+        $(impl ErrorCode for $ty {
+            fn code(&self) -> &'static str {
+                match self {
+                    $(Self::$variant { $(0: $e,)? .. } => error_codes!(@code $($e)?; $($code)?),)*
+                }
+            }
+
+            fn is_transient(&self) -> bool {
+                match self {
+                    $(Self::$variant { $(0: $e,)? .. } => error_codes!(@transient $($e)?),)*
+                }
+            }
+        })*
+    };
+    (@code ; $code:literal) => { $code };
+    (@code $e:ident ;) => { $e.code() };
+    (@transient) => { false };
+    (@transient $e:ident) => { $e.is_transient() };
+}
+
+impl ErrorCode for sqlx::error::Error {
+    fn code(&self) -> &'static str {
+        "database_error"
+    }
+
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::PoolTimedOut | Self::Io(_) => true,
+            // `SQLITE_BUSY` and `SQLITE_LOCKED`, including their extended codes
+            Self::Database(e) => e
+                .code()
+                .and_then(|x| x.parse::<i32>().ok())
+                .is_some_and(|x| matches!(x & 0xff, 5 | 6)),
+            _ => false,
+        }
+    }
+}
+
+impl ErrorCode for argon2::Error {
+    fn code(&self) -> &'static str {
+        "hash_error"
+    }
+}
+
+error_codes! {
+    UsernameError {
+        Empty => "empty_name",
+        TooLong => "name_too_long",
+        InvalidChar => "invalid_name_char",
+        Reserved => "reserved_name",
+    }
+    CreateUserError {
+        SQL(e),
+        UserAlreadyExist => "user_already_exists",
+        InvalidName => "invalid_name",
+    }
+    UpdatePassError {
+        Argon2(e),
+        SQL(e),
+        UserNotExist => "user_not_found",
+    }
+    VerifyPassError {
+        Argon2(e),
+        SQL(e),
+        UserNotExist => "user_not_found",
+        PassUndefined => "password_undefined",
+        LegacyHash => "legacy_hash",
+    }
+    DeleteUserError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+    }
+    DeletePassError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        PassUndefined => "password_undefined",
+    }
+    GetPermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+    }
+    GivePermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        InvalidPerm => "invalid_perm",
+        GetDirectPerm(e),
+        SetPerm(e),
+    }
+    SetPermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        InvalidPerm => "invalid_perm",
+    }
+    RevokePermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        GetPerm(e),
+        SetPerm(e),
+    }
+    CheckPermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        GetDirectPerm(e),
+        SetPerm(e),
+    }
+    ApplyPermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        InvalidPerm => "invalid_perm",
+    }
+    DelegatePermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        Forbidden => "forbidden",
+        GetPerm(e),
+        GivePerm(e),
+        RevokePerm(e),
+    }
+    ParsePermError {
+        Empty => "empty_perm",
+        EmptySegment => "empty_segment",
+        MisplacedWildcard => "misplaced_wildcard",
+        InvalidChar => "invalid_char",
+    }
+    PkceAuthError {
+        VerifyPass(e),
+        Unauthorized => "unauthorized",
+        UnsupportedMethod => "unsupported_method",
+        InsecurePlain => "insecure_plain",
+        FeatureDisabled => "feature_disabled",
+        RateLimited => "rate_limited",
+        Login(e),
+    }
+    PkceTokenError {
+        InvalidCode => "invalid_code",
+        ExpiredCode => "expired_code",
+        InvalidVerifier => "invalid_verifier",
+        FeatureDisabled => "feature_disabled",
+        RateLimited => "rate_limited",
+    }
+    CreateRoleError {
+        SQL(e),
+        RoleAlreadyExist => "role_already_exists",
+        InvalidName => "invalid_name",
+        InvalidPerm => "invalid_perm",
+    }
+    DeleteRoleError {
+        SQL(e),
+        RoleNotExist => "role_not_found",
+    }
+    GetRolePermError {
+        SQL(e),
+        RoleNotExist => "role_not_found",
+    }
+    SetRolePermError {
+        SQL(e),
+        RoleNotExist => "role_not_found",
+        InvalidPerm => "invalid_perm",
+    }
+    AssignRoleError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        RoleNotExist => "role_not_found",
+    }
+    GetUserRoleError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+    }
+    AclGrantError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        InvalidPerm => "invalid_perm",
+    }
+    AclCheckError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        GetPerm(e),
+    }
+    EvaluatePolicyError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        GetPerm(e),
+    }
+    CreateGroupError {
+        SQL(e),
+        GroupAlreadyExist => "group_already_exists",
+        InvalidName => "invalid_name",
+    }
+    DeleteGroupError {
+        SQL(e),
+        GroupNotExist => "group_not_found",
+    }
+    GetGroupError {
+        SQL(e),
+        GroupNotExist => "group_not_found",
+    }
+    ImportPermError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        RoleNotExist => "role_not_found",
+        InvalidRoleName => "invalid_role_name",
+        InvalidPerm => "invalid_perm",
+    }
+    RestoreError {
+        SQL(e),
+        VersionMismatch => "version_mismatch",
+        InvalidName => "invalid_name",
+        InvalidPerm => "invalid_perm",
+        Inconsistent => "inconsistent_backup",
+    }
+    ConfigProblem {
+        EmptyPath => "empty_path",
+        Unwritable => "unwritable",
+        UnsupportedBackend => "unsupported_backend",
+        ZeroConnections => "zero_connections",
+        MinExceedMax => "min_exceed_max",
+        InvalidTablePrefix => "invalid_table_prefix",
+        ZeroInterval => "zero_interval",
+        ZeroTokenTtl => "zero_token_ttl",
+        ZeroMaxSessions => "zero_max_sessions",
+        ShortToken => "short_token",
+        WeakHash => "weak_hash",
+        InvalidHash => "invalid_hash",
+        ZeroWindow => "zero_window",
+        ZeroNameLength => "zero_name_length",
+        InsecureSameSite => "insecure_same_site",
+        ShortSecret => "short_secret",
+        InvalidPerm => "invalid_perm",
+        InvalidSmtp => "invalid_smtp",
+        InvalidOtp => "invalid_otp_config",
+        InvalidMfaGroup => "invalid_mfa_group",
+        ZeroLockout => "zero_lockout",
+        InvalidNetwork => "invalid_network",
+        InvalidHours => "invalid_hours",
+        InsecurePlainPkce => "insecure_plain_pkce",
+    }
+    ConfigError {
+        Invalid => "invalid_config",
+        InvalidEnv => "invalid_env",
+        RequiresRestart => "requires_restart",
+        Read => "config_unreadable",
+        Parse => "config_syntax",
+        UnknownFormat => "unknown_config_format",
+        UnresolvedSecret => "unresolved_secret",
+    }
+    InitError {
+        SQL(e),
+        Config(e),
+    }
+    AuthError {
+        SQL(e),
+        MissingToken => "missing_token",
+        InvalidToken => "invalid_token",
+        Forbidden => "forbidden",
+        Credentials(e),
+        ReauthRequired => "reauth_required",
+    }
+    CredentialsError {
+        Empty => "empty_credentials",
+        UnsupportedScheme => "unsupported_scheme",
+        MissingValue => "missing_credentials",
+        InvalidToken => "malformed_token",
+        InvalidBase64 => "malformed_credentials",
+        InvalidUtf8 => "malformed_credentials",
+        MissingColon => "malformed_credentials",
+        FeatureDisabled => "feature_disabled",
+    }
+    LoginError {
+        SQL(e),
+        Argon2(e),
+        Unauthorized => "unauthorized",
+        Challenge(e),
+        RateLimited => "rate_limited",
+        MfaRequired => "mfa_required",
+        InvalidOtp => "invalid_otp",
+        MfaEnrollmentRequired => "mfa_enrollment_required",
+        PushDenied => "push_denied",
+        PushTimedOut => "push_timeout",
+        PushFailed => "push_failed",
+        IpBlocked => "ip_blocked",
+        AccountLocked => "account_locked",
+        RiskDenied => "risk_denied",
+    }
+    ChallengeError {
+        Required => "challenge_required",
+        Failed => "challenge_failed",
+    }
+    WebhookError {
+        Undelivered => "webhook_undelivered",
+    }
+    ImpersonateError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        SelfImpersonation => "self_impersonation",
+        MissingReason => "missing_reason",
+        Forbidden => "forbidden",
+        GetPerm(e),
+        FeatureDisabled => "feature_disabled",
+    }
+    SamlError {
+        SQL(e),
+        Login(e),
+        CreateUser(e),
+        GivePerm(e),
+        NoVerifier => "saml_unconfigured",
+        Malformed => "malformed_saml",
+        Unsupported => "unsupported_saml",
+        Status => "idp_error",
+        InvalidSignature => "invalid_signature",
+        InvalidIssuer => "invalid_issuer",
+        InvalidDestination => "invalid_destination",
+        InvalidAudience => "invalid_audience",
+        Expired => "expired_assertion",
+        UnknownRequest => "unknown_request",
+        MissingUser => "missing_user_attribute",
+        UserNotExist => "user_not_found",
+    }
+    LinkIdentityError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        AlreadyLinked => "identity_already_linked",
+    }
+    OidcError {
+        SQL(e),
+        Login(e),
+        CreateUser(e),
+        UnknownProvider => "unknown_provider",
+        UnknownState => "unknown_request",
+        Exchange => "token_exchange_failed",
+        Malformed => "malformed_token",
+        UnsupportedAlgorithm => "unsupported_algorithm",
+        NoVerifier => "oidc_unconfigured",
+        InvalidSignature => "invalid_signature",
+        InvalidClaims => "invalid_claims",
+        MissingClaim => "missing_claim",
+        Expired => "expired_token",
+        Unlinked => "unlinked_identity",
+    }
+    ContactError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        AddressTaken => "address_taken",
+    }
+    SendMessageError {
+        SQL(e),
+        NoMessenger => "messenger_unconfigured",
+        NoContact => "no_contact",
+        Delivery => "delivery_failed",
+    }
+    MagicLinkError {
+        SQL(e),
+        Send(e),
+        Login(e),
+        Unconfigured => "magic_link_unconfigured",
+        InvalidToken => "invalid_token",
+        Expired => "expired_token",
+        FeatureDisabled => "feature_disabled",
+    }
+    QrLoginError {
+        SQL(e),
+        Login(e),
+        UserNotExist => "user_not_found",
+        InvalidCode => "invalid_code",
+        Expired => "expired_code",
+        Denied => "access_denied",
+        SlowDown => "slow_down",
+        FeatureDisabled => "feature_disabled",
+    }
+    MfaError {
+        SQL(e),
+        UserNotExist => "user_not_found",
+        NotEnrolled => "mfa_not_enrolled",
+        AlreadyEnrolled => "mfa_already_enrolled",
+        Unverified => "unverified_contact",
+        NoVerifier => "webauthn_unconfigured",
+        NoApprover => "push_unconfigured",
+        FeatureDisabled => "feature_disabled",
+    }
+    OobError {
+        SQL(e),
+        Send(e),
+        Login(e),
+        Unverified => "unverified_contact",
+        SlowDown => "slow_down",
+        Disabled => "feature_disabled",
+        InvalidCode => "invalid_code",
+    }
 }
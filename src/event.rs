@@ -0,0 +1,300 @@
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use crate::{Basileus, audit::AuditEvent, perm::PermEvent};
+
+/// Identifies a subscription, used to unsubscribe later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Callback<E> = Arc<dyn Fn(&E) + Send + Sync>;
+
+/// A list of callbacks subscribed to events of type `E`.
+///
+/// Callbacks are invoked synchronously in the order of subscription,
+/// so they should return quickly, e.g. by forwarding the event into a channel.
+pub struct Subscribers<E> {
+    next: AtomicU64,
+    list: RwLock<Vec<(SubscriptionId, Callback<E>)>>,
+}
+
+impl<E> Default for Subscribers<E> {
+    fn default() -> Self {
+        Self {
+            next: AtomicU64::new(0),
+            list: RwLock::new(vec![]),
+        }
+    }
+}
+
+impl<E> Subscribers<E> {
+    /// Register a callback invoked on every event.
+    pub fn subscribe(&self, f: impl Fn(&E) + Send + Sync + 'static) -> SubscriptionId {
+        let id = SubscriptionId(self.next.fetch_add(1, Ordering::Relaxed));
+        self.list.write().unwrap().push((id, Arc::new(f)));
+        id
+    }
+
+    /// Remove a previously registered callback.
+    /// Returns whether the subscription existed.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut list = self.list.write().unwrap();
+        let prev = list.len();
+        list.retain(|(x, _)| *x != id);
+        list.len() != prev
+    }
+
+    /// Invoke every registered callback with the event.
+    pub fn emit(&self, event: &E) {
+        // clone the list so that callbacks may (un)subscribe without deadlocking
+        let list: Vec<_> = self
+            .list
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(_, f)| f.clone())
+            .collect();
+        for f in list {
+            f(event);
+        }
+    }
+}
+
+/// Severity of an [`AuthEvent`], ordered from least to most severe,
+/// e.g. to page an operator on [critical](Self::Critical) events only.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Severity {
+    /// Routine operation, e.g. a successful login.
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "info"))]
+    Info,
+    /// Possibly malicious activity, e.g. a wrong password.
+    #[cfg_attr(feature = "serde", serde(rename = "warning"))]
+    Warning,
+    /// Likely an attack in progress, e.g. an account locked after repeated wrong passwords.
+    #[cfg_attr(feature = "serde", serde(rename = "critical"))]
+    Critical,
+}
+
+impl Severity {
+    /// Name of the severity, as in its serialized form.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Selects [`AuthEvent`]s passed to a subscriber, see [`Basileus::subscribe_events_filtered`].
+///
+/// The default filter accepts every event.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Least severity of events accepted.
+    pub severity: Severity,
+    /// [Types](AuthEvent::kind) of events accepted, e.g. `login_failed`, or every type if empty.
+    pub kinds: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept only events at least as severe as `severity`.
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Accept events of type `kind`, besides those of types previously added.
+    pub fn kind(mut self, kind: impl Into<String>) -> Self {
+        self.kinds.push(kind.into());
+        self
+    }
+
+    /// Whether `event` is accepted.
+    pub fn matches(&self, event: &AuthEvent) -> bool {
+        event.severity() >= self.severity
+            && (self.kinds.is_empty() || self.kinds.iter().any(|x| x == event.kind()))
+    }
+}
+
+/// An event of the authentication lifecycle, see [`Basileus::subscribe_events`].
+///
+/// This is what downstream systems, e.g. SIEMs, are kept in sync with, possibly through [webhooks](crate::webhook).
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
+#[non_exhaustive]
+pub enum AuthEvent {
+    #[cfg_attr(feature = "serde", serde(rename = "user_created"))]
+    UserCreated { user: String },
+    #[cfg_attr(feature = "serde", serde(rename = "user_deleted"))]
+    UserDeleted { user: String },
+    #[cfg_attr(feature = "serde", serde(rename = "pass_changed"))]
+    PassChanged { user: String },
+    /// A password was verified successfully.
+    #[cfg_attr(feature = "serde", serde(rename = "login_succeeded"))]
+    LoginSucceeded { user: String },
+    /// A wrong password was given, or the user or their password does not exist.
+    #[cfg_attr(feature = "serde", serde(rename = "login_failed"))]
+    LoginFailed { user: String },
+    #[cfg_attr(feature = "serde", serde(rename = "token_issued"))]
+    TokenIssued { user: String },
+    /// A token was invalidated explicitly, not by expiring.
+    #[cfg_attr(feature = "serde", serde(rename = "token_revoked"))]
+    TokenRevoked { user: String },
+    /// Every token of a user was invalidated.
+    #[cfg_attr(feature = "serde", serde(rename = "sessions_revoked"))]
+    SessionsRevoked { user: String },
+    /// An account was locked by the [lockout policy](crate::lockout::LockoutPolicy)
+    /// after `failures` wrong passwords, until the given time or until an admin unlocks it.
+    #[cfg_attr(feature = "serde", serde(rename = "account_locked"))]
+    AccountLocked {
+        user: String,
+        until: Option<SystemTime>,
+        failures: u32,
+    },
+    /// A locked account was [unlocked](Basileus::unlock_user) by an admin.
+    #[cfg_attr(feature = "serde", serde(rename = "account_unlocked"))]
+    AccountUnlocked { user: String },
+    /// An admin started [impersonating](Basileus::impersonate) a user.
+    #[cfg_attr(feature = "serde", serde(rename = "impersonation_started"))]
+    ImpersonationStarted {
+        id: String,
+        admin: String,
+        user: String,
+        reason: String,
+    },
+    /// An impersonation was [ended](Basileus::end_impersonation) before its token expired.
+    #[cfg_attr(feature = "serde", serde(rename = "impersonation_ended"))]
+    ImpersonationEnded {
+        id: String,
+        admin: String,
+        user: String,
+    },
+    /// Effective permissions changed, as reported to [`Basileus::subscribe_perm`].
+    #[cfg_attr(feature = "serde", serde(rename = "perm_changed"))]
+    PermChanged { change: PermEvent },
+    /// A [canary token](Basileus::issue_canary_token) labelled `label` was presented,
+    /// with the thumbprint of the client certificate, the source address and the operation of the request, if known.
+    #[cfg_attr(feature = "serde", serde(rename = "canary_tripped"))]
+    CanaryTripped {
+        label: String,
+        cert: Option<String>,
+        ip: Option<IpAddr>,
+        op: Option<String>,
+    },
+}
+
+impl AuthEvent {
+    /// Name of the type of event, e.g. `login_failed`, as in its serialized form.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UserCreated { .. } => "user_created",
+            Self::UserDeleted { .. } => "user_deleted",
+            Self::PassChanged { .. } => "pass_changed",
+            Self::LoginSucceeded { .. } => "login_succeeded",
+            Self::LoginFailed { .. } => "login_failed",
+            Self::TokenIssued { .. } => "token_issued",
+            Self::TokenRevoked { .. } => "token_revoked",
+            Self::SessionsRevoked { .. } => "sessions_revoked",
+            Self::AccountLocked { .. } => "account_locked",
+            Self::AccountUnlocked { .. } => "account_unlocked",
+            Self::ImpersonationStarted { .. } => "impersonation_started",
+            Self::ImpersonationEnded { .. } => "impersonation_ended",
+            Self::PermChanged { .. } => "perm_changed",
+            Self::CanaryTripped { .. } => "canary_tripped",
+        }
+    }
+
+    /// How alarming the event is, e.g. for deciding whether to page an operator.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::UserCreated { .. }
+            | Self::UserDeleted { .. }
+            | Self::PassChanged { .. }
+            | Self::LoginSucceeded { .. }
+            | Self::TokenIssued { .. }
+            | Self::TokenRevoked { .. }
+            | Self::SessionsRevoked { .. }
+            | Self::AccountUnlocked { .. }
+            | Self::ImpersonationEnded { .. }
+            | Self::PermChanged { .. } => Severity::Info,
+            Self::LoginFailed { .. } | Self::ImpersonationStarted { .. } => Severity::Warning,
+            Self::AccountLocked { .. } | Self::CanaryTripped { .. } => Severity::Critical,
+        }
+    }
+}
+
+impl Basileus {
+    /// Register a callback invoked on every [`AuthEvent`].
+    ///
+    /// Callbacks are invoked synchronously, so they should return quickly, e.g. by forwarding the event into a channel.
+    pub fn subscribe_events(
+        &self,
+        f: impl Fn(&AuthEvent) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        self.events.subscribe(f)
+    }
+
+    /// Register a callback invoked on every [`AuthEvent`] accepted by `filter`,
+    /// e.g. to drive alerting from [critical](Severity::Critical) events.
+    ///
+    /// Like [`Self::subscribe_events`], callbacks are invoked synchronously.
+    pub fn subscribe_events_filtered(
+        &self,
+        filter: EventFilter,
+        f: impl Fn(&AuthEvent) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        self.events.subscribe(move |event| {
+            if filter.matches(event) {
+                f(event)
+            }
+        })
+    }
+
+    /// Remove a callback registered by [`Self::subscribe_events`] or [`Self::subscribe_events_filtered`].
+    pub fn unsubscribe_events(&self, id: SubscriptionId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    /// Pass an event to every [subscriber](Self::subscribe_events).
+    pub(crate) fn emit(&self, event: AuthEvent) {
+        self.emit_from(event, None);
+    }
+
+    /// Like [`Self::emit`], recording the source address of the request with the [audit event](AuditEvent).
+    pub(crate) fn emit_from(&self, event: AuthEvent, ip: Option<IpAddr>) {
+        let audit = AuditEvent {
+            ip,
+            ..AuditEvent::from(&event)
+        };
+        self.audit(&audit);
+        self.events.emit(&event);
+    }
+
+    /// Pass a permission change to every [permission subscriber](Self::subscribe_perm) as well as every event subscriber.
+    pub(crate) fn emit_perm(&self, event: PermEvent) {
+        self.perm.events.emit(&event);
+        self.emit(AuthEvent::PermChanged { change: event });
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::SystemTime;
+
+use crate::{
+    Basileus,
+    err::{CreateGroupError, DeleteGroupError, GetGroupError},
+    from_unix,
+    perm::{Perm, PermEvent, PermPath},
+    unix_now,
+};
+use sqlx::{query, query_as};
+
+use tracing::info;
+
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS grp (
+    grp TEXT NOT NULL PRIMARY KEY,
+    description TEXT NOT NULL,
+    created_by TEXT,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_grp_grp ON grp (grp);
+"#;
+
+/// Metadata of an explicitly created group.
+///
+/// Permissions need not be created as groups before being granted,
+/// but doing so documents them and allows cleaning them up with [`Basileus::delete_group`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Group {
+    /// Name of the group, i.e. the permission it stands for.
+    pub name: String,
+    /// Human-readable description.
+    pub description: String,
+    /// The user who created the group, if known.
+    pub created_by: Option<String>,
+    /// Time of creation.
+    pub created_at: SystemTime,
+}
+
+impl Basileus {
+    /// Check whether a group has been created.
+    pub async fn exist_group(&self, group: &str) -> Result<bool, sqlx::error::Error> {
+        let query =
+            query_as(self.sql("SELECT EXISTS(SELECT 1 FROM grp WHERE grp = ?)")).bind(group);
+        let (res,): (i32,) = query.fetch_one(&self.db).await?;
+        Ok(res == 1)
+    }
+
+    /// Create a new group.
+    ///
+    /// The name must be a valid [`PermPath`].
+    pub async fn create_group(
+        &self,
+        group: &str,
+        description: &str,
+        created_by: Option<&str>,
+    ) -> Result<(), CreateGroupError> {
+        self.spanned("create_group", None, async {
+            if self.exist_group(group).await? {
+                return Err(CreateGroupError::GroupAlreadyExist(group.into()));
+            }
+            if group.parse::<PermPath>().is_err() {
+                return Err(CreateGroupError::InvalidName(group.into()));
+            }
+            let query = query(self.sql(
+                "INSERT INTO grp (grp, description, created_by, created_at) VALUES (?, ?, ?, ?);",
+            ))
+            .bind(group)
+            .bind(description)
+            .bind(created_by)
+            .bind(unix_now());
+            query.execute(&self.writer).await?;
+            info!("created group {group}");
+            Ok(())
+        })
+        .await
+    }
+
+    /// Get metadata of a group.
+    pub async fn get_group(&self, group: &str) -> Result<Group, GetGroupError> {
+        let query = query_as(
+            self.sql("SELECT grp, description, created_by, created_at FROM grp WHERE grp = ?"),
+        )
+        .bind(group);
+        let res: Option<(String, String, Option<String>, i64)> =
+            query.fetch_optional(&self.db).await?;
+        let Some((name, description, created_by, created_at)) = res else {
+            return Err(GetGroupError::GroupNotExist(group.into()));
+        };
+        Ok(Group {
+            name,
+            description,
+            created_by,
+            created_at: from_unix(created_at),
+        })
+    }
+
+    /// Update the description of a group.
+    pub async fn set_group_description(
+        &self,
+        group: &str,
+        description: &str,
+    ) -> Result<(), GetGroupError> {
+        let query = query(self.sql("UPDATE grp SET description = ? WHERE grp = ?"))
+            .bind(description)
+            .bind(group);
+        let res = query.execute(&self.writer).await?;
+        if res.rows_affected() == 0 {
+            return Err(GetGroupError::GroupNotExist(group.into()));
+        }
+        Ok(())
+    }
+
+    /// List all created groups.
+    pub async fn list_groups(&self) -> Result<Vec<Group>, sqlx::error::Error> {
+        let query = query_as(
+            self.sql("SELECT grp, description, created_by, created_at FROM grp ORDER BY grp"),
+        );
+        let res: Vec<(String, String, Option<String>, i64)> = query.fetch_all(&self.db).await?;
+        let res = res
+            .into_iter()
+            .map(|(name, description, created_by, created_at)| Group {
+                name,
+                description,
+                created_by,
+                created_at: from_unix(created_at),
+            })
+            .collect();
+        Ok(res)
+    }
+
+    /// Delete a group, removing it from the permissions of every user and role,
+    /// including deny entries of the group.
+    pub async fn delete_group(&self, group: &str) -> Result<(), DeleteGroupError> {
+        self.spanned("delete_group", None, async {
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql("DELETE FROM grp WHERE grp = ?")).bind(group);
+            if q.execute(&mut *tx).await?.rows_affected() == 0 {
+                return Err(DeleteGroupError::GroupNotExist(group.into()));
+            }
+            let member = Perm::from(format!("{group} !{group}"));
+            let q = query_as(
+                self.sql("SELECT DISTINCT user FROM user_perm WHERE grp = ? OR grp = '!' || ?"),
+            )
+            .bind(group)
+            .bind(group);
+            let users: Vec<(String,)> = q.fetch_all(&mut *tx).await?;
+            let mut events = vec![];
+            for (user,) in users {
+                let res = self
+                    .write_perm(&mut tx, None, &user, |prev| prev - &member)
+                    .await?;
+                if let Some((added, removed)) = res {
+                    events.push(PermEvent::User {
+                        user,
+                        actor: None,
+                        added,
+                        removed,
+                    });
+                }
+            }
+            let q = query(self.sql("DELETE FROM role_perm WHERE grp = ? OR grp = '!' || ?"))
+                .bind(group)
+                .bind(group);
+            q.execute(&mut *tx).await?;
+            tx.commit().await?;
+            // the whole deletion is committed at once, so caches are only invalidated afterwards
+            self.perm.invalidate_all();
+            for event in events {
+                self.emit_perm(event);
+            }
+            self.emit_perm(PermEvent::GroupDeleted {
+                group: group.into(),
+            });
+            info!("deleted group {group}");
+            Ok(())
+        })
+        .await
+    }
+}
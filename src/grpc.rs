@@ -0,0 +1,170 @@
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::{HeaderMap, HeaderValue, Request, Response, header};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    Basileus,
+    err::{AuthError, ErrorCode},
+    perm::Perm,
+    token::{AuthUser, Presentation},
+    tower::{bearer_token, presentation},
+};
+
+/// Layer authenticating gRPC calls, e.g. of a tonic server through `Server::builder().layer(..)`.
+///
+/// The token is read from the `authorization` metadata, and the [`AuthUser`] is inserted into the request extensions,
+/// where tonic handlers find it through `request.extensions()`.
+/// Rejected calls are answered with the gRPC status `UNAUTHENTICATED` or `PERMISSION_DENIED`.
+///
+/// Unlike a tonic interceptor, which is synchronous, this may look up permissions in the database.
+#[derive(Clone)]
+pub struct GrpcAuthLayer {
+    basileus: Arc<Basileus>,
+    /// Map from methods, e.g. `/pkg.Service/Method`, or whole services, e.g. `/pkg.Service`, to required permissions.
+    perms: Arc<HashMap<String, Perm>>,
+    /// Methods or services callable without a token.
+    public: Arc<HashSet<String>>,
+}
+
+impl GrpcAuthLayer {
+    /// Create a layer accepting any valid token for every method.
+    pub fn new(basileus: Arc<Basileus>) -> Self {
+        Self {
+            basileus,
+            perms: Default::default(),
+            public: Default::default(),
+        }
+    }
+
+    /// Require `perm` for calls of `method`, given as `/pkg.Service/Method`, or of every method of a service, given as `/pkg.Service`.
+    ///
+    /// A requirement of a method takes precedence over one of its service.
+    pub fn require(mut self, method: &str, perm: impl Into<Perm>) -> Self {
+        Arc::make_mut(&mut self.perms).insert(method.into(), perm.into());
+        self
+    }
+
+    /// Allow calls of `method`, or of every method of a service, without a token, e.g. for health checks.
+    pub fn public(mut self, method: &str) -> Self {
+        Arc::make_mut(&mut self.public).insert(method.into());
+        self
+    }
+
+    /// Look up the entry of a method or its service in `map`.
+    fn lookup<'a, T>(map: &'a HashMap<String, T>, path: &str) -> Option<&'a T> {
+        map.get(path).or_else(|| {
+            let (service, _) = path.rsplit_once('/')?;
+            map.get(service)
+        })
+    }
+
+    /// Authenticate a call, returning [`None`] for public methods.
+    async fn check(
+        &self,
+        path: &str,
+        headers: &HeaderMap,
+        from: &Presentation<'_>,
+    ) -> Result<Option<AuthUser>, AuthError> {
+        let public = self.public.contains(path)
+            || path
+                .rsplit_once('/')
+                .is_some_and(|(service, _)| self.public.contains(service));
+        if public {
+            return Ok(None);
+        }
+        let token = bearer_token(headers)?;
+        let auth = match Self::lookup(&self.perms, path) {
+            Some(perm) => self.basileus.authorize_from(&token, from, perm).await?,
+            None => self.basileus.authenticate_from(&token, from).await?,
+        };
+        Ok(Some(auth))
+    }
+}
+
+impl<S> Layer<S> for GrpcAuthLayer {
+    type Service = GrpcAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcAuthService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service produced by [`GrpcAuthLayer`].
+#[derive(Clone)]
+pub struct GrpcAuthService<S> {
+    inner: S,
+    layer: GrpcAuthLayer,
+}
+
+/// gRPC status code of an error, see [the gRPC documentation](https://grpc.github.io/grpc/core/md_doc_statuscodes.html).
+fn grpc_status(e: &AuthError) -> u16 {
+    match e {
+        AuthError::Forbidden(_) => 7,
+        AuthError::MissingToken
+        | AuthError::InvalidToken
+        | AuthError::Credentials(_)
+        | AuthError::ReauthRequired => 16,
+        e if e.is_transient() => 14,
+        _ => 13,
+    }
+}
+
+/// Response to a rejected call, consisting of trailers only.
+fn reject<B: Default>(e: &AuthError) -> Response<B> {
+    let mut res = Response::new(B::default());
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    headers.insert("grpc-status", HeaderValue::from(grpc_status(e)));
+    // the code is plain ASCII, so it needs no percent-encoding
+    headers.insert("grpc-message", HeaderValue::from_static(e.code()));
+    res
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for GrpcAuthService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // the ready service must be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let path = req.uri().path();
+            let from = presentation(req.extensions(), path);
+            match layer.check(path, req.headers(), &from).await {
+                Ok(auth) => {
+                    if let Some(auth) = auth {
+                        req.extensions_mut().insert(auth);
+                    }
+                    inner.call(req).await
+                }
+                Err(e) => Ok(reject(&e)),
+            }
+        })
+    }
+}
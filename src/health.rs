@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{Basileus, migrate::MIGRATIONS};
+use sqlx::query;
+
+use tracing::warn;
+
+/// Status report of the library, see [`Basileus::health`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Health {
+    /// Whether every check passed.
+    pub healthy: bool,
+    /// Round trip time of a trivial query, or [`None`] if the database is unreachable.
+    pub db_latency: Option<Duration>,
+    /// Error encountered accessing the database, if any.
+    pub db_error: Option<String>,
+    /// Current version of the database schema, if known.
+    pub schema_version: Option<i64>,
+    /// Number of migrations not yet applied to the database.
+    pub pending_migrations: usize,
+    /// Number of tokens currently issued.
+    pub tokens: usize,
+    /// Time the last [maintenance](Basileus::maintain) pass finished, if any.
+    pub last_maintenance: Option<SystemTime>,
+    /// Whether maintenance has fallen behind, i.e. no pass finished within twice the [interval](crate::maintenance::MaintenanceConfig::interval)
+    /// although maintenance has run before.
+    pub maintenance_stalled: bool,
+}
+
+impl Basileus {
+    /// Check the health of the library, e.g. for an HTTP health endpoint.
+    ///
+    /// This never fails; problems are reported in the returned [`Health`] instead.
+    pub async fn health(&self) -> Health {
+        let start = Instant::now();
+        let ping = query(self.sql("SELECT 1")).execute(&self.db).await;
+        let db_latency = start.elapsed();
+        let version = match ping {
+            Ok(_) => self.schema_version().await,
+            Err(e) => Err(e),
+        };
+        let (db_latency, db_error, schema_version) = match version {
+            Ok(version) => (Some(db_latency), None, Some(version)),
+            Err(e) => {
+                warn!("health check failed: {e}");
+                (None, Some(e.to_string()), None)
+            }
+        };
+        let pending_migrations = match schema_version {
+            Some(version) => MIGRATIONS.iter().filter(|m| m.version > version).count(),
+            None => 0,
+        };
+        let last_maintenance = self.last_maintenance();
+        let deadline = Duration::from_secs(self.config.read().unwrap().maintenance.interval * 2);
+        let maintenance_stalled =
+            last_maintenance.is_some_and(|x| x.elapsed().is_ok_and(|elapsed| elapsed > deadline));
+        Health {
+            healthy: db_error.is_none() && pending_migrations == 0 && !maintenance_stalled,
+            db_latency,
+            db_error,
+            schema_version,
+            pending_migrations,
+            tokens: self.token_cnt(),
+            last_maintenance,
+            maintenance_stalled,
+        }
+    }
+}
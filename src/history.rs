@@ -0,0 +1,115 @@
+use std::{net::IpAddr, time::SystemTime};
+
+use sqlx::{query, query_as};
+use tracing::warn;
+
+use crate::{Basileus, from_unix, to_unix};
+
+/// Initialize the table of login attempts, see [`Basileus::login_history`].
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS login_history (
+    id INTEGER NOT NULL PRIMARY KEY,
+    user TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    success BOOLEAN NOT NULL,
+    method TEXT NOT NULL,
+    ip TEXT,
+    user_agent TEXT,
+    code TEXT,
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_login_history_user ON login_history (user, time);
+"#;
+
+/// An attempt to log in as a user, see [`Basileus::login_history`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct LoginAttempt {
+    pub time: SystemTime,
+    pub success: bool,
+    /// How the user authenticated, i.e. `password`, `magic_link`, `code`, `qr`, `oidc` or `saml`.
+    pub method: String,
+    /// Source address of the attempt, if known.
+    pub ip: Option<IpAddr>,
+    /// User agent of the client, if known.
+    pub user_agent: Option<String>,
+    /// [Code](crate::err::ErrorCode::code) of the error if the attempt failed, e.g. `invalid_otp`.
+    pub code: Option<String>,
+}
+
+impl LoginAttempt {
+    pub(crate) fn new(method: &str) -> Self {
+        Self {
+            time: SystemTime::now(),
+            success: true,
+            method: method.into(),
+            ip: None,
+            user_agent: None,
+            code: None,
+        }
+    }
+}
+
+impl Basileus {
+    /// Recent attempts to log in as `user`, newest first and at most `limit`,
+    /// e.g. for a page showing users their recent activity.
+    ///
+    /// Attempts through [`Self::verify_login`] are recorded with the [client metadata](crate::login::LoginContext) given,
+    /// and successful logins by magic link, login code, QR code, OpenID Connect or SAML without.
+    /// Attempts are kept for [`MaintenanceConfig::login_history_retention`](crate::maintenance::MaintenanceConfig::login_history_retention).
+    pub async fn login_history(
+        &self,
+        user: &str,
+        limit: u32,
+    ) -> Result<Vec<LoginAttempt>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT time, success, method, ip, user_agent, code FROM login_history
+            WHERE user = ? ORDER BY time DESC, id DESC LIMIT ?",
+        ))
+        .bind(user)
+        .bind(limit);
+        type Row = (
+            i64,
+            bool,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        );
+        let res: Vec<Row> = q.fetch_all(&self.db).await?;
+        Ok(res
+            .into_iter()
+            .map(
+                |(time, success, method, ip, user_agent, code)| LoginAttempt {
+                    time: from_unix(time),
+                    success,
+                    method,
+                    ip: ip.and_then(|x| x.parse().ok()),
+                    user_agent,
+                    code,
+                },
+            )
+            .collect())
+    }
+
+    /// Record `attempt` to log in as `user`, unless the user does not exist.
+    ///
+    /// Failing to record is logged rather than failing the login.
+    pub(crate) async fn record_login_attempt(&self, user: &str, attempt: &LoginAttempt) {
+        let q = query(self.sql(
+            "INSERT INTO login_history (user, time, success, method, ip, user_agent, code)
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7 WHERE EXISTS (SELECT 1 FROM user WHERE user = ?1)",
+        ))
+        .bind(user)
+        .bind(to_unix(attempt.time))
+        .bind(attempt.success)
+        .bind(&attempt.method)
+        .bind(attempt.ip.map(|x| x.to_string()))
+        .bind(&attempt.user_agent)
+        .bind(&attempt.code);
+        if let Err(e) = q.execute(&self.writer).await {
+            warn!("failed to record login attempt: {e}");
+        }
+    }
+}
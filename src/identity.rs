@@ -0,0 +1,105 @@
+use std::time::SystemTime;
+
+use sqlx::{query, query_as};
+use tracing::info;
+
+use crate::{Basileus, err::LinkIdentityError, from_unix, unix_now};
+
+/// Initialize the table of linked external identities.
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS identity (
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user TEXT NOT NULL,
+    linked_at INTEGER NOT NULL,
+    PRIMARY KEY (provider, subject),
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_identity_user ON identity (user);
+"#;
+
+/// An identity of a user at an external provider, e.g. a subject of an [OIDC](crate::oidc) provider.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Identity {
+    /// Name of the provider, as configured.
+    pub provider: String,
+    /// Identifier of the user at the provider, which never changes, unlike e.g. their email address.
+    pub subject: String,
+    pub linked_at: SystemTime,
+}
+
+impl Basileus {
+    /// Link the identity `subject` at `provider` to `user`, so that logging in through the provider logs in as the user.
+    pub async fn link_identity(
+        &self,
+        user: &str,
+        provider: &str,
+        subject: &str,
+    ) -> Result<(), LinkIdentityError> {
+        if !self.exist_user(user).await? {
+            return Err(LinkIdentityError::UserNotExist(user.into()));
+        }
+        let q = query(self.sql(
+            "INSERT OR IGNORE INTO identity (provider, subject, user, linked_at) VALUES (?, ?, ?, ?);",
+        ))
+        .bind(provider)
+        .bind(subject)
+        .bind(user)
+        .bind(unix_now());
+        if q.execute(&self.writer).await?.rows_affected() == 0 {
+            return Err(LinkIdentityError::AlreadyLinked(
+                provider.into(),
+                subject.into(),
+            ));
+        }
+        info!("linked identity at {provider} to {}", self.log_user(user));
+        Ok(())
+    }
+
+    /// Remove the link of the identity `subject` at `provider`.
+    ///
+    /// Returns whether the identity was linked.
+    pub async fn unlink_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        let q = query(self.sql("DELETE FROM identity WHERE provider = ? AND subject = ?"))
+            .bind(provider)
+            .bind(subject);
+        Ok(q.execute(&self.writer).await?.rows_affected() > 0)
+    }
+
+    /// Get the user the identity `subject` at `provider` is linked to, if any.
+    pub async fn linked_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<String>, sqlx::error::Error> {
+        let q = query_as(self.sql("SELECT user FROM identity WHERE provider = ? AND subject = ?"))
+            .bind(provider)
+            .bind(subject);
+        let res: Option<(String,)> = q.fetch_optional(&self.db).await?;
+        Ok(res.map(|(user,)| user))
+    }
+
+    /// List the external identities linked to a user.
+    pub async fn list_identities(&self, user: &str) -> Result<Vec<Identity>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT provider, subject, linked_at FROM identity WHERE user = ? ORDER BY provider, subject",
+        ))
+        .bind(user);
+        let res: Vec<(String, String, i64)> = q.fetch_all(&self.db).await?;
+        let res = res
+            .into_iter()
+            .map(|(provider, subject, linked_at)| Identity {
+                provider,
+                subject,
+                linked_at: from_unix(linked_at),
+            })
+            .collect();
+        Ok(res)
+    }
+}
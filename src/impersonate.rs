@@ -0,0 +1,150 @@
+use std::time::{Duration, SystemTime};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use tracing::{info, warn};
+
+use crate::{Basileus, err::ImpersonateError, event::AuthEvent, rand_buf};
+
+/// Marker of a session issued by [`Basileus::impersonate`].
+#[derive(Clone, Debug)]
+pub(crate) struct Impersonator {
+    /// Identifier to [end](Basileus::end_impersonation) the impersonation with.
+    pub(crate) id: String,
+    /// The admin acting as the user of the session.
+    pub(crate) admin: String,
+    pub(crate) reason: String,
+    /// Time the session expires, regardless of [`TokenConfig::ttl`](crate::token::TokenConfig::ttl).
+    pub(crate) expires: SystemTime,
+}
+
+/// An active impersonation, see [`Basileus::list_impersonations`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Impersonation {
+    /// Identifier to [end](Basileus::end_impersonation) the impersonation with.
+    pub id: String,
+    /// The admin acting as [`Self::user`].
+    pub admin: String,
+    /// The impersonated user.
+    pub user: String,
+    /// Reason given when starting the impersonation.
+    pub reason: String,
+    pub started_at: SystemTime,
+    pub expires_at: SystemTime,
+}
+
+impl Basileus {
+    /// Issue a token to `admin` which acts as `user`, e.g. for support staff reproducing a problem of the user,
+    /// recording `reason` in the audit log.
+    ///
+    /// The token expires after [`TokenConfig::impersonation_ttl`](crate::token::TokenConfig::impersonation_ttl),
+    /// and [authenticates](Self::authenticate) as `user` with [`AuthUser::impersonator`](crate::token::AuthUser::impersonator)
+    /// set to `admin`, so that actions taken can be attributed to the admin.
+    /// It never passes [`Self::require_recent_auth`], so that sensitive operations remain reserved to the user.
+    ///
+    /// Impersonation must not escalate privileges, so this fails with [`ImpersonateError::Forbidden`]
+    /// unless `admin` [outranks](Self::outranks) `user`.
+    /// Whether `admin` may impersonate at all is up to the caller.
+    pub async fn impersonate(
+        &self,
+        admin: &str,
+        user: &str,
+        reason: &str,
+    ) -> Result<String, ImpersonateError> {
+        self.spanned("impersonate", Some(user), async {
+            if !self.config.read().unwrap().modules.impersonation {
+                return Err(ImpersonateError::FeatureDisabled);
+            }
+            if admin == user {
+                return Err(ImpersonateError::SelfImpersonation);
+            }
+            if reason.trim().is_empty() {
+                return Err(ImpersonateError::MissingReason);
+            }
+            for x in [admin, user] {
+                if !self.exist_user(x).await? {
+                    return Err(ImpersonateError::UserNotExist(x.into()));
+                }
+            }
+            if !self.outranks(admin, user).await? {
+                warn!(
+                    "refused impersonation of {} by {}",
+                    self.log_user(user),
+                    self.log_user(admin)
+                );
+                return Err(ImpersonateError::Forbidden(admin.into()));
+            }
+            let ttl = self.config.read().unwrap().token.impersonation_ttl;
+            let impersonator = Impersonator {
+                id: BASE64_URL_SAFE_NO_PAD.encode(rand_buf(16)),
+                admin: admin.into(),
+                reason: reason.into(),
+                expires: SystemTime::now() + Duration::from_secs(ttl),
+            };
+            let token = self.issue_session_as(user, false, Some(impersonator.clone()), None);
+            info!(
+                "{} impersonates {}: {reason}",
+                self.log_user(admin),
+                self.log_user(user)
+            );
+            self.emit(AuthEvent::ImpersonationStarted {
+                id: impersonator.id,
+                admin: admin.into(),
+                user: user.into(),
+                reason: reason.into(),
+            });
+            Ok(token)
+        })
+        .await
+    }
+
+    /// Impersonations whose token has neither expired nor been invalidated, oldest first.
+    pub fn list_impersonations(&self) -> Vec<Impersonation> {
+        let now = SystemTime::now();
+        let store = self.token.store.read().unwrap();
+        let mut res: Vec<_> = store
+            .values()
+            .filter_map(|x| {
+                let imp = x.impersonator.as_ref().filter(|imp| imp.expires > now)?;
+                Some(Impersonation {
+                    id: imp.id.clone(),
+                    admin: imp.admin.clone(),
+                    user: x.user.clone(),
+                    reason: imp.reason.clone(),
+                    started_at: x.issued,
+                    expires_at: imp.expires,
+                })
+            })
+            .collect();
+        res.sort_by_key(|x| x.started_at);
+        res
+    }
+
+    /// Invalidate the token of the impersonation `id`, returning whether it was active.
+    pub fn end_impersonation(&self, id: &str) -> bool {
+        let ended = {
+            let mut store = self.token.store.write().unwrap();
+            let token = store
+                .iter()
+                .find(|(_, x)| x.impersonator.as_ref().is_some_and(|imp| imp.id == id))
+                .map(|(token, _)| token.clone());
+            token.and_then(|x| store.remove(&x))
+        };
+        let Some(session) = ended else {
+            return false;
+        };
+        let admin = session.impersonator.map(|x| x.admin).unwrap_or_default();
+        info!(
+            "ended impersonation of {} by {}",
+            self.log_user(&session.user),
+            self.log_user(&admin)
+        );
+        self.emit(AuthEvent::ImpersonationEnded {
+            id: id.into(),
+            admin,
+            user: session.user,
+        });
+        true
+    }
+}
@@ -1,69 +1,395 @@
+pub mod acl;
+pub mod audit;
+pub mod authorization;
+#[cfg(feature = "axum")]
+pub mod axum;
+pub mod blocklist;
+pub mod canary;
+pub mod challenge;
+pub mod config;
+pub mod contact;
+#[cfg(feature = "cookie")]
+pub mod cookie;
+pub mod csrf;
+pub mod db;
+pub mod device;
 pub mod err;
+pub mod event;
+pub mod group;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod history;
+pub mod identity;
+pub mod impersonate;
+pub mod lockout;
+pub mod logging;
+pub mod login;
+pub mod magic_link;
+pub mod maintenance;
+pub mod matrix;
+pub mod messenger;
+pub mod metrics;
+pub mod mfa;
+pub mod migrate;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+pub mod oob;
 pub mod pass;
 pub mod perm;
 pub mod pkce;
+pub mod policy;
 pub mod prelude;
+pub mod push;
+pub mod qr_login;
+pub mod ratelimit;
+pub mod risk;
+pub mod role;
+#[cfg(feature = "saml")]
+pub mod saml;
 pub mod token;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod tx;
 pub mod user;
+#[cfg(feature = "warp")]
+pub mod warp;
+pub mod webauthn;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use sqlx::{SqlitePool, query, sqlite::SqliteConnectOptions};
+use sqlx::SqlitePool;
 
-use token::TokenModule;
+use token::{TokenConfig, TokenModule};
 use tracing::{info, trace};
 
+pub use db::MEMORY;
 pub use prelude::*;
 
-use crate::pkce::{PkceConfig, PkceModule};
+use crate::{
+    audit::{AuditConfig, AuditModule},
+    canary::CanaryModule,
+    challenge::{ChallengeConfig, ChallengeModule},
+    config::ModuleConfig,
+    contact::{ContactConfig, ContactModule},
+    db::SqliteConfig,
+    err::InitError,
+    event::{AuthEvent, Subscribers},
+    lockout::{LockoutModule, LockoutPolicy},
+    logging::{LogConfig, LogModule},
+    magic_link::{MagicLinkConfig, MagicLinkModule},
+    maintenance::{MaintenanceConfig, MaintenanceModule},
+    messenger::Messenger,
+    metrics::Metrics,
+    mfa::{MfaConfig, MfaModule},
+    oob::{OobConfig, OobModule},
+    pass::PassConfig,
+    perm::{PermConfig, PermModule},
+    pkce::{PkceConfig, PkceModule},
+    policy::PolicyConfig,
+    push::PushModule,
+    qr_login::{QrLoginConfig, QrLoginModule},
+    ratelimit::{RateLimitConfig, Throttles},
+    risk::RiskModule,
+    user::{UserConfig, UserModule},
+    webauthn::{WebauthnConfig, WebauthnModule},
+};
 
-fn rand_buf<const N: usize>() -> [u8; N] {
-    let mut buf = [0u8; N];
+fn rand_buf(len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
     getrandom::fill(&mut buf).unwrap();
     buf
 }
 
+/// Random string of `len` decimal digits, e.g. a one-time code sent to a user.
+fn rand_digits(len: usize) -> String {
+    let mut res = String::with_capacity(len);
+    while res.len() < len {
+        for x in rand_buf(len) {
+            // reject the remainder of 256 so that every digit is equally likely
+            if x < 250 && res.len() < len {
+                res.push((b'0' + x % 10) as char);
+            }
+        }
+    }
+    res
+}
+
+/// Compare two strings in time independent of where they differ.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Percent-encode a query parameter.
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Current time as seconds since the Unix epoch, the representation of time in the database.
+fn unix_now() -> i64 {
+    to_unix(SystemTime::now())
+}
+
+/// Convert a [`SystemTime`] into seconds since the Unix epoch.
+fn to_unix(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Convert seconds since the Unix epoch back into a [`SystemTime`].
+fn from_unix(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
 /// Configuration for [`Basileus`].
 #[derive(Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Config {
     /// Path to the SQLite storage.
+    ///
+    /// The special path `:memory:` uses a private in-memory database instead, which is lost on drop.
+    /// SQLite is the only backend, so URLs of other databases, e.g. `mysql://`, are rejected.
     #[cfg_attr(feature = "serde", serde(rename = "database-path"))]
     pub db: PathBuf,
+    /// SQLite tuning.
+    #[cfg_attr(feature = "serde", serde(rename = "sqlite"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sqlite: SqliteConfig,
+    /// User management configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "user"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub user: UserConfig,
+    /// Password hashing configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "pass"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pass: PassConfig,
+    /// Session token configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "token"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub token: TokenConfig,
+    /// Switches of optional subsystems.
+    #[cfg_attr(feature = "serde", serde(rename = "modules"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub modules: ModuleConfig,
     /// PKCE configuration.
     #[cfg_attr(feature = "serde", serde(rename = "pkce"))]
     #[cfg_attr(feature = "serde", serde(default))]
     pub pkce: PkceConfig,
+    /// Permission management configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "perm"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub perm: PermConfig,
+    /// Attribute-based policy configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "policy"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub policy: PolicyConfig,
+    /// Rate limiting configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "rate-limit"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rate_limit: RateLimitConfig,
+    /// Account lockout policy.
+    #[cfg_attr(feature = "serde", serde(rename = "lockout"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub lockout: LockoutPolicy,
+    /// Log redaction configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "log"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub log: LogConfig,
+    /// Periodic maintenance configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "maintenance"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub maintenance: MaintenanceConfig,
+    /// Audit configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "audit"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub audit: AuditConfig,
+    /// Contact address configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "contact"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub contact: ContactConfig,
+    /// Magic link configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "magic-link"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub magic_link: MagicLinkConfig,
+    /// Cross-device login configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "qr-login"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub qr_login: QrLoginConfig,
+    /// Challenge configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "challenge"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub challenge: ChallengeConfig,
+    /// Multi-factor authentication configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "mfa"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mfa: MfaConfig,
+    /// Out-of-band one-time code configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "oob"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub oob: OobConfig,
+    /// WebAuthn relying party configuration.
+    #[cfg_attr(feature = "serde", serde(rename = "webauthn"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub webauthn: WebauthnConfig,
+    /// Session cookie configuration.
+    #[cfg(feature = "cookie")]
+    #[cfg_attr(feature = "serde", serde(rename = "cookie"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cookie: cookie::CookieConfig,
+    /// Webhook configuration.
+    #[cfg(feature = "webhook")]
+    #[cfg_attr(feature = "serde", serde(rename = "webhook"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub webhook: webhook::WebhookConfig,
+    /// SAML service provider configuration.
+    #[cfg(feature = "saml")]
+    #[cfg_attr(feature = "serde", serde(rename = "saml"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub saml: saml::SamlConfig,
+    /// OpenID Connect federation configuration.
+    #[cfg(feature = "oidc")]
+    #[cfg_attr(feature = "serde", serde(rename = "oidc"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub oidc: oidc::OidcConfig,
+    /// SMTP configuration.
+    #[cfg(feature = "smtp")]
+    #[cfg_attr(feature = "serde", serde(rename = "smtp"))]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub smtp: messenger::SmtpConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             db: "./basileus.db".into(),
+            sqlite: Default::default(),
+            user: Default::default(),
+            pass: Default::default(),
+            token: Default::default(),
+            modules: Default::default(),
             pkce: Default::default(),
+            perm: Default::default(),
+            policy: Default::default(),
+            rate_limit: Default::default(),
+            lockout: Default::default(),
+            log: Default::default(),
+            maintenance: Default::default(),
+            audit: Default::default(),
+            contact: Default::default(),
+            magic_link: Default::default(),
+            qr_login: Default::default(),
+            challenge: Default::default(),
+            mfa: Default::default(),
+            oob: Default::default(),
+            webauthn: Default::default(),
+            #[cfg(feature = "cookie")]
+            cookie: Default::default(),
+            #[cfg(feature = "webhook")]
+            webhook: Default::default(),
+            #[cfg(feature = "saml")]
+            saml: Default::default(),
+            #[cfg(feature = "oidc")]
+            oidc: Default::default(),
+            #[cfg(feature = "smtp")]
+            smtp: Default::default(),
         }
     }
 }
 
 /// Entry point for the library.
 pub struct Basileus {
-    /// Configurations.
-    pub config: Config,
-    /// Database connection.
+    /// Configurations, partially [reloadable](Self::reload_config).
+    config: RwLock<Config>,
+    /// Database connections for reading.
     db: SqlitePool,
+    /// Database connections for writing.
+    writer: SqlitePool,
+    /// Module switches at startup, since the tables of modules disabled then may be missing.
+    schema: ModuleConfig,
+    /// User management module.
+    user: UserModule,
     /// Token management module.
     token: TokenModule,
     pkce: PkceModule,
+    /// Permission management module.
+    perm: PermModule,
+    /// Periodic maintenance module.
+    maintenance: MaintenanceModule,
+    /// Log redaction module.
+    log: LogModule,
+    /// Audit module.
+    audit: AuditModule,
+    /// Subscribers to authentication events.
+    events: Subscribers<AuthEvent>,
+    /// Contact address module.
+    contact: ContactModule,
+    /// Magic link module.
+    magic_link: MagicLinkModule,
+    /// Cross-device login module.
+    qr_login: QrLoginModule,
+    /// Challenge module.
+    challenge: ChallengeModule,
+    /// Second factor module.
+    mfa: MfaModule,
+    /// Out-of-band one-time code module.
+    oob: OobModule,
+    /// Push approval module.
+    push: PushModule,
+    /// WebAuthn module.
+    webauthn: WebauthnModule,
+    /// Rate limiters.
+    throttles: Throttles,
+    /// Account lockout module.
+    lockout: LockoutModule,
+    /// Risk evaluation module.
+    risk: RiskModule,
+    /// Canary token module.
+    canary: CanaryModule,
+    /// Session cookie module.
+    #[cfg(feature = "cookie")]
+    cookie: cookie::CookieModule,
+    /// SAML service provider module.
+    #[cfg(feature = "saml")]
+    saml: saml::SamlModule,
+    /// OpenID Connect federation module.
+    #[cfg(feature = "oidc")]
+    oidc: oidc::OidcModule,
+    /// Receiver of performance measurements.
+    metrics: RwLock<Option<Arc<dyn Metrics>>>,
+    /// Deliverer of messages to users.
+    messenger: RwLock<Option<Arc<dyn Messenger>>>,
 }
 
-/// Initialize the database.
-pub const DB_INIT: &str = r#"
+/// Initialize the table of public keys, see [`ModuleConfig::pubkey`].
+pub const DB_INIT_PUBKEY: &str = r#"
 CREATE TABLE IF NOT EXISTS pubkey (
     user TEXT NOT NULL PRIMARY KEY,
     key BLOB NOT NULL,
     FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
 );
 CREATE INDEX IF NOT EXISTS idx_pubkey_user ON pubkey (user);
+"#;
+
+/// Initialize the database.
+pub const DB_INIT: &str = r#"
 CREATE TABLE IF NOT EXISTS token (
     user TEXT NOT NULL PRIMARY KEY,
     token TEXT,
@@ -74,29 +400,98 @@ CREATE INDEX IF NOT EXISTS idx_token_user ON token (user);
 
 impl Basileus {
     /// Initialize the library, creating the database if missing.
-    pub async fn new(config: Config) -> Result<Self, sqlx::error::Error> {
-        let opt = SqliteConnectOptions::default()
-            .filename(&config.db)
-            .create_if_missing(true);
-        let db = SqlitePool::connect_with(opt).await?;
+    ///
+    /// The configuration is [validated](Config::validate) first, and its secrets [resolved](Config::resolve_secrets).
+    pub async fn new(config: Config) -> Result<Self, InitError> {
+        config.validate()?;
+        let config = config.resolve_secrets()?;
+        let (db, writer) = db::connect(&config.db, &config.sqlite).await?;
         info!("connected to {:?}", config.db);
-        query(user::DB_INIT).execute(&db).await?;
-        query(pass::DB_INIT).execute(&db).await?;
-        query(perm::DB_INIT).execute(&db).await?;
-        query(DB_INIT).execute(&db).await?;
+        migrate::migrate(&writer, &config.sqlite.table_prefix, &config.modules).await?;
         trace!("database initialized");
-        let pkce = PkceModule::new(config.pkce.clone());
-        Ok(Self {
-            config,
+        let user = UserModule::new();
+        let pkce = PkceModule::new();
+        let perm = PermModule::new(&config.perm);
+        let throttles = Throttles::new(&config.rate_limit);
+        #[cfg(feature = "smtp")]
+        let messenger: Option<Arc<dyn Messenger>> = match config.smtp.host {
+            Some(_) => Some(Arc::new(messenger::SmtpMessenger::new(&config.smtp)?)),
+            None => None,
+        };
+        #[cfg(not(feature = "smtp"))]
+        let messenger = None;
+        let schema = config.modules.clone();
+        let basileus = Self {
+            config: RwLock::new(config),
             db,
+            writer,
+            schema,
+            user,
             token: TokenModule::new(),
             pkce,
+            perm,
+            maintenance: Default::default(),
+            log: LogModule::new(),
+            audit: AuditModule::new(),
+            events: Default::default(),
+            contact: ContactModule::new(),
+            magic_link: MagicLinkModule::new(),
+            qr_login: QrLoginModule::new(),
+            challenge: ChallengeModule::new(),
+            mfa: MfaModule::new(),
+            oob: OobModule::new(),
+            push: PushModule::new(),
+            webauthn: WebauthnModule::new(),
+            throttles,
+            lockout: LockoutModule::new(),
+            risk: RiskModule::new(),
+            canary: CanaryModule::new(),
+            #[cfg(feature = "cookie")]
+            cookie: cookie::CookieModule::new(),
+            #[cfg(feature = "saml")]
+            saml: saml::SamlModule::new(),
+            #[cfg(feature = "oidc")]
+            oidc: oidc::OidcModule::new(),
+            metrics: RwLock::new(None),
+            messenger: RwLock::new(messenger),
+        };
+        basileus.load_tokens().await?;
+        basileus.load_canary_tokens().await?;
+        Ok(basileus)
+    }
+
+    /// Shut down the library, closing the database after pending writes have finished.
+    ///
+    /// [Maintenance](Self::run_maintenance) stops after its current interval.
+    ///
+    /// If `persist_tokens` or [`TokenConfig::persist`] is set, issued tokens are saved to the database
+    /// and restored the next time the library is [initialized](Self::new),
+    /// so that users stay logged in across restarts.
+    ///
+    /// Any further database operation fails after closing.
+    pub async fn close(&self, persist_tokens: bool) -> Result<(), sqlx::error::Error> {
+        self.flush_audit().await?;
+        if persist_tokens || self.config.read().unwrap().token.persist {
+            self.persist_tokens().await?;
+        }
+        self.writer.close().await;
+        self.db.close().await;
+        info!("closed {:?}", self.config.read().unwrap().db);
+        Ok(())
+    }
+
+    /// Initialize the library with a fresh in-memory database and default configurations, e.g. for tests.
+    pub async fn new_in_memory() -> Result<Self, InitError> {
+        Self::new(Config {
+            db: MEMORY.into(),
+            ..Default::default()
         })
+        .await
     }
 
     /// Count the number of users.
     pub async fn user_cnt(&self) -> Result<i64, sqlx::error::Error> {
-        let (cnt,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM user")
+        let (cnt,): (i64,) = sqlx::query_as(self.sql("SELECT COUNT(*) FROM user"))
             .fetch_one(&self.db)
             .await?;
         Ok(cnt)
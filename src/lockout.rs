@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use sqlx::{query, query_as};
+use tracing::{info, warn};
+
+use crate::{
+    Basileus, err::LoginError, event::AuthEvent, from_unix, messenger::Channel, to_unix, unix_now,
+};
+
+/// Initialize the table of locked accounts, see [`Basileus::lockout_status`].
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS account_lock (
+    user TEXT NOT NULL PRIMARY KEY,
+    locked_at INTEGER NOT NULL,
+    until INTEGER,
+    failures INTEGER NOT NULL,
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+"#;
+
+/// Account lockout policy, evaluated on every wrong password given for a user.
+///
+/// Unlike the [throttle](crate::ratelimit::Throttles::user) of users, which merely slows attempts down,
+/// locks are stored in the database and may require an admin to [unlock](Basileus::unlock_user) the account.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LockoutPolicy {
+    /// Wrong passwords within [`Self::window`] after which the account is locked, or `0` to never lock accounts.
+    #[cfg_attr(feature = "serde", serde(rename = "threshold"))]
+    pub threshold: u32,
+    /// Time in seconds over which wrong passwords are counted.
+    #[cfg_attr(feature = "serde", serde(rename = "window"))]
+    pub window: u64,
+    /// Time in seconds after which locked accounts are unlocked, if [`Self::auto_unlock`] is set.
+    #[cfg_attr(feature = "serde", serde(rename = "duration"))]
+    pub duration: u64,
+    /// Whether locked accounts are unlocked after [`Self::duration`], rather than only by an admin.
+    #[cfg_attr(feature = "serde", serde(rename = "auto-unlock"))]
+    pub auto_unlock: bool,
+    /// Whether to tell users by email that their account was locked, if they have an email address.
+    #[cfg_attr(feature = "serde", serde(rename = "notify"))]
+    pub notify: bool,
+    /// Subject of the email telling users that their account was locked.
+    #[cfg_attr(feature = "serde", serde(rename = "subject"))]
+    pub subject: String,
+    /// Body of the email telling users that their account was locked.
+    #[cfg_attr(feature = "serde", serde(rename = "body"))]
+    pub body: String,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 0,
+            window: 900,
+            duration: 1800,
+            auto_unlock: true,
+            notify: false,
+            subject: "Account locked".into(),
+            body: "Your account was locked after too many failed login attempts.\n\nIf these were not yours, consider changing your password once it is unlocked.".into(),
+        }
+    }
+}
+
+/// A lock of an account, see [`Basileus::lockout_status`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Lockout {
+    pub locked_at: SystemTime,
+    /// Time the account is unlocked, or [`None`] if only an admin can [unlock](Basileus::unlock_user) it.
+    pub until: Option<SystemTime>,
+    /// Wrong passwords which led to the lock.
+    pub failures: u32,
+}
+
+/// Lockout state of an account, see [`Basileus::lockout_status`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct LockoutStatus {
+    /// Wrong passwords counted within [`LockoutPolicy::window`].
+    pub failures: u32,
+    /// Wrong passwords after which the account is locked, or `0` if accounts are never locked.
+    pub threshold: u32,
+    /// The current lock, if the account is locked.
+    pub lock: Option<Lockout>,
+}
+
+#[derive(Default)]
+pub struct LockoutModule {
+    /// Times of recent wrong passwords per user.
+    failures: Mutex<HashMap<String, Vec<Instant>>>,
+}
+
+impl LockoutModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget failures which no longer count towards a lock.
+    pub(crate) fn prune(&self, window: Duration) {
+        let now = Instant::now();
+        self.failures.lock().unwrap().retain(|_, x| {
+            x.retain(|t| now.duration_since(*t) < window);
+            !x.is_empty()
+        });
+    }
+}
+
+impl Basileus {
+    /// Lockout state of `user`, i.e. the wrong passwords counted and the current lock, if any.
+    pub async fn lockout_status(&self, user: &str) -> Result<LockoutStatus, sqlx::error::Error> {
+        let policy = self.config.read().unwrap().lockout.clone();
+        let window = Duration::from_secs(policy.window);
+        let now = Instant::now();
+        let failures = (self.lockout.failures.lock().unwrap().get(user)).map_or(0, |x| {
+            x.iter()
+                .filter(|t| now.duration_since(**t) < window)
+                .count()
+        });
+        Ok(LockoutStatus {
+            failures: failures as u32,
+            threshold: policy.threshold,
+            lock: self.account_lock(user).await?,
+        })
+    }
+
+    /// Unlock the account of `user` and forget their wrong passwords, returning whether it was locked.
+    pub async fn unlock_user(&self, user: &str) -> Result<bool, sqlx::error::Error> {
+        self.lockout.failures.lock().unwrap().remove(user);
+        let q = query(
+            self.sql("DELETE FROM account_lock WHERE user = ? AND (until IS NULL OR until > ?)"),
+        )
+        .bind(user)
+        .bind(unix_now());
+        let res = q.execute(&self.writer).await?.rows_affected() > 0;
+        if res {
+            info!("unlocked {}", self.log_user(user));
+            self.emit(AuthEvent::AccountUnlocked { user: user.into() });
+        }
+        Ok(res)
+    }
+
+    /// The current lock of the account of `user`, if any.
+    async fn account_lock(&self, user: &str) -> Result<Option<Lockout>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT locked_at, until, failures FROM account_lock
+            WHERE user = ? AND (until IS NULL OR until > ?)",
+        ))
+        .bind(user)
+        .bind(unix_now());
+        let res: Option<(i64, Option<i64>, u32)> = q.fetch_optional(&self.db).await?;
+        Ok(res.map(|(locked_at, until, failures)| Lockout {
+            locked_at: from_unix(locked_at),
+            until: until.map(from_unix),
+            failures,
+        }))
+    }
+
+    /// Fail with [`LoginError::AccountLocked`] if the account of `user` is locked.
+    pub(crate) async fn check_lockout(&self, user: &str) -> Result<(), LoginError> {
+        match self.account_lock(user).await? {
+            Some(lock) => Err(LoginError::AccountLocked(lock.until)),
+            None => Ok(()),
+        }
+    }
+
+    /// Count a wrong password given for `user` against the [lockout policy](LockoutPolicy),
+    /// locking the account once the threshold is reached.
+    pub(crate) async fn primary_factor_failed(&self, user: &str) -> Result<(), sqlx::error::Error> {
+        let policy = self.config.read().unwrap().lockout.clone();
+        if policy.threshold == 0 {
+            return Ok(());
+        }
+        let window = Duration::from_secs(policy.window);
+        let now = Instant::now();
+        let failures = {
+            let mut map = self.lockout.failures.lock().unwrap();
+            let list = map.entry(user.into()).or_default();
+            list.retain(|t| now.duration_since(*t) < window);
+            list.push(now);
+            list.len() as u32
+        };
+        if failures < policy.threshold {
+            return Ok(());
+        }
+        self.lockout.failures.lock().unwrap().remove(user);
+        let locked_at = SystemTime::now();
+        let until = (policy.auto_unlock).then(|| locked_at + Duration::from_secs(policy.duration));
+        let q = query(self.sql(
+            "INSERT OR REPLACE INTO account_lock (user, locked_at, until, failures)
+            SELECT ?1, ?2, ?3, ?4 WHERE EXISTS (SELECT 1 FROM user WHERE user = ?1)",
+        ))
+        .bind(user)
+        .bind(to_unix(locked_at))
+        .bind(until.map(to_unix))
+        .bind(failures);
+        if q.execute(&self.writer).await?.rows_affected() == 0 {
+            return Ok(());
+        }
+        warn!(
+            "locked {} after {failures} failed attempts",
+            self.log_user(user)
+        );
+        self.count("lockout.locked");
+        self.emit(AuthEvent::AccountLocked {
+            user: user.into(),
+            until,
+            failures,
+        });
+        if policy.notify
+            && let Err(e) =
+                (self.message_user(user, Channel::Email, &policy.subject, &policy.body)).await
+        {
+            warn!("failed to notify {} of lockout: {e}", self.log_user(user));
+        }
+        Ok(())
+    }
+
+    /// Forget the wrong passwords given for `user`, e.g. after a successful login.
+    pub(crate) fn clear_failures(&self, user: &str) {
+        self.lockout.failures.lock().unwrap().remove(user);
+    }
+}
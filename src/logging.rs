@@ -0,0 +1,362 @@
+use std::{
+    borrow::Cow,
+    fmt::{Debug, Display},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use sha2::{Digest, Sha256};
+use tracing::{Instrument, Span, field::Empty, info_span, warn};
+
+use crate::{
+    Basileus,
+    err::{ErrorCode, ErrorContext},
+};
+
+/// Configuration of identity data appearing in logs.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LogConfig {
+    /// Whether to log a hash of usernames instead of the usernames themselves.
+    ///
+    /// The hash still allows correlating events of the same user.
+    #[cfg_attr(feature = "serde", serde(rename = "hash-users"))]
+    pub hash_users: bool,
+    /// Whether to log the first characters of tokens.
+    #[cfg_attr(feature = "serde", serde(rename = "token-prefix"))]
+    pub token_prefix: bool,
+    /// Log only one in this many successful authentications, which are logged at trace level,
+    /// or none if `0`.
+    #[cfg_attr(feature = "serde", serde(rename = "trace-sample"))]
+    pub trace_sample: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            hash_users: false,
+            token_prefix: true,
+            trace_sample: 1,
+        }
+    }
+}
+
+/// Secret material, e.g. a password, a token or a key, which is masked when formatted with [`Debug`],
+/// so that it does not end up in logs when a structure containing it is logged.
+///
+/// It deliberately implements neither [`Display`] nor [`Deref`](std::ops::Deref),
+/// so that the value has to be [exposed](Self::expose) explicitly where it is needed.
+/// It is serialized as the bare value, leaving wire formats unchanged.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The secret value, which must not be logged.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Unwrap the secret value, which must not be logged.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret<String> {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("**")
+    }
+}
+
+#[derive(Default)]
+pub struct LogModule {
+    /// Number of sampled events so far.
+    sampled: AtomicU64,
+}
+
+impl LogModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Run `op`, attaching the operation name `name`, the affected `user` and the time taken to its error,
+    /// and logging them as structured fields.
+    ///
+    /// This is useful around any operation of the library, e.g.
+    /// `basileus.traced("verify_pass", Some(user), basileus.verify_pass(user, pass)).await`.
+    pub async fn traced<T, E: ErrorCode + Display>(
+        &self,
+        name: &'static str,
+        user: Option<&str>,
+        op: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, ErrorContext<E>> {
+        let start = Instant::now();
+        let error = match op.await {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        let elapsed = start.elapsed();
+        let user = user.map(|x| self.log_user(x).into_owned());
+        warn!(
+            op = name,
+            user = user.as_deref(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            code = error.code(),
+            transient = error.is_transient(),
+            "{error}"
+        );
+        Err(ErrorContext {
+            op: name,
+            user,
+            elapsed,
+            error,
+        })
+    }
+
+    /// Span of the public operation `op` affecting `user`, with the attributes
+    ///
+    /// - `op` and `otel.name`, the name of the operation, e.g. `verify_pass`,
+    /// - `user`, the affected user as [represented in logs](LogConfig::hash_users), if known,
+    /// - `outcome`, `ok` or the [code](ErrorCode::code) of the error, recorded on completion.
+    pub(crate) fn span(&self, op: &'static str, user: Option<&str>) -> Span {
+        let user = user.map(|x| self.log_user(x));
+        info_span!(
+            "basileus",
+            op,
+            otel.name = op,
+            user = user.as_deref(),
+            outcome = Empty
+        )
+    }
+
+    /// Run the public operation `op` affecting `user` in its [span](Self::span), recording the outcome.
+    pub(crate) async fn spanned<T, E: ErrorCode>(
+        &self,
+        op: &'static str,
+        user: Option<&str>,
+        fut: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let span = self.span(op, user);
+        let res = fut.instrument(span.clone()).await;
+        record_outcome(&span, &res);
+        self.try_flush_audit().await;
+        res
+    }
+
+    /// Like [`Self::spanned`], but for synchronous operations.
+    pub(crate) fn spanned_sync<T, E: ErrorCode>(
+        &self,
+        op: &'static str,
+        user: Option<&str>,
+        f: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let span = self.span(op, user);
+        let res = span.in_scope(f);
+        record_outcome(&span, &res);
+        res
+    }
+
+    /// Record the affected `user` on the current span, once only known during the operation.
+    pub(crate) fn record_user(&self, user: &str) {
+        Span::current().record("user", self.log_user(user).as_ref());
+    }
+
+    /// Representation of `user` in logs, see [`LogConfig::hash_users`].
+    pub(crate) fn log_user<'a>(&self, user: &'a str) -> Cow<'a, str> {
+        if !self.config.read().unwrap().log.hash_users {
+            return user.into();
+        }
+        let hash = Sha256::digest(user);
+        let hex: String = hash[..6].iter().map(|x| format!("{x:02x}")).collect();
+        format!("#{hex}").into()
+    }
+
+    /// Representation of `token` in logs, see [`LogConfig::token_prefix`].
+    pub(crate) fn log_token(&self, token: &str) -> String {
+        if !self.config.read().unwrap().log.token_prefix {
+            return "**".into();
+        }
+        let end = token.char_indices().nth(4).map_or(token.len(), |(i, _)| i);
+        format!("{}**", &token[..end])
+    }
+
+    /// Whether to log the current trace-level authentication event, see [`LogConfig::trace_sample`].
+    pub(crate) fn sampled(&self) -> bool {
+        let rate = self.config.read().unwrap().log.trace_sample;
+        rate != 0
+            && self
+                .log
+                .sampled
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(rate)
+    }
+}
+
+/// Scheme and authority of `url`, e.g. `https://example.com`,
+/// omitting the path and query which may carry credentials, e.g. of webhooks.
+pub(crate) fn redact_url(url: &str) -> Cow<'_, str> {
+    let start = url.find("://").map_or(0, |i| i + 3);
+    let end = url[start..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |i| start + i);
+    let authority = &url[start..end];
+    // drop the user information too
+    match authority.rfind('@') {
+        Some(i) => format!("{}{}", &url[..start], &authority[i + 1..]).into(),
+        None => url[..end].into(),
+    }
+}
+
+/// Record the outcome of an operation on its span, see [`Basileus::span`].
+fn record_outcome<T, E: ErrorCode>(span: &Span, res: &Result<T, E>) {
+    match res {
+        Ok(_) => span.record("outcome", "ok"),
+        Err(e) => span.record("outcome", e.code()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    };
+
+    use base64::{Engine, prelude::BASE64_URL_SAFE};
+    use sha2::{Digest, Sha256};
+    use tracing::{
+        Event, Metadata, Subscriber,
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+    };
+
+    use crate::{Basileus, login::LoginContext, pkce::CodeChallenge};
+
+    /// Subscriber keeping every span and event at every level as a line of text.
+    #[derive(Clone, Default)]
+    struct Capture {
+        lines: Arc<Mutex<Vec<String>>>,
+        next: Arc<AtomicU64>,
+    }
+
+    struct Line(String);
+
+    impl Visit for Line {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0 += &format!(" {}={value:?}", field.name());
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0 += &format!(" {}={value}", field.name());
+        }
+    }
+
+    impl Capture {
+        fn push(&self, line: Line) {
+            self.lines.lock().unwrap().push(line.0);
+        }
+
+        fn text(&self) -> String {
+            self.lines.lock().unwrap().join("\n")
+        }
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut line = Line(span.metadata().name().into());
+            span.record(&mut line);
+            self.push(line);
+            Id::from_u64(self.next.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            let mut line = Line(String::new());
+            values.record(&mut line);
+            self.push(line);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut line = Line(event.metadata().level().to_string());
+            event.record(&mut line);
+            self.push(line);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[tokio::test]
+    async fn secrets_not_logged() {
+        let capture = Capture::default();
+        let _guard = tracing::subscriber::set_default(capture.clone());
+
+        let pass = "correct-horse-battery-staple";
+        let wrong = "incorrect-horse-battery-staple";
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = BASE64_URL_SAFE.encode(Sha256::digest(verifier));
+
+        let b = Basileus::new_in_memory().await.unwrap();
+        b.create_user("alice").await.unwrap();
+        b.update_pass("alice", pass).await.unwrap();
+        let ctx = LoginContext::new();
+
+        assert!(b.login("alice", wrong, &ctx).await.is_err());
+        let token = b.login("alice", pass, &ctx).await.unwrap();
+        assert!(b.verify_token(&token).is_some());
+
+        let code = (b.pkce_auth_req("alice", pass, CodeChallenge::new(challenge.clone()), &ctx))
+            .await
+            .unwrap();
+        assert!(b.pkce_token_req(&code, wrong).is_err());
+        let pkce_token = b.pkce_token_req(&code, verifier).unwrap();
+        assert!(b.verify_token(&pkce_token).is_some());
+
+        let issued = b.issue_token("alice");
+        b.invalidate_token(&issued);
+        assert!(b.verify_token(&issued).is_none());
+
+        let logs = capture.text();
+        assert!(logs.contains("pkce_auth_req"), "nothing captured:\n{logs}");
+        for secret in [
+            pass,
+            wrong,
+            verifier,
+            &challenge,
+            &code,
+            &token,
+            &pkce_token,
+            &issued,
+        ] {
+            assert!(!logs.contains(secret), "'{secret}' logged:\n{logs}");
+        }
+    }
+}
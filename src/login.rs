@@ -0,0 +1,404 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use tracing::{debug, info};
+
+use crate::{
+    Basileus,
+    err::{AuthError, ErrorCode, LoginError},
+    history::LoginAttempt,
+    logging::Secret,
+    mfa::MfaKind,
+    push::PushDecision,
+    risk::RiskVerdict,
+    token::token_key,
+};
+
+/// Circumstances of a login attempt, see [`Basileus::login`].
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct LoginContext {
+    /// Source address of the attempt, if known.
+    pub ip: Option<IpAddr>,
+    /// User agent of the client, if known, recorded in the [login history](Basileus::login_history).
+    pub user_agent: Option<String>,
+    /// Response to a [challenge](crate::challenge) presented earlier, if any.
+    pub challenge: Option<Secret<String>>,
+    /// One-time password of a [second factor](crate::mfa), if any.
+    pub otp: Option<Secret<String>>,
+    /// Response to a [WebAuthn challenge](Basileus::begin_webauthn_assertion) presented earlier, if any.
+    pub webauthn: Option<Secret<String>>,
+    /// Token of a [trusted device](Basileus::trust_device), if any.
+    pub device: Option<Secret<String>>,
+    /// Identifier of a [`MfaChallenge`](crate::mfa::MfaChallenge) issued earlier, if any.
+    pub mfa_challenge: Option<String>,
+    /// Whether to ask the user to approve the attempt on their device instead of presenting a second factor,
+    /// see [`Basileus::enable_push`].
+    pub push: bool,
+    /// Thumbprint of the client certificate of the connection, if any,
+    /// which the token issued by [`Basileus::login`] is [bound to](Basileus::issue_bound_token).
+    pub cert: Option<String>,
+}
+
+impl LoginContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the source address of the attempt.
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+
+    /// Set the user agent of the client.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Set the response to a challenge.
+    pub fn challenge(mut self, response: impl Into<Secret<String>>) -> Self {
+        self.challenge = Some(response.into());
+        self
+    }
+
+    /// Set the one-time password of a second factor.
+    pub fn otp(mut self, code: impl Into<Secret<String>>) -> Self {
+        self.otp = Some(code.into());
+        self
+    }
+
+    /// Set the response to a WebAuthn challenge.
+    pub fn webauthn(mut self, response: impl Into<Secret<String>>) -> Self {
+        self.webauthn = Some(response.into());
+        self
+    }
+
+    /// Set the token of a trusted device.
+    pub fn device(mut self, token: impl Into<Secret<String>>) -> Self {
+        self.device = Some(token.into());
+        self
+    }
+
+    /// Ask the user to approve the attempt on their device.
+    pub fn push(mut self) -> Self {
+        self.push = true;
+        self
+    }
+
+    /// Continue the login which issued the [`MfaChallenge`](crate::mfa::MfaChallenge) `id`.
+    pub fn mfa_challenge(mut self, id: impl Into<String>) -> Self {
+        self.mfa_challenge = Some(id.into());
+        self
+    }
+
+    /// Set the [thumbprint](crate::token::cert_thumbprint) of the client certificate of the connection.
+    pub fn cert(mut self, cert: impl Into<String>) -> Self {
+        self.cert = Some(cert.into());
+        self
+    }
+}
+
+impl Basileus {
+    /// Log in with a username and password, returning a session token.
+    ///
+    /// This combines [`Self::verify_login`] and [`Self::issue_token`],
+    /// binding the token to the [client certificate](LoginContext::cert), if any.
+    pub async fn login(
+        &self,
+        user: &str,
+        pass: &str,
+        ctx: &LoginContext,
+    ) -> Result<String, LoginError> {
+        self.spanned("login", Some(user), async {
+            let mfa = self.verify_login(user, pass, ctx).await?;
+            Ok(self.issue_session_as(user, mfa, None, ctx.cert.clone()))
+        })
+        .await
+    }
+
+    /// Verify a username and password, and the one-time password of a second factor if the user
+    /// [enabled](Self::mfa_enabled) any.
+    ///
+    /// This combines [`Self::check_challenge`] and [`Self::verify_pass`],
+    /// counting failures from the source address towards future challenges.
+    /// Failures are also counted against the [throttles](Self::throttles) of the user and the source address,
+    /// and attempts are rejected while either is exhausted, or while the source address is [blocked](Self::block_ip).
+    /// Addresses exhausting their budget repeatedly are locked out for longer every time,
+    /// see [`RateLimitConfig::ip_max_lockout`](crate::ratelimit::RateLimitConfig::ip_max_lockout).
+    /// Wrong passwords are counted against the [lockout policy](crate::lockout::LockoutPolicy) as well,
+    /// and attempts on locked accounts fail with [`LoginError::AccountLocked`] even with the right password.
+    /// Once the password is verified, the [risk evaluator](Self::set_risk_evaluator) may require a second factor
+    /// or deny the attempt with [`LoginError::RiskDenied`].
+    ///
+    /// Attempts with a correct password but with neither one-time password nor WebAuthn response
+    /// fail with [`LoginError::MfaRequired`], carrying a [challenge](crate::mfa::MfaChallenge)
+    /// so that the client may ask for one and retry with the [identifier](LoginContext::mfa_challenge)
+    /// of the challenge in place of the password.
+    /// If [login codes](Self::enable_oob) are the only second factor of the user, one is sent meanwhile.
+    /// Attempts asking for [push approval](LoginContext::push) wait for the user to respond instead.
+    /// Users who have to [enroll](Self::mfa_required) a second factor but did not
+    /// fail with [`LoginError::MfaEnrollmentRequired`] despite a correct password,
+    /// so that the client may lead them through enrollment.
+    /// A [trusted device](Self::trust_device) skips the second factor,
+    /// though sessions established so do not count as [having presented one](Self::require_recent_auth).
+    ///
+    /// Completed attempts are recorded in the [login history](Self::login_history) of the user,
+    /// as are those failing with a wrong password or second factor, or denied push approval.
+    ///
+    /// Returns whether a second factor was verified.
+    pub async fn verify_login(
+        &self,
+        user: &str,
+        pass: &str,
+        ctx: &LoginContext,
+    ) -> Result<bool, LoginError> {
+        let res = self.verify_login_inner(user, pass, ctx).await;
+        let code = match &res {
+            Ok(_) => None,
+            Err(
+                e @ (LoginError::Unauthorized
+                | LoginError::InvalidOtp
+                | LoginError::PushDenied
+                | LoginError::PushTimedOut
+                | LoginError::AccountLocked(_)
+                | LoginError::RiskDenied),
+            ) => Some(e.code()),
+            Err(_) => return res,
+        };
+        let attempt = LoginAttempt {
+            success: code.is_none(),
+            ip: ctx.ip,
+            user_agent: ctx.user_agent.clone(),
+            code: code.map(Into::into),
+            ..LoginAttempt::new("password")
+        };
+        self.record_login_attempt(user, &attempt).await;
+        res
+    }
+
+    async fn verify_login_inner(
+        &self,
+        user: &str,
+        pass: &str,
+        ctx: &LoginContext,
+    ) -> Result<bool, LoginError> {
+        self.throttles
+            .user
+            .check(user)
+            .map_err(LoginError::RateLimited)?;
+        if let Some(ip) = ctx.ip {
+            if self.is_ip_blocked(ip).await? {
+                return Err(LoginError::IpBlocked);
+            }
+            self.throttles
+                .ip
+                .check(&ip.to_string())
+                .map_err(LoginError::RateLimited)?;
+        }
+        self.check_lockout(user).await?;
+        let continued =
+            (ctx.mfa_challenge.as_deref()).is_some_and(|id| self.check_mfa_challenge(id, user));
+        if !continued {
+            self.check_challenge(ctx.ip, ctx.challenge.as_ref().map(|x| x.expose().as_str()))
+                .await?;
+            let valid =
+                match (self.verify_pass_from(user, pass, ctx.ip).await).map_err(LoginError::from) {
+                    Ok(valid) => valid,
+                    Err(LoginError::Unauthorized) => false,
+                    Err(e) => return Err(e),
+                };
+            if !valid {
+                self.login_failed(user, ctx);
+                self.primary_factor_failed(user).await?;
+                return Err(LoginError::Unauthorized);
+            }
+        }
+        let mfa = self.mfa_enabled(user).await?;
+        if continued && !mfa {
+            // the second factor was removed meanwhile, so the password has to be verified again
+            return Err(LoginError::Unauthorized);
+        }
+        let mut trusted = match &ctx.device {
+            Some(device) if mfa && !continued => {
+                self.check_trusted_device(user, device.expose()).await?
+            }
+            _ => false,
+        };
+        if !continued {
+            let user_agent = ctx.user_agent.as_deref();
+            match (self.evaluate_risk(user, ctx.ip, user_agent, trusted, mfa)).await? {
+                RiskVerdict::Allow => {}
+                RiskVerdict::StepUp if mfa => trusted = false,
+                RiskVerdict::StepUp | RiskVerdict::Deny => return Err(LoginError::RiskDenied),
+            }
+        }
+        if mfa && !trusted && ctx.push && self.mfa_kind_enabled(MfaKind::Push) {
+            match self.request_push_approval(user, ctx.ip).await? {
+                PushDecision::Approved => {}
+                PushDecision::Denied => {
+                    self.login_failed(user, ctx);
+                    return Err(LoginError::PushDenied);
+                }
+                // counted as well, so that users cannot be flooded with requests
+                PushDecision::TimedOut => {
+                    self.login_failed(user, ctx);
+                    return Err(LoginError::PushTimedOut);
+                }
+            }
+            if let Some(id) = &ctx.mfa_challenge {
+                self.finish_mfa_challenge(id);
+            }
+        } else if mfa && !trusted {
+            let Some(otp) = ctx.webauthn.as_ref().or(ctx.otp.as_ref()) else {
+                self.send_second_factor_code(user).await?;
+                let challenge = self.issue_mfa_challenge(user).await?;
+                return Err(LoginError::MfaRequired(challenge));
+            };
+            if !self.verify_second_factor(user, otp.expose()).await? {
+                self.login_failed(user, ctx);
+                return Err(LoginError::InvalidOtp);
+            }
+            if let Some(id) = &ctx.mfa_challenge {
+                self.finish_mfa_challenge(id);
+            }
+        } else if self.mfa_required(user).await? {
+            return Err(LoginError::MfaEnrollmentRequired);
+        }
+        self.throttles.user.reset(user);
+        self.clear_failures(user);
+        Ok(mfa && !trusted)
+    }
+
+    /// Check that `user`, who proved only a single factor without a password, may have a session.
+    ///
+    /// Locked accounts fail with [`LoginError::AccountLocked`].
+    /// Users with a second factor fail with [`LoginError::MfaRequired`] and continue by [`Self::login`]
+    /// with the challenge, while users who have to enroll one fail with [`LoginError::MfaEnrollmentRequired`].
+    pub(crate) async fn check_passwordless(&self, user: &str) -> Result<(), LoginError> {
+        self.check_lockout(user).await?;
+        if self.mfa_enabled(user).await? {
+            self.send_second_factor_code(user).await?;
+            let challenge = self.issue_mfa_challenge(user).await?;
+            return Err(LoginError::MfaRequired(challenge));
+        }
+        if self.mfa_required(user).await? {
+            return Err(LoginError::MfaEnrollmentRequired);
+        }
+        Ok(())
+    }
+
+    /// Check that the user holding `token` proved their identity within `max_age`,
+    /// presenting a second factor if they [enabled](Self::mfa_enabled) any, returning the user.
+    ///
+    /// Sensitive operations such as changing the email address or deleting the account should call this,
+    /// and on [`AuthError::ReauthRequired`] ask the user to [reauthenticate](Self::reauthenticate) before retrying.
+    /// Logging in counts as proving one's identity, while [impersonating](Self::impersonate) a user never does.
+    pub async fn require_recent_auth(
+        &self,
+        token: &str,
+        max_age: Duration,
+    ) -> Result<String, AuthError> {
+        self.require_recent_auth_bound(token, None, max_age).await
+    }
+
+    /// Like [`Self::require_recent_auth`], for a request over a connection presenting the client certificate
+    /// with the [thumbprint](crate::token::cert_thumbprint) `cert`, if any, see [`Self::verify_bound_token`].
+    pub async fn require_recent_auth_bound(
+        &self,
+        token: &str,
+        cert: Option<&str>,
+        max_age: Duration,
+    ) -> Result<String, AuthError> {
+        self.spanned("require_recent_auth", None, async {
+            let user = (self.verify_bound_token(token, cert)).ok_or(AuthError::InvalidToken)?;
+            self.record_user(&user);
+            let session = self
+                .token
+                .store
+                .read()
+                .unwrap()
+                .get(&token_key(token))
+                .map(|x| (x.authed, x.mfa, x.impersonator.is_some()));
+            let (authed, mfa, impersonated) = session.ok_or(AuthError::InvalidToken)?;
+            let recent = !authed.elapsed().is_ok_and(|d| d > max_age);
+            if impersonated || !recent || (!mfa && self.mfa_enabled(&user).await?) {
+                debug!("{} has to reauthenticate", self.log_user(&user));
+                return Err(AuthError::ReauthRequired);
+            }
+            Ok(user)
+        })
+        .await
+    }
+
+    /// Prove the identity of the user holding `token` again, so that [`Self::require_recent_auth`] passes.
+    ///
+    /// `second_factor` is a one-time password or WebAuthn response if the user [enabled](Self::mfa_enabled) a second factor,
+    /// and the password of the user otherwise.
+    /// Failures are counted against the [throttle](Self::throttles) and [lockout policy](crate::lockout::LockoutPolicy)
+    /// of the user, as those of [`Self::verify_login`].
+    pub async fn reauthenticate(&self, token: &str, second_factor: &str) -> Result<(), LoginError> {
+        self.reauthenticate_bound(token, None, second_factor).await
+    }
+
+    /// Like [`Self::reauthenticate`], for a request over a connection presenting the client certificate
+    /// with the [thumbprint](crate::token::cert_thumbprint) `cert`, if any, see [`Self::verify_bound_token`].
+    pub async fn reauthenticate_bound(
+        &self,
+        token: &str,
+        cert: Option<&str>,
+        second_factor: &str,
+    ) -> Result<(), LoginError> {
+        self.spanned("reauthenticate", None, async {
+            let user = (self.verify_bound_token(token, cert)).ok_or(LoginError::Unauthorized)?;
+            self.record_user(&user);
+            self.throttles
+                .user
+                .check(&user)
+                .map_err(LoginError::RateLimited)?;
+            self.check_lockout(&user).await?;
+            let mfa = self.mfa_enabled(&user).await?;
+            let valid = match mfa {
+                true => self.verify_second_factor(&user, second_factor).await?,
+                false => match self
+                    .verify_pass(&user, second_factor)
+                    .await
+                    .map_err(LoginError::from)
+                {
+                    Ok(valid) => valid,
+                    Err(LoginError::Unauthorized) => false,
+                    Err(e) => return Err(e),
+                },
+            };
+            if !valid {
+                self.throttles.user.hit(&user);
+                if !mfa {
+                    self.primary_factor_failed(&user).await?;
+                }
+                return Err(match mfa {
+                    true => LoginError::InvalidOtp,
+                    false => LoginError::Unauthorized,
+                });
+            }
+            self.throttles.user.reset(&user);
+            if let Some(session) = (self.token.store.write().unwrap()).get_mut(&token_key(token)) {
+                session.authed = SystemTime::now();
+                session.mfa = mfa;
+            }
+            info!("{} reauthenticated", self.log_user(&user));
+            Ok(())
+        })
+        .await
+    }
+
+    fn login_failed(&self, user: &str, ctx: &LoginContext) {
+        self.throttles.user.hit(user);
+        if let Some(ip) = ctx.ip {
+            self.throttles.ip.hit(&ip.to_string());
+            self.record_login_failure(ip);
+        }
+    }
+}
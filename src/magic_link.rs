@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::{
+    Basileus,
+    err::MagicLinkError,
+    event::AuthEvent,
+    history::LoginAttempt,
+    messenger::{Channel, Message},
+    rand_buf,
+};
+
+/// Magic link configuration, see [`Basileus::begin_magic_login`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MagicLinkConfig {
+    /// URL of the page completing the login, where `{token}` is replaced by the link token,
+    /// e.g. `https://example.com/login/magic?token={token}`.
+    ///
+    /// Magic links are disabled while this is empty.
+    #[cfg_attr(feature = "serde", serde(rename = "url"))]
+    pub url: String,
+    /// Time in seconds a link stays valid.
+    #[cfg_attr(feature = "serde", serde(rename = "ttl"))]
+    pub ttl: u64,
+    /// Time in seconds before another link is sent to the same user.
+    #[cfg_attr(feature = "serde", serde(rename = "interval"))]
+    pub interval: u64,
+    /// Whether links are only sent to [verified](crate::contact::Contact::verified) email addresses.
+    #[cfg_attr(feature = "serde", serde(rename = "require-verified"))]
+    pub require_verified: bool,
+    /// Subject of messages carrying links.
+    #[cfg_attr(feature = "serde", serde(rename = "subject"))]
+    pub subject: String,
+    /// Text of messages carrying links, where `{link}` is replaced by the link.
+    #[cfg_attr(feature = "serde", serde(rename = "body"))]
+    pub body: String,
+}
+
+impl Default for MagicLinkConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            ttl: 900,
+            interval: 60,
+            require_verified: true,
+            subject: "Log in".into(),
+            body: "Open the following link to log in:\n\n{link}\n\nIf you did not request this, ignore this message.".into(),
+        }
+    }
+}
+
+/// A link sent and not yet opened.
+struct Link {
+    user: String,
+    issued: SystemTime,
+}
+
+#[derive(Default)]
+pub struct MagicLinkModule {
+    /// Map from hashes of link tokens to pending links, so that the tokens themselves are never kept.
+    links: Mutex<HashMap<String, Link>>,
+    /// Time the last link was sent to each user.
+    sent: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl MagicLinkModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Key of a link token in [`MagicLinkModule::links`].
+fn hash_token(token: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(token))
+}
+
+impl Basileus {
+    /// Email a single-use login link to a user, identified by either their name or their email address.
+    ///
+    /// To not disclose which users exist, this succeeds without sending anything
+    /// if the user does not exist, has no (verified) email address,
+    /// or was sent a link less than [`MagicLinkConfig::interval`] ago.
+    pub async fn begin_magic_login(&self, user_or_email: &str) -> Result<(), MagicLinkError> {
+        self.spanned("begin_magic_login", None, async {
+            if !self.config.read().unwrap().modules.magic_link {
+                return Err(MagicLinkError::FeatureDisabled);
+            }
+            let config = self.config.read().unwrap().magic_link.clone();
+            if config.url.is_empty() {
+                return Err(MagicLinkError::Unconfigured);
+            }
+            let user = match self.contact_user(Channel::Email, user_or_email).await? {
+                Some(user) => user,
+                None if self.exist_user(user_or_email).await? => user_or_email.to_owned(),
+                None => {
+                    debug!("not sending magic link to unknown user");
+                    return Ok(());
+                }
+            };
+            self.record_user(&user);
+            let contact = self.get_contact(&user, Channel::Email).await?;
+            let Some(contact) = contact.filter(|x| x.verified || !config.require_verified) else {
+                debug!(
+                    "{} has no email address to send magic link to",
+                    self.log_user(&user)
+                );
+                return Ok(());
+            };
+            let now = SystemTime::now();
+            let interval = Duration::from_secs(config.interval);
+            {
+                let mut sent = self.magic_link.sent.lock().unwrap();
+                sent.retain(|_, x| now.duration_since(*x).is_ok_and(|d| d < interval));
+                if sent.contains_key(&user) {
+                    warn!("throttled magic link to {}", self.log_user(&user));
+                    self.count("magic.throttled");
+                    return Ok(());
+                }
+                sent.insert(user.clone(), now);
+            }
+            let token = BASE64_URL_SAFE_NO_PAD.encode(rand_buf(32));
+            let link = config.url.replace("{token}", &token);
+            let message = Message {
+                channel: Channel::Email,
+                to: contact.address,
+                subject: config.subject,
+                body: config.body.replace("{link}", &link).into(),
+            };
+            self.send_message(&message).await?;
+            let ttl = Duration::from_secs(config.ttl);
+            let mut links = self.magic_link.links.lock().unwrap();
+            links.retain(|_, x| now.duration_since(x.issued).is_ok_and(|d| d < ttl));
+            links.insert(
+                hash_token(&token),
+                Link {
+                    user: user.clone(),
+                    issued: now,
+                },
+            );
+            info!("sent magic link to {}", self.log_user(&user));
+            Ok(())
+        })
+        .await
+    }
+
+    /// Complete a login by the token of a link sent by [`Self::begin_magic_login`], returning a session token.
+    ///
+    /// Every link is accepted only once, and every other link pending for the user is invalidated.
+    /// Users who enabled or have to enroll a second factor fail as [`Self::login`] does after a correct password.
+    pub async fn complete_magic_login(&self, token: &str) -> Result<String, MagicLinkError> {
+        self.spanned("complete_magic_login", None, async {
+            if !self.config.read().unwrap().modules.magic_link {
+                return Err(MagicLinkError::FeatureDisabled);
+            }
+            let res = self.complete_magic_login_inner(token).await;
+            self.count(match res {
+                Ok(_) => "magic.success",
+                Err(_) => "magic.failure",
+            });
+            res
+        })
+        .await
+    }
+
+    async fn complete_magic_login_inner(&self, token: &str) -> Result<String, MagicLinkError> {
+        let ttl = Duration::from_secs(self.config.read().unwrap().magic_link.ttl);
+        let link = {
+            let mut links = self.magic_link.links.lock().unwrap();
+            let link = links
+                .remove(&hash_token(token))
+                .ok_or(MagicLinkError::InvalidToken)?;
+            links.retain(|_, x| x.user != link.user);
+            link
+        };
+        self.record_user(&link.user);
+        if link.issued.elapsed().is_ok_and(|x| x > ttl) {
+            return Err(MagicLinkError::Expired);
+        }
+        if !self.exist_user(&link.user).await? {
+            return Err(MagicLinkError::InvalidToken);
+        }
+        self.check_passwordless(&link.user).await?;
+        info!("{} logged in by magic link", self.log_user(&link.user));
+        self.emit(AuthEvent::LoginSucceeded {
+            user: link.user.clone(),
+        });
+        let attempt = LoginAttempt::new("magic_link");
+        self.record_login_attempt(&link.user, &attempt).await;
+        Ok(self.issue_token(&link.user))
+    }
+}
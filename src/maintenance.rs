@@ -0,0 +1,128 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{Basileus, unix_now};
+use sqlx::query;
+
+use tracing::{debug, warn};
+
+/// Configuration of periodic maintenance, see [`Basileus::run_maintenance`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MaintenanceConfig {
+    /// Time in seconds between maintenance passes.
+    #[cfg_attr(feature = "serde", serde(rename = "interval"))]
+    pub interval: u64,
+    /// Age in seconds after which entries of the [permission history](Basileus::perm_history) are deleted,
+    /// or [`None`] to keep them forever.
+    #[cfg_attr(feature = "serde", serde(rename = "perm-history-retention"))]
+    pub perm_history_retention: Option<u64>,
+    /// Age in seconds after which records of the [audit log](Basileus::query_audit) are deleted,
+    /// or [`None`] to keep them forever.
+    #[cfg_attr(feature = "serde", serde(rename = "audit-retention"))]
+    pub audit_retention: Option<u64>,
+    /// Age in seconds after which [login attempts](Basileus::login_history) are deleted,
+    /// or [`None`] to keep them forever.
+    #[cfg_attr(feature = "serde", serde(rename = "login-history-retention"))]
+    pub login_history_retention: Option<u64>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            perm_history_retention: None,
+            audit_retention: None,
+            // 90 days
+            login_history_retention: Some(7776000),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MaintenanceModule {
+    /// Time the last maintenance pass finished.
+    last: Mutex<Option<SystemTime>>,
+}
+
+impl Basileus {
+    /// Run a single maintenance pass, pruning expired tokens, PKCE requests, trusted devices, account locks, IP blocks, idle rate limits and old history, audit records and login attempts,
+    /// and optimizing the database.
+    pub async fn maintain(&self) -> Result<(), sqlx::error::Error> {
+        let config = self.config.read().unwrap().maintenance.clone();
+        self.expire_tokens();
+        self.expire_pkce();
+        self.throttles.prune();
+        if self.config.read().unwrap().modules.mfa {
+            let q = query(self.sql("DELETE FROM trusted_device WHERE expires_at <= ?"))
+                .bind(unix_now());
+            let res = q.execute(&self.writer).await?;
+            debug!("pruned {} expired trusted devices", res.rows_affected());
+        }
+        if let Some(retention) = config.perm_history_retention {
+            let q = query(self.sql("DELETE FROM perm_log WHERE time < ?"))
+                .bind(unix_now().saturating_sub(retention as i64));
+            let res = q.execute(&self.writer).await?;
+            debug!("pruned {} permission history entries", res.rows_affected());
+        }
+        self.flush_audit().await?;
+        if let Some(retention) = config.audit_retention {
+            let q = query(self.sql("DELETE FROM audit_log WHERE time < ?"))
+                .bind(unix_now().saturating_sub(retention as i64));
+            let res = q.execute(&self.writer).await?;
+            debug!("pruned {} audit records", res.rows_affected());
+        }
+        let q = query(self.sql("DELETE FROM account_lock WHERE until <= ?")).bind(unix_now());
+        let res = q.execute(&self.writer).await?;
+        debug!("pruned {} expired account locks", res.rows_affected());
+        let window = self.config.read().unwrap().lockout.window;
+        self.lockout.prune(Duration::from_secs(window));
+        let q = query(self.sql("DELETE FROM ip_block WHERE expires_at <= ?")).bind(unix_now());
+        let res = q.execute(&self.writer).await?;
+        debug!("pruned {} expired ip blocks", res.rows_affected());
+        if let Some(retention) = config.login_history_retention {
+            let q = query(self.sql("DELETE FROM login_history WHERE time < ?"))
+                .bind(unix_now().saturating_sub(retention as i64));
+            let res = q.execute(&self.writer).await?;
+            debug!("pruned {} login attempts", res.rows_affected());
+        }
+        query("PRAGMA incremental_vacuum")
+            .execute(&self.writer)
+            .await?;
+        query("PRAGMA optimize").execute(&self.writer).await?;
+        *self.maintenance.last.lock().unwrap() = Some(SystemTime::now());
+        debug!("finished maintenance");
+        Ok(())
+    }
+
+    /// Run [maintenance](Self::maintain) every [interval](MaintenanceConfig::interval) until the library is [closed](Self::close).
+    ///
+    /// The library does not depend on an async runtime, so `sleep` must be provided, e.g. `tokio::time::sleep`.
+    /// Failed passes are logged and retried at the next interval.
+    pub async fn run_maintenance<F, Fut>(&self, sleep: F)
+    where
+        F: Fn(Duration) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        loop {
+            // read every time, since the interval may be reloaded
+            let interval = Duration::from_secs(self.config.read().unwrap().maintenance.interval);
+            sleep(interval).await;
+            if self.writer.is_closed() {
+                break;
+            }
+            if let Err(e) = self.maintain().await {
+                warn!("maintenance failed: {e}");
+            }
+        }
+        debug!("stopped maintenance");
+    }
+
+    /// Time the last [maintenance](Self::maintain) pass finished, if any.
+    pub fn last_maintenance(&self) -> Option<SystemTime> {
+        *self.maintenance.last.lock().unwrap()
+    }
+}
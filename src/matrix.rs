@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    Basileus,
+    acl::{AclGrant, AclSubject},
+    err::ImportPermError,
+    perm::{Perm, PermEvent},
+    role::check_rolename,
+};
+use sqlx::{query, query_as};
+
+use tracing::info;
+
+/// A snapshot of every permission assignment, see [`Basileus::export_perms`].
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PermMatrix {
+    /// Map from users to the permissions they hold directly.
+    #[cfg_attr(feature = "serde", serde(rename = "users"))]
+    pub users: BTreeMap<String, Perm>,
+    /// Map from roles to the permissions they bundle.
+    #[cfg_attr(feature = "serde", serde(rename = "roles"))]
+    pub roles: BTreeMap<String, Perm>,
+    /// Map from users to the roles assigned to them.
+    #[cfg_attr(feature = "serde", serde(rename = "user-roles"))]
+    pub user_roles: BTreeMap<String, Vec<String>>,
+    /// Map from resources to their access control entries.
+    #[cfg_attr(feature = "serde", serde(rename = "acl"))]
+    pub acl: BTreeMap<String, Vec<AclGrant>>,
+}
+
+impl Basileus {
+    /// Export every permission assignment, including roles and access control entries.
+    pub async fn export_perms(&self) -> Result<PermMatrix, sqlx::error::Error> {
+        let mut tx = self.db.begin().await?;
+        let mut matrix = PermMatrix::default();
+
+        let q = query_as(self.sql("SELECT user FROM user"));
+        let users: Vec<(String,)> = q.fetch_all(&mut *tx).await?;
+        for (user,) in users {
+            matrix.users.insert(user.clone(), Perm::default());
+            matrix.user_roles.insert(user, vec![]);
+        }
+        let q = query_as(self.sql("SELECT user, grp FROM user_perm"));
+        let res: Vec<(String, String)> = q.fetch_all(&mut *tx).await?;
+        for (user, grp) in res {
+            matrix.users.entry(user).or_default().insert(grp);
+        }
+
+        let q = query_as(self.sql("SELECT role FROM role"));
+        let roles: Vec<(String,)> = q.fetch_all(&mut *tx).await?;
+        for (role,) in roles {
+            matrix.roles.insert(role, Perm::default());
+        }
+        let q = query_as(self.sql("SELECT role, grp FROM role_perm"));
+        let res: Vec<(String, String)> = q.fetch_all(&mut *tx).await?;
+        for (role, grp) in res {
+            matrix.roles.entry(role).or_default().insert(grp);
+        }
+
+        let q = query_as(self.sql("SELECT user, role FROM user_role ORDER BY user, role"));
+        let res: Vec<(String, String)> = q.fetch_all(&mut *tx).await?;
+        for (user, role) in res {
+            matrix.user_roles.entry(user).or_default().push(role);
+        }
+
+        let q = query_as(self.sql(
+            "SELECT resource, kind, subject, action FROM acl ORDER BY resource, kind, subject, action",
+        ));
+        let res: Vec<(String, String, String, String)> = q.fetch_all(&mut *tx).await?;
+        for (resource, kind, subject, action) in res {
+            matrix.acl.entry(resource).or_default().push(AclGrant {
+                subject: AclSubject::from_row(&kind, subject),
+                action,
+            });
+        }
+
+        tx.commit().await?;
+        Ok(matrix)
+    }
+
+    /// Import permission assignments, e.g. previously [exported](Self::export_perms) from another environment.
+    ///
+    /// Every user, role and resource present in `matrix` has its assignments replaced by those in `matrix`,
+    /// with missing roles created.
+    /// Users, roles and resources absent from `matrix` are left untouched.
+    /// Users are never created, so every user in `matrix` must already exist.
+    ///
+    /// The import is atomic, i.e. either everything or nothing is imported.
+    /// Once imported, a [`PermEvent`] is reported for every user and role whose assignments changed.
+    pub async fn import_perms(&self, matrix: &PermMatrix) -> Result<(), ImportPermError> {
+        let mut tx = self.begin_write().await?;
+        let mut events = vec![];
+
+        let grantees = matrix
+            .acl
+            .values()
+            .flatten()
+            .filter_map(|x| match &x.subject {
+                AclSubject::User(user) => Some(user),
+                AclSubject::Perm(_) => None,
+            });
+        let users: HashSet<&String> = matrix
+            .users
+            .keys()
+            .chain(matrix.user_roles.keys())
+            .chain(grantees)
+            .collect();
+        for user in users {
+            let q =
+                query_as(self.sql("SELECT EXISTS(SELECT 1 FROM user WHERE user = ?)")).bind(user);
+            let (exist,): (i32,) = q.fetch_one(&mut *tx).await?;
+            if exist != 1 {
+                return Err(ImportPermError::UserNotExist(user.into()));
+            }
+        }
+
+        for (role, perm) in &matrix.roles {
+            if !check_rolename(role) {
+                return Err(ImportPermError::InvalidRoleName(role.into()));
+            }
+            if let Some(invalid) = perm.find_invalid() {
+                return Err(ImportPermError::InvalidPerm(invalid.into()));
+            }
+            query(self.sql("INSERT OR IGNORE INTO role (role) VALUES (?);"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            let q = query_as(self.sql("SELECT grp FROM role_perm WHERE role = ?")).bind(role);
+            let prev: Vec<(String,)> = q.fetch_all(&mut *tx).await?;
+            let prev: Perm = prev
+                .into_iter()
+                .map(|(x,)| x)
+                .collect::<HashSet<_>>()
+                .into();
+            if prev != *perm {
+                events.push(PermEvent::Role { role: role.clone() });
+            }
+            query(self.sql("DELETE FROM role_perm WHERE role = ?"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            for grp in perm.iter() {
+                query(self.sql("INSERT INTO role_perm (role, grp) VALUES (?, ?);"))
+                    .bind(role)
+                    .bind(grp)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        for (user, perm) in &matrix.users {
+            if let Some(invalid) = perm.find_invalid() {
+                return Err(ImportPermError::InvalidPerm(invalid.into()));
+            }
+            let Some((added, removed)) = self
+                .write_perm(&mut tx, None, user, |_| perm.clone())
+                .await?
+            else {
+                return Err(ImportPermError::UserNotExist(user.into()));
+            };
+            if !added.is_empty() || !removed.is_empty() {
+                events.push(PermEvent::User {
+                    user: user.clone(),
+                    actor: None,
+                    added,
+                    removed,
+                });
+            }
+        }
+
+        for (user, roles) in &matrix.user_roles {
+            let q = query_as(self.sql("SELECT role FROM user_role WHERE user = ?")).bind(user);
+            let prev: Vec<(String,)> = q.fetch_all(&mut *tx).await?;
+            let prev: HashSet<String> = prev.into_iter().map(|(x,)| x).collect();
+            for role in prev.iter().filter(|x| !roles.contains(x)) {
+                events.push(PermEvent::UserRole {
+                    user: user.clone(),
+                    role: role.clone(),
+                    assigned: false,
+                });
+            }
+            for role in roles.iter().filter(|x| !prev.contains(*x)) {
+                events.push(PermEvent::UserRole {
+                    user: user.clone(),
+                    role: role.clone(),
+                    assigned: true,
+                });
+            }
+            query(self.sql("DELETE FROM user_role WHERE user = ?"))
+                .bind(user)
+                .execute(&mut *tx)
+                .await?;
+            for role in roles {
+                let q = query_as(self.sql("SELECT EXISTS(SELECT 1 FROM role WHERE role = ?)"))
+                    .bind(role);
+                let (exist,): (i32,) = q.fetch_one(&mut *tx).await?;
+                if exist != 1 {
+                    return Err(ImportPermError::RoleNotExist(role.into()));
+                }
+                query(self.sql("INSERT INTO user_role (user, role) VALUES (?, ?);"))
+                    .bind(user)
+                    .bind(role)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        for (resource, grants) in &matrix.acl {
+            query(self.sql("DELETE FROM acl WHERE resource = ?"))
+                .bind(resource)
+                .execute(&mut *tx)
+                .await?;
+            for grant in grants {
+                if let Some(invalid) = grant.subject.find_invalid() {
+                    return Err(ImportPermError::InvalidPerm(invalid.into()));
+                }
+                let (kind, subject) = match &grant.subject {
+                    AclSubject::User(user) => ("user", user.clone()),
+                    AclSubject::Perm(perm) => ("perm", perm.to_string()),
+                };
+                query(
+                    self.sql("INSERT OR IGNORE INTO acl (resource, kind, subject, action) VALUES (?, ?, ?, ?);"),
+                )
+                .bind(resource)
+                .bind(kind)
+                .bind(subject)
+                .bind(&grant.action)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        self.perm.invalidate_all();
+        for event in events {
+            self.emit_perm(event);
+        }
+        info!(
+            "imported permissions of {} users, {} roles and {} resources",
+            matrix.users.len(),
+            matrix.roles.len(),
+            matrix.acl.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use std::{pin::Pin, sync::Arc};
+
+use tracing::{debug, warn};
+
+use crate::{Basileus, err::SendMessageError, logging::Secret};
+
+/// Channel a message is delivered through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum Channel {
+    #[cfg_attr(feature = "serde", serde(rename = "email"))]
+    Email,
+    #[cfg_attr(feature = "serde", serde(rename = "sms"))]
+    Sms,
+}
+
+impl Channel {
+    /// Name of the channel, as stored in the database.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Sms => "sms",
+        }
+    }
+}
+
+/// A message to a user, e.g. carrying a verification code.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub channel: Channel,
+    /// Address of the recipient, i.e. an email address or a phone number.
+    pub to: String,
+    /// Subject of the message, ignored by channels without subjects such as SMS.
+    pub subject: String,
+    /// Plain text of the message, which may carry a code or a link logging the user in.
+    pub body: Secret<String>,
+}
+
+/// Future returned by [`Messenger::send`].
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// Deliverer of messages to users, see [`Basileus::set_messenger`].
+///
+/// The library depends on neither a mail nor an SMS gateway client,
+/// so applications implement this for their provider, or use `SmtpMessenger` of the `smtp` feature for email.
+pub trait Messenger: Send + Sync {
+    /// Deliver `message`, failing with a description of the problem unless the provider accepted it.
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a>;
+}
+
+/// Security of connections to the SMTP server.
+#[cfg(feature = "smtp")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SmtpTls {
+    /// TLS from the start, typically on port 465.
+    #[cfg_attr(feature = "serde", serde(rename = "tls"))]
+    Tls,
+    /// Plain connection upgraded with `STARTTLS`, which is required, typically on port 587.
+    #[cfg_attr(feature = "serde", serde(rename = "starttls"))]
+    StartTls,
+    /// Plain connection, only suitable for relays on the same host.
+    #[cfg_attr(feature = "serde", serde(rename = "none"))]
+    None,
+}
+
+/// SMTP configuration, see [`SmtpMessenger`].
+#[cfg(feature = "smtp")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SmtpConfig {
+    /// Host name of the SMTP server.
+    ///
+    /// If set, [`Basileus::new`] delivers emails through this server.
+    #[cfg_attr(feature = "serde", serde(rename = "host"))]
+    pub host: Option<String>,
+    /// Port of the SMTP server, or the default port of [`Self::tls`] if unset.
+    #[cfg_attr(feature = "serde", serde(rename = "port"))]
+    pub port: Option<u16>,
+    #[cfg_attr(feature = "serde", serde(rename = "tls"))]
+    pub tls: SmtpTls,
+    /// User to log in to the SMTP server as, if it requires authentication.
+    #[cfg_attr(feature = "serde", serde(rename = "username"))]
+    pub username: Option<String>,
+    /// Password to log in to the SMTP server with,
+    /// which may reference the environment or a file, see [`Config::resolve_secrets`](crate::Config::resolve_secrets).
+    #[cfg_attr(feature = "serde", serde(rename = "password"))]
+    pub password: Option<Secret<String>>,
+    /// Sender of emails, e.g. `Example <no-reply@example.com>`.
+    #[cfg_attr(feature = "serde", serde(rename = "from"))]
+    pub from: String,
+}
+
+#[cfg(feature = "smtp")]
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: None,
+            tls: SmtpTls::StartTls,
+            username: None,
+            password: None,
+            from: String::new(),
+        }
+    }
+}
+
+/// [`Messenger`] delivering emails through an SMTP server, using the tokio runtime.
+///
+/// Other channels are rejected.
+#[cfg(feature = "smtp")]
+#[derive(Clone)]
+pub struct SmtpMessenger {
+    transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+    from: lettre::message::Mailbox,
+}
+
+#[cfg(feature = "smtp")]
+impl SmtpMessenger {
+    /// Connect to the server configured, failing if [`SmtpConfig::host`] is unset,
+    /// or the host or [sender](SmtpConfig::from) is invalid.
+    pub fn new(config: &SmtpConfig) -> Result<Self, crate::err::ConfigError> {
+        use crate::err::{ConfigError, ConfigProblem};
+        use lettre::{AsyncSmtpTransport, transport::smtp::authentication::Credentials};
+
+        let invalid = |e: String| ConfigError::Invalid(vec![ConfigProblem::InvalidSmtp(e)]);
+        let host = config
+            .host
+            .as_deref()
+            .ok_or_else(|| invalid("no host".into()))?;
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| invalid(format!("sender '{}': {e}", config.from)))?;
+        let mut builder = match config.tls {
+            SmtpTls::Tls => AsyncSmtpTransport::<lettre::Tokio1Executor>::relay(host),
+            SmtpTls::StartTls => AsyncSmtpTransport::<lettre::Tokio1Executor>::starttls_relay(host),
+            SmtpTls::None => {
+                Ok(AsyncSmtpTransport::<lettre::Tokio1Executor>::builder_dangerous(host))
+            }
+        }
+        .map_err(|e| invalid(format!("host '{host}': {e}")))?;
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &config.username {
+            let password = config.password.clone().unwrap_or_default().into_inner();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[cfg(feature = "smtp")]
+impl Messenger for SmtpMessenger {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+        use lettre::AsyncTransport;
+
+        Box::pin(async move {
+            if message.channel != Channel::Email {
+                return Err(format!(
+                    "can not send {} through SMTP",
+                    message.channel.name()
+                ));
+            }
+            let to = message
+                .to
+                .parse()
+                .map_err(|e| format!("invalid recipient: {e}"))?;
+            let email = lettre::Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(&message.subject)
+                .header(lettre::message::header::ContentType::TEXT_PLAIN)
+                .body(message.body.expose().clone())
+                .map_err(|e| e.to_string())?;
+            self.transport
+                .send(email)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(())
+        })
+    }
+}
+
+impl Basileus {
+    /// Deliver messages to users with `messenger`, replacing any previously set.
+    ///
+    /// Flows sending messages, e.g. [contact verification](Self::begin_contact_verification), fail until a messenger is set.
+    pub fn set_messenger(&self, messenger: impl Messenger + 'static) {
+        *self.messenger.write().unwrap() = Some(Arc::new(messenger));
+    }
+
+    /// Stop delivering messages.
+    pub fn clear_messenger(&self) {
+        *self.messenger.write().unwrap() = None;
+    }
+
+    /// Deliver `message` with the [messenger](Self::set_messenger).
+    pub async fn send_message(&self, message: &Message) -> Result<(), SendMessageError> {
+        let messenger = self.messenger.read().unwrap().clone();
+        let messenger = messenger.ok_or(SendMessageError::NoMessenger)?;
+        match messenger.send(message).await {
+            Ok(()) => {
+                debug!("sent {} message", message.channel.name());
+                self.count(match message.channel {
+                    Channel::Email => "message.email",
+                    Channel::Sms => "message.sms",
+                });
+                Ok(())
+            }
+            Err(e) => {
+                warn!("failed to send {} message: {e}", message.channel.name());
+                self.count("message.failure");
+                Err(SendMessageError::Delivery(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::Basileus;
+
+/// Receiver of performance measurements, see [`Basileus::set_metrics`].
+///
+/// Every method defaults to doing nothing, so implementors only override what they are interested in.
+/// Methods are invoked synchronously on hot paths, so they should return quickly,
+/// e.g. by updating atomic counters or forwarding to a metrics library.
+pub trait Metrics: Send + Sync {
+    /// Record the time an operation took, e.g. `argon2.hash` or `perm.effective`.
+    fn operation(&self, op: &'static str, elapsed: Duration) {
+        let _ = (op, elapsed);
+    }
+
+    /// Record the time waited for a database connection and lock to write.
+    fn acquire(&self, wait: Duration) {
+        let _ = wait;
+    }
+
+    /// Record the state of the pool of connections for writing, i.e. the number of open and idle connections.
+    fn pool(&self, size: u32, idle: usize) {
+        let _ = (size, idle);
+    }
+
+    /// Count an occurrence of an authentication event, i.e. one of
+    ///
+    /// - `login.success`, `login.failure` and `login.error` on [password verification](Basileus::verify_pass),
+    /// - `token.issued`, `token.verified`, `token.rejected` and `token.revoked`,
+    /// - `pkce.code_issued`, `pkce.auth_failed`, `pkce.token_issued` and `pkce.token_failed`,
+    /// - `saml.success` and `saml.failure` on SAML login,
+    /// - `oidc.success` and `oidc.failure` on OIDC login,
+    /// - `message.email`, `message.sms` and `message.failure` on [sending messages](Basileus::send_message),
+    /// - `magic.success`, `magic.failure` and `magic.throttled` on magic link login,
+    /// - `qr.success` on [cross-device login](Basileus::poll_qr_login),
+    /// - `challenge.issued`, `challenge.passed` and `challenge.failed` on [challenges](Basileus::check_challenge),
+    /// - `mfa.success`, `mfa.failure` and `mfa.recovery` on second factor verification,
+    /// - `push.sent` on [push approval](Basileus::set_push_approver) requests,
+    /// - `risk.allow`, `risk.step_up` and `risk.deny` on [risk evaluation](Basileus::set_risk_evaluator),
+    /// - `lockout.locked` when the [lockout policy](crate::lockout::LockoutPolicy) locks an account,
+    /// - `mfa.trusted` when a [trusted device](Basileus::trust_device) skips a second factor,
+    /// - `oob.sent`, `oob.success` and `oob.failure` on [login codes](Basileus::send_login_code).
+    fn event(&self, event: &'static str) {
+        let _ = event;
+    }
+}
+
+/// Forwarder of measurements to the [`metrics`](https://docs.rs/metrics) facade, and thereby e.g. to a Prometheus exporter.
+///
+/// | Measurement | Metric |
+/// | --- | --- |
+/// | [`Metrics::operation`] | histogram `basileus_operation_seconds` labelled by `op` |
+/// | [`Metrics::acquire`] | histogram `basileus_db_acquire_seconds` |
+/// | [`Metrics::pool`] | gauges `basileus_db_connections` and `basileus_db_idle_connections` |
+/// | [`Metrics::event`] | counter `basileus_events_total` labelled by `event` |
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetricsFacade;
+
+#[cfg(feature = "metrics")]
+impl Metrics for MetricsFacade {
+    fn operation(&self, op: &'static str, elapsed: Duration) {
+        ::metrics::histogram!("basileus_operation_seconds", "op" => op).record(elapsed);
+    }
+
+    fn acquire(&self, wait: Duration) {
+        ::metrics::histogram!("basileus_db_acquire_seconds").record(wait);
+    }
+
+    fn pool(&self, size: u32, idle: usize) {
+        ::metrics::gauge!("basileus_db_connections").set(size);
+        ::metrics::gauge!("basileus_db_idle_connections").set(idle as f64);
+    }
+
+    fn event(&self, event: &'static str) {
+        ::metrics::counter!("basileus_events_total", "event" => event).increment(1);
+    }
+}
+
+impl Basileus {
+    /// Report performance measurements to `metrics`, replacing any previously set.
+    pub fn set_metrics(&self, metrics: impl Metrics + 'static) {
+        *self.metrics.write().unwrap() = Some(Arc::new(metrics));
+    }
+
+    /// Stop reporting performance measurements.
+    pub fn clear_metrics(&self) {
+        *self.metrics.write().unwrap() = None;
+    }
+
+    fn metrics(&self) -> Option<Arc<dyn Metrics>> {
+        self.metrics.read().unwrap().clone()
+    }
+
+    /// Report the time elapsed since `start` for `op`.
+    pub(crate) fn record(&self, op: &'static str, start: Instant) {
+        if let Some(metrics) = self.metrics() {
+            metrics.operation(op, start.elapsed());
+        }
+    }
+
+    /// Count an occurrence of `event`.
+    pub(crate) fn count(&self, event: &'static str) {
+        if let Some(metrics) = self.metrics() {
+            metrics.event(event);
+        }
+    }
+
+    /// Report the time elapsed since `start` waiting for the database, along with the state of the pool.
+    pub(crate) fn record_acquire(&self, start: Instant) {
+        if let Some(metrics) = self.metrics() {
+            metrics.acquire(start.elapsed());
+            metrics.pool(self.writer.size(), self.writer.num_idle());
+        }
+    }
+}
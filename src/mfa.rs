@@ -0,0 +1,806 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, Transaction, query, query_as};
+use tracing::{debug, info, warn};
+
+use crate::{
+    Basileus, constant_time_eq,
+    err::{GetPermError, MfaError},
+    from_unix,
+    logging::Secret,
+    rand_buf, unix_now, url_encode,
+};
+
+/// Initialize the table of second factors.
+///
+/// `counter` is the last period used of TOTP secrets, and the next counter expected of HOTP tokens.
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS mfa_factor (
+    id INTEGER NOT NULL PRIMARY KEY,
+    user TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    secret BLOB NOT NULL,
+    counter INTEGER NOT NULL DEFAULT 0,
+    enabled INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    UNIQUE (user, kind, name),
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_mfa_factor_user ON mfa_factor (user);
+"#;
+
+/// Multi-factor authentication configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct MfaConfig {
+    /// Name of the service shown by authenticator apps.
+    #[cfg_attr(feature = "serde", serde(rename = "issuer"))]
+    pub issuer: String,
+    /// Number of digits of one-time passwords, from 6 to 8.
+    #[cfg_attr(feature = "serde", serde(rename = "digits"))]
+    pub digits: u32,
+    /// Time in seconds each TOTP is valid for.
+    #[cfg_attr(feature = "serde", serde(rename = "period"))]
+    pub period: u64,
+    /// Number of periods before and after the current one whose TOTPs are accepted as well,
+    /// tolerating clock drift and typing delays.
+    #[cfg_attr(feature = "serde", serde(rename = "skew"))]
+    pub skew: u64,
+    /// Number of HOTP counters following the last one used whose codes are accepted,
+    /// see [`Basileus::verify_hotp`].
+    #[cfg_attr(feature = "serde", serde(rename = "look-ahead"))]
+    pub look_ahead: u64,
+    /// Length of newly generated secrets in bytes, at least 16.
+    #[cfg_attr(feature = "serde", serde(rename = "secret-length"))]
+    pub secret_length: usize,
+    /// Groups whose members cannot log in before enrolling a second factor, see [`Basileus::mfa_required`].
+    #[cfg_attr(feature = "serde", serde(rename = "required-groups"))]
+    pub required_groups: Vec<String>,
+    /// Number of recovery codes generated at once, see [`Basileus::regenerate_recovery_codes`].
+    #[cfg_attr(feature = "serde", serde(rename = "recovery-codes"))]
+    pub recovery_codes: usize,
+    /// Time in seconds a [trusted device](Basileus::trust_device) skips second factors,
+    /// or [`None`] to never trust devices.
+    #[cfg_attr(feature = "serde", serde(rename = "trusted-device-ttl"))]
+    pub trusted_device_ttl: Option<u64>,
+    /// Time in seconds a [continuation](MfaChallenge) of a login awaiting its second factor stays valid.
+    #[cfg_attr(feature = "serde", serde(rename = "challenge-ttl"))]
+    pub challenge_ttl: u64,
+    /// Time in seconds users have to respond to [push approval](crate::push::PushApprover) requests.
+    #[cfg_attr(feature = "serde", serde(rename = "push-timeout"))]
+    pub push_timeout: u64,
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "basileus".into(),
+            digits: 6,
+            period: 30,
+            skew: 1,
+            look_ahead: 10,
+            secret_length: 20,
+            recovery_codes: 10,
+            required_groups: vec![],
+            trusted_device_ttl: Some(30 * 24 * 3600),
+            challenge_ttl: 300,
+            push_timeout: 60,
+        }
+    }
+}
+
+/// Kind of a second factor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum MfaKind {
+    /// Time-based one-time passwords of an authenticator app, see [`Basileus::begin_totp_enrollment`].
+    #[cfg_attr(feature = "serde", serde(rename = "totp"))]
+    Totp,
+    /// Counter-based one-time passwords of a hardware token, see [`Basileus::enroll_hotp`].
+    #[cfg_attr(feature = "serde", serde(rename = "hotp"))]
+    Hotp,
+    /// Codes delivered out of band, see [`Basileus::enable_oob`].
+    #[cfg_attr(feature = "serde", serde(rename = "oob"))]
+    Oob,
+    /// Security keys, see [`Basileus::begin_webauthn_registration`].
+    #[cfg_attr(feature = "serde", serde(rename = "webauthn"))]
+    Webauthn,
+    /// Approval on a device, see [`Basileus::enable_push`].
+    #[cfg_attr(feature = "serde", serde(rename = "push"))]
+    Push,
+}
+
+impl MfaKind {
+    /// Parse the kind as stored in the database.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "totp" => Some(Self::Totp),
+            "hotp" => Some(Self::Hotp),
+            "oob" => Some(Self::Oob),
+            "webauthn" => Some(Self::Webauthn),
+            "push" => Some(Self::Push),
+            _ => None,
+        }
+    }
+}
+
+/// An enabled second factor, see [`Basileus::mfa_status`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MfaFactor {
+    pub kind: MfaKind,
+    /// Name distinguishing factors of the same kind, i.e. the name of HOTP tokens,
+    /// the channel of out-of-band codes, the credential ID of security keys and the device of push approval,
+    /// and empty for TOTP.
+    pub name: String,
+    pub created_at: SystemTime,
+}
+
+/// Second factors of a user and whether policy requires them, see [`Basileus::mfa_status`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MfaStatus {
+    /// Second factors enabled, oldest first.
+    pub factors: Vec<MfaFactor>,
+    /// Number of unused recovery codes.
+    pub recovery_codes: u64,
+    /// Whether the user [has to](Basileus::mfa_required) enroll a second factor.
+    pub required: bool,
+    /// Whether the user has to enroll a second factor but did not yet, and thus cannot log in.
+    pub enrollment_required: bool,
+}
+
+/// Continuation of a login whose password was verified but whose second factor is missing,
+/// see [`LoginError::MfaRequired`](crate::err::LoginError::MfaRequired).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MfaChallenge {
+    /// Identifier to present as [`LoginContext::mfa_challenge`](crate::login::LoginContext::mfa_challenge)
+    /// along with the second factor, instead of the password.
+    pub id: String,
+    /// Kinds of second factors the user may present.
+    pub methods: Vec<MfaKind>,
+    /// Time in seconds until the challenge expires.
+    pub expires_in: u64,
+}
+
+/// A login awaiting its second factor.
+struct PendingMfa {
+    user: String,
+    issued: SystemTime,
+}
+
+#[derive(Default)]
+pub struct MfaModule {
+    /// Map from [challenge](MfaChallenge) identifiers to the logins awaiting their second factor.
+    pending: Mutex<HashMap<String, PendingMfa>>,
+}
+
+impl MfaModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// A TOTP secret awaiting confirmation, see [`Basileus::begin_totp_enrollment`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct TotpEnrollment {
+    /// The secret, base32-encoded for manual entry into authenticator apps.
+    pub secret: Secret<String>,
+    /// The `otpauth://` URI of the secret, to be displayed as QR code.
+    pub uri: Secret<String>,
+}
+
+/// Encode `buf` as base32 without padding, as defined in [RFC 4648](https://datatracker.ietf.org/doc/html/rfc4648#section-6).
+fn base32(buf: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut res = String::with_capacity(buf.len().div_ceil(5) * 8);
+    for chunk in buf.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block.iter().fold(0u64, |acc, x| acc << 8 | *x as u64);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            res.push(ALPHABET[(bits >> (35 - i * 5)) as usize & 31] as char);
+        }
+    }
+    res
+}
+
+/// Random recovery code of 16 base32 characters, grouped in fours by dashes.
+fn recovery_code() -> String {
+    let code = base32(&rand_buf(10));
+    let groups: Vec<&str> = (0..code.len())
+        .step_by(4)
+        .map(|i| &code[i..i + 4])
+        .collect();
+    groups.join("-")
+}
+
+/// Hash of a recovery code as stored, ignoring case, spaces and dashes of the input.
+fn hash_recovery_code(code: &str) -> Vec<u8> {
+    let code: String = code
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    Sha256::digest(code).to_vec()
+}
+
+/// HMAC-based one-time password of `counter`, as defined in [RFC 4226](https://datatracker.ietf.org/doc/html/rfc4226#section-5.3).
+pub(crate) fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[19] & 0xf) as usize;
+    let bin = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    let code = bin as u64 % 10u64.pow(digits);
+    format!("{code:0width$}", width = digits as usize)
+}
+
+impl Basileus {
+    /// Generate a TOTP secret for `user`, to be added to an authenticator app
+    /// and [confirmed](Self::confirm_totp_enrollment) with a code generated by it.
+    ///
+    /// Any unconfirmed secret is replaced.
+    pub async fn begin_totp_enrollment(&self, user: &str) -> Result<TotpEnrollment, MfaError> {
+        self.spanned("begin_totp_enrollment", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Totp) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            if !self.exist_user(user).await? {
+                return Err(MfaError::UserNotExist(user.into()));
+            }
+            let config = self.config.read().unwrap().mfa.clone();
+            let secret = rand_buf(config.secret_length);
+            let q = query(self.sql(
+                "INSERT INTO mfa_factor (user, kind, name, secret, created_at) VALUES (?, 'totp', '', ?, ?)
+                ON CONFLICT (user, kind, name) DO UPDATE SET secret = excluded.secret, counter = 0,
+                created_at = excluded.created_at WHERE NOT enabled",
+            ))
+            .bind(user)
+            .bind(&secret)
+            .bind(unix_now());
+            if q.execute(&self.writer).await?.rows_affected() == 0 {
+                return Err(MfaError::AlreadyEnrolled(user.into()));
+            }
+            let secret = base32(&secret);
+            let label = format!("{}:{user}", config.issuer);
+            let uri = format!(
+                "otpauth://totp/{}?secret={secret}&issuer={}&algorithm=SHA1&digits={}&period={}",
+                url_encode(&label),
+                url_encode(&config.issuer),
+                config.digits,
+                config.period
+            );
+            debug!("generated TOTP secret for {}", self.log_user(user));
+            Ok(TotpEnrollment {
+                secret: secret.into(),
+                uri: uri.into(),
+            })
+        })
+        .await
+    }
+
+    /// Enable the TOTP secret of `user` [generated](Self::begin_totp_enrollment) earlier,
+    /// if `code` was generated by it.
+    ///
+    /// Returns `None` if the code was wrong, and otherwise the [recovery codes](Self::regenerate_recovery_codes)
+    /// generated for the user, to be shown once.
+    /// No codes are generated if the user has some left, e.g. from enrolling another factor.
+    pub async fn confirm_totp_enrollment(
+        &self,
+        user: &str,
+        code: &str,
+    ) -> Result<Option<Vec<String>>, MfaError> {
+        self.spanned("confirm_totp_enrollment", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Totp) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            if !self.check_totp(user, code, false).await? {
+                return Ok(None);
+            }
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql(
+                "UPDATE mfa_factor SET enabled = 1 WHERE user = ? AND kind = 'totp' AND name = ''",
+            ))
+            .bind(user);
+            q.execute(&mut *tx).await?;
+            let codes = self.initial_recovery_codes(&mut tx, user).await?;
+            tx.commit().await?;
+            info!("enabled TOTP for {}", self.log_user(user));
+            Ok(Some(codes))
+        })
+        .await
+    }
+
+    /// Remove the TOTP secret of `user`, whether confirmed or not.
+    ///
+    /// Recovery codes are removed as well once the user has no second factor left.
+    /// Returns whether a secret existed.
+    pub async fn disable_totp(&self, user: &str) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let mut tx = self.begin_write().await?;
+        let q =
+            query(self.sql("DELETE FROM mfa_factor WHERE user = ? AND kind = 'totp'")).bind(user);
+        let res = q.execute(&mut *tx).await?.rows_affected() > 0;
+        self.prune_recovery_codes(&mut tx, user).await?;
+        tx.commit().await?;
+        if res {
+            info!("disabled TOTP for {}", self.log_user(user));
+        }
+        Ok(res)
+    }
+
+    /// Verify a TOTP of `user` generated within [`MfaConfig::skew`] periods of now.
+    ///
+    /// Every code is accepted once only, and so are earlier codes once a later one was used.
+    pub async fn verify_totp(&self, user: &str, code: &str) -> Result<bool, MfaError> {
+        self.spanned("verify_totp", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Totp) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            let res = self.check_totp(user, code, true).await?;
+            self.count(if res { "mfa.success" } else { "mfa.failure" });
+            Ok(res)
+        })
+        .await
+    }
+
+    /// Check `code` against the TOTP secret of `user`, which must be enabled if `enabled` is set,
+    /// and mark the period of the code used.
+    async fn check_totp(&self, user: &str, code: &str, enabled: bool) -> Result<bool, MfaError> {
+        let q = query_as(self.sql(
+            "SELECT id, secret, counter, enabled FROM mfa_factor WHERE user = ? AND kind = 'totp' AND name = ''",
+        ))
+        .bind(user);
+        let res: Option<(i64, Vec<u8>, i64, bool)> = q.fetch_optional(&self.db).await?;
+        let Some((id, secret, last, on)) = res.filter(|x| x.3 || !enabled) else {
+            return Err(MfaError::NotEnrolled(user.into()));
+        };
+        if on != enabled {
+            return Err(MfaError::AlreadyEnrolled(user.into()));
+        }
+        let config = self.config.read().unwrap().mfa.clone();
+        let now = unix_now().max(0) as u64 / config.period;
+        let step = (now.saturating_sub(config.skew)..=now + config.skew)
+            .find(|x| constant_time_eq(&hotp(&secret, *x, config.digits), code));
+        let Some(step) = step else {
+            debug!("wrong TOTP for {}", self.log_user(user));
+            return Ok(false);
+        };
+        // only succeed if no concurrent verification used this or a later period
+        let q = query(self.sql("UPDATE mfa_factor SET counter = ? WHERE id = ? AND counter < ?"))
+            .bind(step as i64)
+            .bind(id)
+            .bind(step as i64);
+        if step as i64 <= last || q.execute(&self.writer).await?.rows_affected() == 0 {
+            warn!("replayed TOTP for {}", self.log_user(user));
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Enroll a hardware token generating HOTPs from `secret`, naming it `name`, e.g. after its serial number.
+    ///
+    /// `code` is the first code displayed by the token, which is checked against the [look-ahead](MfaConfig::look_ahead)
+    /// window from a counter of zero.
+    /// Returns `None` if the code was wrong, and otherwise the recovery codes as [`Self::confirm_totp_enrollment`].
+    pub async fn enroll_hotp(
+        &self,
+        user: &str,
+        name: &str,
+        secret: &[u8],
+        code: &str,
+    ) -> Result<Option<Vec<String>>, MfaError> {
+        self.spanned("enroll_hotp", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Hotp) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            if !self.exist_user(user).await? {
+                return Err(MfaError::UserNotExist(user.into()));
+            }
+            let config = self.config.read().unwrap().mfa.clone();
+            let Some(counter) = (0..config.look_ahead)
+                .find(|x| constant_time_eq(&hotp(secret, *x, config.digits), code))
+            else {
+                return Ok(None);
+            };
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql(
+                "INSERT OR IGNORE INTO mfa_factor (user, kind, name, secret, counter, enabled, created_at)
+                VALUES (?, 'hotp', ?, ?, ?, 1, ?)",
+            ))
+            .bind(user)
+            .bind(name)
+            .bind(secret)
+            .bind(counter as i64 + 1)
+            .bind(unix_now());
+            if q.execute(&mut *tx).await?.rows_affected() == 0 {
+                return Err(MfaError::AlreadyEnrolled(user.into()));
+            }
+            let codes = self.initial_recovery_codes(&mut tx, user).await?;
+            tx.commit().await?;
+            info!("enrolled HOTP token '{name}' for {}", self.log_user(user));
+            Ok(Some(codes))
+        })
+        .await
+    }
+
+    /// Remove the HOTP token `name` of `user`, returning whether it existed.
+    ///
+    /// Recovery codes are removed as well once the user has no second factor left.
+    pub async fn remove_hotp(&self, user: &str, name: &str) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let mut tx = self.begin_write().await?;
+        let q =
+            query(self.sql("DELETE FROM mfa_factor WHERE user = ? AND kind = 'hotp' AND name = ?"))
+                .bind(user)
+                .bind(name);
+        let res = q.execute(&mut *tx).await?.rows_affected() > 0;
+        self.prune_recovery_codes(&mut tx, user).await?;
+        tx.commit().await?;
+        if res {
+            info!("removed HOTP token '{name}' of {}", self.log_user(user));
+        }
+        Ok(res)
+    }
+
+    /// Names of the HOTP tokens of `user`.
+    pub async fn list_hotp(&self, user: &str) -> Result<Vec<String>, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(vec![]);
+        }
+        let q = query_as(
+            self.sql("SELECT name FROM mfa_factor WHERE user = ? AND kind = 'hotp' ORDER BY name"),
+        )
+        .bind(user);
+        let res: Vec<(String,)> = q.fetch_all(&self.db).await?;
+        Ok(res.into_iter().map(|(x,)| x).collect())
+    }
+
+    /// Verify a HOTP of any token of `user`.
+    ///
+    /// Codes are accepted from the counter following the last code used,
+    /// up to [`MfaConfig::look_ahead`] counters further to tolerate button presses whose codes were never entered.
+    /// Every code is accepted once only, and so are earlier codes once a later one was used.
+    /// No code is accepted while second factors are [disabled](crate::config::ModuleConfig::mfa).
+    pub async fn verify_hotp(&self, user: &str, code: &str) -> Result<bool, sqlx::error::Error> {
+        self.spanned("verify_hotp", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Hotp) {
+                return Ok(false);
+            }
+            let res = self.check_hotp(user, code).await?;
+            self.count(if res { "mfa.success" } else { "mfa.failure" });
+            Ok(res)
+        })
+        .await
+    }
+
+    async fn check_hotp(&self, user: &str, code: &str) -> Result<bool, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT id, name, secret, counter FROM mfa_factor WHERE user = ? AND kind = 'hotp' AND enabled",
+        ))
+        .bind(user);
+        let tokens: Vec<(i64, String, Vec<u8>, i64)> = q.fetch_all(&self.db).await?;
+        let config = self.config.read().unwrap().mfa.clone();
+        for (id, name, secret, next) in tokens {
+            let next = next as u64;
+            let Some(counter) = (next..next + config.look_ahead)
+                .find(|x| constant_time_eq(&hotp(&secret, *x, config.digits), code))
+            else {
+                continue;
+            };
+            // only succeed if no concurrent verification used this or a later counter
+            let q =
+                query(self.sql("UPDATE mfa_factor SET counter = ? WHERE id = ? AND counter = ?"))
+                    .bind(counter as i64 + 1)
+                    .bind(id)
+                    .bind(next as i64);
+            if q.execute(&self.writer).await?.rows_affected() == 0 {
+                warn!("replayed HOTP of token '{name}' of {}", self.log_user(user));
+                return Ok(false);
+            }
+            return Ok(true);
+        }
+        debug!("wrong HOTP for {}", self.log_user(user));
+        Ok(false)
+    }
+
+    /// Replace the recovery codes of `user` with new ones, to be shown once.
+    ///
+    /// Each recovery code may be used once in place of a one-time password,
+    /// so that users who lost their authenticator can still log in.
+    /// Only users who [enabled](Self::mfa_enabled) a second factor have recovery codes.
+    pub async fn regenerate_recovery_codes(&self, user: &str) -> Result<Vec<String>, MfaError> {
+        self.spanned("regenerate_recovery_codes", Some(user), async {
+            if !self.config.read().unwrap().modules.mfa {
+                return Err(MfaError::FeatureDisabled);
+            }
+            if !self.mfa_enabled(user).await? {
+                return Err(MfaError::NotEnrolled(user.into()));
+            }
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql("DELETE FROM mfa_factor WHERE user = ? AND kind = 'recovery'"))
+                .bind(user);
+            q.execute(&mut *tx).await?;
+            let codes = self.issue_recovery_codes(&mut tx, user).await?;
+            tx.commit().await?;
+            info!("regenerated recovery codes of {}", self.log_user(user));
+            Ok(codes)
+        })
+        .await
+    }
+
+    /// Consume a recovery code of `user`, returning whether it was valid and unused.
+    ///
+    /// No code is accepted while second factors are [disabled](crate::config::ModuleConfig::mfa).
+    pub async fn verify_recovery_code(
+        &self,
+        user: &str,
+        code: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        self.spanned("verify_recovery_code", Some(user), async {
+            if !self.config.read().unwrap().modules.mfa {
+                return Ok(false);
+            }
+            let q =
+                query(self.sql(
+                    "DELETE FROM mfa_factor WHERE user = ? AND kind = 'recovery' AND secret = ?",
+                ))
+                .bind(user)
+                .bind(hash_recovery_code(code));
+            let res = q.execute(&self.writer).await?.rows_affected() > 0;
+            if res {
+                warn!("{} used a recovery code", self.log_user(user));
+                self.count("mfa.recovery");
+            }
+            Ok(res)
+        })
+        .await
+    }
+
+    /// Number of unused recovery codes of `user`, e.g. to remind users to regenerate them.
+    pub async fn count_recovery_codes(&self, user: &str) -> Result<u64, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(0);
+        }
+        let q = query_as(
+            self.sql("SELECT COUNT(*) FROM mfa_factor WHERE user = ? AND kind = 'recovery'"),
+        )
+        .bind(user);
+        let (res,): (i64,) = q.fetch_one(&self.db).await?;
+        Ok(res as u64)
+    }
+
+    /// Generate recovery codes for `user` who enabled a second factor, unless some are left.
+    pub(crate) async fn initial_recovery_codes(
+        &self,
+        tx: &mut Transaction<'static, Sqlite>,
+        user: &str,
+    ) -> Result<Vec<String>, sqlx::error::Error> {
+        let q =
+            query_as(self.sql(
+                "SELECT EXISTS(SELECT 1 FROM mfa_factor WHERE user = ? AND kind = 'recovery')",
+            ))
+            .bind(user);
+        let (exist,): (bool,) = q.fetch_one(&mut **tx).await?;
+        match exist {
+            true => Ok(vec![]),
+            false => self.issue_recovery_codes(tx, user).await,
+        }
+    }
+
+    async fn issue_recovery_codes(
+        &self,
+        tx: &mut Transaction<'static, Sqlite>,
+        user: &str,
+    ) -> Result<Vec<String>, sqlx::error::Error> {
+        let count = self.config.read().unwrap().mfa.recovery_codes;
+        let codes: Vec<String> = (0..count).map(|_| recovery_code()).collect();
+        for (i, code) in codes.iter().enumerate() {
+            let q = query(self.sql(
+                "INSERT INTO mfa_factor (user, kind, name, secret, enabled, created_at) VALUES (?, 'recovery', ?, ?, 1, ?)",
+            ))
+            .bind(user)
+            .bind(i.to_string())
+            .bind(hash_recovery_code(code))
+            .bind(unix_now());
+            q.execute(&mut **tx).await?;
+        }
+        Ok(codes)
+    }
+
+    /// Remove the recovery codes of `user` if no second factor is left.
+    pub(crate) async fn prune_recovery_codes(
+        &self,
+        tx: &mut Transaction<'static, Sqlite>,
+        user: &str,
+    ) -> Result<(), sqlx::error::Error> {
+        let q = query(self.sql(
+            "DELETE FROM mfa_factor WHERE user = ? AND kind = 'recovery' AND NOT EXISTS
+            (SELECT 1 FROM mfa_factor WHERE user = ? AND kind <> 'recovery' AND enabled)",
+        ))
+        .bind(user)
+        .bind(user);
+        q.execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    /// Verify `code` against every second factor `user` enabled, see [`Basileus::verify_login`].
+    ///
+    /// `code` is a one-time password, or the response to a pending [WebAuthn](Self::begin_webauthn_assertion) challenge.
+    /// Recovery codes are accepted as well.
+    pub(crate) async fn verify_second_factor(
+        &self,
+        user: &str,
+        code: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        if self.webauthn_pending(user) && self.check_webauthn(user, code).await? {
+            self.count("mfa.success");
+            return Ok(true);
+        }
+        let res = match self.check_totp(user, code, true).await {
+            Ok(res) => res,
+            Err(MfaError::SQL(e)) => return Err(e),
+            Err(_) => false,
+        };
+        if res || self.check_hotp(user, code).await? || self.check_oob_code(user, code).await? {
+            self.count("mfa.success");
+            return Ok(true);
+        }
+        if self.verify_recovery_code(user, code).await? {
+            return Ok(true);
+        }
+        self.count("mfa.failure");
+        Ok(false)
+    }
+
+    /// Whether second factors of `kind` are accepted, see [`ModuleConfig`](crate::config::ModuleConfig).
+    pub(crate) fn mfa_kind_enabled(&self, kind: MfaKind) -> bool {
+        let modules = &self.config.read().unwrap().modules;
+        modules.mfa
+            && match kind {
+                MfaKind::Totp | MfaKind::Hotp => true,
+                MfaKind::Oob => modules.oob,
+                MfaKind::Webauthn => modules.webauthn,
+                MfaKind::Push => modules.push,
+            }
+    }
+
+    /// Whether `user` is a member of any of [`MfaConfig::required_groups`], and thus has to enroll a second factor.
+    ///
+    /// Membership is checked against the effective permissions of the user,
+    /// so that holders of wildcards covering a group count as members.
+    /// Nobody has to while second factors are [disabled](crate::config::ModuleConfig::mfa).
+    pub async fn mfa_required(&self, user: &str) -> Result<bool, sqlx::error::Error> {
+        let groups = self.config.read().unwrap().mfa.required_groups.clone();
+        if groups.is_empty() || !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let perm = match self.get_effective_perm(user).await {
+            Ok(perm) => perm,
+            Err(GetPermError::SQL(e)) => return Err(e),
+            Err(GetPermError::UserNotExist(_)) => return Ok(false),
+        };
+        Ok(groups.iter().any(|x| perm.satisfies(&x.into())))
+    }
+
+    /// Second factors `user` enabled, and whether policy requires them.
+    pub async fn mfa_status(&self, user: &str) -> Result<MfaStatus, MfaError> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Err(MfaError::FeatureDisabled);
+        }
+        if !self.exist_user(user).await? {
+            return Err(MfaError::UserNotExist(user.into()));
+        }
+        let factors = self.mfa_factors(user).await?;
+        let required = self.mfa_required(user).await?;
+        Ok(MfaStatus {
+            enrollment_required: required && factors.is_empty(),
+            factors,
+            recovery_codes: self.count_recovery_codes(user).await?,
+            required,
+        })
+    }
+
+    /// Second factors `user` enabled, oldest first.
+    async fn mfa_factors(&self, user: &str) -> Result<Vec<MfaFactor>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT kind, name, created_at FROM mfa_factor
+            WHERE user = ? AND kind <> 'recovery' AND enabled ORDER BY created_at, id",
+        ))
+        .bind(user);
+        let res: Vec<(String, String, i64)> = q.fetch_all(&self.db).await?;
+        Ok(res
+            .into_iter()
+            .filter_map(|(kind, name, created_at)| {
+                Some(MfaFactor {
+                    kind: MfaKind::from_name(&kind)?,
+                    name,
+                    created_at: from_unix(created_at),
+                })
+            })
+            .collect())
+    }
+
+    /// Issue a [challenge](MfaChallenge) continuing the login of `user`, whose password was verified.
+    pub(crate) async fn issue_mfa_challenge(
+        &self,
+        user: &str,
+    ) -> Result<MfaChallenge, sqlx::error::Error> {
+        let mut methods: Vec<MfaKind> = vec![];
+        for factor in self.mfa_factors(user).await? {
+            if !methods.contains(&factor.kind) && self.mfa_kind_enabled(factor.kind) {
+                methods.push(factor.kind);
+            }
+        }
+        let ttl = self.config.read().unwrap().mfa.challenge_ttl;
+        let id = BASE64_URL_SAFE_NO_PAD.encode(rand_buf(32));
+        let now = SystemTime::now();
+        let mut pending = self.mfa.pending.lock().unwrap();
+        pending.retain(|_, x| {
+            now.duration_since(x.issued)
+                .is_ok_and(|d| d < Duration::from_secs(ttl))
+        });
+        let login = PendingMfa {
+            user: user.into(),
+            issued: now,
+        };
+        pending.insert(id.clone(), login);
+        Ok(MfaChallenge {
+            id,
+            methods,
+            expires_in: ttl,
+        })
+    }
+
+    /// Whether `id` identifies an unexpired [challenge](MfaChallenge) issued to `user`.
+    pub(crate) fn check_mfa_challenge(&self, id: &str, user: &str) -> bool {
+        let ttl = Duration::from_secs(self.config.read().unwrap().mfa.challenge_ttl);
+        let pending = self.mfa.pending.lock().unwrap();
+        pending
+            .get(id)
+            .is_some_and(|x| x.user == user && x.issued.elapsed().is_ok_and(|d| d < ttl))
+    }
+
+    /// Discard the [challenge](MfaChallenge) `id` once its login completed.
+    pub(crate) fn finish_mfa_challenge(&self, id: &str) {
+        self.mfa.pending.lock().unwrap().remove(id);
+    }
+
+    /// Whether `user` has enabled any second factor, and thus has to present one to log in.
+    ///
+    /// Always false while second factors are [disabled](crate::config::ModuleConfig::mfa).
+    pub async fn mfa_enabled(&self, user: &str) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let q = query_as(self.sql(
+            "SELECT EXISTS(SELECT 1 FROM mfa_factor WHERE user = ? AND kind <> 'recovery' AND enabled)",
+        ))
+        .bind(user);
+        let (res,): (bool,) = q.fetch_one(&self.db).await?;
+        Ok(res)
+    }
+}
@@ -0,0 +1,204 @@
+use sqlx::{SqliteConnection, SqlitePool, query, query_as};
+
+use tracing::{info, warn};
+
+use crate::{
+    Basileus, acl, audit, blocklist, canary,
+    config::ModuleConfig,
+    contact,
+    db::{begin_write, prefixed},
+    device, group, history, identity, lockout, mfa, pass, perm, role, token, unix_now, user,
+};
+
+/// Initialize the version table.
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at INTEGER NOT NULL
+);
+"#;
+
+/// A single step of schema migration.
+#[derive(Clone, Copy, Debug)]
+pub struct Migration {
+    /// Version of the schema after applying the migration.
+    pub version: i64,
+    /// Human-readable description.
+    pub description: &'static str,
+    /// SQL scripts executed in order.
+    pub sql: &'static [&'static str],
+}
+
+/// Every migration known to the library, in ascending order of versions.
+///
+/// Migrations must never be modified once released; change the schema by appending a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: &[
+            user::DB_INIT,
+            pass::DB_INIT,
+            perm::DB_INIT,
+            role::DB_INIT,
+            acl::DB_INIT,
+            group::DB_INIT,
+            crate::DB_INIT_PUBKEY,
+            crate::DB_INIT,
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "persisted tokens",
+        sql: &[token::DB_INIT],
+    },
+    Migration {
+        version: 3,
+        description: "linked external identities",
+        sql: &[identity::DB_INIT],
+    },
+    Migration {
+        version: 4,
+        description: "contact addresses",
+        sql: &[contact::DB_INIT],
+    },
+    Migration {
+        version: 5,
+        description: "second factors",
+        sql: &[mfa::DB_INIT],
+    },
+    Migration {
+        version: 6,
+        description: "trusted devices",
+        sql: &[device::DB_INIT],
+    },
+    Migration {
+        version: 7,
+        description: "audit log",
+        sql: &[audit::DB_INIT],
+    },
+    Migration {
+        version: 8,
+        description: "login history",
+        sql: &[history::DB_INIT],
+    },
+    Migration {
+        version: 9,
+        description: "ip blocklist",
+        sql: &[blocklist::DB_INIT],
+    },
+    Migration {
+        version: 10,
+        description: "account lockout",
+        sql: &[lockout::DB_INIT],
+    },
+    Migration {
+        version: 11,
+        description: "certificate-bound tokens",
+        sql: &[token::DB_MIGRATE_CERT],
+    },
+    Migration {
+        version: 12,
+        description: "canary tokens",
+        sql: &[canary::DB_INIT],
+    },
+    Migration {
+        version: 13,
+        description: "hashed persisted tokens",
+        sql: &[token::DB_MIGRATE_HASH],
+    },
+];
+
+/// Scripts creating the tables of a [module](ModuleConfig), with whether the module is enabled.
+///
+/// These are skipped by migrations while the module is disabled,
+/// and run on every start while it is enabled, so they must be idempotent.
+fn module_scripts(modules: &ModuleConfig) -> [(&'static str, bool); 3] {
+    [
+        (crate::DB_INIT_PUBKEY, modules.pubkey),
+        (mfa::DB_INIT, modules.mfa),
+        (device::DB_INIT, modules.mfa),
+    ]
+}
+
+/// Migrate databases created by versions of the library without versioned schema.
+async fn migrate_legacy(conn: &mut SqliteConnection) -> Result<(), sqlx::error::Error> {
+    let (legacy_perm,): (i32,) =
+        query_as("SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)")
+            .bind("perm")
+            .fetch_one(&mut *conn)
+            .await?;
+    if legacy_perm == 1 {
+        query(perm::DB_MIGRATE_LEGACY).execute(&mut *conn).await?;
+        info!("migrated legacy user permissions");
+    }
+    Ok(())
+}
+
+async fn schema_version(db: &SqlitePool, prefix: &str) -> Result<i64, sqlx::error::Error> {
+    let sql = prefixed(
+        prefix,
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+    );
+    let (version,): (i64,) = query_as(sql).fetch_one(db).await?;
+    Ok(version)
+}
+
+/// Bring the database schema up to date by applying every pending migration.
+pub(crate) async fn migrate(
+    db: &SqlitePool,
+    prefix: &str,
+    modules: &ModuleConfig,
+) -> Result<(), sqlx::error::Error> {
+    let scripts = module_scripts(modules);
+    query(prefixed(prefix, DB_INIT)).execute(db).await?;
+    let current = schema_version(db, prefix).await?;
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    if current > latest {
+        warn!("database schema version {current} is newer than the supported version {latest}");
+        return Ok(());
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut tx = begin_write(db).await?;
+        for sql in migration.sql {
+            if scripts.iter().any(|(x, enabled)| x == sql && !enabled) {
+                continue;
+            }
+            query(prefixed(prefix, sql)).execute(&mut *tx).await?;
+        }
+        // legacy versions did not support table prefixes, so a prefixed schema starts empty
+        // rather than taking over the unprefixed legacy tables
+        if current == 0 && migration.version == 1 && prefix.is_empty() {
+            migrate_legacy(&mut tx).await?;
+        }
+        let sql = prefixed(
+            prefix,
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?);",
+        );
+        query(sql)
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(unix_now())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        info!(
+            "migrated database schema to version {}: {}",
+            migration.version, migration.description
+        );
+    }
+    // modules enabled since the database was created
+    for (sql, _) in scripts.iter().filter(|(_, enabled)| *enabled) {
+        query(prefixed(prefix, sql)).execute(db).await?;
+    }
+    Ok(())
+}
+
+impl Basileus {
+    /// Get the version of the database schema, see [`MIGRATIONS`].
+    pub async fn schema_version(&self) -> Result<i64, sqlx::error::Error> {
+        let prefix = self.config.read().unwrap().sqlite.table_prefix.clone();
+        schema_version(&self.db, &prefix).await
+    }
+}
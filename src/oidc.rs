@@ -0,0 +1,438 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::{
+    Basileus,
+    err::{CreateUserError, LinkIdentityError, OidcError},
+    event::AuthEvent,
+    history::LoginAttempt,
+    logging::Secret,
+    rand_buf, to_unix, url_encode,
+};
+
+/// An upstream OpenID Connect provider, see [`OidcConfig::providers`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OidcProvider {
+    /// Issuer identifier, which ID tokens must carry as `iss`, e.g. `https://accounts.google.com`.
+    #[cfg_attr(feature = "serde", serde(rename = "issuer"))]
+    pub issuer: String,
+    #[cfg_attr(feature = "serde", serde(rename = "client-id"))]
+    pub client_id: String,
+    /// Client secret, which may reference the environment or a file, see [`Config::resolve_secrets`](crate::Config::resolve_secrets).
+    ///
+    /// Public clients have none and rely on PKCE only.
+    #[cfg_attr(feature = "serde", serde(rename = "client-secret"))]
+    pub client_secret: Option<Secret<String>>,
+    #[cfg_attr(feature = "serde", serde(rename = "authorization-endpoint"))]
+    pub authorization_endpoint: String,
+    #[cfg_attr(feature = "serde", serde(rename = "token-endpoint"))]
+    pub token_endpoint: String,
+    /// URL the provider redirects back to, which must be registered at the provider.
+    #[cfg_attr(feature = "serde", serde(rename = "redirect-uri"))]
+    pub redirect_uri: String,
+    /// Scopes requested, which must include `openid`.
+    #[cfg_attr(feature = "serde", serde(rename = "scopes"))]
+    pub scopes: Vec<String>,
+    /// Whether to create a user on the first login of an unlinked subject, instead of rejecting it.
+    #[cfg_attr(feature = "serde", serde(rename = "create-users"))]
+    pub create_users: bool,
+    /// Claim the name of created users is taken from, e.g. `preferred_username` or `email`.
+    #[cfg_attr(feature = "serde", serde(rename = "user-claim"))]
+    pub user_claim: String,
+}
+
+impl Default for OidcProvider {
+    fn default() -> Self {
+        Self {
+            issuer: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+            authorization_endpoint: String::new(),
+            token_endpoint: String::new(),
+            redirect_uri: String::new(),
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+            create_users: false,
+            user_claim: "preferred_username".into(),
+        }
+    }
+}
+
+/// OpenID Connect federation configuration, see [`Basileus::oidc_authorize`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OidcConfig {
+    /// Map from names, e.g. `google`, to upstream providers.
+    #[cfg_attr(feature = "serde", serde(rename = "providers"))]
+    pub providers: HashMap<String, OidcProvider>,
+    /// Time in seconds the user may take to authenticate at the provider.
+    #[cfg_attr(feature = "serde", serde(rename = "request-ttl"))]
+    pub request_ttl: u64,
+    /// Tolerated difference in seconds between the clocks of this host and the providers.
+    #[cfg_attr(feature = "serde", serde(rename = "clock-skew"))]
+    pub clock_skew: u64,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            providers: HashMap::new(),
+            request_ttl: 600,
+            clock_skew: 60,
+        }
+    }
+}
+
+/// Verifier of asymmetric ID token signatures, see [`Basileus::set_oidc_verifier`].
+///
+/// `HS256` tokens are verified with the client secret by the library itself,
+/// while e.g. `RS256` or `ES256` require the keys the provider publishes at its `jwks_uri`,
+/// which the application fetches and caches.
+pub trait OidcVerifier: Send + Sync {
+    /// Verify `signature` over `signing_input` with the key `kid` of `provider` for the algorithm `alg`.
+    fn verify(
+        &self,
+        provider: &str,
+        alg: &str,
+        kid: Option<&str>,
+        signing_input: &[u8],
+        signature: &[u8],
+    ) -> bool;
+}
+
+/// An authorization request to redirect the user agent to.
+#[derive(Clone, Debug)]
+pub struct OidcRequest {
+    /// Value of the `state` parameter, which the provider passes back to the redirect URI.
+    pub state: String,
+    /// URL of the provider's authorization endpoint with every parameter.
+    pub url: String,
+}
+
+/// A request to the provider's token endpoint, to be posted as `application/x-www-form-urlencoded`,
+/// see [`Basileus::oidc_callback`].
+#[derive(Clone, Debug)]
+pub struct OidcTokenRequest {
+    pub url: String,
+    pub form: Vec<(&'static str, String)>,
+}
+
+/// A successful login through an upstream provider.
+#[derive(Clone, Debug)]
+pub struct OidcLogin {
+    pub user: String,
+    pub token: Secret<String>,
+    /// Whether the user has been created by this login.
+    pub created: bool,
+}
+
+/// An authorization request awaiting the callback.
+struct Pending {
+    provider: String,
+    nonce: String,
+    verifier: String,
+    issued: SystemTime,
+}
+
+#[derive(Default)]
+pub struct OidcModule {
+    /// Map from `state` to pending requests.
+    pending: Mutex<HashMap<String, Pending>>,
+    verifier: RwLock<Option<Arc<dyn OidcVerifier>>>,
+}
+
+impl OidcModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Random base64URL-encoded string for `state`, `nonce` and the PKCE verifier.
+fn random() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand_buf(32))
+}
+
+fn malformed(e: impl std::fmt::Display) -> OidcError {
+    OidcError::Malformed(e.to_string())
+}
+
+/// Decode a base64URL-encoded JSON object of a JWT.
+fn decode_part(part: &str) -> Result<Value, OidcError> {
+    let buf = BASE64_URL_SAFE_NO_PAD.decode(part).map_err(malformed)?;
+    let value: Value = serde_json::from_slice(&buf).map_err(malformed)?;
+    if !value.is_object() {
+        return Err(malformed("not a JSON object"));
+    }
+    Ok(value)
+}
+
+impl Basileus {
+    /// Verify asymmetric ID token signatures with `verifier`, replacing any previously set.
+    ///
+    /// Such tokens are rejected until a verifier is set.
+    pub fn set_oidc_verifier(&self, verifier: impl OidcVerifier + 'static) {
+        *self.oidc.verifier.write().unwrap() = Some(Arc::new(verifier));
+    }
+
+    /// Start logging in through the upstream `provider` by the authorization code flow with PKCE.
+    ///
+    /// The user agent is redirected to [`OidcRequest::url`],
+    /// and must return within [`OidcConfig::request_ttl`] seconds, see [`Self::oidc_callback`].
+    pub fn oidc_authorize(&self, provider: &str) -> Result<OidcRequest, OidcError> {
+        let config = self.config.read().unwrap().oidc.clone();
+        let upstream = config
+            .providers
+            .get(provider)
+            .ok_or_else(|| OidcError::UnknownProvider(provider.into()))?;
+        let (state, nonce, verifier) = (random(), random(), random());
+        let challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(&verifier));
+        let params = [
+            ("response_type", "code"),
+            ("client_id", &upstream.client_id),
+            ("redirect_uri", &upstream.redirect_uri),
+            ("scope", &upstream.scopes.join(" ")),
+            ("state", &state),
+            ("nonce", &nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        let query: Vec<_> = params
+            .iter()
+            .map(|(k, v)| format!("{k}={}", url_encode(v)))
+            .collect();
+        let sep = if upstream.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!(
+            "{}{sep}{}",
+            upstream.authorization_endpoint,
+            query.join("&")
+        );
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(config.request_ttl);
+        let mut pending = self.oidc.pending.lock().unwrap();
+        pending.retain(|_, x| now.duration_since(x.issued).is_ok_and(|d| d < ttl));
+        pending.insert(
+            state.clone(),
+            Pending {
+                provider: provider.into(),
+                nonce,
+                verifier,
+                issued: now,
+            },
+        );
+        debug!("issued OIDC authorization request to {provider}");
+        Ok(OidcRequest { state, url })
+    }
+
+    /// Finish logging in with the `state` and `code` passed to the redirect URI.
+    ///
+    /// The library does not depend on an HTTP client,
+    /// so `exchange` must post the [`OidcTokenRequest`] and return the body of the successful response.
+    /// The ID token therein is validated, and the user its subject is [linked](Self::link_identity) to is logged in.
+    ///
+    /// Unlinked subjects are given a new user if [`OidcProvider::create_users`] is set, and rejected otherwise.
+    /// Subjects are never linked to existing users by e.g. matching email addresses,
+    /// since that would let the provider take over local accounts.
+    /// Locked accounts fail with [`LoginError::AccountLocked`](crate::err::LoginError::AccountLocked).
+    pub async fn oidc_callback<F, Fut, E>(
+        &self,
+        state: &str,
+        code: &str,
+        exchange: F,
+    ) -> Result<OidcLogin, OidcError>
+    where
+        F: FnOnce(OidcTokenRequest) -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: std::fmt::Display,
+    {
+        let fut = self.oidc_callback_inner(state, code, exchange);
+        let res = self.spanned("oidc_callback", None, fut).await;
+        match &res {
+            Ok(login) => {
+                self.count("oidc.success");
+                self.emit(AuthEvent::LoginSucceeded {
+                    user: login.user.clone(),
+                });
+                let attempt = LoginAttempt::new("oidc");
+                self.record_login_attempt(&login.user, &attempt).await;
+            }
+            Err(_) => self.count("oidc.failure"),
+        }
+        res
+    }
+
+    async fn oidc_callback_inner<F, Fut, E>(
+        &self,
+        state: &str,
+        code: &str,
+        exchange: F,
+    ) -> Result<OidcLogin, OidcError>
+    where
+        F: FnOnce(OidcTokenRequest) -> Fut,
+        Fut: Future<Output = Result<String, E>>,
+        E: std::fmt::Display,
+    {
+        let config = self.config.read().unwrap().oidc.clone();
+        // each request is answered at most once
+        let pending = self.oidc.pending.lock().unwrap().remove(state);
+        let ttl = Duration::from_secs(config.request_ttl);
+        let pending = pending
+            .filter(|x| {
+                SystemTime::now()
+                    .duration_since(x.issued)
+                    .is_ok_and(|d| d < ttl)
+            })
+            .ok_or(OidcError::UnknownState)?;
+        let name = pending.provider;
+        let provider = config
+            .providers
+            .get(&name)
+            .ok_or_else(|| OidcError::UnknownProvider(name.clone()))?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code".to_owned()),
+            ("code", code.to_owned()),
+            ("redirect_uri", provider.redirect_uri.clone()),
+            ("client_id", provider.client_id.clone()),
+            ("code_verifier", pending.verifier),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret.expose().clone()));
+        }
+        let req = OidcTokenRequest {
+            url: provider.token_endpoint.clone(),
+            form,
+        };
+        let body = exchange(req)
+            .await
+            .map_err(|e| OidcError::Exchange(e.to_string()))?;
+        let body: Value = serde_json::from_str(&body).map_err(malformed)?;
+        let id_token = body["id_token"]
+            .as_str()
+            .ok_or_else(|| malformed("missing ID token"))?;
+
+        let claims = self.validate_id_token(&name, provider, id_token)?;
+        let now = to_unix(SystemTime::now());
+        let skew = config.clock_skew as i64;
+        if claims["iss"].as_str() != Some(&provider.issuer) {
+            return Err(OidcError::InvalidClaims("iss".into()));
+        }
+        let aud_ok = match &claims["aud"] {
+            Value::String(aud) => *aud == provider.client_id,
+            Value::Array(aud) => {
+                aud.iter().any(|x| x.as_str() == Some(&provider.client_id))
+                    && (aud.len() == 1 || claims["azp"].as_str() == Some(&provider.client_id))
+            }
+            _ => false,
+        };
+        if !aud_ok {
+            return Err(OidcError::InvalidClaims("aud".into()));
+        }
+        if claims["nonce"].as_str() != Some(&pending.nonce) {
+            return Err(OidcError::InvalidClaims("nonce".into()));
+        }
+        let exp = claims["exp"]
+            .as_i64()
+            .ok_or_else(|| OidcError::MissingClaim("exp".into()))?;
+        if now - skew >= exp {
+            return Err(OidcError::Expired);
+        }
+        if claims["iat"].as_i64().is_some_and(|x| x > now + skew) {
+            return Err(OidcError::Expired);
+        }
+        let subject = claims["sub"]
+            .as_str()
+            .ok_or_else(|| OidcError::MissingClaim("sub".into()))?;
+
+        let mut created = false;
+        let user = match self.linked_user(&name, subject).await? {
+            Some(user) => user,
+            None if provider.create_users => {
+                let user = claims[&provider.user_claim]
+                    .as_str()
+                    .ok_or_else(|| OidcError::MissingClaim(provider.user_claim.clone()))?;
+                match self.create_user(user).await {
+                    Ok(()) => {}
+                    Err(CreateUserError::UserAlreadyExist(_)) => {
+                        return Err(OidcError::Unlinked(subject.into()));
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                match self.link_identity(user, &name, subject).await {
+                    Ok(()) => {}
+                    Err(LinkIdentityError::SQL(e)) => return Err(e.into()),
+                    // linked concurrently by another login
+                    Err(_) => return Err(OidcError::Unlinked(subject.into())),
+                }
+                created = true;
+                user.to_owned()
+            }
+            None => return Err(OidcError::Unlinked(subject.into())),
+        };
+        self.record_user(&user);
+        self.check_lockout(&user).await?;
+        let token = self.issue_token(&user);
+        info!("logged in {} through {name}", self.log_user(&user));
+        Ok(OidcLogin {
+            user,
+            token: token.into(),
+            created,
+        })
+    }
+
+    /// Verify the signature of an ID token, returning its claims.
+    fn validate_id_token(
+        &self,
+        name: &str,
+        provider: &OidcProvider,
+        id_token: &str,
+    ) -> Result<Value, OidcError> {
+        let mut parts = id_token.split('.');
+        let (Some(header), Some(claims), Some(sig), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(malformed("not a JWS in compact serialization"));
+        };
+        let signing_input = &id_token[..header.len() + 1 + claims.len()];
+        let sig = BASE64_URL_SAFE_NO_PAD.decode(sig).map_err(malformed)?;
+        let header = decode_part(header)?;
+        let alg = header["alg"].as_str().unwrap_or_default();
+        let valid = match alg {
+            "HS256" => {
+                let secret = provider
+                    .client_secret
+                    .as_ref()
+                    .ok_or_else(|| OidcError::UnsupportedAlgorithm(alg.into()))?;
+                let mut mac =
+                    <Hmac<Sha256> as Mac>::new_from_slice(secret.expose().as_bytes()).unwrap();
+                mac.update(signing_input.as_bytes());
+                mac.verify_slice(&sig).is_ok()
+            }
+            "none" | "" => return Err(OidcError::UnsupportedAlgorithm(alg.into())),
+            _ => {
+                let verifier = self.oidc.verifier.read().unwrap().clone();
+                let verifier = verifier.ok_or(OidcError::NoVerifier)?;
+                let kid = header["kid"].as_str();
+                verifier.verify(name, alg, kid, signing_input.as_bytes(), &sig)
+            }
+        };
+        if !valid {
+            return Err(OidcError::InvalidSignature);
+        }
+        decode_part(claims)
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use sqlx::{query, query_as};
+use tracing::{debug, info, warn};
+
+use crate::{
+    Basileus, constant_time_eq,
+    err::{MfaError, OobError},
+    event::AuthEvent,
+    history::LoginAttempt,
+    messenger::{Channel, Message},
+    mfa::MfaKind,
+    rand_digits, unix_now,
+};
+
+/// Configuration of one-time codes delivered out of band, see [`Basileus::send_login_code`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OobConfig {
+    /// Number of digits of codes.
+    #[cfg_attr(feature = "serde", serde(rename = "code-length"))]
+    pub code_length: usize,
+    /// Time in seconds a code stays valid.
+    #[cfg_attr(feature = "serde", serde(rename = "ttl"))]
+    pub ttl: u64,
+    /// Time in seconds before another code is sent to the same user.
+    #[cfg_attr(feature = "serde", serde(rename = "interval"))]
+    pub interval: u64,
+    /// Wrong codes accepted before a new code has to be sent.
+    #[cfg_attr(feature = "serde", serde(rename = "max-attempts"))]
+    pub max_attempts: u32,
+    /// Whether codes alone log users in, see [`Basileus::begin_code_login`].
+    ///
+    /// This is only suitable for low-risk applications, since whoever reads the messages of a user can log in,
+    /// bypassing passwords and other second factors.
+    #[cfg_attr(feature = "serde", serde(rename = "passwordless"))]
+    pub passwordless: bool,
+    /// Whether codes are only sent to [verified](crate::contact::Contact::verified) addresses.
+    #[cfg_attr(feature = "serde", serde(rename = "require-verified"))]
+    pub require_verified: bool,
+    /// Subject of messages carrying codes.
+    #[cfg_attr(feature = "serde", serde(rename = "subject"))]
+    pub subject: String,
+    /// Text of messages carrying codes, where `{code}` is replaced by the code.
+    #[cfg_attr(feature = "serde", serde(rename = "body"))]
+    pub body: String,
+}
+
+impl Default for OobConfig {
+    fn default() -> Self {
+        Self {
+            code_length: 6,
+            ttl: 300,
+            interval: 30,
+            max_attempts: 5,
+            passwordless: false,
+            require_verified: true,
+            subject: "Login code".into(),
+            body: "Your login code is {code}.\n\nIf you did not request this, ignore this message."
+                .into(),
+        }
+    }
+}
+
+/// A code sent and not yet entered.
+struct PendingCode {
+    code: String,
+    issued: SystemTime,
+    attempts: u32,
+}
+
+#[derive(Default)]
+pub struct OobModule {
+    /// Map from users to the last code sent to them.
+    pending: Mutex<HashMap<String, PendingCode>>,
+}
+
+impl OobModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Send a one-time code to the address `user` is reached at through `channel`,
+    /// replacing any code sent earlier.
+    ///
+    /// The code is accepted by [`Self::verify_login`] as second factor once the user [enabled](Self::enable_oob)
+    /// the channel, and by [`Self::complete_code_login`] if [passwordless](OobConfig::passwordless) logins are allowed.
+    /// Sending more often than every [`OobConfig::interval`] fails with [`OobError::SlowDown`].
+    pub async fn send_login_code(&self, user: &str, channel: Channel) -> Result<(), OobError> {
+        self.spanned("send_login_code", Some(user), async {
+            if !self.config.read().unwrap().modules.oob {
+                return Err(OobError::Disabled);
+            }
+            let config = self.config.read().unwrap().oob.clone();
+            let contact = self.get_contact(user, channel).await?;
+            let Some(contact) = contact.filter(|x| x.verified || !config.require_verified) else {
+                return Err(OobError::Unverified(user.into(), channel));
+            };
+            let now = SystemTime::now();
+            let interval = Duration::from_secs(config.interval);
+            let code = rand_digits(config.code_length);
+            {
+                let ttl = Duration::from_secs(config.ttl);
+                let mut pending = self.oob.pending.lock().unwrap();
+                pending.retain(|_, x| now.duration_since(x.issued).is_ok_and(|d| d < ttl));
+                let recent = pending
+                    .get(user)
+                    .is_some_and(|x| now.duration_since(x.issued).is_ok_and(|d| d < interval));
+                if recent {
+                    warn!("throttled login code to {}", self.log_user(user));
+                    return Err(OobError::SlowDown);
+                }
+                pending.insert(
+                    user.into(),
+                    PendingCode {
+                        code: code.clone(),
+                        issued: now,
+                        attempts: 0,
+                    },
+                );
+            }
+            let message = Message {
+                channel,
+                to: contact.address,
+                subject: config.subject,
+                body: config.body.replace("{code}", &code).into(),
+            };
+            if let Err(e) = self.send_message(&message).await {
+                self.oob.pending.lock().unwrap().remove(user);
+                return Err(e.into());
+            }
+            self.count("oob.sent");
+            debug!(
+                "sent {} login code to {}",
+                channel.name(),
+                self.log_user(user)
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    /// Consume the code last [sent](Self::send_login_code) to `user`, returning whether `code` matches it.
+    ///
+    /// Codes are rejected once expired, and discarded after [`OobConfig::max_attempts`] wrong guesses.
+    pub fn verify_login_code(&self, user: &str, code: &str) -> bool {
+        if !self.config.read().unwrap().modules.oob {
+            return false;
+        }
+        let config = self.config.read().unwrap().oob.clone();
+        let mut pending = self.oob.pending.lock().unwrap();
+        let Some(entry) = pending.get_mut(user) else {
+            return false;
+        };
+        if entry
+            .issued
+            .elapsed()
+            .is_ok_and(|x| x > Duration::from_secs(config.ttl))
+        {
+            pending.remove(user);
+            return false;
+        }
+        if !constant_time_eq(&entry.code, code) {
+            entry.attempts += 1;
+            if entry.attempts >= config.max_attempts {
+                warn!("too many wrong login codes for {}", self.log_user(user));
+                pending.remove(user);
+            }
+            return false;
+        }
+        pending.remove(user);
+        true
+    }
+
+    /// Accept codes sent through `channel` as second factor of `user`, whose address there must be verified.
+    ///
+    /// Returns the recovery codes as [`Self::confirm_totp_enrollment`].
+    pub async fn enable_oob(&self, user: &str, channel: Channel) -> Result<Vec<String>, MfaError> {
+        self.spanned("enable_oob", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Oob) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            let contact = self.get_contact(user, channel).await?;
+            if !contact.is_some_and(|x| x.verified) {
+                return Err(MfaError::Unverified(user.into(), channel));
+            }
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql(
+                "INSERT OR IGNORE INTO mfa_factor (user, kind, name, secret, enabled, created_at)
+                VALUES (?, 'oob', ?, x'', 1, ?)",
+            ))
+            .bind(user)
+            .bind(channel.name())
+            .bind(unix_now());
+            if q.execute(&mut *tx).await?.rows_affected() == 0 {
+                return Err(MfaError::AlreadyEnrolled(user.into()));
+            }
+            let codes = self.initial_recovery_codes(&mut tx, user).await?;
+            tx.commit().await?;
+            info!(
+                "enabled {} login codes for {}",
+                channel.name(),
+                self.log_user(user)
+            );
+            Ok(codes)
+        })
+        .await
+    }
+
+    /// Stop accepting codes sent through `channel` as second factor of `user`, returning whether they were.
+    ///
+    /// Recovery codes are removed as well once the user has no second factor left.
+    pub async fn disable_oob(
+        &self,
+        user: &str,
+        channel: Channel,
+    ) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let mut tx = self.begin_write().await?;
+        let q =
+            query(self.sql("DELETE FROM mfa_factor WHERE user = ? AND kind = 'oob' AND name = ?"))
+                .bind(user)
+                .bind(channel.name());
+        let res = q.execute(&mut *tx).await?.rows_affected() > 0;
+        self.prune_recovery_codes(&mut tx, user).await?;
+        tx.commit().await?;
+        if res {
+            info!(
+                "disabled {} login codes for {}",
+                channel.name(),
+                self.log_user(user)
+            );
+        }
+        Ok(res)
+    }
+
+    /// Channels `user` [enabled](Self::enable_oob) login codes for.
+    pub async fn oob_channels(&self, user: &str) -> Result<Vec<Channel>, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(vec![]);
+        }
+        let q = query_as(
+            self.sql("SELECT name FROM mfa_factor WHERE user = ? AND kind = 'oob' AND enabled"),
+        )
+        .bind(user);
+        let res: Vec<(String,)> = q.fetch_all(&self.db).await?;
+        Ok([Channel::Email, Channel::Sms]
+            .into_iter()
+            .filter(|x| res.iter().any(|(name,)| name == x.name()))
+            .collect())
+    }
+
+    /// Check `code` against the code last sent to `user`, provided the user enabled login codes as second factor.
+    pub(crate) async fn check_oob_code(
+        &self,
+        user: &str,
+        code: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        if !self.mfa_kind_enabled(MfaKind::Oob) {
+            return Ok(false);
+        }
+        Ok(!self.oob_channels(user).await?.is_empty() && self.verify_login_code(user, code))
+    }
+
+    /// Send a login code to `user` who presented no second factor,
+    /// if login codes are the only second factor the user enabled.
+    pub(crate) async fn send_second_factor_code(
+        &self,
+        user: &str,
+    ) -> Result<(), sqlx::error::Error> {
+        if !self.mfa_kind_enabled(MfaKind::Oob) {
+            return Ok(());
+        }
+        let Some(channel) = self.oob_channels(user).await?.first().copied() else {
+            return Ok(());
+        };
+        let q = query_as(self.sql(
+            "SELECT EXISTS(SELECT 1 FROM mfa_factor WHERE user = ? AND kind NOT IN ('oob', 'recovery') AND enabled)",
+        ))
+        .bind(user);
+        let (other,): (bool,) = q.fetch_one(&self.db).await?;
+        if other {
+            return Ok(());
+        }
+        match self.send_login_code(user, channel).await {
+            Ok(()) | Err(OobError::SlowDown) => Ok(()),
+            Err(OobError::SQL(e)) => Err(e),
+            Err(e) => {
+                warn!("failed to send login code to {}: {e}", self.log_user(user));
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether [passwordless](OobConfig::passwordless) logins by code are allowed.
+    fn code_login_enabled(&self) -> bool {
+        let config = self.config.read().unwrap();
+        config.modules.oob && config.oob.passwordless
+    }
+
+    /// Find the user reached at `user_or_address` through `channel`, or named so.
+    async fn resolve_code_user(
+        &self,
+        user_or_address: &str,
+        channel: Channel,
+    ) -> Result<Option<String>, sqlx::error::Error> {
+        if let Some(user) = self.contact_user(channel, user_or_address).await? {
+            return Ok(Some(user));
+        }
+        Ok(self
+            .exist_user(user_or_address)
+            .await?
+            .then(|| user_or_address.into()))
+    }
+
+    /// Start a passwordless login of the user reached at `user_or_address` through `channel`, or named so,
+    /// by [sending](Self::send_login_code) a code to be entered with [`Self::complete_code_login`].
+    ///
+    /// This requires [`OobConfig::passwordless`] and [`ModuleConfig::oob`](crate::config::ModuleConfig::oob).
+    /// So that users cannot be enumerated, this succeeds without sending anything for unknown users,
+    /// users without a suitable address, and users who were sent a code recently.
+    pub async fn begin_code_login(
+        &self,
+        user_or_address: &str,
+        channel: Channel,
+    ) -> Result<(), OobError> {
+        if !self.code_login_enabled() {
+            return Err(OobError::Disabled);
+        }
+        let Some(user) = self.resolve_code_user(user_or_address, channel).await? else {
+            debug!("not sending login code to unknown user");
+            return Ok(());
+        };
+        match self.send_login_code(&user, channel).await {
+            Ok(()) | Err(OobError::Unverified(..) | OobError::SlowDown) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Complete a passwordless login [started](Self::begin_code_login) with the same `user_or_address` and `channel`,
+    /// returning a session token.
+    ///
+    /// Users who enabled or have to enroll a second factor fail as [`Self::login`] does after a correct password.
+    pub async fn complete_code_login(
+        &self,
+        user_or_address: &str,
+        channel: Channel,
+        code: &str,
+    ) -> Result<String, OobError> {
+        self.spanned("complete_code_login", None, async {
+            if !self.code_login_enabled() {
+                return Err(OobError::Disabled);
+            }
+            let user = self.resolve_code_user(user_or_address, channel).await?;
+            let Some(user) = user.filter(|x| self.verify_login_code(x, code)) else {
+                self.count("oob.failure");
+                return Err(OobError::InvalidCode);
+            };
+            self.record_user(&user);
+            self.check_passwordless(&user).await?;
+            self.count("oob.success");
+            info!("{} logged in by login code", self.log_user(&user));
+            self.emit(AuthEvent::LoginSucceeded { user: user.clone() });
+            let attempt = LoginAttempt::new("code");
+            self.record_login_attempt(&user, &attempt).await;
+            Ok(self.issue_token(&user))
+        })
+        .await
+    }
+}
@@ -1,9 +1,156 @@
-use crate::{Basileus, err::DeletePassError, rand_buf};
+use std::{net::IpAddr, time::Instant};
+
+use crate::{
+    Basileus,
+    err::{DeletePassError, ErrorCode},
+    event::AuthEvent,
+    logging::Secret,
+    rand_buf,
+};
 
 use super::err::{UpdatePassError, VerifyPassError};
 use sqlx::{query, query_as};
 
-use tracing::{info, trace};
+use tracing::{debug, info, trace};
+
+/// Variant of the argon2 password hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashVariant {
+    /// Data-dependent memory access.
+    #[cfg_attr(feature = "serde", serde(rename = "argon2d"))]
+    Argon2d,
+    /// Data-independent memory access.
+    #[cfg_attr(feature = "serde", serde(rename = "argon2i"))]
+    Argon2i,
+    /// Hybrid of the above, recommended for password hashing.
+    #[cfg_attr(feature = "serde", serde(rename = "argon2id"))]
+    Argon2id,
+}
+
+impl HashVariant {
+    /// Name of the variant in PHC strings.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Argon2d => "argon2d",
+            Self::Argon2i => "argon2i",
+            Self::Argon2id => "argon2id",
+        }
+    }
+}
+
+impl From<HashVariant> for argon2::Variant {
+    fn from(value: HashVariant) -> Self {
+        match value {
+            HashVariant::Argon2d => Self::Argon2d,
+            HashVariant::Argon2i => Self::Argon2i,
+            HashVariant::Argon2id => Self::Argon2id,
+        }
+    }
+}
+
+/// Lowest [memory cost](PassConfig::mem_cost) accepted, in KiB.
+pub const MIN_MEM_COST: u32 = 8192;
+
+/// Password hashing configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PassConfig {
+    /// Variant of newly computed hashes.
+    #[cfg_attr(feature = "serde", serde(rename = "variant"))]
+    pub variant: HashVariant,
+    /// Memory used for hashing, in KiB.
+    #[cfg_attr(feature = "serde", serde(rename = "mem-cost"))]
+    pub mem_cost: u32,
+    /// Number of passes over the memory.
+    #[cfg_attr(feature = "serde", serde(rename = "time-cost"))]
+    pub time_cost: u32,
+    /// Degree of parallelism.
+    #[cfg_attr(feature = "serde", serde(rename = "lanes"))]
+    pub lanes: u32,
+    /// Length of the hash, in bytes.
+    #[cfg_attr(feature = "serde", serde(rename = "hash-length"))]
+    pub hash_length: u32,
+    /// Length of the random salt, in bytes.
+    #[cfg_attr(feature = "serde", serde(rename = "salt-length"))]
+    pub salt_length: usize,
+    /// Secret mixed into every hash, kept outside the database so that a leaked database alone can not be cracked.
+    ///
+    /// Hashes computed with a different pepper, or none, no longer verify after changing this.
+    /// This may refer to a secret stored elsewhere, see [`Config::resolve_secrets`](crate::Config::resolve_secrets).
+    #[cfg_attr(feature = "serde", serde(rename = "pepper"))]
+    pub pepper: Option<Secret<String>>,
+    /// Variants still accepted when verifying hashes stored earlier, besides [`Self::variant`].
+    #[cfg_attr(feature = "serde", serde(rename = "legacy"))]
+    pub legacy: Vec<HashVariant>,
+    /// Whether to replace a stored hash computed with outdated parameters or a legacy variant
+    /// upon its next successful verification.
+    #[cfg_attr(feature = "serde", serde(rename = "rehash"))]
+    pub rehash: bool,
+    /// Whether [`Basileus::verify_pass`] reports missing users and passwords as wrong passwords,
+    /// so that clients can not enumerate usernames.
+    ///
+    /// The precise cause is still logged.
+    #[cfg_attr(feature = "serde", serde(rename = "conceal-users"))]
+    pub conceal_users: bool,
+    /// Whether updating the password of a user invalidates every token issued to them,
+    /// so that a session opened with a leaked password does not outlive the password.
+    ///
+    /// [`Basileus::change_pass`] always keeps the session it is called from.
+    #[cfg_attr(feature = "serde", serde(rename = "revoke-sessions"))]
+    pub revoke_sessions: bool,
+}
+
+impl Default for PassConfig {
+    fn default() -> Self {
+        Self {
+            variant: HashVariant::Argon2id,
+            mem_cost: 19456,
+            time_cost: 2,
+            lanes: 1,
+            hash_length: 32,
+            salt_length: 64,
+            pepper: None,
+            legacy: vec![HashVariant::Argon2i, HashVariant::Argon2d],
+            rehash: true,
+            conceal_users: false,
+            revoke_sessions: false,
+        }
+    }
+}
+
+impl PassConfig {
+    fn argon2(&self) -> argon2::Config<'_> {
+        argon2::Config {
+            hash_length: self.hash_length,
+            lanes: self.lanes,
+            mem_cost: self.mem_cost,
+            secret: self.pepper.as_ref().map_or("", |x| x.expose()).as_bytes(),
+            time_cost: self.time_cost,
+            variant: self.variant.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Hash `pass` with a fresh salt.
+    pub(crate) fn hash(&self, pass: &str) -> Result<String, argon2::Error> {
+        argon2::hash_encoded(pass.as_bytes(), &rand_buf(self.salt_length), &self.argon2())
+    }
+
+    /// Whether the variant of `phc` may be verified.
+    fn accepts(&self, phc: &str) -> bool {
+        let variant = phc.split('$').nth(1).unwrap_or_default();
+        variant == self.variant.name() || self.legacy.iter().any(|x| x.name() == variant)
+    }
+
+    /// Whether `phc` was computed with other variant or parameters than currently configured.
+    fn outdated(&self, phc: &str) -> bool {
+        let params = format!("m={},t={},p={}", self.mem_cost, self.time_cost, self.lanes);
+        let items: Vec<_> = phc.split('$').collect();
+        items.get(1) != Some(&self.variant.name()) || items.get(3) != Some(&params.as_str())
+    }
+}
 
 pub const DB_INIT: &str = r#"
 CREATE TABLE IF NOT EXISTS pass (
@@ -17,50 +164,168 @@ CREATE INDEX IF NOT EXISTS idx_pass_user ON pass (user);
 impl Basileus {
     /// Whether a user has defined a password for authorization.
     pub async fn exist_pass(&self, user: &str) -> Result<bool, sqlx::error::Error> {
-        let query = query_as("SELECT EXISTS(SELECT 1 FROM pass WHERE user = ?)").bind(user);
+        let query =
+            query_as(self.sql("SELECT EXISTS(SELECT 1 FROM pass WHERE user = ?)")).bind(user);
         let (res,): (i32,) = query.fetch_one(&self.db).await?;
         Ok(res == 1)
     }
 
+    /// Hash `pass` with the current [`PassConfig`] for storage.
+    pub(crate) fn hash_pass(&self, pass: &str) -> Result<String, argon2::Error> {
+        let start = Instant::now();
+        let config = self.config.read().unwrap().pass.clone();
+        let res = config.hash(pass);
+        self.record("argon2.hash", start);
+        res
+    }
+
     /// Update password for specified user.
+    ///
+    /// If [`PassConfig::revoke_sessions`] is set, every token of the user is invalidated.
     pub async fn update_pass(&self, user: &str, pass: &str) -> Result<(), UpdatePassError> {
-        if !self.exist_user(user).await? {
-            return Err(UpdatePassError::UserNotExist(user.into()));
-        }
-        let hashed = argon2::hash_encoded(pass.as_bytes(), &rand_buf::<64>(), &Default::default())?;
-        let query = query("INSERT OR REPLACE INTO pass (user, phc) VALUES (?, ?);")
-            .bind(user)
-            .bind(hashed);
-        query.execute(&self.db).await?;
-        info!("updated password for {user}");
-        Ok(())
+        self.spanned("update_pass", Some(user), async {
+            let hashed = self.hash_pass(pass)?;
+            let mut tx = self.begin().await?;
+            tx.set_pass_hash(user, hashed).await?;
+            tx.commit().await?;
+            info!("updated password for {}", self.log_user(user));
+            Ok(())
+        })
+        .await
+    }
+
+    /// Update the password of `user` from their session `current`, e.g. on a change requested by the user,
+    /// invalidating every other token of the user regardless of [`PassConfig::revoke_sessions`].
+    pub async fn change_pass(
+        &self,
+        user: &str,
+        pass: &str,
+        current: &str,
+    ) -> Result<(), UpdatePassError> {
+        self.spanned("change_pass", Some(user), async {
+            let hashed = self.hash_pass(pass)?;
+            let mut tx = self.begin().await?;
+            tx.set_pass_hash(user, hashed).await?;
+            tx.keep_session(current);
+            tx.commit().await?;
+            info!("changed password for {}", self.log_user(user));
+            Ok(())
+        })
+        .await
     }
 
     /// Verify given password for user.
+    ///
+    /// If [`PassConfig::rehash`] is set, an outdated hash is replaced after successful verification.
+    ///
+    /// If [`PassConfig::conceal_users`] is set, missing users and passwords fail like wrong passwords.
     pub async fn verify_pass(&self, user: &str, pass: &str) -> Result<bool, VerifyPassError> {
-        if !self.exist_user(user).await? {
-            return Err(VerifyPassError::UserNotExist(user.into()));
+        self.verify_pass_from(user, pass, None).await
+    }
+
+    /// Like [`Self::verify_pass`], recording the source address of the attempt with its audit event.
+    pub(crate) async fn verify_pass_from(
+        &self,
+        user: &str,
+        pass: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<bool, VerifyPassError> {
+        self.spanned("verify_pass", Some(user), async {
+            let start = Instant::now();
+            let res = self.verify_pass_concealed(user, pass).await;
+            self.record("login", start);
+            self.count(match res {
+                Ok(true) => "login.success",
+                Ok(false)
+                | Err(VerifyPassError::UserNotExist(_) | VerifyPassError::PassUndefined(_)) => {
+                    "login.failure"
+                }
+                Err(_) => "login.error",
+            });
+            match res {
+                Ok(true) => self.emit_from(AuthEvent::LoginSucceeded { user: user.into() }, ip),
+                Ok(false)
+                | Err(VerifyPassError::UserNotExist(_) | VerifyPassError::PassUndefined(_)) => {
+                    self.emit_from(AuthEvent::LoginFailed { user: user.into() }, ip)
+                }
+                Err(_) => {}
+            }
+            res
+        })
+        .await
+    }
+
+    /// Verify given password for user, concealing missing users if configured.
+    async fn verify_pass_concealed(&self, user: &str, pass: &str) -> Result<bool, VerifyPassError> {
+        let res = self.verify_pass_exact(user, pass).await;
+        let config = self.config.read().unwrap().pass.clone();
+        if !config.conceal_users {
+            return res;
+        }
+        match res {
+            Err(e @ (VerifyPassError::UserNotExist(_) | VerifyPassError::PassUndefined(_))) => {
+                debug!(
+                    "password authorization of {} failed: {}",
+                    self.log_user(user),
+                    e.code()
+                );
+                // take as long as verifying, so that missing users can not be told apart by timing either
+                let _ = config.hash(pass);
+                Ok(false)
+            }
+            res => res,
         }
-        if !self.exist_pass(user).await? {
+    }
+
+    /// Verify given password for user, telling apart every cause of failure.
+    async fn verify_pass_exact(&self, user: &str, pass: &str) -> Result<bool, VerifyPassError> {
+        let query = query_as(self.sql(
+            "SELECT pass.phc FROM user LEFT JOIN pass ON pass.user = user.user WHERE user.user = ?",
+        ))
+        .bind(user);
+        let res: Option<(Option<String>,)> = query.fetch_optional(&self.db).await?;
+        let Some((phc,)) = res else {
+            return Err(VerifyPassError::UserNotExist(user.into()));
+        };
+        let Some(phc) = phc else {
             return Err(VerifyPassError::PassUndefined(user.into()));
+        };
+        let config = self.config.read().unwrap().pass.clone();
+        if !config.accepts(&phc) {
+            return Err(VerifyPassError::LegacyHash(user.into()));
         }
-        let query = query_as("SELECT phc FROM pass WHERE user = ?").bind(user);
-        let (phc,): (String,) = query.fetch_one(&self.db).await?;
-        let res = argon2::verify_encoded(&phc, pass.as_bytes())?;
-        trace!("authorized {user} by password");
-        Ok(res)
+        let pepper = config.pepper.as_ref().map_or("", |x| x.expose());
+        let start = Instant::now();
+        let res = argon2::verify_encoded_ext(&phc, pass.as_bytes(), pepper.as_bytes(), &[])?;
+        self.record("argon2.verify", start);
+        if !res {
+            return Ok(false);
+        }
+        if self.sampled() {
+            trace!("authorized {} by password", self.log_user(user));
+        }
+        if config.rehash && config.outdated(&phc) {
+            let hashed = self.hash_pass(pass)?;
+            // unless the password has been changed meanwhile
+            let q = sqlx::query(self.sql("UPDATE pass SET phc = ? WHERE user = ? AND phc = ?"))
+                .bind(hashed)
+                .bind(user)
+                .bind(&phc);
+            q.execute(&self.writer).await?;
+            debug!("rehashed password of {}", self.log_user(user));
+        }
+        Ok(true)
     }
 
     /// Delete a user's password.
     pub async fn delete_pass(&self, user: &str) -> Result<(), DeletePassError> {
-        if !self.exist_user(user).await? {
-            return Err(DeletePassError::UserNotExist(user.into()));
-        }
-        if !self.exist_pass(user).await? {
-            return Err(DeletePassError::UserNotExist(user.into()));
-        }
-        let query = query("DELETE FROM pass WHERE user = ?").bind(user);
-        query.execute(&self.db).await?;
-        Ok(())
+        self.spanned("delete_pass", Some(user), async {
+            let query = query(self.sql("DELETE FROM pass WHERE user = ?")).bind(user);
+            if query.execute(&self.writer).await?.rows_affected() == 0 {
+                return Err(DeletePassError::UserNotExist(user.into()));
+            }
+            Ok(())
+        })
+        .await
     }
 }
@@ -1,31 +1,65 @@
 use crate::{
     Basileus,
-    err::{CheckPermError, GetPermError, GivePermError, RevokePermError, SetPermError},
+    audit::AuditEvent,
+    err::{
+        ApplyPermError, CheckPermError, DelegatePermError, GetPermError, GivePermError,
+        ParsePermError, RevokePermError, SetPermError,
+    },
+    event::{Subscribers, SubscriptionId},
+    from_unix, unix_now,
 };
-use sqlx::{query, query_as};
+use sqlx::{SqliteConnection, query, query_as};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     convert::Infallible,
+    fmt::Display,
     ops::{Add, Deref, DerefMut, Mul, Sub},
     str::FromStr,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
 };
+use tracing::{info, warn};
 
 pub const DB_INIT: &str = r#"
-CREATE TABLE IF NOT EXISTS perm (
-    user TEXT NOT NULL PRIMARY KEY,
-    grp TEXT,
+CREATE TABLE IF NOT EXISTS user_perm (
+    user TEXT NOT NULL,
+    grp TEXT NOT NULL,
+    PRIMARY KEY (user, grp),
     FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
 );
-CREATE INDEX IF NOT EXISTS idx_perm_user ON perm (user);
-CREATE TRIGGER IF NOT EXISTS after_user_insert
-AFTER INSERT ON user
-FOR EACH ROW
+CREATE INDEX IF NOT EXISTS idx_user_perm_user ON user_perm (user);
+CREATE INDEX IF NOT EXISTS idx_user_perm_grp ON user_perm (grp);
+DROP TRIGGER IF EXISTS after_user_insert;
+CREATE TABLE IF NOT EXISTS perm_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time INTEGER NOT NULL,
+    actor TEXT,
+    user TEXT NOT NULL,
+    added TEXT NOT NULL,
+    removed TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_perm_log_user ON perm_log (user);
+CREATE TRIGGER IF NOT EXISTS before_perm_log_update
+BEFORE UPDATE ON perm_log
 BEGIN
-    INSERT OR IGNORE INTO perm (user, grp)
-    VALUES (NEW.user, '');
+    SELECT RAISE(ABORT, 'perm_log is append-only');
 END;
 "#;
 
+/// Migrate permissions stored as whitespace-joined strings in the legacy `perm` table
+/// into one row per permission in `user_perm`.
+pub const DB_MIGRATE_LEGACY: &str = r#"
+INSERT OR IGNORE INTO user_perm (user, grp)
+WITH RECURSIVE split(user, grp, rest) AS (
+    SELECT user, '', COALESCE(grp, '') || ' ' FROM perm
+    UNION ALL
+    SELECT user, substr(rest, 1, instr(rest, ' ') - 1), substr(rest, instr(rest, ' ') + 1)
+    FROM split WHERE rest <> ''
+)
+SELECT user, grp FROM split WHERE grp <> '';
+DROP TABLE perm;
+"#;
+
 /// Denotes a specific set of permissions.
 ///
 /// A **"permission"** is a string excluding whitespace representing a certain privilege to a resource or action,
@@ -46,12 +80,45 @@ END;
 /// - `>`  : proper superset
 /// - `>=` : superset
 ///
-/// Note that this is therefore a partial order because sets may be incomparable.
+/// Note that this is therefore a partial order because sets may be incomparable,
+/// in which case [`PartialOrd::partial_cmp`] returns `None` and every one of `<`, `<=`, `>`, `>=` is `false`.
+///
+/// The comparison operators treat every permission literally, e.g. `user_perm >= required_perm` holds
+/// if and only if every permission in `required_perm` is literally present in `user_perm`.
+/// To check if a user has sufficient permissions for a certain action, use [`Perm::satisfies`] instead,
+/// which also takes the hierarchy and deny entries described below into account,
+/// and is what [`Basileus::check_perm`] does.
+///
+/// # Hierarchy
+///
+/// Permissions are dot-separated paths such as `files.share.public`, see [`PermPath`].
+/// Holding a permission implies holding every permission beneath it,
+/// e.g. `files` implies `files.read` and `files.share.public`.
+///
+/// A wildcard grant is a permission ending with `.*`, e.g. `files.*`,
+/// which matches any permission strictly beneath the preceding prefix, such as `files.read`,
+/// but not `files` itself.
+/// A single `*` matches every permission.
 ///
-/// This can be used to check if a user has sufficient permissions for a certain action.
-/// E.g., if `user_perm >= required_perm`, then the user has enough permissions to perform the action requiring `required_perm`.
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Permissions which fail to parse as a [`PermPath`] are only matched literally.
+///
+/// # Deny entries
+///
+/// A permission prefixed with `!`, e.g. `!payments.refund`, is a deny entry.
+/// It revokes the denied permission, as well as everything it implies,
+/// even if it is otherwise granted by another permission in the set, whether held directly or through roles.
+/// That is, deny entries always take precedence over grants, regardless of how specific either one is.
+/// A required permission implying a denied one is not satisfied either, as it is no longer held in full.
+/// E.g. `payments !payments.refund` satisfies `payments.charge` but not `payments.refund` nor `payments`.
+///
+/// # Storage
+///
+/// Every permission is stored separately, so only well-formed permissions (see [`check_perm_name`])
+/// are accepted when permissions are written to the database.
+/// In particular, a permission containing whitespace is rejected rather than silently split into several.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Perm(HashSet<String>);
 
 impl From<HashSet<String>> for Perm {
@@ -60,9 +127,71 @@ impl From<HashSet<String>> for Perm {
     }
 }
 
-impl Into<HashSet<String>> for Perm {
-    fn into(self) -> HashSet<String> {
-        self.0
+impl From<Perm> for HashSet<String> {
+    fn from(value: Perm) -> Self {
+        value.0
+    }
+}
+
+/// Whether the granted permission `grant` matches the required permission `req`, taking the hierarchy into account.
+fn grant_matches(grant: &str, req: &str) -> bool {
+    if grant == req {
+        return true;
+    }
+    match (grant.parse::<PermPath>(), req.parse::<PermPath>()) {
+        (Ok(grant), Ok(req)) => grant.implies(&req),
+        _ => false,
+    }
+}
+
+impl Perm {
+    /// Iterate over the granted permissions, i.e. excluding deny entries.
+    pub fn grants(&self) -> impl Iterator<Item = &str> {
+        self.iter()
+            .filter(|x| !x.starts_with('!'))
+            .map(|x| x.as_str())
+    }
+
+    /// Iterate over the denied permissions, with the leading `!` stripped.
+    pub fn denies(&self) -> impl Iterator<Item = &str> {
+        self.iter().filter_map(|x| x.strip_prefix('!'))
+    }
+
+    /// Find the first permission which is not well-formed according to [`check_perm_name`], if any.
+    pub fn find_invalid(&self) -> Option<&str> {
+        self.iter()
+            .find(|x| !check_perm_name(x))
+            .map(|x| x.as_str())
+    }
+
+    /// Add every permission implied by the held ones according to `rules`, transitively.
+    ///
+    /// Deny entries are left as is and do not imply anything.
+    pub fn expand(&self, rules: &HashMap<String, Perm>) -> Perm {
+        let mut res = self.clone();
+        let mut todo: Vec<&str> = self.grants().collect();
+        while let Some(perm) = todo.pop() {
+            let Some(implied) = rules.get(perm) else {
+                continue;
+            };
+            for x in implied.grants() {
+                if res.insert(x.into()) {
+                    todo.push(x);
+                }
+            }
+        }
+        res
+    }
+
+    /// Whether holding this set of permissions satisfies every permission in `req`,
+    /// taking the hierarchy and deny entries into account.
+    pub fn satisfies(&self, req: &Perm) -> bool {
+        req.iter().all(|req| {
+            !self
+                .denies()
+                .any(|deny| grant_matches(deny, req) || grant_matches(req, deny))
+                && self.grants().any(|grant| grant_matches(grant, req))
+        })
     }
 }
 
@@ -82,23 +211,36 @@ impl DerefMut for Perm {
 
 impl PartialOrd for Perm {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if self == other {
-            Some(std::cmp::Ordering::Equal)
-        } else if self.is_subset(other) {
-            Some(std::cmp::Ordering::Less)
-        } else if self.is_superset(other) {
-            Some(std::cmp::Ordering::Greater)
-        } else {
-            None
+        match (self.is_subset(other), self.is_superset(other)) {
+            (true, true) => Some(std::cmp::Ordering::Equal),
+            (true, false) => Some(std::cmp::Ordering::Less),
+            (false, true) => Some(std::cmp::Ordering::Greater),
+            (false, false) => None,
         }
     }
+
+    fn le(&self, other: &Self) -> bool {
+        self.is_subset(other)
+    }
+
+    fn ge(&self, other: &Self) -> bool {
+        self.is_superset(other)
+    }
+
+    fn lt(&self, other: &Self) -> bool {
+        self.len() < other.len() && self.is_subset(other)
+    }
+
+    fn gt(&self, other: &Self) -> bool {
+        self.len() > other.len() && self.is_superset(other)
+    }
 }
 
 impl Add for &Perm {
     type Output = Perm;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Perm(self.union(&rhs).cloned().collect())
+        Perm(self.union(rhs).cloned().collect())
     }
 }
 
@@ -106,7 +248,7 @@ impl Sub for &Perm {
     type Output = Perm;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Perm(self.difference(&rhs).cloned().collect())
+        Perm(self.difference(rhs).cloned().collect())
     }
 }
 
@@ -114,7 +256,7 @@ impl Mul for &Perm {
     type Output = Perm;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        Perm(self.intersection(&rhs).cloned().collect())
+        Perm(self.intersection(rhs).cloned().collect())
     }
 }
 
@@ -149,71 +291,1015 @@ impl FromStr for Perm {
     }
 }
 
-impl ToString for Perm {
-    fn to_string(&self) -> String {
-        let mut buf = String::new();
-        for grp in self.iter() {
-            buf.push_str(grp);
-            buf.push(' ');
+impl Display for Perm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // sorted so that equal sets are always represented the same
+        let mut grps: Vec<_> = self.iter().collect();
+        grps.sort();
+        for grp in grps {
+            write!(f, "{grp} ")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check at compile time whether `perm` is a well-formed permission,
+/// i.e. a [`PermPath`], optionally prefixed with `!` as a deny entry.
+pub const fn check_perm_name(perm: &str) -> bool {
+    let buf = perm.as_bytes();
+    let mut i = if !buf.is_empty() && buf[0] == b'!' {
+        1
+    } else {
+        0
+    };
+    if i == buf.len() {
+        return false;
+    }
+    let mut seg_start = i;
+    while i <= buf.len() {
+        if i == buf.len() || buf[i] == b'.' {
+            let len = i - seg_start;
+            if len == 0 {
+                return false;
+            }
+            if buf[seg_start] == b'*' && (len != 1 || i != buf.len()) {
+                return false;
+            }
+            seg_start = i + 1;
+        } else if !buf[i].is_ascii_graphic() || buf[i] == b'!' || (buf[i] == b'*' && i != seg_start)
+        {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Build a [`Perm`] from a list of permissions,
+/// each of which may be a string or anything else implementing [`Display`],
+/// such as the constants generated by [`define_perms!`](crate::define_perms).
+///
+/// ```ignore
+/// let perm = perm!["files.read", "files.write"];
+/// ```
+#[macro_export]
+macro_rules! perm {
+    ($($perm:expr),* $(,)?) => {
+        $crate::perm::Perm::from(::std::collections::HashSet::<::std::string::String>::from([
+            $(::std::string::ToString::to_string(&$perm)),*
+        ]))
+    };
+}
+
+/// Define a strongly typed registry of permission constants.
+///
+/// Every permission is checked by [`check_perm_name`] at compile time,
+/// so that a typo'd or malformed permission fails the build instead of silently never matching.
+/// The generated type converts into [`Perm`], and lists every defined permission in `ALL`.
+///
+/// ```ignore
+/// define_perms! {
+///     /// Permissions of the file service.
+///     pub struct FilePerm {
+///         /// Read any file.
+///         READ = "files.read",
+///         /// Write any file.
+///         WRITE = "files.write",
+///     }
+/// }
+///
+/// basileus.check_perm("alice", &FilePerm::READ.into()).await?;
+/// ```
+#[macro_export]
+macro_rules! define_perms {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$item_meta:meta])* $item:ident = $perm:literal),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        $vis struct $name(&'static str);
+
+        impl $name {
+            $(
+                $(#[$item_meta])*
+                pub const $item: Self = Self($perm);
+            )*
+
+            /// Every permission defined in this registry.
+            pub const ALL: &'static [Self] = &[$(Self::$item),*];
+
+            /// The permission as a string.
+            pub const fn as_str(&self) -> &'static str {
+                self.0
+            }
+
+            /// Look up a defined permission by its string representation.
+            pub fn lookup(perm: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|x| x.0 == perm)
+            }
+        }
+
+        const _: () = {
+            $(
+                assert!(
+                    $crate::perm::check_perm_name($perm),
+                    concat!("invalid permission '", $perm, "'")
+                );
+            )*
+        };
+
+        impl ::std::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(self.0)
+            }
+        }
+
+        impl ::std::convert::From<$name> for $crate::perm::Perm {
+            fn from(value: $name) -> Self {
+                $crate::perm![value]
+            }
+        }
+    };
+}
+
+/// A single permission parsed into its dot-separated segments.
+///
+/// Each segment is a non-empty string of ASCII graphic characters other than `.`, `*` and `!`.
+/// The last segment may instead be a single `*`, making the path a wildcard.
+///
+/// # Comparison
+///
+/// Paths are partially ordered by implication:
+/// `a >= b` if and only if holding `a` implies holding `b`, see [`PermPath::implies`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PermPath {
+    segments: Vec<String>,
+    wildcard: bool,
+}
+
+impl PermPath {
+    /// The segments of this path, excluding the trailing wildcard if any.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Whether this path ends with a wildcard `*` segment.
+    pub fn is_wildcard(&self) -> bool {
+        self.wildcard
+    }
+
+    /// The path one level up, or `None` for a top-level path.
+    ///
+    /// The parent of a wildcard `a.b.*` is `a.b`.
+    pub fn parent(&self) -> Option<PermPath> {
+        if self.wildcard {
+            return Some(Self {
+                segments: self.segments.clone(),
+                wildcard: false,
+            })
+            .filter(|p| !p.segments.is_empty());
+        }
+        match self.segments.len() {
+            0 | 1 => None,
+            n => Some(Self {
+                segments: self.segments[..n - 1].to_vec(),
+                wildcard: false,
+            }),
+        }
+    }
+
+    /// Whether holding this permission implies holding `other`.
+    ///
+    /// - `a.b` implies `a.b` itself and everything beneath it, e.g. `a.b.c` and `a.b.*`.
+    /// - `a.b.*` implies everything strictly beneath `a.b`, e.g. `a.b.c` and `a.b.*`, but not `a.b`.
+    pub fn implies(&self, other: &PermPath) -> bool {
+        if !other.segments.starts_with(&self.segments) {
+            return false;
+        }
+        if !self.wildcard {
+            return true;
+        }
+        other.wildcard || other.segments.len() > self.segments.len()
+    }
+}
+
+impl FromStr for PermPath {
+    type Err = ParsePermError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParsePermError::Empty);
+        }
+        let mut segments: Vec<String> = s.split('.').map(|x| x.into()).collect();
+        let wildcard = segments.last().is_some_and(|x| x == "*");
+        if wildcard {
+            segments.pop();
+        }
+        for seg in &segments {
+            if seg.is_empty() {
+                return Err(ParsePermError::EmptySegment(s.into()));
+            }
+            if seg == "*" {
+                return Err(ParsePermError::MisplacedWildcard(s.into()));
+            }
+            if let Some(c) = seg
+                .chars()
+                .find(|&c| !c.is_ascii_graphic() || c == '*' || c == '!')
+            {
+                return Err(ParsePermError::InvalidChar(s.into(), c));
+            }
+        }
+        Ok(Self { segments, wildcard })
+    }
+}
+
+impl Display for PermPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.segments.join("."))?;
+        match (self.wildcard, self.segments.is_empty()) {
+            (true, true) => write!(f, "*"),
+            (true, false) => write!(f, ".*"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl PartialOrd for PermPath {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self.implies(other), other.implies(self)) {
+            (true, true) => Some(std::cmp::Ordering::Equal),
+            (true, false) => Some(std::cmp::Ordering::Greater),
+            (false, true) => Some(std::cmp::Ordering::Less),
+            (false, false) => None,
+        }
+    }
+}
+
+impl Basileus {
+    /// Replace a user's permissions with `f(previous)` on the connection, recording the change in the history.
+    ///
+    /// Returns the permissions added and removed, or [`None`] if the user does not exist.
+    pub(crate) async fn write_perm(
+        &self,
+        conn: &mut SqliteConnection,
+        actor: Option<&str>,
+        user: &str,
+        f: impl FnOnce(&Perm) -> Perm,
+    ) -> Result<Option<(Perm, Perm)>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT user_perm.grp FROM user LEFT JOIN user_perm ON user_perm.user = user.user WHERE user.user = ?",
+        ))
+        .bind(user);
+        let prev: Vec<(Option<String>,)> = q.fetch_all(&mut *conn).await?;
+        if prev.is_empty() {
+            return Ok(None);
+        }
+        let prev = Perm::from(
+            prev.into_iter()
+                .filter_map(|(grp,)| grp)
+                .collect::<HashSet<_>>(),
+        );
+        let next = f(&prev);
+        let added = &next - &prev;
+        let removed = &prev - &next;
+        for grp in removed.iter() {
+            let q = query(self.sql("DELETE FROM user_perm WHERE user = ? AND grp = ?"))
+                .bind(user)
+                .bind(grp);
+            q.execute(&mut *conn).await?;
+        }
+        for grp in added.iter() {
+            let q = query(self.sql("INSERT INTO user_perm (user, grp) VALUES (?, ?);"))
+                .bind(user)
+                .bind(grp);
+            q.execute(&mut *conn).await?;
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            let q = query(self.sql(
+                "INSERT INTO perm_log (time, actor, user, added, removed) VALUES (?, ?, ?, ?, ?);",
+            ))
+            .bind(unix_now())
+            .bind(actor)
+            .bind(user)
+            .bind(added.to_string())
+            .bind(removed.to_string());
+            q.execute(&mut *conn).await?;
+        }
+        Ok(Some((added, removed)))
+    }
+}
+
+/// A change affecting effective permissions of users, see [`Basileus::subscribe_perm`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind"))]
+pub enum PermEvent {
+    /// Permissions held directly by a user changed.
+    #[cfg_attr(feature = "serde", serde(rename = "user"))]
+    User {
+        user: String,
+        actor: Option<String>,
+        added: Perm,
+        removed: Perm,
+    },
+    /// A role was assigned to or unassigned from a user.
+    #[cfg_attr(feature = "serde", serde(rename = "user_role"))]
+    UserRole {
+        user: String,
+        role: String,
+        assigned: bool,
+    },
+    /// Permissions bundled by a role changed, affecting every user holding it.
+    #[cfg_attr(feature = "serde", serde(rename = "role"))]
+    Role { role: String },
+    /// A role was deleted, affecting every user who held it.
+    #[cfg_attr(feature = "serde", serde(rename = "role_deleted"))]
+    RoleDeleted { role: String },
+    /// A group was deleted, affecting every user or role which held it.
+    #[cfg_attr(feature = "serde", serde(rename = "group_deleted"))]
+    GroupDeleted { group: String },
+}
+
+/// A single change of a user's permissions, see [`Basileus::apply_perm_changes`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "op"))]
+pub enum PermChange {
+    /// Replace the user's permissions, like [`Basileus::set_perm`].
+    #[cfg_attr(feature = "serde", serde(rename = "set"))]
+    Set { user: String, perm: Perm },
+    /// Give the user new permissions, like [`Basileus::give_perm`].
+    #[cfg_attr(feature = "serde", serde(rename = "give"))]
+    Give { user: String, perm: Perm },
+    /// Revoke permissions of the user, like [`Basileus::revoke_perm`].
+    #[cfg_attr(feature = "serde", serde(rename = "revoke"))]
+    Revoke { user: String, perm: Perm },
+}
+
+impl PermChange {
+    fn target(&self) -> (&str, &Perm) {
+        match self {
+            PermChange::Set { user, perm }
+            | PermChange::Give { user, perm }
+            | PermChange::Revoke { user, perm } => (user, perm),
+        }
+    }
+}
+
+/// A recorded change of a user's permissions.
+#[derive(Clone, Debug)]
+pub struct PermRecord {
+    /// Time of the change.
+    pub time: SystemTime,
+    /// The user who made the change, if known.
+    pub actor: Option<String>,
+    /// The user whose permissions were changed.
+    pub user: String,
+    /// Permissions added by the change.
+    pub added: Perm,
+    /// Permissions removed by the change.
+    pub removed: Perm,
+}
+
+/// Permission management configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PermConfig {
+    /// Permissions given to newly created users.
+    #[cfg_attr(feature = "serde", serde(rename = "default"))]
+    pub default: Perm,
+    /// Time-to-live of cached effective permissions, in seconds.
+    ///
+    /// Setting this to `0` disables the cache.
+    #[cfg_attr(feature = "serde", serde(rename = "cache-ttl"))]
+    pub cache_ttl: u64,
+    /// Permission a user must hold to delegate their own permissions to others,
+    /// see [`Basileus::give_perm_as`].
+    #[cfg_attr(feature = "serde", serde(rename = "delegate"))]
+    pub delegate: String,
+    /// Implication rules, mapping a permission to the permissions holding it implies,
+    /// e.g. `admin` implying `moderator`, which in turn implies `user`.
+    ///
+    /// Rules apply transitively and are independent of the dot-separated hierarchy of permissions,
+    /// i.e. a rule only applies if its permission is held literally.
+    #[cfg_attr(feature = "serde", serde(rename = "implies"))]
+    pub implies: HashMap<String, Perm>,
+    /// Whether superusers pass every permission check regardless of the permissions they hold.
+    ///
+    /// Every bypassed check is logged.
+    #[cfg_attr(feature = "serde", serde(rename = "superuser-bypass"))]
+    pub superuser_bypass: bool,
+    /// Name of the superuser account, considered only if [`Self::superuser_bypass`] is enabled.
+    #[cfg_attr(feature = "serde", serde(rename = "superuser"))]
+    pub superuser: Option<String>,
+    /// Permission whose holders are superusers, considered only if [`Self::superuser_bypass`] is enabled.
+    ///
+    /// The permission must be held literally, i.e. not through wildcards.
+    #[cfg_attr(feature = "serde", serde(rename = "superuser-group"))]
+    pub superuser_group: Option<String>,
+}
+
+impl Default for PermConfig {
+    fn default() -> Self {
+        Self {
+            default: Default::default(),
+            cache_ttl: 60,
+            delegate: "delegate".into(),
+            implies: Default::default(),
+            superuser_bypass: false,
+            superuser: Some("root".into()),
+            superuser_group: None,
         }
-        buf
+    }
+}
+
+impl PermConfig {
+    /// Whether `user` holding `perm` is a superuser bypassing permission checks.
+    pub(crate) fn is_superuser(&self, user: &str, perm: &Perm) -> bool {
+        self.superuser_bypass
+            && (self.superuser.as_deref() == Some(user)
+                || self
+                    .superuser_group
+                    .as_ref()
+                    .is_some_and(|x| perm.contains(x)))
+    }
+}
+
+pub struct PermModule {
+    /// Permissions given to newly created users, initialized from [`PermConfig::default`].
+    default: RwLock<Perm>,
+    /// Map from users to their cached effective permissions and time of caching.
+    cache: Mutex<HashMap<String, (Perm, Instant)>>,
+    /// Subscribers to permission changes.
+    pub(crate) events: Subscribers<PermEvent>,
+}
+
+impl PermModule {
+    pub fn new(config: &PermConfig) -> Self {
+        let default = RwLock::new(config.default.clone());
+        Self {
+            default,
+            cache: Mutex::new(HashMap::new()),
+            events: Default::default(),
+        }
+    }
+
+    /// Get the cached effective permissions of a user, if present and younger than `ttl` seconds.
+    fn cached(&self, user: &str, ttl: u64) -> Option<Perm> {
+        let ttl = Duration::from_secs(ttl);
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(user) {
+            Some((perm, time)) if time.elapsed() < ttl => Some(perm.clone()),
+            Some(_) => {
+                cache.remove(user);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn cache(&self, user: &str, perm: &Perm, ttl: u64) {
+        if ttl == 0 {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(user.into(), (perm.clone(), Instant::now()));
+    }
+
+    /// Invalidate the cached effective permissions of a user.
+    pub(crate) fn invalidate(&self, user: &str) {
+        self.cache.lock().unwrap().remove(user);
+    }
+
+    /// Invalidate all cached effective permissions.
+    pub(crate) fn invalidate_all(&self) {
+        self.cache.lock().unwrap().clear();
     }
 }
 
 impl Basileus {
+    /// Get permissions given to newly created users.
+    pub fn default_perm(&self) -> Perm {
+        self.perm.default.read().unwrap().clone()
+    }
+
+    /// Set permissions given to newly created users.
+    ///
+    /// This does not affect existing users.
+    pub fn set_default_perm(&self, perm: Perm) {
+        *self.perm.default.write().unwrap() = perm;
+        info!("updated default permissions");
+    }
+
+    /// Register a callback invoked after every change made through this library
+    /// which affects effective permissions of users.
+    ///
+    /// This allows host applications to invalidate their own caches or resynchronize downstream systems.
+    /// Callbacks are invoked synchronously, so they should return quickly, e.g. by forwarding the event into a channel.
+    pub fn subscribe_perm(&self, f: impl Fn(&PermEvent) + Send + Sync + 'static) -> SubscriptionId {
+        self.perm.events.subscribe(f)
+    }
+
+    /// Remove a callback registered by [`Self::subscribe_perm`].
+    pub fn unsubscribe_perm(&self, id: SubscriptionId) -> bool {
+        self.perm.events.unsubscribe(id)
+    }
+
     /// Get permissions the user holds, i.e. group names.
     pub async fn get_perm(&self, user: &str) -> Result<Perm, GetPermError> {
-        if !self.exist_user(user).await? {
-            return Err(GetPermError::UserNotExist(user.into()));
-        }
-        let query = query_as("SELECT grp FROM perm WHERE user = ?").bind(user);
-        let (res,): (String,) = query.fetch_one(&self.db).await?;
-        let perm = res.into();
-        Ok(perm)
+        self.spanned("get_perm", Some(user), async {
+            let query = query_as(self.sql(
+                "SELECT user_perm.grp FROM user LEFT JOIN user_perm ON user_perm.user = user.user WHERE user.user = ?",
+            ))
+            .bind(user);
+            let res: Vec<(Option<String>,)> = query.fetch_all(&self.db).await?;
+            if res.is_empty() {
+                return Err(GetPermError::UserNotExist(user.into()));
+            }
+            let perm = res
+                .into_iter()
+                .filter_map(|(grp,)| grp)
+                .collect::<HashSet<_>>();
+            Ok(perm.into())
+        })
+        .await
+    }
+
+    /// List every distinct permission currently granted to any user, either directly or through roles.
+    ///
+    /// This includes deny entries, but not permissions only implied by hierarchy or [implication rules](PermConfig::implies).
+    pub async fn list_all_perms(&self) -> Result<Perm, sqlx::error::Error> {
+        let query = query_as(self.sql("SELECT grp FROM user_perm UNION SELECT grp FROM role_perm"));
+        let res: Vec<(String,)> = query.fetch_all(&self.db).await?;
+        let perm = res.into_iter().map(|(grp,)| grp).collect::<HashSet<_>>();
+        Ok(perm.into())
+    }
+
+    /// Get effective permissions of the user,
+    /// i.e. permissions held directly together with those bundled by the roles assigned,
+    /// [expanded](Perm::expand) by the [implication rules](PermConfig::implies).
+    ///
+    /// Results are cached for [`PermConfig::cache_ttl`] seconds,
+    /// and invalidated whenever the permissions of the user or their roles change through this library.
+    pub async fn get_effective_perm(&self, user: &str) -> Result<Perm, GetPermError> {
+        self.spanned("get_effective_perm", Some(user), async {
+            let ttl = self.config.read().unwrap().perm.cache_ttl;
+            if let Some(perm) = self.perm.cached(user, ttl) {
+                return Ok(perm);
+            }
+            let start = Instant::now();
+            // the first row is null if and only if the user exists
+            let query = query_as(self.sql(
+                r#"
+    SELECT NULL FROM user WHERE user = ?
+    UNION
+    SELECT grp FROM user_perm WHERE user = ?
+    UNION
+    SELECT role_perm.grp FROM role_perm JOIN user_role ON role_perm.role = user_role.role WHERE user_role.user = ?
+    "#,
+            ))
+            .bind(user)
+            .bind(user)
+            .bind(user);
+            let res: Vec<(Option<String>,)> = query.fetch_all(&self.db).await?;
+            if res.is_empty() {
+                return Err(GetPermError::UserNotExist(user.into()));
+            }
+            let perm = Perm::from(
+                res.into_iter()
+                    .filter_map(|(grp,)| grp)
+                    .collect::<HashSet<_>>(),
+            )
+            .expand(&self.config.read().unwrap().perm.implies);
+            self.perm.cache(user, &perm, ttl);
+            self.record("perm.effective", start);
+            Ok(perm)
+        })
+        .await
+    }
+
+    /// Whether `admin` may act on `user` without escalating privileges, e.g. reset their password,
+    /// i.e. whether the effective permissions of `admin` satisfy every permission granted to `user`,
+    /// who must not be a [superuser](PermConfig::superuser_bypass).
+    pub async fn outranks(&self, admin: &str, user: &str) -> Result<bool, GetPermError> {
+        let admin_perm = self.get_effective_perm(admin).await?;
+        let user_perm = self.get_effective_perm(user).await?;
+        let granted: Perm = user_perm.grants().collect::<Vec<_>>().join(" ").into();
+        let superuser = (self.config.read().unwrap().perm).is_superuser(user, &user_perm);
+        Ok(!superuser && admin_perm.satisfies(&granted))
     }
 
-    /// Check if the user has specified permission.
+    /// Log and [audit](Self::audit) that the superuser `user` passed a check for `req` they may not satisfy.
+    pub(crate) fn superuser_bypassed(&self, user: &str, req: &Perm) {
+        let req = req.to_string();
+        let req = req.trim_end();
+        warn!(
+            "superuser {} bypassed check for [{req}]",
+            self.log_user(user)
+        );
+        self.audit(&AuditEvent {
+            detail: Some(req.into()),
+            ..AuditEvent::new(Some(user), "perm.bypass")
+        });
+    }
+
+    /// Check if the user has specified permission, either directly or through roles.
+    ///
+    /// Superusers always pass if [`PermConfig::superuser_bypass`] is enabled.
     pub async fn check_perm(&self, user: &str, req: &Perm) -> Result<bool, CheckPermError> {
-        if !self.exist_user(user).await? {
-            return Err(CheckPermError::UserNotExist(user.into()));
-        }
-        let perm = self.get_perm(user).await?;
-        Ok(perm >= *req)
+        self.spanned("check_perm", Some(user), async {
+            let perm = match self.get_effective_perm(user).await {
+                Err(GetPermError::UserNotExist(user)) => {
+                    return Err(CheckPermError::UserNotExist(user));
+                }
+                res => res?,
+            };
+            if self.config.read().unwrap().perm.is_superuser(user, &perm) {
+                self.superuser_bypassed(user, req);
+                return Ok(true);
+            }
+            Ok(perm.satisfies(req))
+        })
+        .await
+    }
+
+    /// Check if the user has any of the specified permissions, either directly or through roles.
+    ///
+    /// This is more efficient than calling [`Self::check_perm`] repeatedly,
+    /// as the permissions of the user are only looked up once.
+    pub async fn check_any_perm(&self, user: &str, reqs: &[Perm]) -> Result<bool, CheckPermError> {
+        self.spanned("check_any_perm", Some(user), async {
+            let matched = self.match_perm(user, reqs).await?;
+            Ok(!matched.is_empty())
+        })
+        .await
+    }
+
+    /// Check which of the specified permissions the user has, either directly or through roles.
+    ///
+    /// Superusers always have all of them if [`PermConfig::superuser_bypass`] is enabled.
+    ///
+    /// Returns the indices into `reqs` of the satisfied permissions, in ascending order.
+    pub async fn match_perm(
+        &self,
+        user: &str,
+        reqs: &[Perm],
+    ) -> Result<Vec<usize>, CheckPermError> {
+        self.spanned("match_perm", Some(user), async {
+            let perm = match self.get_effective_perm(user).await {
+                Err(GetPermError::UserNotExist(user)) => {
+                    return Err(CheckPermError::UserNotExist(user));
+                }
+                res => res?,
+            };
+            if self.config.read().unwrap().perm.is_superuser(user, &perm) {
+                warn!(
+                    "superuser {} bypassed check for {} permissions",
+                    self.log_user(user),
+                    reqs.len()
+                );
+                return Ok((0..reqs.len()).collect());
+            }
+            let matched = reqs
+                .iter()
+                .enumerate()
+                .filter(|(_, req)| perm.satisfies(req))
+                .map(|(i, _)| i)
+                .collect();
+            Ok(matched)
+        })
+        .await
     }
 
     /// Sets a user's permission.
     pub async fn set_perm(&self, user: &str, perm: &Perm) -> Result<(), SetPermError> {
-        if !self.exist_user(user).await? {
+        self.spanned(
+            "set_perm",
+            Some(user),
+            self.set_perm_inner(None, user, perm),
+        )
+        .await
+    }
+
+    /// Sets a user's permission on behalf of `actor`, who is recorded in the [history](Self::perm_history).
+    pub async fn set_perm_by(
+        &self,
+        actor: &str,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), SetPermError> {
+        self.spanned(
+            "set_perm_by",
+            Some(user),
+            self.set_perm_inner(Some(actor), user, perm),
+        )
+        .await
+    }
+
+    async fn set_perm_inner(
+        &self,
+        actor: Option<&str>,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), SetPermError> {
+        if let Some(invalid) = perm.find_invalid() {
+            if !self.exist_user(user).await? {
+                return Err(SetPermError::UserNotExist(user.into()));
+            }
+            return Err(SetPermError::InvalidPerm(invalid.into()));
+        }
+        if !self.update_perm(actor, user, |_| perm.clone()).await? {
             return Err(SetPermError::UserNotExist(user.into()));
         }
-        let grp = perm.to_string();
-        let query = query("INSERT OR REPLACE INTO perm (user, grp) VALUES (?, ?);")
-            .bind(user)
-            .bind(grp);
-        query.execute(&self.db).await?;
         Ok(())
     }
 
     /// Gives new permissions to specified user.
     pub async fn give_perm(&self, user: &str, perm: &Perm) -> Result<(), GivePermError> {
-        if !self.exist_user(user).await? {
+        self.spanned(
+            "give_perm",
+            Some(user),
+            self.give_perm_inner(None, user, perm),
+        )
+        .await
+    }
+
+    /// Gives new permissions to specified user on behalf of `actor`, who is recorded in the [history](Self::perm_history).
+    pub async fn give_perm_by(
+        &self,
+        actor: &str,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), GivePermError> {
+        self.spanned(
+            "give_perm_by",
+            Some(user),
+            self.give_perm_inner(Some(actor), user, perm),
+        )
+        .await
+    }
+
+    async fn give_perm_inner(
+        &self,
+        actor: Option<&str>,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), GivePermError> {
+        if let Some(invalid) = perm.find_invalid() {
+            if !self.exist_user(user).await? {
+                return Err(GivePermError::UserNotExist(user.into()));
+            }
+            return Err(GivePermError::InvalidPerm(invalid.into()));
+        }
+        if !self.update_perm(actor, user, |prev| prev + perm).await? {
             return Err(GivePermError::UserNotExist(user.into()));
         }
-        let prev = self.get_perm(user).await?;
-        let sum = &prev + perm;
-        self.set_perm(user, &sum).await?;
         Ok(())
     }
 
     /// Revoke a user's certain permissions.
     /// This does not result in an error if the permission does not currently exist.
     pub async fn revoke_perm(&self, user: &str, perm: &Perm) -> Result<(), RevokePermError> {
-        if !self.exist_user(user).await? {
+        self.spanned(
+            "revoke_perm",
+            Some(user),
+            self.revoke_perm_inner(None, user, perm),
+        )
+        .await
+    }
+
+    /// Revoke a user's certain permissions on behalf of `actor`, who is recorded in the [history](Self::perm_history).
+    /// This does not result in an error if the permission does not currently exist.
+    pub async fn revoke_perm_by(
+        &self,
+        actor: &str,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), RevokePermError> {
+        self.spanned(
+            "revoke_perm_by",
+            Some(user),
+            self.revoke_perm_inner(Some(actor), user, perm),
+        )
+        .await
+    }
+
+    /// Check whether `actor` may delegate `perm`,
+    /// i.e. holds the [delegation permission](PermConfig::delegate) as well as every permission in `perm`.
+    ///
+    /// Deny entries in `perm` are treated as the permissions they deny.
+    pub async fn may_delegate(&self, actor: &str, perm: &Perm) -> Result<bool, DelegatePermError> {
+        self.spanned("may_delegate", Some(actor), async {
+            let held = match self.get_effective_perm(actor).await {
+                Err(GetPermError::UserNotExist(user)) => {
+                    return Err(DelegatePermError::UserNotExist(user));
+                }
+                res => res?,
+            };
+            let mut req: Perm = perm
+                .iter()
+                .map(|x| x.strip_prefix('!').unwrap_or(x).to_owned())
+                .collect::<HashSet<_>>()
+                .into();
+            req.insert(self.config.read().unwrap().perm.delegate.clone());
+            Ok(held.satisfies(&req))
+        })
+        .await
+    }
+
+    /// Gives permissions to `user` on behalf of `actor`,
+    /// provided that the actor [may delegate](Self::may_delegate) them.
+    ///
+    /// This allows users without full administrative rights to manage the permissions they hold themselves.
+    pub async fn give_perm_as(
+        &self,
+        actor: &str,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), DelegatePermError> {
+        self.spanned("give_perm_as", Some(user), async {
+            if !self.may_delegate(actor, perm).await? {
+                return Err(DelegatePermError::Forbidden(actor.into()));
+            }
+            self.give_perm_inner(Some(actor), user, perm).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Revoke permissions of `user` on behalf of `actor`,
+    /// provided that the actor [may delegate](Self::may_delegate) them.
+    pub async fn revoke_perm_as(
+        &self,
+        actor: &str,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), DelegatePermError> {
+        self.spanned("revoke_perm_as", Some(user), async {
+            if !self.may_delegate(actor, perm).await? {
+                return Err(DelegatePermError::Forbidden(actor.into()));
+            }
+            self.revoke_perm_inner(Some(actor), user, perm).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_perm_inner(
+        &self,
+        actor: Option<&str>,
+        user: &str,
+        perm: &Perm,
+    ) -> Result<(), RevokePermError> {
+        if !self.update_perm(actor, user, |prev| prev - perm).await? {
             return Err(RevokePermError::UserNotExist(user.into()));
         }
-        let prev = self.get_perm(user).await?;
-        let diff = &prev - perm;
-        self.set_perm(user, &diff).await?;
         Ok(())
     }
+
+    /// Atomically replace a user's permissions with `f(previous)`, recording the change in the history.
+    ///
+    /// Returns whether the user exists.
+    pub(crate) async fn update_perm(
+        &self,
+        actor: Option<&str>,
+        user: &str,
+        f: impl FnOnce(&Perm) -> Perm,
+    ) -> Result<bool, sqlx::error::Error> {
+        let mut tx = self.begin_write().await?;
+        let Some((added, removed)) = self.write_perm(&mut tx, actor, user, f).await? else {
+            return Ok(false);
+        };
+        tx.commit().await?;
+        self.perm.invalidate(user);
+        info!(
+            "updated permissions for {}: +[{}] -[{}]",
+            self.log_user(user),
+            added.to_string().trim_end(),
+            removed.to_string().trim_end()
+        );
+        if !added.is_empty() || !removed.is_empty() {
+            self.emit_perm(PermEvent::User {
+                user: user.into(),
+                actor: actor.map(|x| x.into()),
+                added,
+                removed,
+            });
+        }
+        Ok(true)
+    }
+
+    /// Apply many permission changes across users atomically,
+    /// i.e. either all of them take effect or, if any one fails, none of them does.
+    ///
+    /// Changes are applied in order, so later changes to the same user see the result of earlier ones.
+    pub async fn apply_perm_changes(&self, changes: Vec<PermChange>) -> Result<(), ApplyPermError> {
+        self.spanned(
+            "apply_perm_changes",
+            None,
+            self.apply_perm_changes_inner(None, changes),
+        )
+        .await
+    }
+
+    /// Apply many permission changes across users atomically on behalf of `actor`,
+    /// who is recorded in the [history](Self::perm_history).
+    pub async fn apply_perm_changes_by(
+        &self,
+        actor: &str,
+        changes: Vec<PermChange>,
+    ) -> Result<(), ApplyPermError> {
+        self.spanned(
+            "apply_perm_changes_by",
+            None,
+            self.apply_perm_changes_inner(Some(actor), changes),
+        )
+        .await
+    }
+
+    async fn apply_perm_changes_inner(
+        &self,
+        actor: Option<&str>,
+        changes: Vec<PermChange>,
+    ) -> Result<(), ApplyPermError> {
+        let mut tx = self.begin_write().await?;
+        let mut events = vec![];
+        for change in &changes {
+            let (user, perm) = change.target();
+            // nothing is committed on error, so validation may follow writing
+            let res = match change {
+                PermChange::Set { perm, .. } => {
+                    self.write_perm(&mut tx, actor, user, |_| perm.clone())
+                        .await?
+                }
+                PermChange::Give { perm, .. } => {
+                    self.write_perm(&mut tx, actor, user, |prev| prev + perm)
+                        .await?
+                }
+                PermChange::Revoke { perm, .. } => {
+                    self.write_perm(&mut tx, actor, user, |prev| prev - perm)
+                        .await?
+                }
+            };
+            let Some((added, removed)) = res else {
+                return Err(ApplyPermError::UserNotExist(user.into()));
+            };
+            if !matches!(change, PermChange::Revoke { .. })
+                && let Some(invalid) = perm.find_invalid()
+            {
+                return Err(ApplyPermError::InvalidPerm(invalid.into()));
+            }
+            events.push(PermEvent::User {
+                user: user.into(),
+                actor: actor.map(|x| x.into()),
+                added,
+                removed,
+            });
+        }
+        tx.commit().await?;
+        for event in events {
+            if let PermEvent::User {
+                user,
+                added,
+                removed,
+                ..
+            } = &event
+            {
+                self.perm.invalidate(user);
+                if added.is_empty() && removed.is_empty() {
+                    continue;
+                }
+            }
+            self.emit_perm(event);
+        }
+        info!("applied {} permission changes", changes.len());
+        Ok(())
+    }
+
+    /// Get the history of permission changes of a user, oldest first.
+    ///
+    /// The history is kept even after the user is deleted.
+    pub async fn perm_history(&self, user: &str) -> Result<Vec<PermRecord>, sqlx::error::Error> {
+        let query = query_as(self.sql(
+            "SELECT time, actor, user, added, removed FROM perm_log WHERE user = ? ORDER BY id",
+        ))
+        .bind(user);
+        let res: Vec<(i64, Option<String>, String, String, String)> =
+            query.fetch_all(&self.db).await?;
+        let res = res
+            .into_iter()
+            .map(|(time, actor, user, added, removed)| PermRecord {
+                time: from_unix(time),
+                actor,
+                user,
+                added: added.into(),
+                removed: removed.into(),
+            })
+            .collect();
+        Ok(res)
+    }
 }
@@ -1,17 +1,21 @@
-use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Mutex, time::Instant};
+use std::{
+    collections::HashMap, fmt::Display, net::IpAddr, str::FromStr, sync::Mutex, time::Instant,
+};
 
 use base64::{Engine, prelude::BASE64_URL_SAFE};
 use sha2::{Digest, Sha256};
-use tracing::warn;
+use tracing::{debug, trace, warn};
 
 use crate::{
     Basileus,
     err::{PkceAuthError, PkceTokenError},
+    login::LoginContext,
 };
 
 /// A client PKCE code challenge, as defined in [RFC 7636](https://datatracker.ietf.org/doc/html/rfc7636#section-4.2).
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct CodeChallenge {
     /// The base64URL-encoded `code_challenge`.
     #[cfg_attr(feature = "serde", serde(rename = "code_challenge"))]
@@ -24,6 +28,7 @@ pub struct CodeChallenge {
 /// The PKCE code challenge method, as defined in [RFC 7636](https://datatracker.ietf.org/doc/html/rfc7636#section-4.2).
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub enum CodeChallengeMethod {
     /// SHA256 transformation.
     #[cfg_attr(feature = "serde", serde(rename = "S256"))]
@@ -92,6 +97,10 @@ pub struct Pkce {
     pub code_challenge: CodeChallenge,
     /// Time of creation.
     pub begin: Instant,
+    /// Whether the user presented a second factor.
+    pub mfa: bool,
+    /// Wrong code verifiers given so far, see [`RateLimitConfig::pkce_attempts`](crate::ratelimit::RateLimitConfig::pkce_attempts).
+    pub failures: u32,
 }
 
 impl Pkce {
@@ -101,13 +110,20 @@ impl Pkce {
             user,
             code_challenge,
             begin: Instant::now(),
+            mfa: false,
+            failures: 0,
         }
     }
 
     /// Check if the PKCE request is still valid.
     /// Expiry time is set to 10 minutes (600 seconds).
     pub fn valid(&self) -> bool {
-        self.begin.elapsed().as_secs() <= 600
+        self.valid_for(600)
+    }
+
+    /// Check if the PKCE request is younger than `ttl` seconds.
+    pub fn valid_for(&self, ttl: u64) -> bool {
+        self.begin.elapsed().as_secs() <= ttl
     }
 }
 
@@ -117,70 +133,114 @@ impl Pkce {
 pub struct PkceConfig {
     /// Whether to allow the `plain` transformation method for PKCE code challenges.
     ///
-    /// **This is a security vulnerability and should always be avoided.**
+    /// **This is a security vulnerability**, since every client of PKCE is public,
+    /// so [validation](crate::Config::validate) rejects configurations allowing it.
     #[cfg(feature = "serde")]
     #[serde_inline_default(false)]
     pub allow_plain: bool,
     /// Whether to allow the `plain` transformation method for PKCE code challenges.
     ///
-    /// **This is a security vulnerability and should always be avoided.**
+    /// **This is a security vulnerability**, since every client of PKCE is public,
+    /// so [validation](crate::Config::validate) rejects configurations allowing it.
     #[cfg(not(feature = "serde"))]
     pub allow_plain: bool,
+    /// Time in seconds an authorization code stays valid.
+    #[cfg(feature = "serde")]
+    #[serde_inline_default(600)]
+    #[serde(rename = "code-ttl")]
+    pub code_ttl: u64,
+    /// Time in seconds an authorization code stays valid.
+    #[cfg(not(feature = "serde"))]
+    pub code_ttl: u64,
 }
 
 impl Default for PkceConfig {
     fn default() -> Self {
-        Self { allow_plain: false }
+        Self {
+            allow_plain: false,
+            code_ttl: 600,
+        }
     }
 }
 
+#[derive(Default)]
 pub struct PkceModule {
-    pub config: PkceConfig,
     /// Map from PKCE challenges to their beloinging users.
     pending: Mutex<HashMap<String, Pkce>>,
 }
 
 impl PkceModule {
-    pub fn new(config: PkceConfig) -> Self {
-        if config.allow_plain {
-            warn!(
-                "allowing `plain` transformation method for PKCE. This is a security vulnerability"
-            );
-        }
-        Self {
-            config,
-            pending: Mutex::new(HashMap::new()),
-        }
+    pub fn new() -> Self {
+        Self::default()
     }
 }
 
 impl Basileus {
+    /// Discard every pending PKCE request which is no longer valid.
+    pub fn expire_pkce(&self) {
+        let ttl = self.config.read().unwrap().pkce.code_ttl;
+        let mut pending = self.pkce.pending.lock().unwrap();
+        let prev = pending.len();
+        pending.retain(|_, pkce| pkce.valid_for(ttl));
+        let diff = prev - pending.len();
+        trace!("expired {diff} PKCE requests");
+    }
+
     /// Handle a PKCE authorization request.
     ///
+    /// The user is authenticated as by [`Self::verify_login`], including any second factor given in `ctx`.
     /// If the authorization is successful, returns a base64URL-encoded [authorization code](https://datatracker.ietf.org/doc/html/rfc6749#section-4.1.2).
     pub async fn pkce_auth_req(
         &self,
         user: &str,
         pass: &str,
         code_challenge: CodeChallenge,
+        ctx: &LoginContext,
+    ) -> Result<String, PkceAuthError> {
+        self.spanned("pkce_auth_req", Some(user), async {
+            let res = self
+                .pkce_auth_req_inner(user, pass, code_challenge, ctx)
+                .await;
+            self.count(match res {
+                Ok(_) => "pkce.code_issued",
+                Err(_) => "pkce.auth_failed",
+            });
+            res
+        })
+        .await
+    }
+
+    async fn pkce_auth_req_inner(
+        &self,
+        user: &str,
+        pass: &str,
+        code_challenge: CodeChallenge,
+        ctx: &LoginContext,
     ) -> Result<String, PkceAuthError> {
-        if code_challenge.method == CodeChallengeMethod::Plain && !self.pkce.config.allow_plain {
+        if !self.config.read().unwrap().modules.pkce {
+            return Err(PkceAuthError::FeatureDisabled);
+        }
+        if code_challenge.method == CodeChallengeMethod::Plain
+            && !self.config.read().unwrap().pkce.allow_plain
+        {
             return Err(PkceAuthError::InsecurePlain);
         }
 
-        if !self.verify_pass(user, pass).await? {
-            return Err(PkceAuthError::Unauthorized);
-        }
+        let mfa = self.verify_login(user, pass, ctx).await?;
 
         let auth_code = Sha256::digest(format!("{user}, {code_challenge}"));
         let auth_code = BASE64_URL_SAFE.encode(auth_code);
 
-        let pkce = Pkce::new(user.into(), code_challenge);
+        let mut pkce = Pkce::new(user.into(), code_challenge);
+        pkce.mfa = mfa;
         self.pkce
             .pending
             .lock()
             .unwrap()
             .insert(auth_code.clone(), pkce);
+        if self.sampled() {
+            trace!("issued PKCE authorization code to {}", self.log_user(user));
+        }
         Ok(auth_code)
     }
 
@@ -189,22 +249,93 @@ impl Basileus {
     /// A successful request requires a valid previously issued authorization code (through [`Self::pkce_auth_req`]) and a matching code verifier.
     ///
     /// Returns the token if successful.
+    ///
+    /// An authorization code is burnt after [`RateLimitConfig::pkce_attempts`](crate::ratelimit::RateLimitConfig::pkce_attempts)
+    /// wrong code verifiers, so that a stolen code can not be exchanged by guessing its verifier,
+    /// and requests for codes of the same user are limited by the [PKCE throttle](crate::ratelimit::Throttles::pkce).
     pub fn pkce_token_req(
         &self,
         code: &str,
         code_verifier: &str,
     ) -> Result<String, PkceTokenError> {
-        let pkce = match self.pkce.pending.lock().unwrap().remove(code) {
-            Some(pkce) => pkce,
-            None => return Err(PkceTokenError::InvalidCode),
+        self.pkce_token_req_from(code, code_verifier, None, None)
+    }
+
+    /// Like [`Self::pkce_token_req`], limiting requests from the client at `ip`, if known,
+    /// and [binding](Self::issue_bound_token) the token to the client certificate with the thumbprint `cert`, if any.
+    ///
+    /// Invalid codes or verifiers count as failed attempts against the [address throttle](crate::ratelimit::Throttles::ip),
+    /// failing further requests with [`PkceTokenError::RateLimited`] once its budget is exhausted.
+    pub fn pkce_token_req_from(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        ip: Option<IpAddr>,
+        cert: Option<&str>,
+    ) -> Result<String, PkceTokenError> {
+        let res = self.spanned_sync("pkce_token_req", None, || {
+            let throttles = self.throttles();
+            if let Some(ip) = ip {
+                (throttles.ip.check(&ip.to_string())).map_err(PkceTokenError::RateLimited)?;
+            }
+            // keyed by the user of the code, since the address of the client may be unknown
+            let user = (self.pkce.pending.lock().unwrap().get(code)).map(|x| x.user.clone());
+            if let Some(user) = user {
+                (throttles.pkce.acquire(&user)).map_err(PkceTokenError::RateLimited)?;
+            }
+            let res = self.pkce_token_req_inner(code, code_verifier, cert);
+            if let (Some(ip), Err(PkceTokenError::InvalidCode | PkceTokenError::InvalidVerifier)) =
+                (ip, &res)
+            {
+                self.throttles.ip.hit(&ip.to_string());
+            }
+            res
+        });
+        self.count(match res {
+            Ok(_) => "pkce.token_issued",
+            Err(_) => "pkce.token_failed",
+        });
+        res
+    }
+
+    fn pkce_token_req_inner(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        cert: Option<&str>,
+    ) -> Result<String, PkceTokenError> {
+        if !self.config.read().unwrap().modules.pkce {
+            return Err(PkceTokenError::FeatureDisabled);
+        }
+        let attempts = self.config.read().unwrap().rate_limit.pkce_attempts;
+        let mut pending = self.pkce.pending.lock().unwrap();
+        let Some(mut pkce) = pending.remove(code) else {
+            return Err(PkceTokenError::InvalidCode);
         };
-        if !pkce.valid() {
+        if !pkce.valid_for(self.config.read().unwrap().pkce.code_ttl) {
             return Err(PkceTokenError::ExpiredCode);
         }
         if !pkce.code_challenge.verify(code_verifier) {
+            pkce.failures += 1;
+            if pkce.failures < attempts {
+                debug!(
+                    "wrong code verifier for authorization code of {}",
+                    self.log_user(&pkce.user)
+                );
+                pending.insert(code.into(), pkce);
+            } else {
+                warn!(
+                    "burnt authorization code of {} after {} wrong code verifiers",
+                    self.log_user(&pkce.user),
+                    pkce.failures
+                );
+                self.count("pkce.code_burnt");
+            }
             return Err(PkceTokenError::InvalidVerifier);
         }
-        let token = self.issue_token(&pkce.user);
+        drop(pending);
+        self.record_user(&pkce.user);
+        let token = self.issue_session_as(&pkce.user, pkce.mfa, None, cert.map(Into::into));
         Ok(token)
     }
 }
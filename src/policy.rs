@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::trace;
+
+use crate::{
+    Basileus,
+    err::{EvaluatePolicyError, GetPermError},
+    perm::Perm,
+};
+
+/// Attribute value in a rule condition which is substituted by the name of the user being evaluated.
+///
+/// E.g. a condition `owner = "$user"` only matches resources owned by the user themselves.
+pub const USER_PLACEHOLDER: &str = "$user";
+
+/// The effect of a matching policy rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Effect {
+    /// Allow the request, unless denied by another rule.
+    #[cfg_attr(feature = "serde", serde(rename = "allow"))]
+    Allow,
+    /// Deny the request regardless of other rules.
+    #[cfg_attr(feature = "serde", serde(rename = "deny"))]
+    Deny,
+}
+
+/// A single policy rule.
+///
+/// A rule matches a request only if every one of its conditions holds.
+/// Empty conditions always hold.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule {
+    /// Whether a matching rule allows or denies the request.
+    #[cfg_attr(feature = "serde", serde(rename = "effect"))]
+    pub effect: Effect,
+    /// Actions the rule applies to, where `*` matches any action.
+    #[cfg_attr(feature = "serde", serde(rename = "actions"))]
+    pub actions: Vec<String>,
+    /// Permissions the user must hold, see [`Perm::satisfies`].
+    #[cfg_attr(feature = "serde", serde(rename = "perm", default))]
+    pub perm: Perm,
+    /// Attributes the resource must have, with [`USER_PLACEHOLDER`] standing for the user being evaluated.
+    #[cfg_attr(feature = "serde", serde(rename = "resource", default))]
+    pub resource: HashMap<String, String>,
+    /// Range of hours `[from, to)` of the day in UTC during which the rule applies, each from 0 to 23.
+    ///
+    /// The range wraps around midnight if `from > to`.
+    #[cfg_attr(feature = "serde", serde(rename = "hours", default))]
+    pub hours: Option<(u8, u8)>,
+    /// IP networks in CIDR notation, e.g. `10.0.0.0/8`, the request must originate from.
+    #[cfg_attr(feature = "serde", serde(rename = "networks", default))]
+    pub networks: Vec<String>,
+}
+
+/// Configuration of the attribute-based policy engine.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PolicyConfig {
+    /// Rules evaluated by [`Basileus::evaluate`].
+    #[cfg_attr(feature = "serde", serde(rename = "rules"))]
+    pub rules: Vec<Rule>,
+}
+
+/// Context of a request evaluated against the policy.
+#[derive(Clone, Debug)]
+pub struct PolicyContext {
+    /// Time of the request.
+    pub time: SystemTime,
+    /// Source IP address of the request, if known.
+    pub ip: Option<IpAddr>,
+}
+
+impl Default for PolicyContext {
+    fn default() -> Self {
+        Self {
+            time: SystemTime::now(),
+            ip: None,
+        }
+    }
+}
+
+/// Parse a network in CIDR notation, e.g. `192.168.0.0/16`, into its address and prefix length.
+/// A plain address without prefix length is a network of itself alone.
+pub(crate) fn parse_network(cidr: &str) -> Option<(IpAddr, u32)> {
+    let (addr, len) = match cidr.split_once('/') {
+        Some((addr, len)) => (addr.parse::<IpAddr>().ok()?, Some(len.parse().ok()?)),
+        None => (cidr.parse().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let len = len.unwrap_or(max);
+    (len <= max).then_some((addr, len))
+}
+
+/// Whether `ip` lies within the network `cidr`, see [`parse_network`].
+fn in_network(ip: &IpAddr, cidr: &str) -> bool {
+    // rejected by validation of the configuration
+    let Some((addr, len)) = parse_network(cidr) else {
+        return false;
+    };
+    match (ip, addr) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            u32::from(*ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            u128::from(*ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+impl Rule {
+    fn matches(
+        &self,
+        user: &str,
+        perm: &Perm,
+        action: &str,
+        resource: &HashMap<String, String>,
+        context: &PolicyContext,
+    ) -> bool {
+        if !self.actions.iter().any(|x| x == "*" || x == action) {
+            return false;
+        }
+        if !perm.satisfies(&self.perm) {
+            return false;
+        }
+        let attrs = self.resource.iter().all(|(key, value)| {
+            let value = if value == USER_PLACEHOLDER {
+                user
+            } else {
+                value
+            };
+            resource.get(key).is_some_and(|x| x == value)
+        });
+        if !attrs {
+            return false;
+        }
+        if let Some((from, to)) = self.hours {
+            let secs = context
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let hour = (secs % 86400 / 3600) as u8;
+            let within = if from <= to {
+                from <= hour && hour < to
+            } else {
+                from <= hour || hour < to
+            };
+            if !within {
+                return false;
+            }
+        }
+        if !self.networks.is_empty() {
+            let Some(ip) = &context.ip else {
+                return false;
+            };
+            if !self.networks.iter().any(|x| in_network(ip, x)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Basileus {
+    /// Evaluate whether the user may perform `action` on a resource with the specified attributes,
+    /// according to the rules in [`PolicyConfig`].
+    ///
+    /// A request is allowed if at least one rule allowing it matches and no rule denying it matches.
+    /// That is, deny rules take precedence, and requests matching no rule are denied.
+    pub async fn evaluate(
+        &self,
+        user: &str,
+        action: &str,
+        resource: &HashMap<String, String>,
+        context: &PolicyContext,
+    ) -> Result<bool, EvaluatePolicyError> {
+        let perm = match self.get_effective_perm(user).await {
+            Err(GetPermError::UserNotExist(user)) => {
+                return Err(EvaluatePolicyError::UserNotExist(user));
+            }
+            res => res?,
+        };
+        let mut allowed = false;
+        let config = self.config.read().unwrap();
+        for rule in &config.policy.rules {
+            if !rule.matches(user, &perm, action, resource, context) {
+                continue;
+            }
+            match rule.effect {
+                Effect::Allow => allowed = true,
+                Effect::Deny => {
+                    trace!("denied {action} to {} by policy", self.log_user(user));
+                    return Ok(false);
+                }
+            }
+        }
+        trace!(
+            "evaluated {action} for {} by policy: {allowed}",
+            self.log_user(user)
+        );
+        Ok(allowed)
+    }
+}
@@ -1,2 +1,5 @@
-pub use super::perm::Perm;
-pub use super::user::check_username;
+pub use super::audit::ResultExt;
+pub use super::err::ErrorCode;
+pub use super::perm::{Perm, PermPath, check_perm_name};
+pub use super::role::check_rolename;
+pub use super::user::{check_username, validate_username};
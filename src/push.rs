@@ -0,0 +1,180 @@
+use std::{
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::{query, query_as};
+use tracing::{debug, info, warn};
+
+use crate::{
+    Basileus,
+    err::{LoginError, MfaError},
+    mfa::MfaKind,
+    rand_buf, unix_now,
+};
+
+/// A request to approve a login, sent by a [`PushApprover`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PushRequest {
+    /// Random identifier of the request, e.g. to correlate the response of the device with.
+    pub id: String,
+    pub user: String,
+    /// Devices the user [enabled](Basileus::enable_push), oldest first.
+    pub devices: Vec<String>,
+    /// Source address of the login attempt, if known, e.g. to show its location to the user.
+    pub ip: Option<IpAddr>,
+    /// Time the user has to respond, see [`crate::mfa::MfaConfig::push_timeout`].
+    pub timeout: Duration,
+}
+
+/// Response of a user to a [`PushRequest`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PushDecision {
+    Approved,
+    Denied,
+    /// The user did not respond within [`PushRequest::timeout`].
+    TimedOut,
+}
+
+/// Future returned by [`PushApprover::request`].
+pub type PushFuture<'a> = Pin<Box<dyn Future<Output = Result<PushDecision, String>> + Send + 'a>>;
+
+/// Sender of approval requests to the devices of users, e.g. through Duo or a companion app,
+/// see [`Basileus::set_push_approver`].
+pub trait PushApprover: Send + Sync {
+    /// Send `request` to one or all of its devices and wait for the user to respond,
+    /// failing with a description of the problem if the request could not be delivered.
+    ///
+    /// The library does not depend on an async runtime,
+    /// so implementations have to resolve with [`PushDecision::TimedOut`] once [`PushRequest::timeout`] elapsed.
+    fn request<'a>(&'a self, request: &'a PushRequest) -> PushFuture<'a>;
+}
+
+#[derive(Default)]
+pub struct PushModule {
+    approver: RwLock<Option<Arc<dyn PushApprover>>>,
+}
+
+impl PushModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Request approval of logins through `approver`, replacing any previously set.
+    ///
+    /// Push approval can neither be enabled nor used until an approver is set.
+    pub fn set_push_approver(&self, approver: impl PushApprover + 'static) {
+        *self.push.approver.write().unwrap() = Some(Arc::new(approver));
+    }
+
+    /// Accept approvals from `device` as second factor of `user`,
+    /// where `device` identifies the device to the [approver](Self::set_push_approver), e.g. a push token.
+    ///
+    /// Returns the recovery codes as [`Self::confirm_totp_enrollment`].
+    pub async fn enable_push(&self, user: &str, device: &str) -> Result<Vec<String>, MfaError> {
+        self.spanned("enable_push", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Push) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            if self.push.approver.read().unwrap().is_none() {
+                return Err(MfaError::NoApprover);
+            }
+            if !self.exist_user(user).await? {
+                return Err(MfaError::UserNotExist(user.into()));
+            }
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql(
+                "INSERT OR IGNORE INTO mfa_factor (user, kind, name, secret, enabled, created_at)
+                VALUES (?, 'push', ?, x'', 1, ?)",
+            ))
+            .bind(user)
+            .bind(device)
+            .bind(unix_now());
+            if q.execute(&mut *tx).await?.rows_affected() == 0 {
+                return Err(MfaError::AlreadyEnrolled(user.into()));
+            }
+            let codes = self.initial_recovery_codes(&mut tx, user).await?;
+            tx.commit().await?;
+            info!("enabled push approval for {}", self.log_user(user));
+            Ok(codes)
+        })
+        .await
+    }
+
+    /// Stop accepting approvals from `device` as second factor of `user`, returning whether they were.
+    ///
+    /// Recovery codes are removed as well once the user has no second factor left.
+    pub async fn disable_push(&self, user: &str, device: &str) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let mut tx = self.begin_write().await?;
+        let q =
+            query(self.sql("DELETE FROM mfa_factor WHERE user = ? AND kind = 'push' AND name = ?"))
+                .bind(user)
+                .bind(device);
+        let res = q.execute(&mut *tx).await?.rows_affected() > 0;
+        self.prune_recovery_codes(&mut tx, user).await?;
+        tx.commit().await?;
+        if res {
+            info!("disabled push approval for {}", self.log_user(user));
+        }
+        Ok(res)
+    }
+
+    /// Devices `user` [enabled](Self::enable_push) push approval for, oldest first.
+    pub async fn push_devices(&self, user: &str) -> Result<Vec<String>, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(vec![]);
+        }
+        let q = query_as(self.sql(
+            "SELECT name FROM mfa_factor WHERE user = ? AND kind = 'push' AND enabled ORDER BY created_at, id",
+        ))
+        .bind(user);
+        let res: Vec<(String,)> = q.fetch_all(&self.db).await?;
+        Ok(res.into_iter().map(|(x,)| x).collect())
+    }
+
+    /// Ask `user` to approve the login attempted from `ip`, waiting for the response.
+    pub(crate) async fn request_push_approval(
+        &self,
+        user: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<PushDecision, LoginError> {
+        let Some(approver) = self.push.approver.read().unwrap().clone() else {
+            return Err(LoginError::PushFailed("no push approver is set".into()));
+        };
+        let devices = self.push_devices(user).await?;
+        if devices.is_empty() {
+            return Ok(PushDecision::Denied);
+        }
+        let timeout = self.config.read().unwrap().mfa.push_timeout;
+        let request = PushRequest {
+            id: BASE64_URL_SAFE_NO_PAD.encode(rand_buf(16)),
+            user: user.into(),
+            devices,
+            ip,
+            timeout: Duration::from_secs(timeout),
+        };
+        self.count("push.sent");
+        debug!("requested push approval from {}", self.log_user(user));
+        let decision = approver.request(&request).await.map_err(|e| {
+            warn!("failed to request push approval: {e}");
+            LoginError::PushFailed(e)
+        })?;
+        match decision {
+            PushDecision::Approved => self.count("mfa.success"),
+            PushDecision::Denied => {
+                warn!("{} denied push approval", self.log_user(user));
+                self.count("mfa.failure");
+            }
+            PushDecision::TimedOut => self.count("mfa.failure"),
+        }
+        Ok(decision)
+    }
+}
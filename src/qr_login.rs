@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::{
+    Basileus, err::QrLoginError, event::AuthEvent, history::LoginAttempt, logging::Secret, rand_buf,
+};
+
+/// Characters of user codes, omitting vowels so that codes do not spell words,
+/// as recommended by [RFC 8628](https://datatracker.ietf.org/doc/html/rfc8628#section-6.1).
+const USER_CODE_CHARS: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// Cross-device login configuration, see [`Basileus::begin_qr_login`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct QrLoginConfig {
+    /// URL of the page approving a login on the authenticated device, where `{code}` is replaced by the user code,
+    /// e.g. `https://example.com/approve?code={code}`, typically rendered as QR code.
+    #[cfg_attr(feature = "serde", serde(rename = "url"))]
+    pub url: String,
+    /// Time in seconds a request stays valid.
+    #[cfg_attr(feature = "serde", serde(rename = "ttl"))]
+    pub ttl: u64,
+    /// Minimum time in seconds between polls of the same request.
+    #[cfg_attr(feature = "serde", serde(rename = "interval"))]
+    pub interval: u64,
+    /// Number of characters of user codes.
+    #[cfg_attr(feature = "serde", serde(rename = "code-length"))]
+    pub code_length: usize,
+}
+
+impl Default for QrLoginConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            ttl: 300,
+            interval: 5,
+            code_length: 8,
+        }
+    }
+}
+
+/// A login request of an unauthenticated device, see [`Basileus::begin_qr_login`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct QrLogin {
+    /// Secret the requesting device polls with, which must not be displayed.
+    pub device_code: Secret<String>,
+    /// Short code displayed to the user, to be entered on the authenticated device, e.g. `BCDF-GHJK`.
+    pub user_code: String,
+    /// [`QrLoginConfig::url`] with the user code filled in, to be displayed as QR code.
+    pub url: String,
+    /// Time in seconds until the request expires.
+    pub expires_in: u64,
+    /// Time in seconds to wait between polls.
+    pub interval: u64,
+}
+
+/// State of a login request, see [`Basileus::poll_qr_login`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "status"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum QrPoll {
+    /// Not yet approved, poll again after the interval.
+    #[cfg_attr(feature = "serde", serde(rename = "pending"))]
+    Pending,
+    /// Approved, logging in the requesting device as `user`.
+    #[cfg_attr(feature = "serde", serde(rename = "approved"))]
+    Approved { user: String, token: String },
+}
+
+enum State {
+    Pending,
+    Approved(String),
+    Denied,
+}
+
+/// A request awaiting approval.
+struct Pending {
+    user_code: String,
+    issued: SystemTime,
+    polled: Option<SystemTime>,
+    state: State,
+}
+
+#[derive(Default)]
+pub struct QrLoginModule {
+    /// Map from hashes of device codes to pending requests.
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl QrLoginModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Key of a device code in [`QrLoginModule::pending`].
+fn hash_code(device_code: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(device_code))
+}
+
+/// Canonical form of a user code as entered, ignoring case, spaces and dashes.
+fn normalize(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Random user code of `len` characters, grouped in fours by dashes.
+fn user_code(len: usize) -> String {
+    let mut code = String::with_capacity(len + len / 4);
+    let mut cnt = 0;
+    while cnt < len {
+        for x in rand_buf(len) {
+            // reject the remainder of 256 so that every character is equally likely
+            if x >= 240 || cnt >= len {
+                continue;
+            }
+            if cnt > 0 && cnt % 4 == 0 {
+                code.push('-');
+            }
+            code.push(USER_CODE_CHARS[x as usize % USER_CODE_CHARS.len()] as char);
+            cnt += 1;
+        }
+    }
+    code
+}
+
+impl Basileus {
+    /// Start a login of an unauthenticated device, which displays the returned user code or URL,
+    /// and [polls](Self::poll_qr_login) until the request is [approved](Self::approve_qr_login) on an authenticated device.
+    ///
+    /// This is the [device authorization grant](https://datatracker.ietf.org/doc/html/rfc8628)
+    /// specialized for first-party applications, which need no client registration.
+    pub fn begin_qr_login(&self) -> Result<QrLogin, QrLoginError> {
+        if !self.config.read().unwrap().modules.qr_login {
+            return Err(QrLoginError::FeatureDisabled);
+        }
+        let config = self.config.read().unwrap().qr_login.clone();
+        let device_code = BASE64_URL_SAFE_NO_PAD.encode(rand_buf(32));
+        let user_code = user_code(config.code_length);
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(config.ttl);
+        let mut pending = self.qr_login.pending.lock().unwrap();
+        pending.retain(|_, x| now.duration_since(x.issued).is_ok_and(|d| d < ttl));
+        pending.insert(
+            hash_code(&device_code),
+            Pending {
+                user_code: normalize(&user_code),
+                issued: now,
+                polled: None,
+                state: State::Pending,
+            },
+        );
+        debug!("started cross-device login");
+        Ok(QrLogin {
+            device_code: device_code.into(),
+            url: config.url.replace("{code}", &user_code),
+            user_code,
+            expires_in: config.ttl,
+            interval: config.interval,
+        })
+    }
+
+    /// Approve the request displaying `user_code`, logging the requesting device in as `user`,
+    /// who must have been authenticated on this device.
+    ///
+    /// Users should be shown what they approve, e.g. the location of the requesting device, before calling this.
+    pub async fn approve_qr_login(&self, user: &str, user_code: &str) -> Result<(), QrLoginError> {
+        self.spanned("approve_qr_login", Some(user), async {
+            if !self.exist_user(user).await? {
+                return Err(QrLoginError::UserNotExist(user.into()));
+            }
+            self.decide_qr_login(user_code, State::Approved(user.into()))?;
+            info!("approved cross-device login of {}", self.log_user(user));
+            let attempt = LoginAttempt::new("qr");
+            self.record_login_attempt(user, &attempt).await;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Deny the request displaying `user_code`, so that the requesting device stops polling.
+    pub fn deny_qr_login(&self, user_code: &str) -> Result<(), QrLoginError> {
+        self.spanned_sync("deny_qr_login", None, || {
+            self.decide_qr_login(user_code, State::Denied)?;
+            debug!("denied cross-device login");
+            Ok(())
+        })
+    }
+
+    fn decide_qr_login(&self, user_code: &str, state: State) -> Result<(), QrLoginError> {
+        if !self.config.read().unwrap().modules.qr_login {
+            return Err(QrLoginError::FeatureDisabled);
+        }
+        let ttl = Duration::from_secs(self.config.read().unwrap().qr_login.ttl);
+        let user_code = normalize(user_code);
+        let mut pending = self.qr_login.pending.lock().unwrap();
+        let req = pending
+            .values_mut()
+            .find(|x| x.user_code == user_code && matches!(x.state, State::Pending))
+            .ok_or(QrLoginError::InvalidCode)?;
+        if req.issued.elapsed().is_ok_and(|x| x > ttl) {
+            return Err(QrLoginError::Expired);
+        }
+        req.state = state;
+        Ok(())
+    }
+
+    /// Check the state of the request of `device_code`, issuing a session token once approved.
+    ///
+    /// The request is discarded once approved or [denied](Self::deny_qr_login).
+    /// Polling more often than every [`QrLoginConfig::interval`] fails with [`QrLoginError::SlowDown`].
+    /// Approved logins of users who enabled or have to enroll a second factor fail as [`Self::login`] does after a correct password.
+    pub async fn poll_qr_login(&self, device_code: &str) -> Result<QrPoll, QrLoginError> {
+        self.spanned("poll_qr_login", None, async {
+            if !self.config.read().unwrap().modules.qr_login {
+                return Err(QrLoginError::FeatureDisabled);
+            }
+            let Some(user) = self.take_qr_approval(device_code)? else {
+                return Ok(QrPoll::Pending);
+            };
+            self.record_user(&user);
+            self.check_passwordless(&user).await?;
+            self.count("qr.success");
+            self.emit(AuthEvent::LoginSucceeded { user: user.clone() });
+            let token = self.issue_token(&user);
+            Ok(QrPoll::Approved { user, token })
+        })
+        .await
+    }
+
+    /// Take the user who approved the request of `device_code`, if any, discarding the request once decided.
+    fn take_qr_approval(&self, device_code: &str) -> Result<Option<String>, QrLoginError> {
+        let config = self.config.read().unwrap().qr_login.clone();
+        let key = hash_code(device_code);
+        let now = SystemTime::now();
+        let mut pending = self.qr_login.pending.lock().unwrap();
+        let req = pending.get_mut(&key).ok_or(QrLoginError::InvalidCode)?;
+        if now
+            .duration_since(req.issued)
+            .is_ok_and(|x| x > Duration::from_secs(config.ttl))
+        {
+            pending.remove(&key);
+            return Err(QrLoginError::Expired);
+        }
+        match &req.state {
+            State::Pending => {
+                let interval = Duration::from_secs(config.interval);
+                let early = req
+                    .polled
+                    .is_some_and(|x| now.duration_since(x).is_ok_and(|d| d < interval));
+                req.polled = Some(now);
+                if early {
+                    return Err(QrLoginError::SlowDown);
+                }
+                Ok(None)
+            }
+            State::Denied => {
+                pending.remove(&key);
+                Err(QrLoginError::Denied)
+            }
+            State::Approved(user) => {
+                let user = user.clone();
+                pending.remove(&key);
+                Ok(Some(user))
+            }
+        }
+    }
+}
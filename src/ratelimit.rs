@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::Basileus;
+
+/// Rate limiting configuration, declaring the budgets of authentication attempts.
+///
+/// A budget of `0` attempts disables the respective limit.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RateLimitConfig {
+    /// Failed login attempts allowed per user within [`Self::window`].
+    #[cfg_attr(feature = "serde", serde(rename = "user-attempts"))]
+    pub user_attempts: u32,
+    /// Failed login attempts allowed per source IP address within [`Self::window`].
+    #[cfg_attr(feature = "serde", serde(rename = "ip-attempts"))]
+    pub ip_attempts: u32,
+    /// Time in seconds over which failed attempts are counted.
+    #[cfg_attr(feature = "serde", serde(rename = "window"))]
+    pub window: u64,
+    /// Time in seconds a user or address exceeding its budget is locked out.
+    #[cfg_attr(feature = "serde", serde(rename = "lockout"))]
+    pub lockout: u64,
+    /// Maximum time in seconds an address is locked out, up to which its lockout doubles
+    /// every time it exceeds its budget again, see [`Limit::backoff`].
+    ///
+    /// Users are locked out for [`Self::lockout`] only, so that attackers cannot lock them out for long.
+    #[cfg_attr(feature = "serde", serde(rename = "ip-max-lockout"))]
+    pub ip_max_lockout: u64,
+    /// Failed code verifier attempts allowed per PKCE authorization code before it is burnt,
+    /// where `0` burns the code upon the first failure like `1`.
+    #[cfg_attr(feature = "serde", serde(rename = "pkce-attempts"))]
+    pub pkce_attempts: u32,
+    /// PKCE token requests allowed per second and user whose authorization codes are exchanged.
+    #[cfg_attr(feature = "serde", serde(rename = "pkce-rate"))]
+    pub pkce_rate: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            user_attempts: 10,
+            ip_attempts: 50,
+            window: 300,
+            lockout: 900,
+            ip_max_lockout: 86400,
+            pkce_attempts: 3,
+            pkce_rate: 10,
+        }
+    }
+}
+
+/// Budget of a [`Throttle`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    /// Hits allowed in a burst, or `0` to disable the limit.
+    pub capacity: u32,
+    /// Time over which the full capacity is regained.
+    pub window: Duration,
+    /// Time a key is locked out once its budget is exhausted, after which its full budget is restored,
+    /// or zero to only wait for the budget to refill.
+    pub lockout: Duration,
+    /// Maximum time a key is locked out, see [`Self::backoff`].
+    pub max_lockout: Duration,
+}
+
+impl Limit {
+    pub fn new(capacity: u32, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            lockout: Duration::ZERO,
+            max_lockout: Duration::ZERO,
+        }
+    }
+
+    /// Lock keys out for `lockout` once their budget is exhausted.
+    pub fn lockout(mut self, lockout: Duration) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    /// Double the lockout every time a key exhausts its budget again, up to `max_lockout`.
+    ///
+    /// Keys are forgiven once `max_lockout` passed without a lockout, or once [reset](Throttle::reset).
+    pub fn backoff(mut self, max_lockout: Duration) -> Self {
+        self.max_lockout = max_lockout;
+        self
+    }
+
+    /// Time a key locked out `strikes` times before is locked out.
+    fn lockout_after(&self, strikes: u32) -> Duration {
+        let max = self.max_lockout.max(self.lockout);
+        let factor = 2u32.saturating_pow(strikes);
+        self.lockout.checked_mul(factor).unwrap_or(max).min(max)
+    }
+
+    /// Hits regained per second.
+    fn rate(&self) -> f64 {
+        self.capacity as f64 / self.window.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    locked_until: Option<Instant>,
+    /// Number of lockouts so far, see [`Limit::backoff`].
+    strikes: u32,
+    /// End of the last lockout, if any.
+    struck: Option<Instant>,
+}
+
+impl Bucket {
+    /// Lock the bucket out, for longer the more often it was before.
+    fn lock(&mut self, limit: &Limit, now: Instant) {
+        let until = now + limit.lockout_after(self.strikes);
+        self.locked_until = Some(until);
+        self.strikes = self.strikes.saturating_add(1);
+        self.struck = Some(until);
+    }
+
+    /// Whether the bucket remembers lockouts which still lengthen the next one.
+    fn remembers(&self, limit: &Limit, now: Instant) -> bool {
+        let max = limit.max_lockout.max(limit.lockout);
+        self.struck.is_some_and(|x| x + max > now)
+    }
+}
+
+/// Token bucket rate limiter with a separate budget for every key, e.g. a user or an IP address.
+///
+/// Every key starts with [`Limit::capacity`] tokens, which are regained steadily over [`Limit::window`].
+/// This is used by the library itself, see [`Basileus::throttles`], and may be reused by adapters for their routes.
+pub struct Throttle {
+    limit: RwLock<Limit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Throttle {
+    pub fn new(limit: Limit) -> Self {
+        Self {
+            limit: RwLock::new(limit),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Replace the budget, keeping the tokens spent so far.
+    pub fn set_limit(&self, limit: Limit) {
+        *self.limit.write().unwrap() = limit;
+    }
+
+    /// Bucket of `key`, refilled up to now.
+    fn refill<'a>(
+        buckets: &'a mut HashMap<String, Bucket>,
+        limit: &Limit,
+        key: &str,
+        now: Instant,
+    ) -> &'a mut Bucket {
+        let bucket = buckets.entry(key.into()).or_insert(Bucket {
+            tokens: limit.capacity as f64,
+            updated: now,
+            locked_until: None,
+            strikes: 0,
+            struck: None,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate()).min(limit.capacity as f64);
+        bucket.updated = now;
+        if bucket.locked_until.is_some_and(|x| x <= now) {
+            // a lockout served restores the full budget
+            bucket.locked_until = None;
+            bucket.tokens = limit.capacity as f64;
+        }
+        if !bucket.remembers(limit, now) {
+            bucket.strikes = 0;
+        }
+        bucket
+    }
+
+    /// Time to wait until `bucket` has a token again, if it has none now.
+    fn wait(bucket: &Bucket, limit: &Limit, now: Instant) -> Option<Duration> {
+        if let Some(until) = bucket.locked_until {
+            return Some(until - now);
+        }
+        (bucket.tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate()))
+    }
+
+    /// Check whether `key` has budget left without spending it,
+    /// failing with the time to wait otherwise.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let limit = *self.limit.read().unwrap();
+        if limit.capacity == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = Self::refill(&mut buckets, &limit, key, now);
+        match Self::wait(bucket, &limit, now) {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    /// Spend a token of `key`, e.g. on a failed attempt, locking it out if the budget is exhausted.
+    pub fn hit(&self, key: &str) {
+        let limit = *self.limit.read().unwrap();
+        if limit.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = Self::refill(&mut buckets, &limit, key, now);
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+        if bucket.tokens < 1.0 && !limit.lockout.is_zero() && bucket.locked_until.is_none() {
+            bucket.lock(&limit, now);
+        }
+    }
+
+    /// Spend a token of `key` if it has budget left, failing with the time to wait otherwise.
+    pub fn acquire(&self, key: &str) -> Result<(), Duration> {
+        let limit = *self.limit.read().unwrap();
+        if limit.capacity == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = Self::refill(&mut buckets, &limit, key, now);
+        if let Some(wait) = Self::wait(bucket, &limit, now) {
+            return Err(wait);
+        }
+        bucket.tokens -= 1.0;
+        if bucket.tokens < 1.0 && !limit.lockout.is_zero() {
+            bucket.lock(&limit, now);
+        }
+        Ok(())
+    }
+
+    /// Restore the full budget of `key`, e.g. after a successful attempt.
+    pub fn reset(&self, key: &str) {
+        self.buckets.lock().unwrap().remove(key);
+    }
+
+    /// Forget keys whose budget is full again and whose lockouts were forgiven, which behave like unseen keys.
+    pub fn prune(&self) {
+        let limit = *self.limit.read().unwrap();
+        let now = Instant::now();
+        self.buckets.lock().unwrap().retain(|_, x| {
+            let elapsed = now.duration_since(x.updated).as_secs_f64();
+            let remembers = x.strikes > 0 && x.remembers(&limit, now);
+            remembers
+                || match x.locked_until {
+                    Some(until) => until > now,
+                    None => x.tokens + elapsed * limit.rate() < limit.capacity as f64,
+                }
+        });
+    }
+}
+
+/// Throttles of the library, configured by [`RateLimitConfig`], see [`Basileus::throttles`].
+pub struct Throttles {
+    /// Failed logins per user.
+    pub user: Throttle,
+    /// Failed logins per source IP address.
+    pub ip: Throttle,
+    /// PKCE token requests per user whose authorization codes are exchanged.
+    pub pkce: Throttle,
+}
+
+impl Throttles {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let limits = config.limits();
+        Self {
+            user: Throttle::new(limits.0),
+            ip: Throttle::new(limits.1),
+            pkce: Throttle::new(limits.2),
+        }
+    }
+
+    /// Apply a reloaded configuration.
+    pub(crate) fn configure(&self, config: &RateLimitConfig) {
+        let limits = config.limits();
+        self.user.set_limit(limits.0);
+        self.ip.set_limit(limits.1);
+        self.pkce.set_limit(limits.2);
+    }
+
+    /// Forget keys whose budget is full again.
+    pub(crate) fn prune(&self) {
+        self.user.prune();
+        self.ip.prune();
+        self.pkce.prune();
+    }
+}
+
+impl RateLimitConfig {
+    /// Limits of the user, IP address and PKCE throttles.
+    fn limits(&self) -> (Limit, Limit, Limit) {
+        let window = Duration::from_secs(self.window);
+        let lockout = Duration::from_secs(self.lockout);
+        (
+            Limit::new(self.user_attempts, window).lockout(lockout),
+            Limit::new(self.ip_attempts, window)
+                .lockout(lockout)
+                .backoff(Duration::from_secs(self.ip_max_lockout)),
+            Limit::new(self.pkce_rate, Duration::from_secs(1)),
+        )
+    }
+}
+
+impl Basileus {
+    /// Throttles applied by the library, e.g. to [logins](Self::login),
+    /// which adapters may consult for their routes too.
+    pub fn throttles(&self) -> &Throttles {
+        &self.throttles
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use tracing::{debug, warn};
+
+use crate::{Basileus, history::LoginAttempt};
+
+/// Number of recent [login attempts](Basileus::login_history) passed to a [`RiskEvaluator`].
+const HISTORY: u32 = 20;
+
+/// Circumstances of a login attempt with a correct password, passed to a [`RiskEvaluator`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RiskContext {
+    pub user: String,
+    /// Source address of the attempt, if known.
+    pub ip: Option<IpAddr>,
+    /// User agent of the client, if known.
+    pub user_agent: Option<String>,
+    pub time: SystemTime,
+    /// Whether the attempt comes from a [trusted device](Basileus::trust_device) of the user.
+    pub trusted_device: bool,
+    /// Whether the user [enabled](Basileus::mfa_enabled) a second factor, i.e. whether they can step up.
+    pub mfa: bool,
+    /// Recent [login attempts](Basileus::login_history) of the user, newest first,
+    /// e.g. to detect impossible travel from the addresses of previous logins.
+    pub history: Vec<LoginAttempt>,
+}
+
+/// Verdict of a [`RiskEvaluator`] on a login attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RiskVerdict {
+    Allow,
+    /// Require a second factor, even from a [trusted device](Basileus::trust_device).
+    ///
+    /// Users without a second factor cannot step up, so their attempts are denied.
+    StepUp,
+    /// Reject the attempt with [`LoginError::RiskDenied`](crate::err::LoginError::RiskDenied).
+    Deny,
+}
+
+/// Future returned by [`RiskEvaluator::evaluate`].
+pub type RiskFuture<'a> = Pin<Box<dyn Future<Output = RiskVerdict> + Send + 'a>>;
+
+/// Judge of login attempts, e.g. detecting impossible travel or unusual velocity,
+/// see [`Basileus::set_risk_evaluator`].
+pub trait RiskEvaluator: Send + Sync {
+    /// Judge the attempt described by `ctx`, whose password was correct.
+    fn evaluate<'a>(&'a self, ctx: &'a RiskContext) -> RiskFuture<'a>;
+}
+
+#[derive(Default)]
+pub struct RiskModule {
+    evaluator: RwLock<Option<Arc<dyn RiskEvaluator>>>,
+}
+
+impl RiskModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Judge [logins](Self::verify_login), and thereby [PKCE authorization requests](Self::pkce_auth_req),
+    /// with `evaluator` once their password is verified, replacing any previously set.
+    pub fn set_risk_evaluator(&self, evaluator: impl RiskEvaluator + 'static) {
+        *self.risk.evaluator.write().unwrap() = Some(Arc::new(evaluator));
+    }
+
+    /// Stop judging logins, allowing every attempt with correct credentials.
+    pub fn clear_risk_evaluator(&self) {
+        *self.risk.evaluator.write().unwrap() = None;
+    }
+
+    /// Ask the [risk evaluator](Self::set_risk_evaluator) to judge a login attempt,
+    /// allowing it if none is set.
+    pub(crate) async fn evaluate_risk(
+        &self,
+        user: &str,
+        ip: Option<IpAddr>,
+        user_agent: Option<&str>,
+        trusted_device: bool,
+        mfa: bool,
+    ) -> Result<RiskVerdict, sqlx::error::Error> {
+        let Some(evaluator) = self.risk.evaluator.read().unwrap().clone() else {
+            return Ok(RiskVerdict::Allow);
+        };
+        let ctx = RiskContext {
+            user: user.into(),
+            ip,
+            user_agent: user_agent.map(Into::into),
+            time: SystemTime::now(),
+            trusted_device,
+            mfa,
+            history: self.login_history(user, HISTORY).await?,
+        };
+        let verdict = evaluator.evaluate(&ctx).await;
+        match verdict {
+            RiskVerdict::Allow => self.count("risk.allow"),
+            RiskVerdict::StepUp => {
+                debug!("risky login of {} requires step-up", self.log_user(user));
+                self.count("risk.step_up");
+            }
+            RiskVerdict::Deny => {
+                warn!("denied risky login of {}", self.log_user(user));
+                self.count("risk.deny");
+            }
+        }
+        Ok(verdict)
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+
+use crate::{
+    Basileus,
+    err::{
+        AssignRoleError, CreateRoleError, DeleteRoleError, GetRolePermError, GetUserRoleError,
+        SetRolePermError,
+    },
+    perm::{Perm, PermEvent},
+};
+use sqlx::{query, query_as};
+
+use tracing::info;
+
+/// Check whether a role name is valid.
+///
+/// The rules are the same as those for usernames.
+pub fn check_rolename(role: &str) -> bool {
+    crate::user::check_username(role)
+}
+
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS role (
+    role TEXT NOT NULL PRIMARY KEY
+);
+CREATE INDEX IF NOT EXISTS idx_role_role ON role (role);
+CREATE TABLE IF NOT EXISTS role_perm (
+    role TEXT NOT NULL,
+    grp TEXT NOT NULL,
+    PRIMARY KEY (role, grp),
+    FOREIGN KEY (role) REFERENCES role(role) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_role_perm_role ON role_perm (role);
+CREATE INDEX IF NOT EXISTS idx_role_perm_grp ON role_perm (grp);
+CREATE TABLE IF NOT EXISTS user_role (
+    user TEXT NOT NULL,
+    role TEXT NOT NULL,
+    PRIMARY KEY (user, role),
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE,
+    FOREIGN KEY (role) REFERENCES role(role) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_user_role_user ON user_role (user);
+"#;
+
+impl Basileus {
+    /// Check whether a role currently exists.
+    pub async fn exist_role(&self, role: &str) -> Result<bool, sqlx::error::Error> {
+        let query =
+            query_as(self.sql("SELECT EXISTS(SELECT 1 FROM role WHERE role = ?)")).bind(role);
+        let (res,): (i32,) = query.fetch_one(&self.db).await?;
+        Ok(res == 1)
+    }
+
+    /// Create a new role bundling the specified permissions.
+    pub async fn create_role(&self, role: &str, perm: &Perm) -> Result<(), CreateRoleError> {
+        self.spanned("create_role", None, async {
+            if self.exist_role(role).await? {
+                return Err(CreateRoleError::RoleAlreadyExist(role.into()));
+            }
+            if !check_rolename(role) {
+                return Err(CreateRoleError::InvalidName(role.into()));
+            }
+            if let Some(invalid) = perm.find_invalid() {
+                return Err(CreateRoleError::InvalidPerm(invalid.into()));
+            }
+            let mut tx = self.begin_write().await?;
+            query(self.sql("INSERT INTO role (role) VALUES (?);"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            for grp in perm.iter() {
+                query(self.sql("INSERT INTO role_perm (role, grp) VALUES (?, ?);"))
+                    .bind(role)
+                    .bind(grp)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            info!("created role {role}");
+            Ok(())
+        })
+        .await
+    }
+
+    /// Delete a role, unassigning it from all users.
+    pub async fn delete_role(&self, role: &str) -> Result<(), DeleteRoleError> {
+        self.spanned("delete_role", None, async {
+            if !self.exist_role(role).await? {
+                return Err(DeleteRoleError::RoleNotExist(role.into()));
+            }
+            let mut tx = self.begin_write().await?;
+            query(self.sql("DELETE FROM user_role WHERE role = ?"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            query(self.sql("DELETE FROM role_perm WHERE role = ?"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            query(self.sql("DELETE FROM role WHERE role = ?"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            self.perm.invalidate_all();
+            self.emit_perm(PermEvent::RoleDeleted { role: role.into() });
+            info!("deleted role {role}");
+            Ok(())
+        })
+        .await
+    }
+
+    /// List all defined roles.
+    pub async fn list_roles(&self) -> Result<Vec<String>, sqlx::error::Error> {
+        let query = query_as(self.sql("SELECT role FROM role ORDER BY role"));
+        let res: Vec<(String,)> = query.fetch_all(&self.db).await?;
+        Ok(res.into_iter().map(|(role,)| role).collect())
+    }
+
+    /// Get permissions bundled by a role.
+    pub async fn get_role_perm(&self, role: &str) -> Result<Perm, GetRolePermError> {
+        if !self.exist_role(role).await? {
+            return Err(GetRolePermError::RoleNotExist(role.into()));
+        }
+        let query = query_as(self.sql("SELECT grp FROM role_perm WHERE role = ?")).bind(role);
+        let res: Vec<(String,)> = query.fetch_all(&self.db).await?;
+        let perm = res.into_iter().map(|(grp,)| grp).collect::<HashSet<_>>();
+        Ok(perm.into())
+    }
+
+    /// Set permissions bundled by a role.
+    ///
+    /// This takes effect immediately for every user holding the role.
+    pub async fn set_role_perm(&self, role: &str, perm: &Perm) -> Result<(), SetRolePermError> {
+        self.spanned("set_role_perm", None, async {
+            if !self.exist_role(role).await? {
+                return Err(SetRolePermError::RoleNotExist(role.into()));
+            }
+            if let Some(invalid) = perm.find_invalid() {
+                return Err(SetRolePermError::InvalidPerm(invalid.into()));
+            }
+            let mut tx = self.begin_write().await?;
+            query(self.sql("DELETE FROM role_perm WHERE role = ?"))
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            for grp in perm.iter() {
+                query(self.sql("INSERT INTO role_perm (role, grp) VALUES (?, ?);"))
+                    .bind(role)
+                    .bind(grp)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            self.perm.invalidate_all();
+            self.emit_perm(PermEvent::Role { role: role.into() });
+            info!("updated permissions of role {role}");
+            Ok(())
+        })
+        .await
+    }
+
+    /// Assign a role to a user.
+    /// This does not result in an error if the user already holds the role.
+    pub async fn assign_role(&self, user: &str, role: &str) -> Result<(), AssignRoleError> {
+        self.spanned("assign_role", Some(user), async {
+            if !self.exist_user(user).await? {
+                return Err(AssignRoleError::UserNotExist(user.into()));
+            }
+            if !self.exist_role(role).await? {
+                return Err(AssignRoleError::RoleNotExist(role.into()));
+            }
+            let query =
+                query(self.sql("INSERT OR IGNORE INTO user_role (user, role) VALUES (?, ?);"))
+                    .bind(user)
+                    .bind(role);
+            query.execute(&self.writer).await?;
+            self.perm.invalidate(user);
+            self.emit_perm(PermEvent::UserRole {
+                user: user.into(),
+                role: role.into(),
+                assigned: true,
+            });
+            info!("assigned role {role} to {}", self.log_user(user));
+            Ok(())
+        })
+        .await
+    }
+
+    /// Unassign a role from a user.
+    /// This does not result in an error if the user does not currently hold the role.
+    pub async fn unassign_role(&self, user: &str, role: &str) -> Result<(), AssignRoleError> {
+        self.spanned("unassign_role", Some(user), async {
+            if !self.exist_user(user).await? {
+                return Err(AssignRoleError::UserNotExist(user.into()));
+            }
+            if !self.exist_role(role).await? {
+                return Err(AssignRoleError::RoleNotExist(role.into()));
+            }
+            let query = query(self.sql("DELETE FROM user_role WHERE user = ? AND role = ?"))
+                .bind(user)
+                .bind(role);
+            query.execute(&self.writer).await?;
+            self.perm.invalidate(user);
+            self.emit_perm(PermEvent::UserRole {
+                user: user.into(),
+                role: role.into(),
+                assigned: false,
+            });
+            info!("unassigned role {role} from {}", self.log_user(user));
+            Ok(())
+        })
+        .await
+    }
+
+    /// Get roles the user holds.
+    pub async fn get_user_roles(&self, user: &str) -> Result<Vec<String>, GetUserRoleError> {
+        let query = query_as(self.sql(
+            "SELECT user_role.role FROM user LEFT JOIN user_role ON user_role.user = user.user WHERE user.user = ? ORDER BY user_role.role",
+        ))
+        .bind(user);
+        let res: Vec<(Option<String>,)> = query.fetch_all(&self.db).await?;
+        if res.is_empty() {
+            return Err(GetUserRoleError::UserNotExist(user.into()));
+        }
+        Ok(res.into_iter().filter_map(|(role,)| role).collect())
+    }
+}
@@ -0,0 +1,558 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use flate2::{Compression, write::DeflateEncoder};
+use quick_xml::{Reader, escape::escape, events::Event};
+use tracing::{debug, info};
+
+use crate::{
+    Basileus,
+    err::{CreateUserError, SamlError},
+    event::AuthEvent,
+    history::LoginAttempt,
+    logging::Secret,
+    perm::Perm,
+    rand_buf, to_unix, url_encode,
+};
+
+/// SAML service provider configuration, see [`Basileus::saml_authn_request`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SamlConfig {
+    /// Entity ID of this service provider, which responses must be addressed to.
+    #[cfg_attr(feature = "serde", serde(rename = "entity-id"))]
+    pub entity_id: String,
+    /// URL of the assertion consumer service, which receives responses through HTTP-POST.
+    #[cfg_attr(feature = "serde", serde(rename = "acs-url"))]
+    pub acs_url: String,
+    /// Entity ID of the identity provider, which responses must be issued by.
+    #[cfg_attr(feature = "serde", serde(rename = "idp-entity-id"))]
+    pub idp_entity_id: String,
+    /// URL of the single sign-on service of the identity provider, which receives requests through HTTP-Redirect.
+    #[cfg_attr(feature = "serde", serde(rename = "idp-sso-url"))]
+    pub idp_sso_url: String,
+    /// Attribute holding the username, or the `NameID` of the subject if [`None`].
+    #[cfg_attr(feature = "serde", serde(rename = "user-attribute"))]
+    pub user_attribute: Option<String>,
+    /// Whether to create users on their first login, instead of rejecting unknown users.
+    #[cfg_attr(feature = "serde", serde(rename = "create-users"))]
+    pub create_users: bool,
+    /// Attribute holding the groups of the user at the identity provider, e.g. `memberOf`.
+    #[cfg_attr(feature = "serde", serde(rename = "group-attribute"))]
+    pub group_attribute: Option<String>,
+    /// Map from values of the [group attribute](Self::group_attribute) to permissions given on login.
+    #[cfg_attr(feature = "serde", serde(rename = "group-perms"))]
+    pub group_perms: HashMap<String, Perm>,
+    /// Time in seconds a request may take to be answered.
+    #[cfg_attr(feature = "serde", serde(rename = "request-ttl"))]
+    pub request_ttl: u64,
+    /// Tolerated difference in seconds between the clocks of this host and the identity provider.
+    #[cfg_attr(feature = "serde", serde(rename = "clock-skew"))]
+    pub clock_skew: u64,
+}
+
+impl Default for SamlConfig {
+    fn default() -> Self {
+        Self {
+            entity_id: String::new(),
+            acs_url: String::new(),
+            idp_entity_id: String::new(),
+            idp_sso_url: String::new(),
+            user_attribute: None,
+            create_users: false,
+            group_attribute: None,
+            group_perms: HashMap::new(),
+            request_ttl: 300,
+            clock_skew: 60,
+        }
+    }
+}
+
+/// Verifier of XML signatures of the identity provider, see [`Basileus::set_saml_verifier`].
+///
+/// The library does not implement XML canonicalization nor RSA,
+/// so this is typically backed by e.g. `xmlsec` or `samael` and the certificate of the identity provider.
+pub trait SamlVerifier: Send + Sync {
+    /// Verify every enveloped signature in the response `xml` against the certificate of the identity provider,
+    /// returning the `ID` attributes of the elements whose signature is valid.
+    ///
+    /// Only assertions in signed elements are accepted,
+    /// so that signatures can not be wrapped around injected assertions.
+    fn verify(&self, xml: &str) -> Vec<String>;
+}
+
+/// An authentication request to redirect the user agent to.
+#[derive(Clone, Debug)]
+pub struct SamlRequest {
+    /// ID of the request, which the response refers to.
+    pub id: String,
+    /// The `AuthnRequest` document.
+    pub xml: String,
+    /// URL of the identity provider with the request encoded for the HTTP-Redirect binding.
+    pub url: String,
+}
+
+/// A successful login through SAML.
+#[derive(Clone, Debug)]
+pub struct SamlLogin {
+    pub user: String,
+    pub token: Secret<String>,
+    /// Whether the user has been created by this login.
+    pub created: bool,
+}
+
+#[derive(Default)]
+pub struct SamlModule {
+    /// Map from IDs of requests awaiting response to the time they were issued.
+    pending: Mutex<HashMap<String, SystemTime>>,
+    verifier: RwLock<Option<Arc<dyn SamlVerifier>>>,
+}
+
+impl SamlModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Content of a response relevant to authentication.
+#[derive(Default)]
+struct SamlResponse {
+    id: Option<String>,
+    in_response_to: Option<String>,
+    destination: Option<String>,
+    issuer: Option<String>,
+    status: Option<String>,
+    /// Number of assertions, encrypted ones included.
+    assertions: usize,
+    encrypted: bool,
+    assertion_id: Option<String>,
+    assertion_issuer: Option<String>,
+    name_id: Option<String>,
+    recipient: Option<String>,
+    subject_in_response_to: Option<String>,
+    subject_not_on_or_after: Option<String>,
+    not_before: Option<String>,
+    not_on_or_after: Option<String>,
+    audiences: Vec<String>,
+    attributes: HashMap<String, Vec<String>>,
+}
+
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+
+fn malformed(e: impl std::fmt::Display) -> SamlError {
+    SamlError::Malformed(e.to_string())
+}
+
+impl SamlResponse {
+    fn parse(xml: &str) -> Result<Self, SamlError> {
+        // text is trimmed once complete, since entity references split it
+        let mut reader = Reader::from_str(xml);
+        let mut res = Self::default();
+        // local names of the open elements
+        let mut path: Vec<String> = vec![];
+        let mut text = String::new();
+        let mut attribute: Option<String> = None;
+        // signatures reference elements by ID, so a duplicate could smuggle in an unsigned element
+        let mut ids = HashSet::new();
+        loop {
+            let event = reader.read_event().map_err(malformed)?;
+            let (elem, empty) = match &event {
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                Event::Text(e) => {
+                    text.push_str(&e.decode().map_err(malformed)?);
+                    continue;
+                }
+                Event::GeneralRef(e) => {
+                    if let Some(c) = e.resolve_char_ref().map_err(malformed)? {
+                        text.push(c);
+                    } else {
+                        let name = e.decode().map_err(malformed)?;
+                        let value = quick_xml::escape::resolve_predefined_entity(&name)
+                            .ok_or_else(|| malformed(format!("unknown entity '{name}'")))?;
+                        text.push_str(value);
+                    }
+                    continue;
+                }
+                Event::End(_) => {
+                    let text = std::mem::take(&mut text);
+                    res.close(&path, &mut attribute, text.trim().into());
+                    path.pop();
+                    continue;
+                }
+                // entities could be declared to expand endlessly
+                Event::DocType(_) => return Err(malformed("document type declarations")),
+                Event::Eof => break,
+                _ => continue,
+            };
+            let name = String::from_utf8_lossy(elem.local_name().as_ref()).into_owned();
+            let mut attrs = HashMap::new();
+            for attr in elem.attributes() {
+                let attr = attr.map_err(malformed)?;
+                let key = String::from_utf8_lossy(attr.key.local_name().as_ref()).into_owned();
+                attrs.insert(key, attr.unescape_value().map_err(malformed)?.into_owned());
+            }
+            if let Some(id) = attrs.get("ID").filter(|x| !ids.insert(x.to_string())) {
+                return Err(malformed(format!("duplicate ID '{id}'")));
+            }
+            path.push(name);
+            text.clear();
+            res.open(&path, attrs, &mut attribute);
+            if empty {
+                res.close(&path, &mut attribute, String::new());
+                path.pop();
+            }
+        }
+        Ok(res)
+    }
+
+    fn open(
+        &mut self,
+        path: &[String],
+        mut attrs: HashMap<String, String>,
+        attribute: &mut Option<String>,
+    ) {
+        let path: Vec<_> = path.iter().map(String::as_str).collect();
+        // assertions anywhere count, so that none can hide e.g. in extensions
+        match path.last() {
+            Some(&"Assertion") => self.assertions += 1,
+            Some(&"EncryptedAssertion") => {
+                self.assertions += 1;
+                self.encrypted = true;
+            }
+            _ => {}
+        }
+        match path[..] {
+            ["Response"] => {
+                self.id = attrs.remove("ID");
+                self.in_response_to = attrs.remove("InResponseTo");
+                self.destination = attrs.remove("Destination");
+            }
+            ["Response", "Status", "StatusCode"] => self.status = attrs.remove("Value"),
+            ["Response", "Assertion"] => self.assertion_id = attrs.remove("ID"),
+            [
+                "Response",
+                "Assertion",
+                "Subject",
+                "SubjectConfirmation",
+                "SubjectConfirmationData",
+            ] => {
+                self.recipient = attrs.remove("Recipient");
+                self.subject_in_response_to = attrs.remove("InResponseTo");
+                self.subject_not_on_or_after = attrs.remove("NotOnOrAfter");
+            }
+            ["Response", "Assertion", "Conditions"] => {
+                self.not_before = attrs.remove("NotBefore");
+                self.not_on_or_after = attrs.remove("NotOnOrAfter");
+            }
+            ["Response", "Assertion", "AttributeStatement", "Attribute"] => {
+                *attribute = attrs.remove("Name");
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, path: &[String], attribute: &mut Option<String>, text: String) {
+        let path: Vec<_> = path.iter().map(String::as_str).collect();
+        match path[..] {
+            ["Response", "Issuer"] => self.issuer = Some(text),
+            ["Response", "Assertion", "Issuer"] => self.assertion_issuer = Some(text),
+            ["Response", "Assertion", "Subject", "NameID"] => self.name_id = Some(text),
+            [
+                "Response",
+                "Assertion",
+                "Conditions",
+                "AudienceRestriction",
+                "Audience",
+            ] => self.audiences.push(text),
+            [
+                "Response",
+                "Assertion",
+                "AttributeStatement",
+                "Attribute",
+                "AttributeValue",
+            ] => {
+                if let Some(name) = attribute {
+                    self.attributes.entry(name.clone()).or_default().push(text);
+                }
+            }
+            ["Response", "Assertion", "AttributeStatement", "Attribute"] => *attribute = None,
+            _ => {}
+        }
+    }
+}
+
+/// Number of days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Format seconds since the Unix epoch as an `xs:dateTime` in UTC, e.g. `2024-01-01T00:00:00Z`.
+fn format_instant(secs: i64) -> String {
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!(
+        "{y:04}-{m:02}-{d:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Parse an `xs:dateTime` in UTC, as SAML requires, into seconds since the Unix epoch.
+///
+/// Fractional seconds are truncated.
+fn parse_instant(s: &str) -> Option<i64> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|x| x.parse::<i64>().ok());
+    let (y, m, d) = (date.next()??, date.next()??, date.next()??);
+    let time = time.split('.').next()?;
+    let mut time = time.splitn(3, ':').map(|x| x.parse::<i64>().ok());
+    let (h, min, sec) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) || h > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+    Some(days_from_civil(y, m, d) * 86400 + h * 3600 + min * 60 + sec)
+}
+
+impl Basileus {
+    /// Verify signatures of SAML responses with `verifier`, replacing any previously set.
+    ///
+    /// Responses are rejected until a verifier is set.
+    pub fn set_saml_verifier(&self, verifier: impl SamlVerifier + 'static) {
+        *self.saml.verifier.write().unwrap() = Some(Arc::new(verifier));
+    }
+
+    /// Create an `AuthnRequest` to the [identity provider](SamlConfig::idp_sso_url),
+    /// optionally carrying `relay_state`, e.g. the page to return to, through the identity provider.
+    ///
+    /// The request must be answered within [`SamlConfig::request_ttl`] seconds, see [`Self::saml_response`].
+    pub fn saml_authn_request(&self, relay_state: Option<&str>) -> SamlRequest {
+        let config = self.config.read().unwrap().saml.clone();
+        let hex: String = rand_buf(16).iter().map(|x| format!("{x:02x}")).collect();
+        // IDs must not start with a digit
+        let id = format!("_{hex}");
+        let now = SystemTime::now();
+        let xml = format!(
+            concat!(
+                r#"<samlp:AuthnRequest xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" "#,
+                r#"xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="{}" Version="2.0" "#,
+                r#"IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" "#,
+                r#"ProtocolBinding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST">"#,
+                r#"<saml:Issuer>{}</saml:Issuer><samlp:NameIDPolicy AllowCreate="true"/>"#,
+                r#"</samlp:AuthnRequest>"#
+            ),
+            id,
+            format_instant(to_unix(now)),
+            escape(&config.idp_sso_url),
+            escape(&config.acs_url),
+            escape(&config.entity_id),
+        );
+        let mut deflate = DeflateEncoder::new(vec![], Compression::default());
+        // writing into memory never fails
+        deflate.write_all(xml.as_bytes()).unwrap();
+        let encoded = BASE64_STANDARD.encode(deflate.finish().unwrap());
+        let sep = if config.idp_sso_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let mut url = format!(
+            "{}{sep}SAMLRequest={}",
+            config.idp_sso_url,
+            url_encode(&encoded)
+        );
+        if let Some(relay_state) = relay_state {
+            url += &format!("&RelayState={}", url_encode(relay_state));
+        }
+        let ttl = Duration::from_secs(config.request_ttl);
+        let mut pending = self.saml.pending.lock().unwrap();
+        pending.retain(|_, x| now.duration_since(*x).is_ok_and(|d| d < ttl));
+        pending.insert(id.clone(), now);
+        debug!("issued SAML request {id}");
+        SamlRequest { id, xml, url }
+    }
+
+    /// Handle the `SAMLResponse` parameter posted to the [assertion consumer service](SamlConfig::acs_url),
+    /// logging the user in if the response answers a [request](Self::saml_authn_request) and its assertion is valid.
+    ///
+    /// Unknown users are created if [`SamlConfig::create_users`] is set,
+    /// and every user is given the permissions [mapped](SamlConfig::group_perms) from their groups.
+    /// Locked accounts fail with [`LoginError::AccountLocked`](crate::err::LoginError::AccountLocked).
+    ///
+    /// Encrypted assertions and unsolicited responses are not supported.
+    pub async fn saml_response(&self, saml_response: &str) -> Result<SamlLogin, SamlError> {
+        let res = self
+            .spanned(
+                "saml_response",
+                None,
+                self.saml_response_inner(saml_response),
+            )
+            .await;
+        match &res {
+            Ok(login) => {
+                self.count("saml.success");
+                self.emit(AuthEvent::LoginSucceeded {
+                    user: login.user.clone(),
+                });
+                let attempt = LoginAttempt::new("saml");
+                self.record_login_attempt(&login.user, &attempt).await;
+            }
+            Err(_) => self.count("saml.failure"),
+        }
+        res
+    }
+
+    async fn saml_response_inner(&self, saml_response: &str) -> Result<SamlLogin, SamlError> {
+        let config = self.config.read().unwrap().saml.clone();
+        let verifier = self.saml.verifier.read().unwrap().clone();
+        let verifier = verifier.ok_or(SamlError::NoVerifier)?;
+        let buf: String = saml_response.split_whitespace().collect();
+        let buf = BASE64_STANDARD.decode(buf).map_err(malformed)?;
+        let xml = String::from_utf8(buf).map_err(malformed)?;
+        let res = SamlResponse::parse(&xml)?;
+
+        let status = res.status.as_deref().unwrap_or_default();
+        if status != STATUS_SUCCESS {
+            return Err(SamlError::Status(status.into()));
+        }
+        if res.encrypted {
+            return Err(SamlError::Unsupported("encrypted assertions".into()));
+        }
+        if res.assertions != 1 || res.assertion_id.is_none() {
+            return Err(SamlError::Unsupported(format!(
+                "{} assertions in a response",
+                res.assertions
+            )));
+        }
+        let verified: HashSet<_> = verifier.verify(&xml).into_iter().collect();
+        let signed = |id: &Option<String>| id.as_ref().is_some_and(|x| verified.contains(x));
+        if !signed(&res.id) && !signed(&res.assertion_id) {
+            return Err(SamlError::InvalidSignature);
+        }
+
+        for issuer in [&res.issuer, &res.assertion_issuer].into_iter().flatten() {
+            if *issuer != config.idp_entity_id {
+                return Err(SamlError::InvalidIssuer(issuer.clone()));
+            }
+        }
+        if res.assertion_issuer.is_none() {
+            return Err(SamlError::Malformed("missing issuer of assertion".into()));
+        }
+        for dest in [&res.destination, &res.recipient].into_iter().flatten() {
+            if *dest != config.acs_url {
+                return Err(SamlError::InvalidDestination(dest.clone()));
+            }
+        }
+        if !res.audiences.contains(&config.entity_id) {
+            return Err(SamlError::InvalidAudience);
+        }
+
+        let now = to_unix(SystemTime::now());
+        let skew = config.clock_skew as i64;
+        let instant = |x: &Option<String>| match x {
+            Some(x) => parse_instant(x)
+                .map(Some)
+                .ok_or_else(|| malformed(format!("invalid time '{x}'"))),
+            None => Ok(None),
+        };
+        if instant(&res.not_before)?.is_some_and(|x| now + skew < x) {
+            return Err(SamlError::Expired);
+        }
+        for end in [&res.not_on_or_after, &res.subject_not_on_or_after] {
+            if instant(end)?.is_some_and(|x| now - skew >= x) {
+                return Err(SamlError::Expired);
+            }
+        }
+
+        let request = res
+            .in_response_to
+            .as_ref()
+            .or(res.subject_in_response_to.as_ref())
+            .ok_or(SamlError::UnknownRequest)?;
+        if res
+            .subject_in_response_to
+            .as_ref()
+            .is_some_and(|x| x != request)
+        {
+            return Err(SamlError::UnknownRequest);
+        }
+        // each request is answered at most once, so that responses can not be replayed
+        let issued = self.saml.pending.lock().unwrap().remove(request);
+        let ttl = Duration::from_secs(config.request_ttl);
+        let valid = issued.is_some_and(|x| {
+            SystemTime::now()
+                .duration_since(x)
+                .is_ok_and(|d| d < ttl + Duration::from_secs(config.clock_skew))
+        });
+        if !valid {
+            return Err(SamlError::UnknownRequest);
+        }
+
+        let user = match &config.user_attribute {
+            Some(name) => res
+                .attributes
+                .get(name)
+                .and_then(|x| x.first())
+                .cloned()
+                .ok_or_else(|| SamlError::MissingUser(name.clone()))?,
+            None => res
+                .name_id
+                .clone()
+                .ok_or_else(|| SamlError::MissingUser("NameID".into()))?,
+        };
+        self.record_user(&user);
+        let mut created = false;
+        if !self.exist_user(&user).await? {
+            if !config.create_users {
+                return Err(SamlError::UserNotExist(user));
+            }
+            match self.create_user(&user).await {
+                Ok(()) => created = true,
+                // created concurrently by another login
+                Err(CreateUserError::UserAlreadyExist(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.check_lockout(&user).await?;
+        if let Some(name) = &config.group_attribute {
+            let perm = res
+                .attributes
+                .get(name)
+                .into_iter()
+                .flatten()
+                .filter_map(|x| config.group_perms.get(x))
+                .fold(Perm::default(), |acc, x| &acc + x);
+            if !perm.is_empty() {
+                self.give_perm(&user, &perm).await?;
+            }
+        }
+        let token = self.issue_token(&user);
+        info!("logged in {} through SAML", self.log_user(&user));
+        Ok(SamlLogin {
+            user,
+            token: token.into(),
+            created,
+        })
+    }
+}
@@ -1,44 +1,318 @@
 use std::{
     collections::HashMap,
+    net::IpAddr,
     sync::RwLock,
     time::{Duration, SystemTime},
 };
 
-use crate::{Basileus, rand_buf};
-use base64::{Engine, prelude::BASE64_STANDARD};
+use crate::{
+    Basileus, constant_time_eq,
+    err::{AuthError, GetPermError},
+    event::AuthEvent,
+    from_unix,
+    impersonate::Impersonator,
+    perm::Perm,
+    rand_buf, to_unix,
+};
+use base64::{
+    Engine,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+};
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as};
+
+use tracing::{debug, trace, warn};
+
+/// Tokens persisted across restarts, see [`Basileus::close`].
+pub const DB_INIT: &str = r#"
+CREATE TABLE IF NOT EXISTS token_store (
+    token TEXT NOT NULL PRIMARY KEY,
+    user TEXT NOT NULL,
+    issued_at INTEGER NOT NULL,
+    FOREIGN KEY (user) REFERENCES user(user) ON DELETE CASCADE
+);
+"#;
+
+/// Bind persisted tokens to client certificates, see [`Basileus::issue_bound_token`].
+pub const DB_MIGRATE_CERT: &str = r#"
+ALTER TABLE token_store ADD COLUMN cert TEXT;
+"#;
+
+/// Persist hashes of tokens rather than the tokens, so that a copy of the database does not yield working sessions.
+///
+/// Tokens persisted before can not be hashed in SQL, so they are discarded.
+pub const DB_MIGRATE_HASH: &str = r#"
+DELETE FROM token_store;
+ALTER TABLE token_store RENAME COLUMN token TO token_hash;
+"#;
+
+/// Thumbprint of the client certificate of a request, see [`cert_thumbprint`],
+/// inserted as extension by the layer terminating TLS, e.g. for [`Basileus::authenticate_bound`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ClientCert(pub String);
+
+/// Source address of a request, inserted as extension by a middleware in front of the handlers,
+/// e.g. taken from `ConnectInfo` or from a header set by a trusted proxy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Circumstances a token is presented in, see [`Basileus::verify_token_from`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Presentation<'a> {
+    /// [Thumbprint](cert_thumbprint) of the client certificate of the connection, if any.
+    pub cert: Option<&'a str>,
+    /// Source address of the request, if known.
+    pub ip: Option<IpAddr>,
+    /// Operation requested, e.g. the path of the request or the gRPC method.
+    pub op: Option<&'a str>,
+}
+
+/// Thumbprint of the DER-encoded certificate `der`, i.e. its base64url-encoded SHA-256 hash,
+/// as in the `x5t#S256` confirmation method of [RFC 8705](https://datatracker.ietf.org/doc/html/rfc8705#section-3.1).
+pub fn cert_thumbprint(der: &[u8]) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(der))
+}
 
-use tracing::{debug, trace};
+/// Encoding of issued tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenEncoding {
+    /// Standard base64 with padding.
+    #[cfg_attr(feature = "serde", serde(rename = "base64"))]
+    Base64,
+    /// URL-safe base64 without padding, e.g. for tokens passed in URLs.
+    #[cfg_attr(feature = "serde", serde(rename = "base64url"))]
+    Base64Url,
+    /// Lowercase hexadecimal.
+    #[cfg_attr(feature = "serde", serde(rename = "hex"))]
+    Hex,
+}
+
+impl TokenEncoding {
+    pub(crate) fn encode(self, buf: &[u8]) -> String {
+        match self {
+            Self::Base64 => BASE64_STANDARD.encode(buf),
+            Self::Base64Url => BASE64_URL_SAFE_NO_PAD.encode(buf),
+            Self::Hex => buf.iter().map(|x| format!("{x:02x}")).collect(),
+        }
+    }
+}
+
+/// Session token configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TokenConfig {
+    /// Age in seconds after which tokens expire, or [`None`] to keep them until invalidated.
+    #[cfg_attr(feature = "serde", serde(rename = "ttl"))]
+    pub ttl: Option<u64>,
+    /// Time in seconds after which tokens not [verified](Basileus::verify_token) expire,
+    /// or [`None`] to keep idle tokens.
+    #[cfg_attr(feature = "serde", serde(rename = "idle-timeout"))]
+    pub idle_timeout: Option<u64>,
+    /// Maximum number of tokens issued to a single user at the same time,
+    /// beyond which the oldest are invalidated, or [`None`] for no limit.
+    #[cfg_attr(feature = "serde", serde(rename = "max-sessions"))]
+    pub max_sessions: Option<usize>,
+    /// Number of random bytes in a token.
+    #[cfg_attr(feature = "serde", serde(rename = "length"))]
+    pub length: usize,
+    /// Encoding of the random bytes of a token.
+    #[cfg_attr(feature = "serde", serde(rename = "encoding"))]
+    pub encoding: TokenEncoding,
+    /// Whether to persist tokens when [closing](Basileus::close), regardless of the argument given.
+    #[cfg_attr(feature = "serde", serde(rename = "persist"))]
+    pub persist: bool,
+    /// Age in seconds after which tokens issued by [`Basileus::impersonate`] expire.
+    #[cfg_attr(feature = "serde", serde(rename = "impersonation-ttl"))]
+    pub impersonation_ttl: u64,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            ttl: None,
+            idle_timeout: None,
+            max_sessions: None,
+            length: 64,
+            encoding: TokenEncoding::Base64,
+            persist: false,
+            impersonation_ttl: 900,
+        }
+    }
+}
 
+impl TokenConfig {
+    /// Whether a token issued at `issued` and last verified at `used` has expired.
+    pub(crate) fn expired(&self, issued: SystemTime, used: SystemTime) -> bool {
+        let older = |time: SystemTime, secs: u64| {
+            time.elapsed().is_ok_and(|d| d >= Duration::from_secs(secs))
+        };
+        self.ttl.is_some_and(|x| older(issued, x))
+            || self.idle_timeout.is_some_and(|x| older(used, x))
+    }
+}
+
+/// An issued token.
+pub(crate) struct Session {
+    pub(crate) user: String,
+    /// Time of issuing.
+    pub(crate) issued: SystemTime,
+    /// Time of the last verification.
+    pub(crate) used: SystemTime,
+    /// CSRF token of the session, issued on demand, see [`Basileus::csrf_token`].
+    pub(crate) csrf: Option<String>,
+    /// Time the user last proved their identity, see [`Basileus::require_recent_auth`].
+    pub(crate) authed: SystemTime,
+    /// Whether the user presented a second factor at that time.
+    pub(crate) mfa: bool,
+    /// The admin acting as the user, if the session [impersonates](Basileus::impersonate) them.
+    pub(crate) impersonator: Option<Impersonator>,
+    /// Thumbprint of the client certificate the token is [bound to](Basileus::issue_bound_token), if any.
+    pub(crate) cert: Option<String>,
+}
+
+impl Session {
+    /// Whether the session has expired.
+    pub(crate) fn expired(&self, config: &TokenConfig) -> bool {
+        config.expired(self.issued, self.used)
+            || (self.impersonator.as_ref()).is_some_and(|x| x.expires <= SystemTime::now())
+    }
+
+    /// Whether the session may be used over a connection presenting the client certificate `cert`, if any.
+    fn accepts(&self, cert: Option<&str>) -> bool {
+        match (&self.cert, cert) {
+            (None, _) => true,
+            (Some(bound), Some(cert)) => constant_time_eq(bound, cert),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// A user authenticated by token, see [`Basileus::authenticate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct AuthUser {
+    /// Name of the user.
+    pub user: String,
+    /// Effective permissions of the user at the time of authentication.
+    pub perm: Perm,
+    /// The admin acting as the user, if the token was issued by [`Basileus::impersonate`].
+    ///
+    /// Actions should be attributed to the admin in audit records then.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub impersonator: Option<String>,
+}
+
+#[derive(Default)]
 pub struct TokenModule {
-    store: RwLock<HashMap<String, (String, SystemTime)>>,
+    /// Map from [hashes](token_key) of tokens to their sessions.
+    pub(crate) store: RwLock<HashMap<String, Session>>,
+}
+
+/// Key of `token` in [`TokenModule::store`] and in the database, i.e. its base64url-encoded SHA-256 hash.
+pub(crate) fn token_key(token: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(token))
 }
 
 impl TokenModule {
     pub fn new() -> Self {
-        Self {
-            store: RwLock::new(HashMap::new()),
-        }
+        Self::default()
     }
 }
 
 impl Basileus {
     /// Issue a new token to the specified user.
+    ///
+    /// If the user already holds [`TokenConfig::max_sessions`] tokens, the oldest are invalidated.
     pub fn issue_token(&self, user: &str) -> String {
-        let buf = rand_buf::<64>();
-        let token = BASE64_STANDARD.encode(buf);
-        self.token
-            .store
-            .write()
-            .unwrap()
-            .insert(token.clone(), (user.to_owned(), SystemTime::now()));
-        debug!("issued token '{}**' for '{user}'", &token[0..4]);
+        self.issue_session(user, false)
+    }
+
+    /// Issue a new token to `user` which is only accepted over connections presenting the client certificate
+    /// with the [thumbprint](cert_thumbprint) `cert`, so that a stolen token is useless without the private key of the client,
+    /// as in [RFC 8705](https://datatracker.ietf.org/doc/html/rfc8705).
+    ///
+    /// Bound tokens are [verified](Self::verify_bound_token) against the certificate of the connection,
+    /// and rejected by [`Self::verify_token`] and [`Self::authenticate`], which know none.
+    pub fn issue_bound_token(&self, user: &str, cert: &str) -> String {
+        self.issue_session_as(user, false, None, Some(cert.into()))
+    }
+
+    /// Issue a new token to `user`, who presented a second factor if `mfa` is set.
+    pub(crate) fn issue_session(&self, user: &str, mfa: bool) -> String {
+        self.issue_session_as(user, mfa, None, None)
+    }
+
+    /// Like [`Self::issue_session`], marking the session as impersonated by `impersonator`, if any,
+    /// and binding it to the client certificate `cert`, if any.
+    pub(crate) fn issue_session_as(
+        &self,
+        user: &str,
+        mfa: bool,
+        impersonator: Option<Impersonator>,
+        cert: Option<String>,
+    ) -> String {
+        let span = self.span("issue_token", Some(user)).entered();
+        let config = self.config.read().unwrap().token.clone();
+        let token = config.encoding.encode(&rand_buf(config.length));
+        let now = SystemTime::now();
+        let mut store = self.token.store.write().unwrap();
+        if let Some(max) = config.max_sessions {
+            let mut held: Vec<_> = store
+                .iter()
+                .filter(|(_, x)| x.user == user)
+                .map(|(token, x)| (x.issued, token.clone()))
+                .collect();
+            if held.len() >= max {
+                held.sort();
+                for (_, token) in &held[..=held.len() - max.max(1)] {
+                    store.remove(token);
+                }
+                trace!(
+                    "evicted sessions of {} exceeding {max}",
+                    self.log_user(user)
+                );
+            }
+        }
+        store.insert(
+            token_key(&token),
+            Session {
+                user: user.to_owned(),
+                issued: now,
+                used: now,
+                csrf: None,
+                authed: now,
+                mfa,
+                impersonator,
+                cert,
+            },
+        );
+        debug!(
+            "issued token '{}' for {}",
+            self.log_token(&token),
+            self.log_user(user)
+        );
+        span.record("outcome", "ok");
+        self.count("token.issued");
+        self.emit(AuthEvent::TokenIssued { user: user.into() });
         token
     }
 
+    /// Count the number of tokens currently issued.
+    pub fn token_cnt(&self) -> usize {
+        self.token.store.read().unwrap().len()
+    }
+
     /// Invalidate a token.
     pub fn invalidate_token(&self, token: &str) {
-        self.token.store.write().unwrap().remove(token);
-        trace!("invalidated token '{}'", token);
+        let removed = self.token.store.write().unwrap().remove(&token_key(token));
+        if let Some(session) = removed {
+            self.count("token.revoked");
+            self.emit(AuthEvent::TokenRevoked { user: session.user });
+        }
+        trace!("invalidated token '{}'", self.log_token(token));
     }
 
     /// Invalidate all tokens related to `user`.
@@ -47,30 +321,269 @@ impl Basileus {
             .store
             .write()
             .unwrap()
-            .retain(|_, (u, _)| u != user);
-        trace!("invalidated user session '{user}'")
+            .retain(|_, x| x.user != user);
+        trace!("invalidated sessions of {}", self.log_user(user));
+        self.emit(AuthEvent::SessionsRevoked { user: user.into() });
+    }
+
+    /// Invalidate all tokens related to `user` except `token`, e.g. after the user changed their password.
+    pub fn invalidate_other_tokens(&self, user: &str, token: &str) {
+        let key = token_key(token);
+        self.token
+            .store
+            .write()
+            .unwrap()
+            .retain(|k, x| x.user != user || *k == key);
+        trace!("invalidated other sessions of {}", self.log_user(user));
+        self.emit(AuthEvent::SessionsRevoked { user: user.into() });
     }
 
     /// Make all tokens older than `duration` expire.
     pub fn expire_token(&self, duration: Duration) {
         let mut token = self.token.store.write().unwrap();
         let prev = token.len();
-        token.retain(|_, (_, time)| {
+        token.retain(|_, x| {
             SystemTime::now()
-                .duration_since(*time)
+                .duration_since(x.issued)
                 .is_ok_and(|d| d < duration)
         });
         let diff = prev - token.len();
         trace!("expired {diff} tokens");
     }
 
+    /// Discard every token exceeding its [lifetime](TokenConfig::ttl) or [idle timeout](TokenConfig::idle_timeout).
+    pub fn expire_tokens(&self) {
+        let config = self.config.read().unwrap().token.clone();
+        let mut token = self.token.store.write().unwrap();
+        let prev = token.len();
+        token.retain(|_, x| !x.expired(&config));
+        let diff = prev - token.len();
+        trace!("expired {diff} tokens");
+    }
+
     /// Verify token, return the user it belongs to if successful.
+    ///
+    /// Expired tokens are invalidated instead.
+    /// [Bound tokens](Self::issue_bound_token) are rejected, see [`Self::verify_bound_token`].
     pub fn verify_token(&self, token: &str) -> Option<String> {
-        let map = self.token.store.read().unwrap();
-        let res = map.get(token).map(|(user, _)| user.clone());
-        if let Some(user) = &res {
-            trace!("authorized {user} by token")
+        self.verify_bound_token(token, None)
+    }
+
+    /// Like [`Self::verify_token`], for a request over a connection presenting the client certificate
+    /// with the [thumbprint](cert_thumbprint) `cert`, if any.
+    ///
+    /// Tokens [bound](Self::issue_bound_token) to a different certificate, or to any if `cert` is [`None`], are rejected,
+    /// but remain valid for their own client.
+    pub fn verify_bound_token(&self, token: &str, cert: Option<&str>) -> Option<String> {
+        let from = Presentation {
+            cert,
+            ..Default::default()
+        };
+        self.verify_token_from(token, &from)
+    }
+
+    /// Like [`Self::verify_bound_token`] with the certificate of `from`,
+    /// whose source address and operation are reported if the token is a [canary](Self::issue_canary_token).
+    pub fn verify_token_from(&self, token: &str, from: &Presentation) -> Option<String> {
+        let cert = from.cert;
+        let span = self.span("verify_token", None).entered();
+        let config = self.config.read().unwrap().token.clone();
+        let key = token_key(token);
+        let mut map = self.token.store.write().unwrap();
+        let Some(session) = map.get_mut(&key) else {
+            drop(map);
+            span.record("outcome", "invalid_token");
+            self.count("token.rejected");
+            self.check_canary_token(token, from);
+            return None;
+        };
+        if session.expired(&config) {
+            map.remove(&key);
+            drop(map);
+            trace!("token '{}' expired", self.log_token(token));
+            span.record("outcome", "invalid_token");
+            self.count("token.rejected");
+            return None;
+        }
+        if !session.accepts(cert) {
+            let user = session.user.clone();
+            drop(map);
+            warn!(
+                "rejected token '{}' of {} presented without its client certificate",
+                self.log_token(token),
+                self.log_user(&user)
+            );
+            span.record("outcome", "invalid_token");
+            self.count("token.cert_mismatch");
+            return None;
+        }
+        session.used = SystemTime::now();
+        let user = session.user.clone();
+        drop(map);
+        span.record("user", self.log_user(&user).as_ref());
+        span.record("outcome", "ok");
+        self.count("token.verified");
+        if self.sampled() {
+            trace!("authorized {} by token", self.log_user(&user));
+        }
+        Some(user)
+    }
+
+    /// Authenticate a request by its bearer token, looking up the effective permissions of the user.
+    pub async fn authenticate(&self, token: &str) -> Result<AuthUser, AuthError> {
+        self.authenticate_bound(token, None).await
+    }
+
+    /// Like [`Self::authenticate`], for a request over a connection presenting the client certificate
+    /// with the [thumbprint](cert_thumbprint) `cert`, if any, see [`Self::verify_bound_token`].
+    pub async fn authenticate_bound(
+        &self,
+        token: &str,
+        cert: Option<&str>,
+    ) -> Result<AuthUser, AuthError> {
+        let from = Presentation {
+            cert,
+            ..Default::default()
+        };
+        self.authenticate_from(token, &from).await
+    }
+
+    /// Like [`Self::authenticate`], for a request presented as in `from`, see [`Self::verify_token_from`].
+    pub async fn authenticate_from(
+        &self,
+        token: &str,
+        from: &Presentation<'_>,
+    ) -> Result<AuthUser, AuthError> {
+        self.spanned("authenticate", None, async {
+            let user = (self.verify_token_from(token, from)).ok_or(AuthError::InvalidToken)?;
+            self.record_user(&user);
+            let impersonator = (self.token.store.read().unwrap().get(&token_key(token)))
+                .and_then(|x| x.impersonator.as_ref().map(|x| x.admin.clone()));
+            let perm = match self.get_effective_perm(&user).await {
+                Ok(perm) => perm,
+                // the user has been deleted since the token was issued
+                Err(GetPermError::UserNotExist(_)) => return Err(AuthError::InvalidToken),
+                Err(GetPermError::SQL(e)) => return Err(e.into()),
+            };
+            Ok(AuthUser {
+                user,
+                perm,
+                impersonator,
+            })
+        })
+        .await
+    }
+
+    /// Like [`Self::authenticate`], but additionally require the user to hold `req`, as in [`Self::check_perm`].
+    pub async fn authorize(&self, token: &str, req: &Perm) -> Result<AuthUser, AuthError> {
+        self.authorize_bound(token, None, req).await
+    }
+
+    /// Like [`Self::authorize`], for a request over a connection presenting the client certificate
+    /// with the [thumbprint](cert_thumbprint) `cert`, if any, see [`Self::verify_bound_token`].
+    pub async fn authorize_bound(
+        &self,
+        token: &str,
+        cert: Option<&str>,
+        req: &Perm,
+    ) -> Result<AuthUser, AuthError> {
+        let from = Presentation {
+            cert,
+            ..Default::default()
+        };
+        self.authorize_from(token, &from, req).await
+    }
+
+    /// Like [`Self::authorize`], for a request presented as in `from`, see [`Self::verify_token_from`].
+    pub async fn authorize_from(
+        &self,
+        token: &str,
+        from: &Presentation<'_>,
+        req: &Perm,
+    ) -> Result<AuthUser, AuthError> {
+        self.spanned("authorize", None, async {
+            let auth = self.authenticate_from(token, from).await?;
+            self.record_user(&auth.user);
+            let superuser = self
+                .config
+                .read()
+                .unwrap()
+                .perm
+                .is_superuser(&auth.user, &auth.perm);
+            if superuser {
+                self.superuser_bypassed(&auth.user, req);
+                return Ok(auth);
+            }
+            if !auth.perm.satisfies(req) {
+                return Err(AuthError::Forbidden(auth.user));
+            }
+            Ok(auth)
+        })
+        .await
+    }
+
+    /// Write every issued token to the database, replacing previously persisted ones.
+    ///
+    /// Only [hashes](token_key) of tokens are written, which suffice to look sessions up once loaded again.
+    pub(crate) async fn persist_tokens(&self) -> Result<(), sqlx::error::Error> {
+        let tokens: Vec<_> = self
+            .token
+            .store
+            .read()
+            .unwrap()
+            .iter()
+            // impersonation is meant to be short-lived
+            .filter(|(_, x)| x.impersonator.is_none())
+            .map(|(key, x)| (key.clone(), x.user.clone(), x.issued, x.cert.clone()))
+            .collect();
+        let mut tx = self.begin_write().await?;
+        query(self.sql("DELETE FROM token_store"))
+            .execute(&mut *tx)
+            .await?;
+        for (key, user, time, cert) in &tokens {
+            let q = query(self.sql(
+                "INSERT INTO token_store (token_hash, user, issued_at, cert) VALUES (?, ?, ?, ?);",
+            ))
+            .bind(key)
+            .bind(user)
+            .bind(to_unix(*time))
+            .bind(cert);
+            q.execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        debug!("persisted {} tokens", tokens.len());
+        Ok(())
+    }
+
+    /// Load tokens persisted by [`Basileus::close`], removing them from the database.
+    pub(crate) async fn load_tokens(&self) -> Result<(), sqlx::error::Error> {
+        let mut tx = self.begin_write().await?;
+        let q = query_as(self.sql("SELECT token_hash, user, issued_at, cert FROM token_store"));
+        let tokens: Vec<(String, String, i64, Option<String>)> = q.fetch_all(&mut *tx).await?;
+        query(self.sql("DELETE FROM token_store"))
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        let cnt = tokens.len();
+        let mut store = self.token.store.write().unwrap();
+        let now = SystemTime::now();
+        for (key, user, issued_at, cert) in tokens {
+            let issued = from_unix(issued_at);
+            let session = Session {
+                user,
+                issued,
+                used: now,
+                csrf: None,
+                authed: issued,
+                mfa: false,
+                impersonator: None,
+                cert,
+            };
+            store.insert(key, session);
+        }
+        if cnt > 0 {
+            debug!("loaded {cnt} persisted tokens");
         }
-        res
+        Ok(())
     }
 }
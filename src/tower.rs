@@ -0,0 +1,152 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::{Extensions, HeaderMap, HeaderValue, Request, Response, StatusCode, header};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    Basileus,
+    authorization::bearer,
+    err::{AuthError, CredentialsError, HttpStatus},
+    perm::Perm,
+    token::{AuthUser, ClientCert, ClientIp, Presentation},
+};
+
+/// Get the bearer token from the `Authorization` header, see [`bearer`].
+pub fn bearer_token(headers: &HeaderMap) -> Result<String, AuthError> {
+    let value = headers.get(header::AUTHORIZATION).map(|x| x.to_str());
+    match value {
+        Some(Ok(value)) => bearer(Some(value)),
+        Some(Err(_)) => Err(CredentialsError::InvalidToken.into()),
+        None => bearer(None),
+    }
+}
+
+/// Circumstances of a request to `path` by its [`ClientCert`] and [`ClientIp`] extensions, if any.
+pub(crate) fn presentation<'a>(extensions: &'a Extensions, path: &'a str) -> Presentation<'a> {
+    Presentation {
+        cert: extensions.get::<ClientCert>().map(|x| x.0.as_str()),
+        ip: extensions.get::<ClientIp>().map(|x| x.0),
+        op: Some(path),
+    }
+}
+
+/// Layer authenticating requests by their bearer token, usable with any `http`-based framework, e.g. hyper, tonic or axum.
+///
+/// The [`AuthUser`] is inserted into the request extensions.
+/// [`ClientCert`] and [`ClientIp`] extensions of the request are taken into account, see [`Basileus::authenticate_from`].
+/// Rejected requests are answered with an empty body and the status of the [`AuthError`],
+/// along with a `WWW-Authenticate` challenge as in [RFC 6750](https://datatracker.ietf.org/doc/html/rfc6750#section-3).
+#[derive(Clone)]
+pub struct AuthLayer {
+    basileus: Arc<Basileus>,
+    perm: Option<Perm>,
+}
+
+impl AuthLayer {
+    /// Create a layer accepting any valid token.
+    pub fn new(basileus: Arc<Basileus>) -> Self {
+        Self {
+            basileus,
+            perm: None,
+        }
+    }
+
+    /// Additionally require the user to hold `perm`, as in [`Basileus::authorize`].
+    pub fn require(mut self, perm: impl Into<Perm>) -> Self {
+        self.perm = Some(perm.into());
+        self
+    }
+
+    /// Authenticate a request by its headers, presented as in `from`.
+    async fn check(
+        &self,
+        headers: &HeaderMap,
+        from: &Presentation<'_>,
+    ) -> Result<AuthUser, AuthError> {
+        let token = bearer_token(headers)?;
+        match &self.perm {
+            Some(perm) => self.basileus.authorize_from(&token, from, perm).await,
+            None => self.basileus.authenticate_from(&token, from).await,
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service produced by [`AuthLayer`].
+#[derive(Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    layer: AuthLayer,
+}
+
+/// Response to a rejected request.
+fn reject<B: Default>(e: &AuthError) -> Response<B> {
+    let status = StatusCode::from_u16(e.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let challenge = match e {
+        AuthError::MissingToken => Some("Bearer"),
+        AuthError::InvalidToken | AuthError::Credentials(_) => {
+            Some("Bearer error=\"invalid_token\"")
+        }
+        AuthError::Forbidden(_) => Some("Bearer error=\"insufficient_scope\""),
+        // see RFC 9470
+        AuthError::ReauthRequired => Some("Bearer error=\"insufficient_user_authentication\""),
+        _ => None,
+    };
+    let mut res = Response::new(B::default());
+    *res.status_mut() = status;
+    if let Some(challenge) = challenge {
+        res.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(challenge),
+        );
+    }
+    res
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AuthService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // the ready service must be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let from = presentation(req.extensions(), req.uri().path());
+            match layer.check(req.headers(), &from).await {
+                Ok(auth) => {
+                    req.extensions_mut().insert(auth);
+                    inner.call(req).await
+                }
+                Err(e) => Ok(reject(&e)),
+            }
+        })
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{
+    Basileus,
+    err::{
+        AssignRoleError, CreateUserError, GivePermError, RevokePermError, SetPermError,
+        UpdatePassError,
+    },
+    event::AuthEvent,
+    perm::{Perm, PermEvent},
+};
+use sqlx::{Sqlite, Transaction, query, query_as};
+
+use tracing::trace;
+
+/// A database transaction spanning operations of several modules, see [`Basileus::begin`].
+///
+/// Nothing takes effect until [`Tx::commit`] is called.
+/// Dropping the transaction without committing rolls everything back.
+pub struct Tx<'a> {
+    basileus: &'a Basileus,
+    tx: Transaction<'static, Sqlite>,
+    /// Events emitted after commit.
+    events: Vec<AuthEvent>,
+    /// Users created, whose cached existence is invalidated after commit.
+    created: Vec<String>,
+    /// Users whose password changed, whose tokens are invalidated after commit if configured.
+    pass_changed: Vec<String>,
+    /// Token kept when invalidating tokens of users whose password changed, see [`Basileus::change_pass`].
+    keep: Option<String>,
+}
+
+impl Basileus {
+    /// Begin a transaction, so that several operations either all take effect or none does,
+    /// e.g. to provision a user with password and permissions without risking half-provisioned accounts.
+    pub async fn begin(&self) -> Result<Tx<'_>, sqlx::error::Error> {
+        let tx = self.begin_write().await?;
+        Ok(Tx {
+            basileus: self,
+            tx,
+            events: vec![],
+            created: vec![],
+            pass_changed: vec![],
+            keep: None,
+        })
+    }
+}
+
+impl Tx<'_> {
+    fn sql(&self, sql: &'static str) -> &'static str {
+        self.basileus.sql(sql)
+    }
+
+    async fn exist_user(&mut self, user: &str) -> Result<bool, sqlx::error::Error> {
+        let q = query_as(self.sql("SELECT EXISTS(SELECT 1 FROM user WHERE user = ?)")).bind(user);
+        let (res,): (i32,) = q.fetch_one(&mut *self.tx).await?;
+        Ok(res == 1)
+    }
+
+    /// Create a new user, see [`Basileus::create_user`].
+    pub async fn create_user(&mut self, user: &str) -> Result<(), CreateUserError> {
+        if self.exist_user(user).await? {
+            return Err(CreateUserError::UserAlreadyExist(user.into()));
+        }
+        let valid = self.basileus.config.read().unwrap().user.check_name(user);
+        if let Err(e) = valid {
+            return Err(CreateUserError::InvalidName(user.into(), e));
+        }
+        let q = query(self.sql("INSERT INTO user (user) VALUES (?);")).bind(user);
+        q.execute(&mut *self.tx).await?;
+        for grp in self.basileus.default_perm().iter() {
+            let q = query(self.sql("INSERT INTO user_perm (user, grp) VALUES (?, ?);"))
+                .bind(user)
+                .bind(grp);
+            q.execute(&mut *self.tx).await?;
+        }
+        self.created.push(user.into());
+        self.events
+            .push(AuthEvent::UserCreated { user: user.into() });
+        Ok(())
+    }
+
+    /// Update password for specified user, see [`Basileus::update_pass`].
+    pub async fn update_pass(&mut self, user: &str, pass: &str) -> Result<(), UpdatePassError> {
+        let hashed = self.basileus.hash_pass(pass)?;
+        self.set_pass_hash(user, hashed).await
+    }
+
+    /// Store the password `hashed` by [`Basileus::hash_pass`] for `user`,
+    /// so that hashing does not hold the write lock of the transaction.
+    pub(crate) async fn set_pass_hash(
+        &mut self,
+        user: &str,
+        hashed: String,
+    ) -> Result<(), UpdatePassError> {
+        if !self.exist_user(user).await? {
+            return Err(UpdatePassError::UserNotExist(user.into()));
+        }
+        let q = query(self.sql("INSERT OR REPLACE INTO pass (user, phc) VALUES (?, ?);"))
+            .bind(user)
+            .bind(hashed);
+        q.execute(&mut *self.tx).await?;
+        self.events
+            .push(AuthEvent::PassChanged { user: user.into() });
+        self.pass_changed.push(user.into());
+        Ok(())
+    }
+
+    /// Invalidate every other token of users whose password changed, keeping `token`.
+    pub(crate) fn keep_session(&mut self, token: &str) {
+        self.keep = Some(token.into());
+    }
+
+    /// Returns whether the user exists.
+    async fn update_perm(
+        &mut self,
+        user: &str,
+        f: impl FnOnce(&Perm) -> Perm,
+    ) -> Result<bool, sqlx::error::Error> {
+        let Some((added, removed)) = self
+            .basileus
+            .write_perm(&mut self.tx, None, user, f)
+            .await?
+        else {
+            return Ok(false);
+        };
+        if !added.is_empty() || !removed.is_empty() {
+            self.events.push(AuthEvent::PermChanged {
+                change: PermEvent::User {
+                    user: user.into(),
+                    actor: None,
+                    added,
+                    removed,
+                },
+            });
+        }
+        Ok(true)
+    }
+
+    /// Set permissions for a user, see [`Basileus::set_perm`].
+    pub async fn set_perm(&mut self, user: &str, perm: &Perm) -> Result<(), SetPermError> {
+        if let Some(invalid) = perm.find_invalid() {
+            if !self.exist_user(user).await? {
+                return Err(SetPermError::UserNotExist(user.into()));
+            }
+            return Err(SetPermError::InvalidPerm(invalid.into()));
+        }
+        if !self.update_perm(user, |_| perm.clone()).await? {
+            return Err(SetPermError::UserNotExist(user.into()));
+        }
+        Ok(())
+    }
+
+    /// Give new permissions to a user, see [`Basileus::give_perm`].
+    pub async fn give_perm(&mut self, user: &str, perm: &Perm) -> Result<(), GivePermError> {
+        if let Some(invalid) = perm.find_invalid() {
+            if !self.exist_user(user).await? {
+                return Err(GivePermError::UserNotExist(user.into()));
+            }
+            return Err(GivePermError::InvalidPerm(invalid.into()));
+        }
+        if !self.update_perm(user, |prev| prev + perm).await? {
+            return Err(GivePermError::UserNotExist(user.into()));
+        }
+        Ok(())
+    }
+
+    /// Revoke a user's certain permissions, see [`Basileus::revoke_perm`].
+    pub async fn revoke_perm(&mut self, user: &str, perm: &Perm) -> Result<(), RevokePermError> {
+        if !self.update_perm(user, |prev| prev - perm).await? {
+            return Err(RevokePermError::UserNotExist(user.into()));
+        }
+        Ok(())
+    }
+
+    /// Assign a role to a user, see [`Basileus::assign_role`].
+    pub async fn assign_role(&mut self, user: &str, role: &str) -> Result<(), AssignRoleError> {
+        if !self.exist_user(user).await? {
+            return Err(AssignRoleError::UserNotExist(user.into()));
+        }
+        let q = query_as(self.sql("SELECT EXISTS(SELECT 1 FROM role WHERE role = ?)")).bind(role);
+        let (exist,): (i32,) = q.fetch_one(&mut *self.tx).await?;
+        if exist != 1 {
+            return Err(AssignRoleError::RoleNotExist(role.into()));
+        }
+        let q = query(self.sql("INSERT OR IGNORE INTO user_role (user, role) VALUES (?, ?);"))
+            .bind(user)
+            .bind(role);
+        q.execute(&mut *self.tx).await?;
+        self.events.push(AuthEvent::PermChanged {
+            change: PermEvent::UserRole {
+                user: user.into(),
+                role: role.into(),
+                assigned: true,
+            },
+        });
+        Ok(())
+    }
+
+    /// Commit the transaction, making every operation take effect.
+    pub async fn commit(self) -> Result<(), sqlx::error::Error> {
+        self.tx.commit().await?;
+        for user in &self.created {
+            self.basileus.user.invalidate(user);
+        }
+        self.basileus.perm.invalidate_all();
+        for event in self.events {
+            match event {
+                AuthEvent::PermChanged { change } => self.basileus.emit_perm(change),
+                event => self.basileus.emit(event),
+            }
+        }
+        let revoke = self.basileus.config.read().unwrap().pass.revoke_sessions;
+        for user in &self.pass_changed {
+            match &self.keep {
+                Some(token) => self.basileus.invalidate_other_tokens(user, token),
+                None if revoke => self.basileus.invalidate_user_token(user),
+                None => {}
+            }
+        }
+        trace!("committed transaction");
+        Ok(())
+    }
+
+    /// Roll back the transaction, discarding every operation.
+    pub async fn rollback(self) -> Result<(), sqlx::error::Error> {
+        self.tx.rollback().await
+    }
+}
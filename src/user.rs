@@ -1,16 +1,35 @@
-use crate::Basileus;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use super::err::{CreateUserError, DeleteUserError};
+use crate::{Basileus, event::AuthEvent};
+
+use super::err::{CreateUserError, DeleteUserError, UsernameError};
 use sqlx::{query, query_as};
 
 use tracing::info;
 
+/// Check whether a username is valid, i.e. non-empty and consisting of printable ASCII characters except whitespace.
 pub fn check_username(user: &str) -> bool {
+    validate_username(user).is_ok()
+}
+
+/// Like [`check_username`], but tells which rule is violated.
+///
+/// Users are additionally subject to [`UserConfig::max_name_length`] and [`UserConfig::reserved`] on creation.
+pub fn validate_username(user: &str) -> Result<(), UsernameError> {
     if user.is_empty() {
-        return false;
+        return Err(UsernameError::Empty);
+    }
+    match user
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_graphic() || c.is_whitespace())
+    {
+        Some((i, c)) => Err(UsernameError::InvalidChar(i, c)),
+        None => Ok(()),
     }
-    user.chars()
-        .all(|c| c.is_ascii_graphic() && !c.is_whitespace())
 }
 
 pub const DB_INIT: &str = r#"
@@ -20,36 +39,142 @@ CREATE TABLE IF NOT EXISTS user (
 CREATE INDEX IF NOT EXISTS idx_user_user ON user (user);
 "#;
 
+/// User management configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct UserConfig {
+    /// Time-to-live of cached results of [`Basileus::exist_user`], in seconds.
+    ///
+    /// Both existing and missing users are cached.
+    /// Setting this to `0` disables the cache.
+    #[cfg_attr(feature = "serde", serde(rename = "exist-cache-ttl"))]
+    pub exist_cache_ttl: u64,
+    /// Maximum length of names of newly created users, in characters.
+    #[cfg_attr(feature = "serde", serde(rename = "max-name-length"))]
+    pub max_name_length: usize,
+    /// Names which can not be taken by newly created users, compared case-insensitively, e.g. `admin`.
+    #[cfg_attr(feature = "serde", serde(rename = "reserved"))]
+    pub reserved: Vec<String>,
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        Self {
+            exist_cache_ttl: 5,
+            max_name_length: 255,
+            reserved: vec![],
+        }
+    }
+}
+
+impl UserConfig {
+    /// Check whether `user` may be taken by a newly created user.
+    pub fn check_name(&self, user: &str) -> Result<(), UsernameError> {
+        validate_username(user)?;
+        if user.len() > self.max_name_length {
+            return Err(UsernameError::TooLong(self.max_name_length));
+        }
+        if self.reserved.iter().any(|x| x.eq_ignore_ascii_case(user)) {
+            return Err(UsernameError::Reserved);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct UserModule {
+    /// Map from users to whether they exist and time of caching.
+    cache: Mutex<HashMap<String, (bool, Instant)>>,
+}
+
+impl UserModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the cached existence of a user, if present and younger than `ttl` seconds.
+    fn cached(&self, user: &str, ttl: u64) -> Option<bool> {
+        let ttl = Duration::from_secs(ttl);
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(user) {
+            Some((exist, time)) if time.elapsed() < ttl => Some(*exist),
+            Some(_) => {
+                cache.remove(user);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn cache(&self, user: &str, exist: bool, ttl: u64) {
+        if ttl == 0 {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(user.into(), (exist, Instant::now()));
+    }
+
+    /// Invalidate the cached existence of a user.
+    pub(crate) fn invalidate(&self, user: &str) {
+        self.cache.lock().unwrap().remove(user);
+    }
+
+    /// Invalidate the cached existence of all users.
+    pub(crate) fn invalidate_all(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
 impl Basileus {
     /// Check whether a user currently exists.
+    ///
+    /// Results are cached for [`UserConfig::exist_cache_ttl`] seconds,
+    /// and invalidated whenever the user is created or deleted through this library.
     pub async fn exist_user(&self, user: &str) -> Result<bool, sqlx::error::Error> {
-        let query = query_as("SELECT EXISTS(SELECT 1 FROM user WHERE user = ?)").bind(user);
+        let ttl = self.config.read().unwrap().user.exist_cache_ttl;
+        if let Some(exist) = self.user.cached(user, ttl) {
+            return Ok(exist);
+        }
+        let query =
+            query_as(self.sql("SELECT EXISTS(SELECT 1 FROM user WHERE user = ?)")).bind(user);
         let (res,): (i32,) = query.fetch_one(&self.db).await?;
+        self.user.cache(user, res == 1, ttl);
         Ok(res == 1)
     }
 
-    /// Create a new user.
+    /// List all users.
+    pub async fn list_users(&self) -> Result<Vec<String>, sqlx::error::Error> {
+        let query = query_as(self.sql("SELECT user FROM user ORDER BY user"));
+        let res: Vec<(String,)> = query.fetch_all(&self.db).await?;
+        Ok(res.into_iter().map(|(user,)| user).collect())
+    }
+
+    /// Create a new user, giving them the [default permissions](Self::default_perm).
     pub async fn create_user(&self, user: &str) -> Result<(), CreateUserError> {
-        if self.exist_user(user).await? {
-            return Err(CreateUserError::UserAlreadyExist(user.into()));
-        }
-        if !check_username(user) {
-            return Err(CreateUserError::InvalidName(user.into()));
-        }
-        let q = query("INSERT INTO user (user) VALUES (?);").bind(user);
-        q.execute(&self.db).await?;
-        info!("created user {user}");
-        Ok(())
+        self.spanned("create_user", Some(user), async {
+            let mut tx = self.begin().await?;
+            tx.create_user(user).await?;
+            tx.commit().await?;
+            info!("created user {}", self.log_user(user));
+            Ok(())
+        })
+        .await
     }
 
     /// Delete a user.
     pub async fn delete_user(&self, user: &str) -> Result<(), DeleteUserError> {
-        if !self.exist_user(user).await? {
-            return Err(DeleteUserError::UserNotExist(user.into()));
-        }
-        let query = query("DELETE FROM user WHERE user = ?").bind(user);
-        query.execute(&self.db).await?;
-        info!("deleted user {user}");
-        Ok(())
+        self.spanned("delete_user", Some(user), async {
+            let query = query(self.sql("DELETE FROM user WHERE user = ?")).bind(user);
+            if query.execute(&self.writer).await?.rows_affected() == 0 {
+                return Err(DeleteUserError::UserNotExist(user.into()));
+            }
+            self.user.invalidate(user);
+            self.perm.invalidate(user);
+            info!("deleted user {}", self.log_user(user));
+            self.emit(AuthEvent::UserDeleted { user: user.into() });
+            Ok(())
+        })
+        .await
     }
 }
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use warp::{
+    Filter, Rejection, Reply,
+    http::StatusCode,
+    reject::{self, Reject},
+    reply,
+};
+
+use crate::{
+    Basileus,
+    authorization::bearer,
+    err::{AuthError, ErrorBody, HttpStatus},
+    perm::Perm,
+    token::AuthUser,
+};
+
+impl Reject for AuthError {}
+
+/// Filter extracting the user authenticated by the bearer token of the request.
+///
+/// Requests are rejected with an [`AuthError`], which [`handle_rejection`] turns into a response.
+pub fn with_auth(
+    basileus: Arc<Basileus>,
+) -> impl Filter<Extract = (AuthUser,), Error = Rejection> + Clone {
+    auth(basileus, None)
+}
+
+/// Like [`with_auth`], but additionally require the user to hold `perm`, as in [`Basileus::authorize`].
+pub fn require_perm(
+    basileus: Arc<Basileus>,
+    perm: impl Into<Perm>,
+) -> impl Filter<Extract = (AuthUser,), Error = Rejection> + Clone {
+    auth(basileus, Some(perm.into()))
+}
+
+fn auth(
+    basileus: Arc<Basileus>,
+    perm: Option<Perm>,
+) -> impl Filter<Extract = (AuthUser,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(move |value: Option<String>| {
+        let basileus = basileus.clone();
+        let perm = perm.clone();
+        async move {
+            let token = bearer(value.as_deref()).map_err(reject::custom)?;
+            let auth = match &perm {
+                Some(perm) => basileus.authorize(&token, perm).await,
+                None => basileus.authenticate(&token).await,
+            };
+            auth.map_err(reject::custom)
+        }
+    })
+}
+
+/// Turn rejections by [`with_auth`] and [`require_perm`] into responses with an [`ErrorBody`],
+/// passing other rejections on, e.g. `routes.recover(handle_rejection)`.
+pub async fn handle_rejection(rejection: Rejection) -> Result<reply::Response, Rejection> {
+    let Some(e) = rejection.find::<AuthError>() else {
+        return Err(rejection);
+    };
+    let status = StatusCode::from_u16(e.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let res = reply::with_status(reply::json(&ErrorBody::new(e)), status);
+    if status == StatusCode::UNAUTHORIZED {
+        return Ok(reply::with_header(res, "www-authenticate", "Bearer").into_response());
+    }
+    Ok(res.into_response())
+}
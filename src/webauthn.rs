@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use sqlx::{query, query_as};
+use tracing::{debug, info, warn};
+
+use crate::{Basileus, err::MfaError, mfa::MfaKind, rand_buf, unix_now};
+
+/// WebAuthn relying party configuration.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WebauthnConfig {
+    /// Relying party identifier, i.e. the domain credentials are scoped to, e.g. `example.com`.
+    #[cfg_attr(feature = "serde", serde(rename = "rp-id"))]
+    pub rp_id: String,
+    /// Name of the relying party shown by authenticators.
+    #[cfg_attr(feature = "serde", serde(rename = "rp-name"))]
+    pub rp_name: String,
+    /// Time in seconds a challenge stays valid.
+    #[cfg_attr(feature = "serde", serde(rename = "ttl"))]
+    pub ttl: u64,
+}
+
+impl Default for WebauthnConfig {
+    fn default() -> Self {
+        Self {
+            rp_id: "localhost".into(),
+            rp_name: "basileus".into(),
+            ttl: 300,
+        }
+    }
+}
+
+/// A public key credential registered by an authenticator, e.g. a security key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebauthnCredential {
+    /// The credential ID chosen by the authenticator.
+    pub id: Vec<u8>,
+    /// The credential public key, in whatever encoding the [verifier](WebauthnVerifier) uses, e.g. COSE.
+    pub public_key: Vec<u8>,
+    /// The signature counter of the authenticator, or `0` if it does not keep one.
+    pub sign_count: u32,
+}
+
+/// A verified assertion, see [`WebauthnVerifier::authenticate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebauthnAssertion {
+    /// ID of the credential which signed the assertion.
+    pub id: Vec<u8>,
+    /// The signature counter reported by the authenticator.
+    pub sign_count: u32,
+}
+
+/// Verifier of WebAuthn ceremonies, see [`Basileus::set_webauthn_verifier`].
+///
+/// Parsing attestation objects and checking signatures is left to the application,
+/// e.g. on top of `webauthn-rs`, whereas the library issues challenges, stores credentials,
+/// rejects cloned authenticators by their signature counters, and enforces the [MFA](crate::mfa) policy.
+/// Responses are passed through as sent by the client, e.g. the JSON serialization of a `PublicKeyCredential`.
+pub trait WebauthnVerifier: Send + Sync {
+    /// Verify a registration `response` to `challenge`, including its client data and origin,
+    /// returning the new credential.
+    fn register(&self, challenge: &[u8], response: &str) -> Option<WebauthnCredential>;
+
+    /// Verify an authentication `response` to `challenge` signed by one of `credentials`.
+    fn authenticate(
+        &self,
+        challenge: &[u8],
+        credentials: &[WebauthnCredential],
+        response: &str,
+    ) -> Option<WebauthnAssertion>;
+}
+
+/// Parameters of a WebAuthn ceremony to pass to `navigator.credentials`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct WebauthnChallenge {
+    /// The base64URL-encoded challenge.
+    pub challenge: String,
+    /// See [`WebauthnConfig::rp_id`].
+    pub rp_id: String,
+    /// See [`WebauthnConfig::rp_name`].
+    pub rp_name: String,
+    /// Base64URL-encoded IDs of the credentials of the user,
+    /// to be excluded on registration and allowed on authentication.
+    pub credentials: Vec<String>,
+    /// Time in seconds until the challenge expires.
+    pub timeout: u64,
+}
+
+/// Purpose a challenge was issued for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Ceremony {
+    Register,
+    Authenticate,
+}
+
+/// A challenge issued and not yet answered.
+struct PendingChallenge {
+    challenge: Vec<u8>,
+    issued: SystemTime,
+}
+
+#[derive(Default)]
+pub struct WebauthnModule {
+    verifier: RwLock<Option<Arc<dyn WebauthnVerifier>>>,
+    /// Map from users and ceremonies to the last challenge issued.
+    pending: Mutex<HashMap<(String, Ceremony), PendingChallenge>>,
+}
+
+impl WebauthnModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Basileus {
+    /// Verify WebAuthn ceremonies with `verifier`, replacing any previously set.
+    ///
+    /// Security keys can neither be registered nor used until a verifier is set.
+    pub fn set_webauthn_verifier(&self, verifier: impl WebauthnVerifier + 'static) {
+        *self.webauthn.verifier.write().unwrap() = Some(Arc::new(verifier));
+    }
+
+    /// Credentials registered by `user`.
+    async fn webauthn_credentials(
+        &self,
+        user: &str,
+    ) -> Result<Vec<(i64, WebauthnCredential)>, sqlx::error::Error> {
+        let q = query_as(self.sql(
+            "SELECT id, name, secret, counter FROM mfa_factor WHERE user = ? AND kind = 'webauthn' AND enabled",
+        ))
+        .bind(user);
+        let res: Vec<(i64, String, Vec<u8>, i64)> = q.fetch_all(&self.db).await?;
+        Ok(res
+            .into_iter()
+            .filter_map(|(row, id, public_key, sign_count)| {
+                let id = BASE64_URL_SAFE_NO_PAD.decode(id).ok()?;
+                let credential = WebauthnCredential {
+                    id,
+                    public_key,
+                    sign_count: sign_count as u32,
+                };
+                Some((row, credential))
+            })
+            .collect())
+    }
+
+    /// Issue a challenge to `user` for `ceremony`, replacing any issued earlier.
+    async fn webauthn_challenge(
+        &self,
+        user: &str,
+        ceremony: Ceremony,
+    ) -> Result<WebauthnChallenge, MfaError> {
+        if self.webauthn.verifier.read().unwrap().is_none() {
+            return Err(MfaError::NoVerifier);
+        }
+        let credentials: Vec<String> = self
+            .webauthn_credentials(user)
+            .await?
+            .iter()
+            .map(|(_, x)| BASE64_URL_SAFE_NO_PAD.encode(&x.id))
+            .collect();
+        if ceremony == Ceremony::Authenticate && credentials.is_empty() {
+            return Err(MfaError::NotEnrolled(user.into()));
+        }
+        let config = self.config.read().unwrap().webauthn.clone();
+        let challenge = rand_buf(32);
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(config.ttl);
+        let mut pending = self.webauthn.pending.lock().unwrap();
+        pending.retain(|_, x| now.duration_since(x.issued).is_ok_and(|d| d < ttl));
+        pending.insert(
+            (user.into(), ceremony),
+            PendingChallenge {
+                challenge: challenge.clone(),
+                issued: now,
+            },
+        );
+        Ok(WebauthnChallenge {
+            challenge: BASE64_URL_SAFE_NO_PAD.encode(challenge),
+            rp_id: config.rp_id,
+            rp_name: config.rp_name,
+            credentials,
+            timeout: config.ttl,
+        })
+    }
+
+    /// Take the challenge issued to `user` for `ceremony`, if any and not expired.
+    fn take_webauthn_challenge(&self, user: &str, ceremony: Ceremony) -> Option<Vec<u8>> {
+        let ttl = Duration::from_secs(self.config.read().unwrap().webauthn.ttl);
+        let key = (user.to_owned(), ceremony);
+        let pending = self.webauthn.pending.lock().unwrap().remove(&key)?;
+        let PendingChallenge { challenge, issued } = pending;
+        issued.elapsed().is_ok_and(|d| d < ttl).then_some(challenge)
+    }
+
+    /// Whether a challenge for signing with a security key is pending for `user`.
+    pub(crate) fn webauthn_pending(&self, user: &str) -> bool {
+        let key = (user.to_owned(), Ceremony::Authenticate);
+        self.webauthn.pending.lock().unwrap().contains_key(&key)
+    }
+
+    /// Start registering a security key of `user` as second factor, to be completed by
+    /// [`Self::complete_webauthn_registration`] with the response of `navigator.credentials.create`.
+    pub async fn begin_webauthn_registration(
+        &self,
+        user: &str,
+    ) -> Result<WebauthnChallenge, MfaError> {
+        self.spanned("begin_webauthn_registration", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Webauthn) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            if !self.exist_user(user).await? {
+                return Err(MfaError::UserNotExist(user.into()));
+            }
+            self.webauthn_challenge(user, Ceremony::Register).await
+        })
+        .await
+    }
+
+    /// Register the security key of `user` from the `response` to the challenge of
+    /// [`Self::begin_webauthn_registration`].
+    ///
+    /// Returns `None` if the response was rejected, and otherwise the recovery codes as [`Self::confirm_totp_enrollment`].
+    pub async fn complete_webauthn_registration(
+        &self,
+        user: &str,
+        response: &str,
+    ) -> Result<Option<Vec<String>>, MfaError> {
+        self.spanned("complete_webauthn_registration", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Webauthn) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            let verifier = self.webauthn.verifier.read().unwrap().clone();
+            let verifier = verifier.ok_or(MfaError::NoVerifier)?;
+            let Some(challenge) = self.take_webauthn_challenge(user, Ceremony::Register) else {
+                debug!("no WebAuthn registration pending for {}", self.log_user(user));
+                return Ok(None);
+            };
+            let Some(credential) = verifier.register(&challenge, response) else {
+                warn!("rejected WebAuthn registration of {}", self.log_user(user));
+                return Ok(None);
+            };
+            let id = BASE64_URL_SAFE_NO_PAD.encode(&credential.id);
+            let mut tx = self.begin_write().await?;
+            let q = query(self.sql(
+                "INSERT OR IGNORE INTO mfa_factor (user, kind, name, secret, counter, enabled, created_at)
+                VALUES (?, 'webauthn', ?, ?, ?, 1, ?)",
+            ))
+            .bind(user)
+            .bind(&id)
+            .bind(&credential.public_key)
+            .bind(credential.sign_count as i64)
+            .bind(unix_now());
+            if q.execute(&mut *tx).await?.rows_affected() == 0 {
+                return Err(MfaError::AlreadyEnrolled(user.into()));
+            }
+            let codes = self.initial_recovery_codes(&mut tx, user).await?;
+            tx.commit().await?;
+            info!(
+                "registered WebAuthn credential '{id}' of {}",
+                self.log_user(user)
+            );
+            Ok(Some(codes))
+        })
+        .await
+    }
+
+    /// Base64URL-encoded IDs of the credentials `user` registered.
+    pub async fn list_webauthn(&self, user: &str) -> Result<Vec<String>, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(vec![]);
+        }
+        let q = query_as(self.sql(
+            "SELECT name FROM mfa_factor WHERE user = ? AND kind = 'webauthn' ORDER BY created_at",
+        ))
+        .bind(user);
+        let res: Vec<(String,)> = q.fetch_all(&self.db).await?;
+        Ok(res.into_iter().map(|(x,)| x).collect())
+    }
+
+    /// Remove the credential of `user` with the base64URL-encoded `id`, returning whether it existed.
+    ///
+    /// Recovery codes are removed as well once the user has no second factor left.
+    pub async fn remove_webauthn(&self, user: &str, id: &str) -> Result<bool, sqlx::error::Error> {
+        if !self.config.read().unwrap().modules.mfa {
+            return Ok(false);
+        }
+        let mut tx = self.begin_write().await?;
+        let q = query(
+            self.sql("DELETE FROM mfa_factor WHERE user = ? AND kind = 'webauthn' AND name = ?"),
+        )
+        .bind(user)
+        .bind(id);
+        let res = q.execute(&mut *tx).await?.rows_affected() > 0;
+        self.prune_recovery_codes(&mut tx, user).await?;
+        tx.commit().await?;
+        if res {
+            info!(
+                "removed WebAuthn credential '{id}' of {}",
+                self.log_user(user)
+            );
+        }
+        Ok(res)
+    }
+
+    /// Issue a challenge for `user` to sign with a registered security key through `navigator.credentials.get`,
+    /// and to present as [`LoginContext::webauthn`](crate::login::LoginContext::webauthn).
+    pub async fn begin_webauthn_assertion(
+        &self,
+        user: &str,
+    ) -> Result<WebauthnChallenge, MfaError> {
+        self.spanned("begin_webauthn_assertion", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Webauthn) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            self.webauthn_challenge(user, Ceremony::Authenticate).await
+        })
+        .await
+    }
+
+    /// Verify the `response` of `user` to the challenge of [`Self::begin_webauthn_assertion`].
+    ///
+    /// Every challenge is accepted once only.
+    /// Assertions whose signature counter did not increase are rejected,
+    /// since they indicate a cloned authenticator, unless the authenticator keeps no counter.
+    pub async fn verify_webauthn(&self, user: &str, response: &str) -> Result<bool, MfaError> {
+        self.spanned("verify_webauthn", Some(user), async {
+            if !self.mfa_kind_enabled(MfaKind::Webauthn) {
+                return Err(MfaError::FeatureDisabled);
+            }
+            let res = self.check_webauthn(user, response).await?;
+            self.count(if res { "mfa.success" } else { "mfa.failure" });
+            Ok(res)
+        })
+        .await
+    }
+
+    pub(crate) async fn check_webauthn(
+        &self,
+        user: &str,
+        response: &str,
+    ) -> Result<bool, sqlx::error::Error> {
+        if !self.mfa_kind_enabled(MfaKind::Webauthn) {
+            return Ok(false);
+        }
+        let Some(verifier) = self.webauthn.verifier.read().unwrap().clone() else {
+            return Ok(false);
+        };
+        let Some(challenge) = self.take_webauthn_challenge(user, Ceremony::Authenticate) else {
+            return Ok(false);
+        };
+        let rows = self.webauthn_credentials(user).await?;
+        let credentials: Vec<WebauthnCredential> = rows.iter().map(|(_, x)| x.clone()).collect();
+        let Some(assertion) = verifier.authenticate(&challenge, &credentials, response) else {
+            debug!("rejected WebAuthn assertion of {}", self.log_user(user));
+            return Ok(false);
+        };
+        let Some((row, credential)) = rows.iter().find(|(_, x)| x.id == assertion.id) else {
+            return Ok(false);
+        };
+        let prev = credential.sign_count;
+        if (prev != 0 || assertion.sign_count != 0) && assertion.sign_count <= prev {
+            warn!(
+                "signature counter of WebAuthn credential of {} did not increase, possibly cloned",
+                self.log_user(user)
+            );
+            return Ok(false);
+        }
+        // only succeed if no concurrent assertion updated the counter meanwhile
+        let q = query(self.sql("UPDATE mfa_factor SET counter = ? WHERE id = ? AND counter = ?"))
+            .bind(assertion.sign_count as i64)
+            .bind(row)
+            .bind(prev as i64);
+        Ok(q.execute(&self.writer).await?.rows_affected() > 0)
+    }
+}
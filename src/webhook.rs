@@ -0,0 +1,195 @@
+use std::{fmt::Display, time::Duration};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::{
+    Basileus,
+    err::WebhookError,
+    event::{AuthEvent, Severity},
+    logging::{Secret, redact_url},
+    rand_buf, unix_now,
+};
+
+/// Configuration of webhooks, see [`Basileus::deliver_webhook`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WebhookConfig {
+    /// URLs every event is posted to.
+    #[cfg_attr(feature = "serde", serde(rename = "endpoints"))]
+    pub endpoints: Vec<String>,
+    /// Secret payloads are signed with,
+    /// which may reference the environment or a file, see [`Config::resolve_secrets`](crate::Config::resolve_secrets).
+    ///
+    /// If unset, payloads are not signed.
+    #[cfg_attr(feature = "serde", serde(rename = "secret"))]
+    pub secret: Option<Secret<String>>,
+    /// [Types](AuthEvent::kind) of events delivered, e.g. `login_failed`, or every type if empty.
+    #[cfg_attr(feature = "serde", serde(rename = "events"))]
+    pub events: Vec<String>,
+    /// Least [severity](AuthEvent::severity) of events delivered.
+    #[cfg_attr(feature = "serde", serde(rename = "severity"))]
+    pub severity: Severity,
+    /// Number of attempts to deliver an event to an endpoint before giving up.
+    #[cfg_attr(feature = "serde", serde(rename = "max-attempts"))]
+    pub max_attempts: u32,
+    /// Delay in seconds before the first retry, doubled for every further retry.
+    #[cfg_attr(feature = "serde", serde(rename = "retry-delay"))]
+    pub retry_delay: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![],
+            secret: None,
+            events: vec![],
+            severity: Severity::Info,
+            max_attempts: 5,
+            retry_delay: 1,
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// Whether events of this type and severity are delivered.
+    pub fn accepts(&self, event: &AuthEvent) -> bool {
+        event.severity() >= self.severity
+            && (self.events.is_empty() || self.events.iter().any(|x| x == event.kind()))
+    }
+}
+
+/// Body of a webhook request.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct WebhookPayload {
+    /// Identifies the delivery, which stays the same across retries, so that receivers may discard duplicates.
+    pub id: String,
+    /// Time the event was dispatched, in seconds since the Unix epoch.
+    pub time: i64,
+    pub event: AuthEvent,
+}
+
+/// A `POST` request to be sent by the application's HTTP client, see [`Basileus::deliver_webhook`].
+#[derive(Clone, Debug)]
+pub struct WebhookRequest {
+    pub url: String,
+    /// Headers to send, including `Content-Type` and, if a [secret](WebhookConfig::secret) is configured,
+    /// `X-Basileus-Signature`, see [`sign`].
+    pub headers: Vec<(&'static str, String)>,
+    /// The [`WebhookPayload`] as JSON.
+    pub body: String,
+}
+
+/// Signature of a webhook body, of the form `t=<time>,v1=<hex>`,
+/// where `<hex>` is the HMAC-SHA256 of `<time>.<body>` under `secret`.
+///
+/// Receivers recompute the signature to verify the request,
+/// and should reject old timestamps to prevent replays.
+pub fn sign(secret: &str, time: i64, body: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{time}.{body}").as_bytes());
+    let sig = mac.finalize().into_bytes();
+    let hex: String = sig.iter().map(|x| format!("{x:02x}")).collect();
+    format!("t={time},v1={hex}")
+}
+
+impl Basileus {
+    /// Build the requests delivering `event` to every [endpoint](WebhookConfig::endpoints),
+    /// or none if its type is [filtered out](WebhookConfig::events).
+    pub fn webhook_requests(&self, event: &AuthEvent) -> Vec<WebhookRequest> {
+        let config = self.config.read().unwrap().webhook.clone();
+        if !config.accepts(event) {
+            return vec![];
+        }
+        let id: String = rand_buf(16).iter().map(|x| format!("{x:02x}")).collect();
+        let time = unix_now();
+        let payload = WebhookPayload {
+            id: id.clone(),
+            time,
+            event: event.clone(),
+        };
+        // serializing plain strings and permission sets never fails
+        let body = serde_json::to_string(&payload).unwrap();
+        let mut headers = vec![
+            ("Content-Type", "application/json".to_owned()),
+            ("X-Basileus-Event", event.kind().to_owned()),
+            ("X-Basileus-Severity", event.severity().to_string()),
+            ("X-Basileus-Delivery", id),
+        ];
+        if let Some(secret) = &config.secret {
+            headers.push(("X-Basileus-Signature", sign(secret.expose(), time, &body)));
+        }
+        config
+            .endpoints
+            .into_iter()
+            .map(|url| WebhookRequest {
+                url,
+                headers: headers.clone(),
+                body: body.clone(),
+            })
+            .collect()
+    }
+
+    /// Deliver `event` to every [endpoint](WebhookConfig::endpoints),
+    /// retrying each up to [`WebhookConfig::max_attempts`] times with exponential backoff.
+    ///
+    /// The library depends on neither an HTTP client nor an async runtime,
+    /// so `send` must post the request and fail unless the endpoint accepted it,
+    /// and `sleep` must be provided, e.g. `tokio::time::sleep`.
+    ///
+    /// Events are typically forwarded from [`Self::subscribe_events`] into a channel,
+    /// whose receiving task calls this for each of them.
+    pub async fn deliver_webhook<F, Fut, E, S, SFut>(
+        &self,
+        event: &AuthEvent,
+        send: F,
+        sleep: S,
+    ) -> Result<(), WebhookError>
+    where
+        F: Fn(WebhookRequest) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+        S: Fn(Duration) -> SFut,
+        SFut: Future<Output = ()>,
+    {
+        let (max_attempts, retry_delay) = {
+            let config = &self.config.read().unwrap().webhook;
+            (config.max_attempts.max(1), config.retry_delay)
+        };
+        let mut failed = vec![];
+        for req in self.webhook_requests(event) {
+            let mut delay = Duration::from_secs(retry_delay);
+            let mut attempt = 1;
+            loop {
+                let url = req.url.clone();
+                let origin = redact_url(&url);
+                match send(req.clone()).await {
+                    Ok(()) => {
+                        debug!("delivered {} event to {origin}", event.kind());
+                        break;
+                    }
+                    Err(e) if attempt >= max_attempts => {
+                        warn!(
+                            "giving up delivering {} event to {origin} after {attempt} attempts: {e}",
+                            event.kind()
+                        );
+                        failed.push(url);
+                        break;
+                    }
+                    Err(e) => {
+                        debug!("attempt {attempt} to deliver to {origin} failed: {e}");
+                    }
+                }
+                sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+        if !failed.is_empty() {
+            return Err(WebhookError::Undelivered(failed));
+        }
+        Ok(())
+    }
+}